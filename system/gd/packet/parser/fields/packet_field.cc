@@ -136,11 +136,6 @@ void PacketField::GenBoundsCheck(std::ostream& s, Size start_offset, Size, std::
   if (size.bits() < 8) {
     return;
   }
-  s << "if bytes.len() < " << start_offset.bytes() + size.bytes() << " {";
-  s << " return Err(Error::InvalidLengthError{";
-  s << "    obj: \"" << parent_name << "\".to_string(),";
-  s << "    field: \"" << GetName() << "\".to_string(),";
-  s << "    wanted: " << start_offset.bytes() + size.bytes() << ",";
-  s << "    got: bytes.len()});";
-  s << "}";
+  s << "pdl_runtime::check_len(\"" << parent_name << "\", \"" << GetName() << "\", ";
+  s << start_offset.bytes() + size.bytes() << ", bytes.len())?;";
 }
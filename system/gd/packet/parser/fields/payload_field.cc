@@ -124,13 +124,8 @@ void PayloadField::GenBoundsCheck(std::ostream& s, Size start_offset, Size, std:
       s << " - ((" << size_modifier_.substr(1) << ") / 8)";
     }
     s << ";";
-    s << "if bytes.len() < want_ {";
-    s << " return Err(Error::InvalidLengthError{";
-    s << "    obj: \"" << parent_name << "\".to_string(),";
-    s << "    field: \"" << GetName() << "\".to_string(),";
-    s << "    wanted: want_,";
-    s << "    got: bytes.len()});";
-    s << "}";
+    s << "pdl_runtime::check_len(\"" << parent_name << "\", \"" << GetName() << "\", ";
+    s << "want_, bytes.len())?;";
     if (!size_modifier_.empty()) {
       s << "if ((" << size_field_->GetName() << " as usize) < ((" << size_modifier_.substr(1) << ") / 8)) {";
       s << " return Err(Error::ImpossibleStructError);";
@@ -265,13 +265,8 @@ void VectorField::GenBoundsCheck(std::ostream& s, Size start_offset, Size, std::
       s << " - ((" << GetSizeModifier().substr(1) << ") / 8)";
     }
     s << ";";
-    s << "if bytes.len() < want_ {";
-    s << " return Err(Error::InvalidLengthError{";
-    s << "    obj: \"" << parent_name << "\".to_string(),";
-    s << "    field: \"" << GetName() << "\".to_string(),";
-    s << "    wanted: want_,";
-    s << "    got: bytes.len()});";
-    s << "}";
+    s << "pdl_runtime::check_len(\"" << parent_name << "\", \"" << GetName() << "\", ";
+    s << "want_, bytes.len())?;";
     if (GetSizeModifier() != "") {
       s << "if ((" << size_field_->GetName() << " as usize) < ((" << GetSizeModifier().substr(1) << ") / 8)) {";
       s << " return Err(Error::ImpossibleStructError);";
@@ -282,13 +277,8 @@ void VectorField::GenBoundsCheck(std::ostream& s, Size start_offset, Size, std::
       !element_size.has_dynamic()) {
     s << "let want_ = " << start_offset.bytes() << " + ((" << size_field_->GetName() << " as usize) * "
       << element_size.bytes() << ");";
-    s << "if bytes.len() < want_ {";
-    s << " return Err(Error::InvalidLengthError{";
-    s << "    obj: \"" << parent_name << "\".to_string(),";
-    s << "    field: \"" << GetName() << "\".to_string(),";
-    s << "    wanted: want_,";
-    s << "    got: bytes.len()});";
-    s << "}";
+    s << "pdl_runtime::check_len(\"" << parent_name << "\", \"" << GetName() << "\", ";
+    s << "want_, bytes.len())?;";
   } else if (size_field_ == nullptr && element_field_type == ScalarField::kFieldType) {
     s << "let rem_ = (bytes.len() - " << start_offset.bytes() << ") % " << element_size.bytes() << ";";
     s << "if rem_ != 0 {";
@@ -243,6 +243,83 @@ using ::bluetooth::packet::parser::ChecksumTypeChecker;
   return true;
 }
 
+// Generate a libFuzzer entry point parsing the root packets. The first
+// byte of the input selects the root packet, the remaining bytes are
+// parsed and printed if valid.
+bool generate_cpp_fuzzer_one_file(
+    const Declarations& decls,
+    const std::filesystem::path& input_file,
+    const std::filesystem::path& include_dir,
+    const std::filesystem::path& out_dir,
+    const std::string& root_namespace) {
+  auto gen_relative_path = input_file.lexically_relative(include_dir).parent_path();
+
+  auto input_filename = input_file.filename().string().substr(0, input_file.filename().string().find(".pdl"));
+  auto gen_path = out_dir / gen_relative_path;
+
+  std::filesystem::create_directories(gen_path);
+
+  auto gen_file = gen_path / (input_filename + "_fuzzer.cc");
+  auto gen_relative_header = gen_relative_path / (input_filename + ".h");
+
+  std::cout << "generating " << gen_file << std::endl;
+
+  std::ofstream out_file;
+  out_file.open(gen_file);
+  if (!out_file.is_open()) {
+    std::cerr << "can't open " << gen_file << std::endl;
+    return false;
+  }
+
+  std::vector<const PacketDef*> roots;
+  for (const auto& packet_def : decls.packet_defs_queue_) {
+    if (packet_def.second->parent_ == nullptr) {
+      roots.push_back(packet_def.second);
+    }
+  }
+
+  out_file << "// @generated libFuzzer entry point for " << input_file.filename().string() << "\n\n";
+  out_file << "#include <cstddef>\n";
+  out_file << "#include <cstdint>\n";
+  out_file << "#include <memory>\n";
+  out_file << "#include <vector>\n\n";
+  out_file << "#include " << gen_relative_header << "\n\n";
+
+  std::vector<std::string> namespace_list;
+  parse_namespace(root_namespace, gen_relative_path, &namespace_list);
+  generate_namespace_open(namespace_list, out_file);
+  out_file << "\n";
+  for (const auto root : roots) {
+    root->GenLibFuzzerTarget(out_file);
+    out_file << "\n";
+  }
+  generate_namespace_close(namespace_list, out_file);
+
+  std::string qualifier;
+  for (const auto& ns : namespace_list) {
+    qualifier += ns + "::";
+  }
+  out_file << "\nextern \"C\" int LLVMFuzzerTestOneInput(const uint8_t* data, size_t size) {\n";
+  if (!roots.empty()) {
+    out_file << "  if (size == 0) {\n";
+    out_file << "    return 0;\n";
+    out_file << "  }\n";
+    out_file << "  switch (data[0] % " << roots.size() << ") {\n";
+    for (size_t i = 0; i < roots.size(); i++) {
+      out_file << "    case " << i << ":\n";
+      out_file << "      " << qualifier << "Fuzz" << roots[i]->name_ << "(data + 1, size - 1);\n";
+      out_file << "      break;\n";
+    }
+    out_file << "  }\n";
+  }
+  out_file << "  return 0;\n";
+  out_file << "}\n";
+
+  out_file.close();
+
+  return true;
+}
+
 // Get the out_file shard at a symbol_count
 std::ofstream& get_out_file(size_t symbol_count, size_t symbol_total, std::vector<std::ofstream>* out_files) {
   auto symbols_per_shard = symbol_total / out_files->size();
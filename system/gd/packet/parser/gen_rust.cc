@@ -16,8 +16,13 @@
 
 #include <filesystem>
 #include <fstream>
+#include <future>
 #include <iostream>
+#include <map>
+#include <sstream>
+#include <vector>
 #include "declarations.h"
+#include "util.h"
 
 void generate_rust_packet_preamble(std::ostream& s) {
   s <<
@@ -27,82 +32,23 @@ use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
-use thiserror::Error;
 use std::sync::Arc;
 
-type Result<T> = std::result::Result<T, Error>;
-
-#[derive(Debug, Error)]
-pub enum Error {
-  #[error("Packet parsing failed")]
-  InvalidPacketError,
-  #[error("{field} was {value:x}, which is not known")]
-  ConstraintOutOfBounds {
-    field: String,
-    value: u64,
-  },
-  #[error("when parsing {obj}.{field} needed length of {wanted} but got {got}")]
-  InvalidLengthError {
-    obj: String,
-    field: String,
-    wanted: usize,
-    got: usize,
-  },
-  #[error("Due to size restrictions a struct could not be parsed.")]
-  ImpossibleStructError,
-  #[error("when parsing field {obj}.{field}, {value} is not a valid {type_} value")]
-  InvalidEnumValueError {
-    obj: String,
-    field: String,
-    value: u64,
-    type_: String,
-  },
-}
-
-#[derive(Debug, Error)]
-#[error("{0}")]
-pub struct TryFromError(&'static str);
-
-pub trait Packet {
-  fn to_bytes(self) -> Bytes;
-  fn to_vec(self) -> Vec<u8>;
-}
+use pdl_runtime::Result;
+pub use pdl_runtime::{Error, Packet, TryFromError};
 
 )";
 }
 
-bool generate_rust_source_one_file(
-    const Declarations& decls,
-    const std::filesystem::path& input_file,
-    const std::filesystem::path& include_dir,
-    const std::filesystem::path& out_dir,
-    __attribute__((unused)) const std::string& root_namespace) {
-  auto gen_relative_path = input_file.lexically_relative(include_dir).parent_path();
-
-  auto input_filename = input_file.filename().string().substr(0, input_file.filename().string().find(".pdl"));
-  auto gen_path = out_dir / gen_relative_path;
-
-  std::filesystem::create_directories(gen_path);
-
-  auto gen_file = gen_path / (input_filename + ".rs");
-
-  std::cout << "generating " << gen_file << std::endl;
-
-  std::ofstream out_file;
-  out_file.open(gen_file);
-  if (!out_file.is_open()) {
-    std::cerr << "can't open " << gen_file << std::endl;
-    return false;
-  }
-
-  out_file << "// @generated rust packets from " << input_file.filename().string() << "\n\n";
-
-  generate_rust_packet_preamble(out_file);
+// Generate the command expectations of the HCI and NCI packets, and link
+// each command to its response.
+void generate_rust_command_expectations(
+    const Declarations& decls, const std::string& input_filename, std::ostream& s) {
   if (input_filename == "hci_packets") {
-    out_file << "pub trait CommandExpectations { "
-             << "type ResponseType;"
-             << "fn _to_response_type(pkt: EventPacket) -> Self::ResponseType;"
-             << "}";
+    s << "pub trait CommandExpectations { "
+      << "type ResponseType;"
+      << "fn _to_response_type(pkt: EventPacket) -> Self::ResponseType;"
+      << "}";
 
     for (const auto& packet_def : decls.packet_defs_queue_) {
       auto packet = packet_def.second;
@@ -151,11 +97,11 @@ bool generate_rust_source_one_file(
       opcode_index->try_from_enum_ = opcode;
     }
   } else if (input_filename == "nci_packets") {
-    out_file << "type EventPacket = NciPacket;"
-             << "pub trait CommandExpectations { "
-             << "type ResponseType;"
-             << "fn _to_response_type(pkt: EventPacket) -> Self::ResponseType;"
-             << "}";
+    s << "type EventPacket = NciPacket;"
+      << "pub trait CommandExpectations { "
+      << "type ResponseType;"
+      << "fn _to_response_type(pkt: EventPacket) -> Self::ResponseType;"
+      << "}";
 
     for (const auto& packet_def : decls.packet_defs_queue_) {
       auto packet = packet_def.second;
@@ -204,6 +150,78 @@ bool generate_rust_source_one_file(
       opcode_index->try_from_enum_ = opcode;
     }
   }
+}
+
+// Generate the C header declaring the extern "C" wrappers of the packets,
+// generated by generate_rust_ffi.
+bool generate_ffi_header(
+    const Declarations& decls, const std::filesystem::path& input_file, const std::filesystem::path& gen_file) {
+  auto input_filename = input_file.filename().string().substr(0, input_file.filename().string().find(".pdl"));
+  std::cout << "generating " << gen_file << std::endl;
+  std::ofstream out_file;
+  out_file.open(gen_file);
+  if (!out_file.is_open()) {
+    std::cerr << "can't open " << gen_file << std::endl;
+    return false;
+  }
+  out_file << "// @generated C bindings of the rust packets from " << input_file.filename().string() << "\n\n";
+  out_file << "#pragma once\n\n";
+  out_file << "#include <stddef.h>\n";
+  out_file << "#include <stdint.h>\n\n";
+  out_file << "#ifdef __cplusplus\n";
+  out_file << "extern \"C\" {\n";
+  out_file << "#endif\n\n";
+  for (const auto& packet_def : decls.packet_defs_queue_) {
+    packet_def.second->GenFfiHeader(out_file, input_filename);
+  }
+  out_file << "#ifdef __cplusplus\n";
+  out_file << "}\n";
+  out_file << "#endif\n";
+  out_file.close();
+  return true;
+}
+
+// Generate extern "C" wrappers of the packets: decode, encode and free
+// functions operating on opaque handles, and getters of the scalar and
+// enum fields.
+void generate_rust_ffi(const Declarations& decls, const std::string& input_filename, std::ostream& s) {
+  for (const auto& packet_def : decls.packet_defs_queue_) {
+    packet_def.second->GenRustFfi(s, input_filename);
+    s << "\n";
+  }
+}
+
+bool generate_rust_source_one_file(
+    const Declarations& decls,
+    const std::filesystem::path& input_file,
+    const std::filesystem::path& include_dir,
+    const std::filesystem::path& out_dir,
+    __attribute__((unused)) const std::string& root_namespace,
+    bool round_trip_tests,
+    bool ffi,
+    bool keep_trailing_bytes) {
+  auto gen_relative_path = input_file.lexically_relative(include_dir).parent_path();
+
+  auto input_filename = input_file.filename().string().substr(0, input_file.filename().string().find(".pdl"));
+  auto gen_path = out_dir / gen_relative_path;
+
+  std::filesystem::create_directories(gen_path);
+
+  auto gen_file = gen_path / (input_filename + ".rs");
+
+  std::cout << "generating " << gen_file << std::endl;
+
+  std::ofstream out_file;
+  out_file.open(gen_file);
+  if (!out_file.is_open()) {
+    std::cerr << "can't open " << gen_file << std::endl;
+    return false;
+  }
+
+  out_file << "// @generated rust packets from " << input_file.filename().string() << "\n\n";
+
+  generate_rust_packet_preamble(out_file);
+  generate_rust_command_expectations(decls, input_filename, out_file);
 
   for (const auto& e : decls.type_defs_queue_) {
     if (e.second->GetDefinitionType() == TypeDef::Type::ENUM) {
@@ -223,10 +241,133 @@ bool generate_rust_source_one_file(
   }
 
   for (const auto& packet_def : decls.packet_defs_queue_) {
-    packet_def.second->GenRustDef(out_file);
+    packet_def.second->GenRustDef(out_file, keep_trailing_bytes);
+    if (round_trip_tests) {
+      packet_def.second->GenRustRoundTripTest(out_file);
+    }
     out_file << "\n\n";
   }
 
+  if (ffi) {
+    out_file << "#[allow(clippy::missing_safety_doc)]\n";
+    out_file << "pub mod ffi {\nuse super::*;\n";
+    generate_rust_ffi(decls, input_filename, out_file);
+    out_file << "}\n";
+  }
+
   out_file.close();
-  return true;
+  return ffi ? generate_ffi_header(decls, input_file, gen_path / (input_filename + "_ffi.h")) : true;
+}
+
+// Return the name of the module holding the packets derived from the
+// same root packet.
+std::string get_rust_family_module(const PacketDef* packet) {
+  const ParentDef* root = packet;
+  while (root->parent_ != nullptr) {
+    root = root->parent_;
+  }
+  return util::CamelCaseToUnderScore(root->name_) + "_packets";
+}
+
+bool generate_rust_source_split(
+    const Declarations& decls,
+    const std::filesystem::path& input_file,
+    const std::filesystem::path& include_dir,
+    const std::filesystem::path& out_dir,
+    bool round_trip_tests,
+    bool ffi,
+    bool keep_trailing_bytes) {
+  auto gen_relative_path = input_file.lexically_relative(include_dir).parent_path();
+
+  auto input_filename = input_file.filename().string().substr(0, input_file.filename().string().find(".pdl"));
+  auto gen_path = out_dir / gen_relative_path / input_filename;
+
+  std::filesystem::create_directories(gen_path);
+
+  // Group the packets by family, in declaration order.
+  std::vector<std::string> modules;
+  std::map<std::string, std::vector<const PacketDef*>> families;
+  std::map<std::string, std::ostringstream> sources;
+  for (const auto& packet_def : decls.packet_defs_queue_) {
+    auto module = get_rust_family_module(packet_def.second);
+    if (families.find(module) == families.end()) {
+      modules.push_back(module);
+    }
+    families[module].push_back(packet_def.second);
+  }
+
+  // The declarations are not modified during generation: generate the
+  // families in parallel, each into its own stream.
+  std::vector<std::future<void>> tasks;
+  for (const auto& module : modules) {
+    auto* source = &sources[module];
+    const auto* packets = &families[module];
+    tasks.push_back(std::async(std::launch::async, [source, packets, round_trip_tests, keep_trailing_bytes]() {
+      for (const auto* packet_def : *packets) {
+        packet_def->GenRustDef(*source, keep_trailing_bytes);
+        if (round_trip_tests) {
+          packet_def->GenRustRoundTripTest(*source);
+        }
+        *source << "\n\n";
+      }
+    }));
+  }
+
+  std::ostringstream mod_source;
+  generate_rust_packet_preamble(mod_source);
+  generate_rust_command_expectations(decls, input_filename, mod_source);
+  mod_source << "\n\nmod types;\npub use types::*;\n";
+  for (const auto& module : modules) {
+    mod_source << "mod " << module << ";\npub use " << module << "::*;\n";
+  }
+  if (ffi) {
+    mod_source << "#[allow(clippy::missing_safety_doc)]\npub mod ffi;\n";
+    generate_rust_ffi(decls, input_filename, sources["ffi"]);
+  }
+
+  std::ostringstream& types_source = sources["types"];
+  for (const auto& e : decls.type_defs_queue_) {
+    if (e.second->GetDefinitionType() == TypeDef::Type::ENUM) {
+      const auto* enum_def = static_cast<const EnumDef*>(e.second);
+      EnumGen gen(*enum_def);
+      gen.GenRustDef(types_source);
+      types_source << "\n\n";
+    }
+  }
+  for (auto& s : decls.type_defs_queue_) {
+    if (s.second->GetDefinitionType() == TypeDef::Type::STRUCT) {
+      const auto* struct_def = static_cast<const StructDef*>(s.second);
+      struct_def->GenRustDef(types_source);
+      types_source << "\n\n";
+    }
+  }
+
+  for (auto& task : tasks) {
+    task.get();
+  }
+
+  auto write_file = [&](const std::string& name, const std::string& preamble, const std::string& content) {
+    auto gen_file = gen_path / name;
+    std::cout << "generating " << gen_file << std::endl;
+    std::ofstream out_file;
+    out_file.open(gen_file);
+    if (!out_file.is_open()) {
+      std::cerr << "can't open " << gen_file << std::endl;
+      return false;
+    }
+    out_file << "// @generated rust packets from " << input_file.filename().string() << "\n\n";
+    out_file << preamble << content;
+    out_file.close();
+    return true;
+  };
+
+  if (!write_file("mod.rs", "", mod_source.str())) {
+    return false;
+  }
+  for (const auto& [module, source] : sources) {
+    if (!write_file(module + ".rs", "use super::*;\n\n", source.str())) {
+      return false;
+    }
+  }
+  return ffi ? generate_ffi_header(decls, input_file, gen_path / (input_filename + "_ffi.h")) : true;
 }
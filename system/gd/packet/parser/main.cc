@@ -44,6 +44,13 @@ bool generate_cpp_headers_one_file(
     const std::filesystem::path& out_dir,
     const std::string& root_namespace);
 
+bool generate_cpp_fuzzer_one_file(
+    const Declarations& decls,
+    const std::filesystem::path& input_file,
+    const std::filesystem::path& include_dir,
+    const std::filesystem::path& out_dir,
+    const std::string& root_namespace);
+
 bool generate_pybind11_sources_one_file(
     const Declarations& decls,
     const std::filesystem::path& input_file,
@@ -57,7 +64,19 @@ bool generate_rust_source_one_file(
     const std::filesystem::path& input_file,
     const std::filesystem::path& include_dir,
     const std::filesystem::path& out_dir,
-    const std::string& root_namespace);
+    const std::string& root_namespace,
+    bool round_trip_tests,
+    bool ffi,
+    bool keep_trailing_bytes);
+
+bool generate_rust_source_split(
+    const Declarations& decls,
+    const std::filesystem::path& input_file,
+    const std::filesystem::path& include_dir,
+    const std::filesystem::path& out_dir,
+    bool round_trip_tests,
+    bool ffi,
+    bool keep_trailing_bytes);
 
 bool parse_declarations_one_file(const std::filesystem::path& input_file, Declarations* declarations) {
   void* scanner;
@@ -123,6 +142,23 @@ void usage(const char* prog) {
 
   ofs << std::setw(24) << "--num_shards= ";
   ofs << "Number of shards per output pybind11 cc file." << std::endl;
+
+  ofs << std::setw(24) << "--libfuzzer ";
+  ofs << "Without --rust, also generate <file>_fuzzer.cc, a libFuzzer entry point parsing the root packets."
+      << std::endl;
+
+  ofs << std::setw(24) << "--rust_split ";
+  ofs << "With --rust, generate a module directory per file, with one source per packet family." << std::endl;
+
+  ofs << std::setw(24) << "--rust_round_trip_tests ";
+  ofs << "With --rust, generate a round-trip test for every leaf packet." << std::endl;
+
+  ofs << std::setw(24) << "--rust_ffi ";
+  ofs << "With --rust, generate extern \"C\" wrappers of the packets and their C header <file>_ffi.h."
+      << std::endl;
+
+  ofs << std::setw(24) << "--rust_keep_trailing_bytes ";
+  ofs << "With --rust, keep the unparsed trailing bytes of the packets, and encode them back." << std::endl;
 }
 
 int main(int argc, const char** argv) {
@@ -134,12 +170,22 @@ int main(int argc, const char** argv) {
   // Number of shards per output pybind11 cc file
   size_t num_shards = 1;
   bool generate_rust = false;
+  bool split_rust = false;
+  bool round_trip_tests = false;
+  bool ffi = false;
+  bool keep_trailing_bytes = false;
+  bool libfuzzer = false;
   std::queue<std::filesystem::path> input_files;
 
   const std::string arg_out = "--out=";
   const std::string arg_include = "--include=";
   const std::string arg_namespace = "--root_namespace=";
   const std::string arg_num_shards = "--num_shards=";
+  const std::string arg_libfuzzer = "--libfuzzer";
+  const std::string arg_rust_split = "--rust_split";
+  const std::string arg_rust_round_trip_tests = "--rust_round_trip_tests";
+  const std::string arg_rust_ffi = "--rust_ffi";
+  const std::string arg_rust_keep_trailing_bytes = "--rust_keep_trailing_bytes";
   const std::string arg_rust = "--rust";
   const std::string arg_source_root = "--source_root=";
 
@@ -162,6 +208,16 @@ int main(int argc, const char** argv) {
       root_namespace = arg.substr(arg_namespace.size());
     } else if (arg.find(arg_num_shards) == 0) {
       num_shards = std::stoul(arg.substr(arg_num_shards.size()));
+    } else if (arg.find(arg_libfuzzer) == 0) {
+      libfuzzer = true;
+    } else if (arg.find(arg_rust_split) == 0) {
+      split_rust = true;
+    } else if (arg.find(arg_rust_round_trip_tests) == 0) {
+      round_trip_tests = true;
+    } else if (arg.find(arg_rust_ffi) == 0) {
+      ffi = true;
+    } else if (arg.find(arg_rust_keep_trailing_bytes) == 0) {
+      keep_trailing_bytes = true;
     } else if (arg.find(arg_rust) == 0) {
       generate_rust = true;
     } else if (arg.find(arg_source_root) == 0) {
@@ -184,9 +240,24 @@ int main(int argc, const char** argv) {
       std::cerr << "Cannot parse " << input_files.front() << " correctly" << std::endl;
       return 2;
     }
-    if (generate_rust) {
+    if (generate_rust && split_rust) {
+      std::cout << "generating split rust" << std::endl;
+      if (!generate_rust_source_split(
+              declarations, input_files.front(), include_dir, out_dir, round_trip_tests, ffi, keep_trailing_bytes)) {
+        std::cerr << "Didn't generate rust source for " << input_files.front() << std::endl;
+        return 5;
+      }
+    } else if (generate_rust) {
       std::cout << "generating rust" << std::endl;
-      if (!generate_rust_source_one_file(declarations, input_files.front(), include_dir, out_dir, root_namespace)) {
+      if (!generate_rust_source_one_file(
+              declarations,
+              input_files.front(),
+              include_dir,
+              out_dir,
+              root_namespace,
+              round_trip_tests,
+              ffi,
+              keep_trailing_bytes)) {
         std::cerr << "Didn't generate rust source for " << input_files.front() << std::endl;
         return 5;
       }
@@ -201,6 +272,11 @@ int main(int argc, const char** argv) {
         std::cerr << "Didn't generate pybind11 sources for " << input_files.front() << std::endl;
         return 4;
       }
+      if (libfuzzer &&
+          !generate_cpp_fuzzer_one_file(declarations, input_files.front(), include_dir, out_dir, root_namespace)) {
+        std::cerr << "Didn't generate libFuzzer entry point for " << input_files.front() << std::endl;
+        return 6;
+      }
     }
     input_files.pop();
   }
@@ -19,6 +19,7 @@
 #include <iomanip>
 #include <list>
 #include <set>
+#include <sstream>
 
 #include "fields/all_fields.h"
 #include "packet_dependency.h"
@@ -510,6 +511,16 @@ void PacketDef::GenFuzzTestDefine(std::ostream& s) const {
   s << "\n#endif";
 }
 
+void PacketDef::GenLibFuzzerTarget(std::ostream& s) const {
+  s << "void Fuzz" << name_ << "(const uint8_t* data, size_t size) {";
+  s << "auto bytes = std::make_shared<std::vector<uint8_t>>(data, data + size);";
+  s << "auto view = " << name_ << "View::Create(PacketView<" << (is_little_endian_ ? "" : "!")
+    << "kLittleEndian>(bytes));";
+  s << "if (!view.IsValid()) { return; }";
+  s << "view.ToString();";
+  s << "}\n";
+}
+
 FieldList PacketDef::GetParametersToValidate() const {
   FieldList params_to_validate;
   for (const auto& field : GetParamList()) {
@@ -807,7 +818,7 @@ void PacketDef::GenRustChildEnums(std::ostream& s) const {
   }
 }
 
-void PacketDef::GenRustStructDeclarations(std::ostream& s) const {
+void PacketDef::GenRustStructDeclarations(std::ostream& s, bool keep_trailing_bytes) const {
   s << "#[derive(Debug)] ";
   s << "struct " << name_ << "Data {";
 
@@ -816,6 +827,12 @@ void PacketDef::GenRustStructDeclarations(std::ostream& s) const {
   if (HasChildEnums()) {
     s << "child: " << name_ << "DataChild,";
   }
+  if (KeepsPayloadTrailingBytes(keep_trailing_bytes)) {
+    s << "payload_trailing_bytes: Bytes,";
+  }
+  if (keep_trailing_bytes && parent_ == nullptr) {
+    s << "trailing_bytes: Bytes,";
+  }
   s << "}\n";
 
   // Generate accessor struct
@@ -883,7 +900,7 @@ void PacketDef::GenRustStructFieldNames(std::ostream& s) const {
   }
 }
 
-void PacketDef::GenRustStructImpls(std::ostream& s) const {
+void PacketDef::GenRustStructImpls(std::ostream& s, bool keep_trailing_bytes) const {
   auto packet_dep = PacketDependency(GetRootDef());
 
   s << "impl " << name_ << "Data {";
@@ -1073,6 +1090,13 @@ void PacketDef::GenRustStructImpls(std::ostream& s) const {
     s << "};";
   }
 
+  if (KeepsPayloadTrailingBytes(keep_trailing_bytes)) {
+    // The bytes of the sized payload following the parsed child are
+    // unknown fields of a newer specification.
+    s << "let payload_trailing_bytes = "
+      << "Bytes::copy_from_slice(payload.get(child.get_total_size()..).unwrap_or_default());";
+  }
+
   s << "Ok(Self {";
   fields = fields_.GetFieldsWithoutTypes({
       BodyField::kFieldType,
@@ -1095,12 +1119,32 @@ void PacketDef::GenRustStructImpls(std::ostream& s) const {
   if (HasChildEnums()) {
     s << "child,";
   }
-  s << "})\n";
+  if (KeepsPayloadTrailingBytes(keep_trailing_bytes)) {
+    s << "payload_trailing_bytes,";
+  }
+  if (keep_trailing_bytes && parent_ == nullptr) {
+    // The bytes following the last field of the packet, or of its
+    // parsed children, are unknown fields of a newer specification.
+    s << "trailing_bytes: Bytes::new(),";
+    s << "}.with_trailing_bytes(bytes))\n";
+  } else {
+    s << "})\n";
+  }
   s << "}\n";
 
+  if (keep_trailing_bytes && parent_ == nullptr) {
+    s << "fn with_trailing_bytes(mut self, bytes: &[u8]) -> Self {";
+    s << "let size = self.get_total_size();";
+    s << "if bytes.len() > size {";
+    s << "self.trailing_bytes = Bytes::copy_from_slice(&bytes[size..]);";
+    s << "}";
+    s << "self";
+    s << "}\n";
+  }
+
   // write_to function
   s << "fn write_to(&self, buffer: &mut BytesMut) {";
-  GenRustWriteToFields(s);
+  GenRustWriteToFields(s, KeepsPayloadTrailingBytes(keep_trailing_bytes));
 
   if (HasChildEnums()) {
     s << "match &self.child {";
@@ -1114,11 +1158,23 @@ void PacketDef::GenRustStructImpls(std::ostream& s) const {
     s << name_ << "DataChild::None => {}";
     s << "}";
   }
+  if (KeepsPayloadTrailingBytes(keep_trailing_bytes)) {
+    auto offset = GetOffsetForField("payload");
+    s << "let offset = " << offset.bytes() << " + self.child.get_total_size();";
+    s << "buffer[offset..offset + self.payload_trailing_bytes.len()]"
+      << ".copy_from_slice(&self.payload_trailing_bytes[..]);";
+  }
+  if (keep_trailing_bytes && parent_ == nullptr) {
+    s << "let size = self.get_total_size();";
+    s << "buffer[size..size + self.trailing_bytes.len()].copy_from_slice(&self.trailing_bytes[..]);";
+  }
 
   s << "}\n";
 
   s << "fn get_total_size(&self) -> usize {";
-  if (HasChildEnums()) {
+  if (KeepsPayloadTrailingBytes(keep_trailing_bytes)) {
+    s << "self.get_size() + self.child.get_total_size() + self.payload_trailing_bytes.len()";
+  } else if (HasChildEnums()) {
     s << "self.get_size() + self.child.get_total_size()";
   } else {
     s << "self.get_size()";
@@ -1131,7 +1187,7 @@ void PacketDef::GenRustStructImpls(std::ostream& s) const {
   s << "}\n";
 }
 
-void PacketDef::GenRustAccessStructImpls(std::ostream& s) const {
+void PacketDef::GenRustAccessStructImpls(std::ostream& s, bool keep_trailing_bytes) const {
   if (complement_ != nullptr) {
     auto complement_root = complement_->GetRootDef();
     auto complement_root_accessor = util::CamelCaseToUnderScore(complement_root->name_);
@@ -1148,14 +1204,30 @@ void PacketDef::GenRustAccessStructImpls(std::ostream& s) const {
   auto root = GetRootDef();
   auto root_accessor = util::CamelCaseToUnderScore(root->name_);
 
-  s << "fn to_bytes(self) -> Bytes {";
-  s << " let mut buffer = BytesMut::new();";
-  s << " buffer.resize(self." << root_accessor << ".get_total_size(), 0);";
-  s << " self." << root_accessor << ".write_to(&mut buffer);";
-  s << " buffer.freeze()";
+  s << "fn encoded_len(&self) -> usize {";
+  s << " self." << root_accessor << ".get_total_size()";
+  if (keep_trailing_bytes) {
+    s << " + self." << root_accessor << ".trailing_bytes.len()";
+  }
+  s << "}\n";
+
+  s << "fn encode(&self, buffer: &mut BytesMut) {";
+  s << " pdl_runtime::write_with(buffer, self.encoded_len(), |buffer| self." << root_accessor
+    << ".write_to(buffer))";
   s << "}\n";
 
-  s << "fn to_vec(self) -> Vec<u8> { self.to_bytes().to_vec() }\n";
+  s << "fn decode(bytes: &[u8]) -> Result<Self> {";
+  if (parent_ == nullptr) {
+    s << " Self::parse(bytes)";
+  } else {
+    // Decode failures are reported to the runtime hook with the name of
+    // the decoded packet.
+    s << " let data = " << root->name_ << "Data::parse(bytes)"
+      << ".map_err(|err| pdl_runtime::decode_failed(\"" << name_ << "\", err))?;";
+    s << " Self::new(Arc::new(data))"
+      << ".map_err(|_| pdl_runtime::decode_failed(\"" << name_ << "\", Error::InvalidPacketError))";
+  }
+  s << "}\n";
   s << "}";
 
   s << "impl From<" << name_ << "Packet"
@@ -1174,11 +1246,13 @@ void PacketDef::GenRustAccessStructImpls(std::ostream& s) const {
   s << "}\n";
   s << "}\n";
 
-  if (root != this) {
-    s << "impl TryFrom<" << root->name_ << "Packet"
+  // Every packet holds the data of the root packet, from which the views
+  // of its ancestors and descendants are created.
+  for (const auto ancestor : GetAncestors()) {
+    s << "impl TryFrom<" << ancestor->name_ << "Packet"
       << "> for " << name_ << "Packet {\n";
     s << "type Error = TryFromError;\n";
-    s << "fn try_from(value: " << root->name_ << "Packet)"
+    s << "fn try_from(value: " << ancestor->name_ << "Packet)"
       << " -> std::result::Result<Self, Self::Error> {\n";
     s << "Self::new(value." << root_accessor << ").map_err(TryFromError)\n", s << "}\n";
     s << "}\n";
@@ -1187,10 +1261,21 @@ void PacketDef::GenRustAccessStructImpls(std::ostream& s) const {
   s << "impl " << name_ << "Packet {";
   if (parent_ == nullptr) {
     s << "pub fn parse(bytes: &[u8]) -> Result<Self> { ";
-    s << "Ok(Self::new(Arc::new(" << name_ << "Data::parse(bytes)?)).unwrap())";
+    s << "let data = " << name_ << "Data::parse(bytes)"
+      << ".map_err(|err| pdl_runtime::decode_failed(\"" << name_ << "\", err))?;";
+    s << "Ok(Self::new(Arc::new(data)).unwrap())";
     s << "}";
   }
 
+  if (keep_trailing_bytes) {
+    s << "/// Return the bytes following the packet, which are encoded back after it.\n";
+    s << "/// The unknown bytes of sized payloads are kept, and encoded back, by the\n";
+    s << "/// packets owning the size fields.\n";
+    s << "pub fn get_trailing_bytes(&self) -> &Bytes {";
+    s << " &self." << root_accessor << ".trailing_bytes";
+    s << "}\n";
+  }
+
   if (HasChildEnums()) {
     s << " pub fn specialize(&self) -> " << name_ << "Child {";
     s << " match &self." << util::CamelCaseToUnderScore(name_) << ".child {";
@@ -1259,16 +1344,16 @@ void PacketDef::GenRustAccessStructImpls(std::ostream& s) const {
   lineage = GetAncestors();
   for (auto it = lineage.begin(); it != lineage.end(); it++) {
     auto def = *it;
-    s << "impl Into<" << def->name_ << "Packet> for " << name_ << "Packet {";
-    s << " fn into(self) -> " << def->name_ << "Packet {";
-    s << def->name_ << "Packet::new(self." << util::CamelCaseToUnderScore(root->name_) << ")"
+    s << "impl From<" << name_ << "Packet> for " << def->name_ << "Packet {";
+    s << " fn from(packet: " << name_ << "Packet) -> Self {";
+    s << def->name_ << "Packet::new(packet." << util::CamelCaseToUnderScore(root->name_) << ")"
       << ".unwrap()";
     s << " }";
     s << "}\n";
   }
 }
 
-void PacketDef::GenRustBuilderStructImpls(std::ostream& s) const {
+void PacketDef::GenRustBuilderStructImpls(std::ostream& s, bool keep_trailing_bytes) const {
   if (complement_ != nullptr) {
     auto complement_root = complement_->GetRootDef();
     auto complement_root_accessor = util::CamelCaseToUnderScore(complement_root->name_);
@@ -1337,6 +1422,12 @@ void PacketDef::GenRustBuilderStructImpls(std::ostream& s) const {
           << util::CamelCaseToUnderScore(prev->name_) << "),";
       }
     }
+    if (ancestor->KeepsPayloadTrailingBytes(keep_trailing_bytes)) {
+      s << "payload_trailing_bytes: Bytes::new(),";
+    }
+    if (keep_trailing_bytes && ancestor->parent_ == nullptr) {
+      s << "trailing_bytes: Bytes::new(),";
+    }
     s << "});";
     prev = ancestor;
   }
@@ -1352,6 +1443,238 @@ void PacketDef::GenRustBuilderStructImpls(std::ostream& s) const {
   }
 }
 
+// Return the boundary flag of a transport packet: an enum field of the
+// packet whose tags all name a fragment boundary, e.g. FIRST_FRAGMENT or
+// CONTINUING_FRAGMENT. The tags are mapped to the boundaries of
+// pdl_runtime::Boundary.
+static EnumField* GetFragmentBoundaryField(
+    const FieldList& fields, std::map<std::string, std::string>* boundaries) {
+  for (auto field : fields) {
+    if (field->GetFieldType() != EnumField::kFieldType) {
+      continue;
+    }
+    auto enum_field = static_cast<EnumField*>(field);
+    std::map<std::string, std::string> tags;
+    for (const auto& constant : enum_field->GetEnumDef().constants_) {
+      const auto& tag = constant.second;
+      if (tag.rfind("FIRST", 0) == 0) {
+        tags[tag] = "First";
+      } else if (tag.rfind("CONTINU", 0) == 0) {
+        tags[tag] = "Continuation";
+      } else if (tag.rfind("LAST", 0) == 0) {
+        tags[tag] = "Last";
+      } else if (tag.rfind("COMPLETE", 0) == 0) {
+        tags[tag] = "Complete";
+      } else {
+        tags.clear();
+        break;
+      }
+    }
+    auto has = [&tags](const std::string& boundary) {
+      for (const auto& tag : tags) {
+        if (tag.second == boundary) return true;
+      }
+      return false;
+    };
+    if (has("First") && has("Continuation")) {
+      *boundaries = tags;
+      return enum_field;
+    }
+  }
+  return nullptr;
+}
+
+void PacketDef::GenRustFragmentImpls(std::ostream& s) const {
+  // Only root packets carrying a sized payload and a boundary flag are
+  // fragmented, e.g. HCI ACL and ISO packets.
+  if (parent_ != nullptr || !fields_.HasPayload()) {
+    return;
+  }
+  const SizeField* size_field = nullptr;
+  for (auto field : fields_) {
+    if (field->GetFieldType() == SizeField::kFieldType &&
+        static_cast<const SizeField*>(field)->GetSizedFieldName() == "payload") {
+      size_field = static_cast<const SizeField*>(field);
+    }
+  }
+  std::map<std::string, std::string> boundaries;
+  auto boundary_field = GetFragmentBoundaryField(fields_, &boundaries);
+  if (size_field == nullptr || boundary_field == nullptr) {
+    return;
+  }
+
+  auto enum_type = boundary_field->GetDataType();
+  auto tag_of = [&boundaries](const std::string& boundary) {
+    for (const auto& tag : boundaries) {
+      if (tag.second == boundary) return util::ConstantCaseToCamelCase(tag.first);
+    }
+    return std::string();
+  };
+  auto continuation = tag_of("Continuation");
+  auto last = tag_of("Last");
+  auto complete = tag_of("Complete");
+  auto getter = util::CamelCaseToUnderScore(boundary_field->GetGetterFunctionName());
+
+  s << "impl " << name_ << "Packet {";
+  s << "/// Return the position of the payload of the packet in the fragmented payload.\n";
+  s << "pub fn fragment_boundary(&self) -> pdl_runtime::Boundary {";
+  s << "match self." << getter << "() {";
+  for (const auto& tag : boundaries) {
+    s << enum_type << "::" << util::ConstantCaseToCamelCase(tag.first) << " => pdl_runtime::Boundary::"
+      << tag.second << ",";
+  }
+  s << "}";
+  s << "}";
+  s << "}\n";
+
+  auto params = GetParamList().GetFieldsWithoutTypes({
+      PayloadField::kFieldType,
+      BodyField::kFieldType,
+  });
+  s << "impl " << name_ << "Builder {";
+  s << "/// Split the payload of the packet in fragments of at most `mtu` bytes.\n";
+  s << "/// The first fragment keeps the boundary flag of the builder. An empty payload\n";
+  s << "/// yields no packet.\n";
+  s << "pub fn fragment(self, mtu: usize) -> Vec<" << name_ << "Packet> {";
+  s << "let mtu = std::cmp::min(mtu, " << ((uint64_t(1) << size_field->GetSize().bits()) - 1) << ");";
+  s << "let payload = self.payload.clone().unwrap_or_default();";
+  s << "pdl_runtime::fragments(&payload, mtu).map(|(boundary, fragment)| " << name_ << "Builder {";
+  for (auto param : params) {
+    s << param->GetName() << ": ";
+    if (param == boundary_field) {
+      s << "match boundary {";
+      s << "pdl_runtime::Boundary::First => self." << param->GetName() << ",";
+      s << "pdl_runtime::Boundary::Continuation => " << enum_type << "::" << continuation << ",";
+      s << "pdl_runtime::Boundary::Last => " << enum_type << "::" << (last.empty() ? continuation : last)
+        << ",";
+      if (complete.empty()) {
+        s << "pdl_runtime::Boundary::Complete => self." << param->GetName() << ",";
+      } else {
+        s << "pdl_runtime::Boundary::Complete => " << enum_type << "::" << complete << ",";
+      }
+      s << "}";
+    } else {
+      s << "self." << param->GetName() << ".clone()";
+    }
+    s << ", ";
+  }
+  s << "payload: Some(Bytes::copy_from_slice(fragment)),";
+  s << "}.build()).collect()";
+  s << "}";
+  s << "}\n";
+}
+
+// Collect the fields constrained by the descendants of a declaration.
+static void GetConstrainedFieldNames(const ParentDef* def, std::set<std::string>* names) {
+  for (const auto& constraint : def->parent_constraints_) {
+    names->insert(constraint.first);
+  }
+  for (const auto child : def->children_) {
+    GetConstrainedFieldNames(child, names);
+  }
+}
+
+void PacketDef::GenRustRoundTripTest(std::ostream& s) const {
+  // Only leaf packets are tested, so that the parsed packet is not
+  // specialized to another child.
+  if (!children_.empty()) {
+    return;
+  }
+  auto lineage = GetAncestors();
+  lineage.push_back(this);
+  for (const auto ancestor : lineage) {
+    for (const auto field : ancestor->fields_) {
+      if (field->GetFieldType() == ChecksumStartField::kFieldType) {
+        return;
+      }
+    }
+  }
+
+  // Fields selecting the children of the family cannot be varied, as
+  // other values would parse as another packet.
+  std::set<std::string> constrained;
+  GetConstrainedFieldNames(GetRootDef(), &constrained);
+
+  auto params = GetParamList();
+  if (params.HasBody()) {
+    return;
+  }
+  std::vector<std::string> names;
+  std::vector<std::string> base_values;
+  std::vector<std::vector<std::string>> variations;
+  for (const auto param : params) {
+    if (param->GetFieldType() == PayloadField::kFieldType) {
+      continue;
+    }
+    if (constrained.count(param->GetName()) != 0) {
+      return;
+    }
+    std::vector<std::string> values;
+    if (param->GetFieldType() == ScalarField::kFieldType) {
+      auto bits = param->GetSize().bits();
+      std::stringstream max;
+      max << "0x" << std::hex << (~uint64_t(0) >> (64 - bits));
+      values = {"0", max.str()};
+    } else if (param->GetFieldType() == EnumField::kFieldType) {
+      auto enum_def = static_cast<EnumField*>(param)->GetEnumDef();
+      if (enum_def.constants_.empty()) {
+        return;
+      }
+      values = {param->GetDataType() + "::" + util::ConstantCaseToCamelCase(enum_def.constants_.begin()->second)};
+    } else if (param->GetFieldType() == VectorField::kFieldType) {
+      values = {"vec![]"};
+    } else {
+      // Arrays, structs and custom fields have no generic values.
+      return;
+    }
+    names.push_back(param->GetName());
+    base_values.push_back(values[0]);
+    variations.push_back(std::vector<std::string>(values.begin() + 1, values.end()));
+  }
+
+  auto root = GetRootDef();
+  auto builder = [&](size_t index, const std::string& value) {
+    std::stringstream b;
+    b << name_ << "Builder {";
+    for (size_t i = 0; i < names.size(); i++) {
+      b << names[i] << ": " << (i == index ? value : base_values[i]) << ",";
+    }
+    if (fields_.HasPayload()) {
+      b << "payload: None,";
+    }
+    b << "}";
+    return b.str();
+  };
+
+  s << "#[test]\n";
+  s << "fn " << util::CamelCaseToUnderScore(name_) << "_round_trip() {";
+  s << "let check = |builder: " << name_ << "Builder| {";
+  s << "let packet: " << root->name_ << "Packet = builder.build().into();";
+  s << "let bytes = packet.to_bytes();";
+  s << "match " << root->name_ << "Packet::parse(&bytes) {";
+  s << "Ok(parsed) => assert_eq!(parsed.to_bytes(), bytes),";
+  s << "Err(e) => panic!(\"could not parse " << name_ << ": {:?} {:02x?}\", e, bytes),";
+  s << "}";
+  s << "};";
+  s << "check(" << builder(names.size(), "") << ");";
+  for (size_t i = 0; i < names.size(); i++) {
+    for (const auto& value : variations[i]) {
+      s << "check(" << builder(i, value) << ");";
+    }
+  }
+  // Enumerate the tags of small enums.
+  for (size_t i = 0; i < names.size(); i++) {
+    auto param = params.GetField(names[i]);
+    if (param->GetFieldType() == EnumField::kFieldType && param->GetSize().bits() <= 16) {
+      s << "for value in (0..(1u64 << " << param->GetSize().bits() << ")).filter_map(" << param->GetDataType()
+        << "::from_u64) {";
+      s << "check(" << builder(i, "value") << ");";
+      s << "}";
+    }
+  }
+  s << "}\n";
+}
+
 void PacketDef::GenRustBuilderTest(std::ostream& s) const {
   auto lineage = GetAncestors();
   lineage.push_back(this);
@@ -1431,11 +1754,89 @@ void PacketDef::GenRustBuilderTest(std::ostream& s) const {
   }
 }
 
-void PacketDef::GenRustDef(std::ostream& s) const {
+void PacketDef::GenRustDef(std::ostream& s, bool keep_trailing_bytes) const {
   GenRustChildEnums(s);
-  GenRustStructDeclarations(s);
-  GenRustStructImpls(s);
-  GenRustAccessStructImpls(s);
-  GenRustBuilderStructImpls(s);
+  GenRustStructDeclarations(s, keep_trailing_bytes);
+  GenRustStructImpls(s, keep_trailing_bytes);
+  GenRustAccessStructImpls(s, keep_trailing_bytes);
+  GenRustBuilderStructImpls(s, keep_trailing_bytes);
+  GenRustFragmentImpls(s);
   GenRustBuilderTest(s);
 }
+
+std::vector<PacketField*> PacketDef::GetFfiFields() const {
+  std::vector<PacketField*> fields;
+  auto lineage = GetAncestors();
+  lineage.push_back(this);
+  for (const auto def : lineage) {
+    for (const auto field : def->fields_) {
+      auto type = field->GetFieldType();
+      if ((type == ScalarField::kFieldType || type == EnumField::kFieldType) && field->GetSize().bits() <= 64) {
+        fields.push_back(field);
+      }
+    }
+  }
+  return fields;
+}
+
+// Return the C type of an unsigned Rust integer type.
+static std::string GetCTypeForRustType(const std::string& rust_type) {
+  return "uint" + rust_type.substr(1) + "_t";
+}
+
+void PacketDef::GenRustFfi(std::ostream& s, const std::string& prefix) const {
+  auto function = prefix + "_" + util::CamelCaseToUnderScore(name_);
+  auto packet = name_ + "Packet";
+
+  s << "#[no_mangle]\n";
+  s << "pub unsafe extern \"C\" fn " << function << "_decode(data: *const u8, len: usize) -> *mut " << packet
+    << " {";
+  s << "if data.is_null() { return std::ptr::null_mut(); }";
+  s << "match " << packet << "::decode(std::slice::from_raw_parts(data, len)) {";
+  s << "Ok(packet) => Box::into_raw(Box::new(packet)),";
+  s << "Err(_) => std::ptr::null_mut(),";
+  s << "}}\n";
+
+  s << "#[no_mangle]\n";
+  s << "pub unsafe extern \"C\" fn " << function << "_encode(packet: *const " << packet
+    << ", buffer: *mut u8, len: usize) -> usize {";
+  s << "if packet.is_null() { return 0; }";
+  s << "let mut bytes = BytesMut::new();";
+  s << "(*packet).encode(&mut bytes);";
+  s << "if !buffer.is_null() && bytes.len() <= len {";
+  s << "std::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());";
+  s << "}";
+  s << "bytes.len()";
+  s << "}\n";
+
+  s << "#[no_mangle]\n";
+  s << "pub unsafe extern \"C\" fn " << function << "_free(packet: *mut " << packet << ") {";
+  s << "if !packet.is_null() { drop(Box::from_raw(packet)); }";
+  s << "}\n";
+
+  for (const auto field : GetFfiFields()) {
+    auto rust_type = util::GetRustTypeForSize(field->GetSize().bits());
+    s << "#[no_mangle]\n";
+    s << "pub unsafe extern \"C\" fn " << function << "_get_" << field->GetName() << "(packet: *const " << packet
+      << ") -> " << rust_type << " {";
+    s << "if packet.is_null() { return 0; }";
+    s << "(*packet).get_" << field->GetName() << "() as " << rust_type;
+    s << "}\n";
+  }
+}
+
+void PacketDef::GenFfiHeader(std::ostream& s, const std::string& prefix) const {
+  auto function = prefix + "_" + util::CamelCaseToUnderScore(name_);
+  auto type = function + "_t";
+
+  s << "// " << name_ << "\n";
+  s << "typedef struct " << function << " " << type << ";\n";
+  s << type << "* " << function << "_decode(const uint8_t* data, size_t len);\n";
+  s << "size_t " << function << "_encode(const " << type << "* packet, uint8_t* buffer, size_t len);\n";
+  s << "void " << function << "_free(" << type << "* packet);\n";
+  for (const auto field : GetFfiFields()) {
+    auto c_type = GetCTypeForRustType(util::GetRustTypeForSize(field->GetSize().bits()));
+    s << c_type << " " << function << "_get_" << field->GetName() << "(const " << type << "* packet);\n";
+  }
+  s << "\n";
+}
@@ -53,6 +53,8 @@ class PacketDef : public ParentDef {
 
   void GenFuzzTestDefine(std::ostream& s) const;
 
+  void GenLibFuzzerTarget(std::ostream& s) const;
+
   FieldList GetParametersToValidate() const;
 
   void GenBuilderCreate(std::ostream& s) const;
@@ -67,19 +69,35 @@ class PacketDef : public ParentDef {
 
   void GenRustChildEnums(std::ostream& s) const;
 
-  void GenRustStructDeclarations(std::ostream& s) const;
+  void GenRustStructDeclarations(std::ostream& s, bool keep_trailing_bytes) const;
 
   bool GenRustStructFieldNameAndType(std::ostream& s) const;
 
   void GenRustStructFieldNames(std::ostream& s) const;
 
-  void GenRustStructImpls(std::ostream& s) const;
+  void GenRustStructImpls(std::ostream& s, bool keep_trailing_bytes) const;
+
+  void GenRustAccessStructImpls(std::ostream& s, bool keep_trailing_bytes) const;
 
-  void GenRustAccessStructImpls(std::ostream& s) const;
+  void GenRustBuilderStructImpls(std::ostream& s, bool keep_trailing_bytes) const;
 
-  void GenRustBuilderStructImpls(std::ostream& s) const;
+  void GenRustFragmentImpls(std::ostream& s) const;
 
   void GenRustBuilderTest(std::ostream& s) const;
 
-  void GenRustDef(std::ostream& s) const;
+  void GenRustRoundTripTest(std::ostream& s) const;
+
+  // Scalar and enum fields of the packet and its ancestors, which have a
+  // C getter.
+  std::vector<PacketField*> GetFfiFields() const;
+
+  void GenRustFfi(std::ostream& s, const std::string& prefix) const;
+
+  void GenFfiHeader(std::ostream& s, const std::string& prefix) const;
+
+  // With keep_trailing_bytes, the root packets keep the bytes following
+  // their last parsed field, and the packets with a sized payload keep the
+  // bytes of the payload following their parsed child. Both are encoded
+  // back, and counted in the size fields.
+  void GenRustDef(std::ostream& s, bool keep_trailing_bytes) const;
 };
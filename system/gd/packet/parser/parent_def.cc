@@ -608,6 +608,16 @@ bool ParentDef::HasChildEnums() const {
   return !children_.empty() || fields_.HasPayload();
 }
 
+bool ParentDef::KeepsPayloadTrailingBytes(bool keep_trailing_bytes) const {
+  if (!keep_trailing_bytes || children_.empty() || !fields_.HasPayload()) {
+    return false;
+  }
+  auto payload_field = fields_.GetFieldsWithTypes({
+      PayloadField::kFieldType,
+  });
+  return static_cast<const PayloadField*>(payload_field[0])->size_field_ != nullptr;
+}
+
 void ParentDef::GenRustConformanceCheck(std::ostream& s) const {
   auto fields = fields_.GetFieldsWithTypes({
       FixedScalarField::kFieldType,
@@ -625,7 +635,7 @@ void ParentDef::GenRustConformanceCheck(std::ostream& s) const {
   }
 }
 
-void ParentDef::GenRustWriteToFields(std::ostream& s) const {
+void ParentDef::GenRustWriteToFields(std::ostream& s, bool payload_trailing_bytes) const {
   auto fields = fields_.GetFieldsWithoutTypes({
       BodyField::kFieldType,
       PaddingField::kFieldType,
@@ -653,8 +663,11 @@ void ParentDef::GenRustWriteToFields(std::ostream& s) const {
           ERROR(field) << __func__ << ": size modifiers not implemented yet for " << field_name;
         }
 
-        s << "let " << field->GetName() << " = " << field->GetRustDataType()
-          << "::try_from(self.child.get_total_size()).expect(\"payload size did not fit\");";
+        s << "let " << field->GetName() << " = " << field->GetRustDataType() << "::try_from(self.child.get_total_size()";
+        if (payload_trailing_bytes) {
+          s << " + self.payload_trailing_bytes.len()";
+        }
+        s << ").expect(\"payload size did not fit\");";
       } else if (sized_field->GetFieldType() == BodyField::kFieldType) {
         s << "let " << field->GetName() << " = " << field->GetRustDataType()
           << "::try_from(self.get_total_size() - self.get_size()).expect(\"payload size did not fit\");";
@@ -92,7 +92,12 @@ class ParentDef : public TypeDef {
 
   bool HasChildEnums() const;
 
-  void GenRustWriteToFields(std::ostream& s) const;
+  // With keep_trailing_bytes, a Rust packet with children and a sized payload
+  // keeps the bytes of its payload following the parsed child, and counts them
+  // in the size field when encoding.
+  bool KeepsPayloadTrailingBytes(bool keep_trailing_bytes) const;
+
+  void GenRustWriteToFields(std::ostream& s, bool payload_trailing_bytes = false) const;
 
   void GenSizeRetVal(std::ostream& s) const;
 
@@ -364,7 +364,7 @@ void StructDef::GenRustDeclarations(std::ostream& s) const {
 void StructDef::GenRustImpls(std::ostream& s) const {
   s << "impl " << name_ << "{";
 
-  s << "fn conforms(bytes: &[u8]) -> bool {";
+  s << "pub(crate) fn conforms(bytes: &[u8]) -> bool {";
   GenRustConformanceCheck(s);
   s << " true";
   s << "}";
@@ -408,11 +408,11 @@ void StructDef::GenRustImpls(std::ostream& s) const {
   s << "})}\n";
 
   // write_to function
-  s << "fn write_to(&self, buffer: &mut [u8]) {";
+  s << "pub(crate) fn write_to(&self, buffer: &mut [u8]) {";
   GenRustWriteToFields(s);
   s << "}\n";
 
-  s << "fn get_total_size(&self) -> usize {";
+  s << "pub(crate) fn get_total_size(&self) -> usize {";
   GenSizeRetVal(s);
   s << "}";
   s << "}\n";
@@ -0,0 +1,26 @@
+/*
+ * Copyright 2023 The Android Open Source Project
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *      http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// Compile check of the C header generated with --rust_ffi.
+
+#include "packet/parser/test/rust_test_packets_ffi.h"
+
+size_t rust_ffi_header_test_encode(const uint8_t* data, size_t len, uint8_t* buffer, size_t buffer_len) {
+  rust_test_packets_test_enum_t* packet = rust_test_packets_test_enum_decode(data, len);
+  size_t size = rust_test_packets_test_enum_encode(packet, buffer, buffer_len);
+  rust_test_packets_test_enum_free(packet);
+  return size;
+}
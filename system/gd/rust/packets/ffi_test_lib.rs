@@ -0,0 +1,90 @@
+//! reimport of generated packets with their extern "C" wrappers
+
+#![allow(clippy::all)]
+#![allow(unused)]
+#![allow(missing_docs)]
+
+use std::convert::TryFrom;
+use std::fmt;
+
+pub mod test_packets {
+
+    // Custom boolean type
+    #[derive(Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, Debug)]
+    pub struct Boolean {
+        pub value: u8,
+    }
+
+    impl fmt::Display for Boolean {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:02x}", self.value)
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct InvalidBooleanError;
+
+    impl TryFrom<&[u8]> for Boolean {
+        type Error = InvalidBooleanError;
+
+        fn try_from(slice: &[u8]) -> std::result::Result<Self, Self::Error> {
+            if slice.len() != 1 || slice[0] > 1 {
+                Err(InvalidBooleanError)
+            } else {
+                Ok(Boolean { value: slice[0] })
+            }
+        }
+    }
+
+    impl From<Boolean> for [u8; 1] {
+        fn from(b: Boolean) -> [u8; 1] {
+            [b.value]
+        }
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/rust_test_packets.rs"));
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::test_packets::ffi::*;
+    use std::ptr;
+
+    #[test]
+    fn test_ffi_round_trip() {
+        let input = [0x1];
+        unsafe {
+            let packet = rust_test_packets_test_enum_decode(input.as_ptr(), input.len());
+            assert!(!packet.is_null());
+            assert_eq!(rust_test_packets_test_enum_get_v(packet), 1);
+            assert_eq!(rust_test_packets_test_enum_encode(packet, ptr::null_mut(), 0), input.len());
+            let mut output = [0u8; 1];
+            assert_eq!(
+                rust_test_packets_test_enum_encode(packet, output.as_mut_ptr(), output.len()),
+                input.len()
+            );
+            assert_eq!(output, input);
+            rust_test_packets_test_enum_free(packet);
+        }
+    }
+
+    #[test]
+    fn test_ffi_invalid_packet() {
+        // 0x0 is not a recognized Enum value.
+        let input = [0x0];
+        unsafe {
+            assert!(rust_test_packets_test_enum_decode(input.as_ptr(), input.len()).is_null());
+            assert!(rust_test_packets_test_enum_decode(ptr::null(), 0).is_null());
+        }
+    }
+
+    #[test]
+    fn test_ffi_null_packet() {
+        let mut output = [0u8; 1];
+        unsafe {
+            assert_eq!(rust_test_packets_test_enum_encode(ptr::null(), output.as_mut_ptr(), 1), 0);
+            assert_eq!(rust_test_packets_test_enum_get_v(ptr::null()), 0);
+            rust_test_packets_test_enum_free(ptr::null_mut());
+        }
+    }
+}
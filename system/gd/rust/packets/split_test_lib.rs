@@ -0,0 +1,69 @@
+//! reimport of generated packets, split per packet family
+
+#![allow(clippy::all)]
+#![allow(unused)]
+#![allow(missing_docs)]
+
+use std::convert::TryFrom;
+use std::fmt;
+
+pub mod test_packets {
+
+    // Custom boolean type
+    #[derive(Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, Debug)]
+    pub struct Boolean {
+        pub value: u8,
+    }
+
+    impl fmt::Display for Boolean {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:02x}", self.value)
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct InvalidBooleanError;
+
+    impl TryFrom<&[u8]> for Boolean {
+        type Error = InvalidBooleanError;
+
+        fn try_from(slice: &[u8]) -> std::result::Result<Self, Self::Error> {
+            if slice.len() != 1 || slice[0] > 1 {
+                Err(InvalidBooleanError)
+            } else {
+                Ok(Boolean { value: slice[0] })
+            }
+        }
+    }
+
+    impl From<Boolean> for [u8; 1] {
+        fn from(b: Boolean) -> [u8; 1] {
+            [b.value]
+        }
+    }
+
+    include!(concat!(env!("OUT_DIR"), "/mod.rs"));
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::test_packets::*;
+
+    #[test]
+    fn test_struct_array_round_trip() {
+        // The structs are declared in the types module, and serialized
+        // from the test_array_size_packets module.
+        let input = [0x4, 0x1, 0x2, 0x2, 0x3];
+        let packet = TestArraySizePacket::parse(&input).unwrap();
+        assert_eq!(packet.get_array().len(), 2);
+        assert_eq!(packet.to_vec(), input);
+    }
+
+    #[test]
+    fn test_invalid_array_count() {
+        // Count 2, have 1.
+        let input = [0x2, 0x0, 0x0];
+        let res = TestArrayCountPacket::parse(&input);
+        assert!(res.is_err());
+    }
+}
@@ -98,4 +98,19 @@ pub mod test {
         let res = TestBodySizePacket::parse(&input);
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn test_sized_payload_trailing_bytes() {
+        // Size 3: the value, and two bytes of a newer specification,
+        // followed by a byte after the packet.
+        let input = [0x3, 0x1, 0xe1, 0xe2, 0xff];
+        let packet = SizedEventPacket::parse(&input).unwrap();
+        match packet.specialize() {
+            SizedEventChild::SizedEventV1(child) => assert_eq!(child.get_value(), 1),
+            _ => panic!("SizedEventV1 was not parsed"),
+        }
+        assert_eq!(&packet.get_trailing_bytes()[..], &[0xff]);
+        // The size field still counts the unknown bytes of the payload.
+        assert_eq!(packet.to_vec(), input);
+    }
 }
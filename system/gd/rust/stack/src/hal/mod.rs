@@ -10,6 +10,7 @@ pub mod snoop;
 mod hidl_hal;
 
 use gddi::module;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use thiserror::Error;
 
 #[cfg(target_os = "android")]
@@ -34,6 +35,17 @@ module! {
 /// H4 packet header size
 const H4_HEADER_SIZE: usize = 1;
 
+static DECODE_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of HCI packets which failed to decode
+pub fn decode_failures() -> usize {
+    DECODE_FAILURES.load(Ordering::Relaxed)
+}
+
+fn count_decode_failure(_failure: &pdl_runtime::DecodeFailure) {
+    DECODE_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
 pub use snoop::{AclHal, ControlHal, IsoHal, ScoHal};
 
 mod internal {
@@ -68,6 +80,7 @@ mod internal {
 
     impl InnerHal {
         pub fn new() -> (RawHal, Self) {
+            pdl_runtime::set_decode_failure_hook(Some(super::count_decode_failure));
             let (cmd_tx, cmd_rx) = unbounded_channel();
             let (evt_tx, evt_rx) = unbounded_channel();
             let (acl_down_tx, acl_down_rx) = unbounded_channel();
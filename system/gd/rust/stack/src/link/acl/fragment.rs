@@ -2,12 +2,13 @@
 
 use bt_common::Bluetooth;
 use bt_packets::hci::PacketBoundaryFlag::{
-    ContinuingFragment, FirstAutomaticallyFlushable, FirstNonAutomaticallyFlushable,
+    FirstAutomaticallyFlushable, FirstNonAutomaticallyFlushable,
 };
 use bt_packets::hci::{AclBuilder, AclChild, AclPacket, BroadcastFlag};
-use bytes::{Buf, Bytes, BytesMut};
+use bytes::Bytes;
 use futures::stream::{self, StreamExt};
 use log::{error, info, warn};
+use pdl_runtime::ReassemblyError;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
 use tokio_stream::wrappers::ReceiverStream;
@@ -15,15 +16,14 @@ use tokio_stream::wrappers::ReceiverStream;
 const L2CAP_BASIC_FRAME_HEADER_LEN: usize = 4;
 
 pub struct Reassembler {
-    buffer: Option<BytesMut>,
-    remaining: usize,
+    reassembler: pdl_runtime::Reassembler,
     out: Sender<Bytes>,
 }
 
 impl Reassembler {
     /// Create a new reassembler
     pub fn new(out: Sender<Bytes>) -> Self {
-        Self { buffer: None, remaining: 0, out }
+        Self { reassembler: pdl_runtime::Reassembler::with_length(get_l2cap_pdu_size), out }
     }
 
     /// Injest the packet and send out if fully reassembled
@@ -41,49 +41,37 @@ impl Reassembler {
             return;
         }
 
-        match packet.get_packet_boundary_flag() {
-            FirstNonAutomaticallyFlushable => error!("not allowed to send FIRST_NON_AUTOMATICALLY_FLUSHABLE to host except loopback mode"),
-            FirstAutomaticallyFlushable => {
-                if self.buffer.take().is_some() {
-                    error!("got a start packet without finishing previous reassembly - dropping previous");
-                }
+        if let FirstNonAutomaticallyFlushable = packet.get_packet_boundary_flag() {
+            error!("not allowed to send FIRST_NON_AUTOMATICALLY_FLUSHABLE to host except loopback mode");
+            return;
+        }
 
-                let full_size = get_l2cap_pdu_size(&payload);
-                self.remaining = full_size - (payload.len() - L2CAP_BASIC_FRAME_HEADER_LEN);
-                if self.remaining > 0 {
-                    let mut buffer = BytesMut::with_capacity(full_size);
-                    buffer.extend_from_slice(&payload[..]);
-                    self.buffer = Some(buffer);
-                } else {
-                    self.out.send(payload).await.unwrap();
-                }
-            },
-            ContinuingFragment => {
-                match self.buffer.take() {
-                    None => warn!("got continuation packet without pending reassembly"),
-                    Some(_) if self.remaining < payload.len() => warn!("remote sent unexpected L2CAP PDU - dropping entire packet"),
-                    Some(mut buffer) => {
-                        self.remaining -= payload.len();
-                        buffer.extend_from_slice(&payload[..]);
-                        if self.remaining == 0 {
-                            self.out.send(buffer.freeze()).await.unwrap();
-                        } else {
-                            self.buffer = Some(buffer);
-                        }
-                    }
-                }
-            },
+        let dropped = self.reassembler.dropped();
+        let result = self.reassembler.push(packet.fragment_boundary(), &payload[..]);
+        if self.reassembler.dropped() > dropped {
+            error!("got a start packet without finishing previous reassembly - dropping previous");
+        }
+        match result {
+            Ok(Some(pdu)) => self.out.send(pdu).await.unwrap(),
+            Ok(None) => (),
+            Err(ReassemblyError::UnexpectedContinuation) => {
+                warn!("got continuation packet without pending reassembly")
+            }
+            Err(ReassemblyError::Overflow { .. }) => {
+                warn!("remote sent unexpected L2CAP PDU - dropping entire packet")
+            }
         }
     }
 }
 
-fn get_l2cap_pdu_size(first_packet: &Bytes) -> usize {
-    if first_packet.len() <= L2CAP_BASIC_FRAME_HEADER_LEN {
-        error!("invalid l2cap starting packet");
-
-        0
-    } else {
-        (&first_packet[..]).get_u16_le() as usize
+/// Return the size of the L2CAP PDU starting with `buffer`, header
+/// included, or `None` until its length is received.
+fn get_l2cap_pdu_size(buffer: &[u8]) -> Option<usize> {
+    match buffer {
+        [low, high, ..] => {
+            Some(u16::from_le_bytes([*low, *high]) as usize + L2CAP_BASIC_FRAME_HEADER_LEN)
+        }
+        _ => None,
     }
 }
 
@@ -98,22 +86,16 @@ pub fn fragmenting_stream(
 > {
     rx.flat_map(move |data| {
         stream::iter(
-            data.chunks(mtu)
-                .enumerate()
-                .map(move |(i, chunk)| {
-                    AclBuilder {
-                        handle,
-                        packet_boundary_flag: match bt {
-                            Bluetooth::Classic if i == 0 => FirstAutomaticallyFlushable,
-                            Bluetooth::Le if i == 0 => FirstNonAutomaticallyFlushable,
-                            _ => ContinuingFragment,
-                        },
-                        broadcast_flag: BroadcastFlag::PointToPoint,
-                        payload: Some(Bytes::copy_from_slice(chunk)),
-                    }
-                    .build()
-                })
-                .collect::<Vec<AclPacket>>(),
+            AclBuilder {
+                handle,
+                packet_boundary_flag: match bt {
+                    Bluetooth::Classic => FirstAutomaticallyFlushable,
+                    Bluetooth::Le => FirstNonAutomaticallyFlushable,
+                },
+                broadcast_flag: BroadcastFlag::PointToPoint,
+                payload: Some(data),
+            }
+            .fragment(mtu),
         )
     })
     .take_until(close_rx)
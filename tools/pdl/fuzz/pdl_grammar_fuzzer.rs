@@ -0,0 +1,10 @@
+//! Fuzz the compiler with structured pseudo-PDL grammars, see
+//! `src/fuzz.rs`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    pdl_build::fuzz::check(&pdl_build::fuzz::grammar_source(data));
+});
@@ -0,0 +1,12 @@
+//! Fuzz the compiler with arbitrary source text, mostly exercising the
+//! parser.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        pdl_build::fuzz::check(source);
+    }
+});
@@ -0,0 +1,339 @@
+//! Runtime support for the Rust packets generated from PDL grammars.
+//!
+//! The generated files import the error types, the [`Packet`] trait
+//! and the buffer helpers from this crate instead of embedding their
+//! own copies, so that packets generated from different grammars share
+//! the same types and can be handled generically across crates.
+
+use bytes::{Bytes, BytesMut};
+use thiserror::Error;
+
+/// Result of the generated parsers.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Error returned by the generated parsers.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Packet parsing failed")]
+    InvalidPacketError,
+    #[error("{field} was {value:x}, which is not known")]
+    ConstraintOutOfBounds { field: String, value: u64 },
+    #[error("when parsing {obj}.{field} needed length of {wanted} but got {got}")]
+    InvalidLengthError { obj: String, field: String, wanted: usize, got: usize },
+    #[error("Due to size restrictions a struct could not be parsed.")]
+    ImpossibleStructError,
+    #[error("when parsing field {obj}.{field}, {value} is not a valid {type_} value")]
+    InvalidEnumValueError { obj: String, field: String, value: u64, type_: String },
+}
+
+/// Error returned when converting a packet to one of its children
+/// fails.
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct TryFromError(pub &'static str);
+
+/// Packet generated from a PDL grammar.
+pub trait Packet {
+    /// Return the size of the encoded packet, in bytes.
+    fn encoded_len(&self) -> usize;
+
+    /// Append the encoded packet to the buffer.
+    fn encode(&self, buffer: &mut BytesMut);
+
+    /// Decode a packet. The packet is decoded as its root packet, and
+    /// fails to decode if it does not match the constraints of the
+    /// packet.
+    fn decode(bytes: &[u8]) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Encode the packet.
+    fn to_bytes(self) -> Bytes
+    where
+        Self: Sized,
+    {
+        let mut buffer = BytesMut::with_capacity(self.encoded_len());
+        self.encode(&mut buffer);
+        buffer.freeze()
+    }
+
+    /// Encode the packet to a vector.
+    fn to_vec(self) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        self.to_bytes().to_vec()
+    }
+}
+
+/// Check that the input holds the `wanted` bytes required to parse
+/// the field `field` of `obj`.
+pub fn check_len(obj: &str, field: &str, wanted: usize, got: usize) -> Result<()> {
+    if got < wanted {
+        return Err(Error::InvalidLengthError {
+            obj: obj.to_owned(),
+            field: field.to_owned(),
+            wanted,
+            got,
+        });
+    }
+    Ok(())
+}
+
+/// Decode failure of a generated packet, reported to the decode
+/// failure hook.
+#[derive(Debug)]
+pub struct DecodeFailure<'a> {
+    /// Name of the packet which failed to decode.
+    pub packet: &'static str,
+    /// For length errors, the offset at which the input ended, relative
+    /// to the declaration named by the error.
+    pub offset: Option<usize>,
+    pub error: &'a Error,
+}
+
+/// Function invoked on every decode failure of the generated packets,
+/// e.g. to count or log malformed packets.
+pub type DecodeFailureHook = fn(&DecodeFailure);
+
+#[cfg(feature = "decode-hook")]
+static DECODE_FAILURE_HOOK: std::sync::RwLock<Option<DecodeFailureHook>> =
+    std::sync::RwLock::new(None);
+
+/// Install the function invoked on every decode failure of the
+/// generated packets, or remove it with `None`. The hook is shared by
+/// the packets of all grammars. Requires the `decode-hook` feature.
+#[cfg(feature = "decode-hook")]
+pub fn set_decode_failure_hook(hook: Option<DecodeFailureHook>) {
+    *DECODE_FAILURE_HOOK.write().unwrap_or_else(|err| err.into_inner()) = hook;
+}
+
+#[cfg(feature = "decode-hook")]
+fn report_decode_failure(failure: &DecodeFailure) {
+    let hook = *DECODE_FAILURE_HOOK.read().unwrap_or_else(|err| err.into_inner());
+    if let Some(hook) = hook {
+        hook(failure)
+    }
+}
+
+#[cfg(not(feature = "decode-hook"))]
+fn report_decode_failure(_failure: &DecodeFailure) {}
+
+/// Report the decode failure of `packet` to the decode failure hook,
+/// and return the error. Called by the generated parsers; without the
+/// `decode-hook` feature, the error is returned unchanged.
+pub fn decode_failed(packet: &'static str, error: Error) -> Error {
+    let offset = match &error {
+        Error::InvalidLengthError { got, .. } => Some(*got),
+        _ => None,
+    };
+    report_decode_failure(&DecodeFailure { packet, offset, error: &error });
+    error
+}
+
+/// Append `len` zero bytes to the buffer, and fill them with `write`.
+/// The generated writers expect a buffer of the exact encoded size.
+pub fn write_with(buffer: &mut BytesMut, len: usize, write: impl FnOnce(&mut BytesMut)) {
+    let mut tail = buffer.split_off(buffer.len());
+    tail.resize(len, 0);
+    write(&mut tail);
+    buffer.unsplit(tail);
+}
+
+/// Position of a fragment in a fragmented payload, as signaled by
+/// the boundary flags of transport packets such as HCI ACL and ISO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    First,
+    Continuation,
+    Last,
+    /// Payload sent in a single fragment.
+    Complete,
+}
+
+/// Split a payload into fragments of at most `mtu` bytes, with their
+/// boundaries. An empty payload yields no fragment.
+pub fn fragments(payload: &[u8], mtu: usize) -> impl Iterator<Item = (Boundary, &[u8])> {
+    assert!(mtu > 0, "invalid MTU");
+    let count = payload.chunks(mtu).len();
+    (0..count).map(move |index| {
+        let boundary = match index {
+            _ if count == 1 => Boundary::Complete,
+            0 => Boundary::First,
+            _ if index == count - 1 => Boundary::Last,
+            _ => Boundary::Continuation,
+        };
+        let start = index * mtu;
+        (boundary, &payload[start..std::cmp::min(start + mtu, payload.len())])
+    })
+}
+
+/// Error reported when reassembling fragments.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ReassemblyError {
+    #[error("continuation fragment without a first fragment")]
+    UnexpectedContinuation,
+    #[error("payload of {got} bytes exceeds its length of {wanted} bytes")]
+    Overflow { wanted: usize, got: usize },
+}
+
+/// Function returning the length of a payload from its first bytes,
+/// or `None` until enough bytes are received to read it.
+pub type PayloadLength = fn(&[u8]) -> Option<usize>;
+
+/// Reassembler of fragmented payloads.
+///
+/// Payloads end with a fragment marked [`Boundary::Last`] or
+/// [`Boundary::Complete`]. For transports without a last fragment
+/// flag, such as HCI ACL, the length of the payload is read from its
+/// first bytes by the function given to [`Reassembler::with_length`].
+pub struct Reassembler {
+    buffer: Option<BytesMut>,
+    length: Option<PayloadLength>,
+    dropped: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Reassembler { buffer: None, length: None, dropped: 0 }
+    }
+
+    /// Reassembler of payloads whose length is given by `length`.
+    pub fn with_length(length: PayloadLength) -> Self {
+        Reassembler { buffer: None, length: Some(length), dropped: 0 }
+    }
+
+    /// Add a fragment, and return the reassembled payload if it is
+    /// complete. A first fragment received before the end of the
+    /// pending payload drops the pending payload, see
+    /// [`Reassembler::dropped`]. On error the pending payload is
+    /// dropped as well.
+    pub fn push(
+        &mut self,
+        boundary: Boundary,
+        fragment: &[u8],
+    ) -> std::result::Result<Option<Bytes>, ReassemblyError> {
+        let mut buffer = match (boundary, self.buffer.take()) {
+            (Boundary::First | Boundary::Complete, pending) => {
+                if pending.is_some() {
+                    self.dropped += 1;
+                }
+                BytesMut::new()
+            }
+            (Boundary::Continuation | Boundary::Last, Some(pending)) => pending,
+            (Boundary::Continuation | Boundary::Last, None) => {
+                return Err(ReassemblyError::UnexpectedContinuation)
+            }
+        };
+        buffer.extend_from_slice(fragment);
+        let complete = match self.length.and_then(|length| length(&buffer)) {
+            Some(wanted) if buffer.len() > wanted => {
+                return Err(ReassemblyError::Overflow { wanted, got: buffer.len() })
+            }
+            Some(wanted) => buffer.len() == wanted,
+            None => matches!(boundary, Boundary::Last | Boundary::Complete),
+        };
+        if complete {
+            return Ok(Some(buffer.freeze()));
+        }
+        self.buffer = Some(buffer);
+        Ok(None)
+    }
+
+    /// Return the number of payloads dropped because a first fragment
+    /// was received before their end.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Reassembler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_len() {
+        assert!(check_len("Acl", "payload", 4, 4).is_ok());
+        match check_len("Acl", "payload", 4, 2) {
+            Err(Error::InvalidLengthError { obj, field, wanted: 4, got: 2 }) => {
+                assert_eq!((obj.as_str(), field.as_str()), ("Acl", "payload"))
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[cfg(feature = "decode-hook")]
+    #[test]
+    fn test_decode_failure_hook() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static FAILURES: AtomicUsize = AtomicUsize::new(0);
+        set_decode_failure_hook(Some(|failure| {
+            assert_eq!((failure.packet, failure.offset), ("Acl", Some(2)));
+            FAILURES.fetch_add(1, Ordering::SeqCst);
+        }));
+        let error = check_len("Acl", "payload", 4, 2).unwrap_err();
+        assert!(matches!(decode_failed("Acl", error), Error::InvalidLengthError { .. }));
+        set_decode_failure_hook(None);
+        decode_failed("Acl", Error::InvalidPacketError);
+        assert_eq!(FAILURES.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_write_with() {
+        let mut buffer = BytesMut::from(&[1u8, 2][..]);
+        write_with(&mut buffer, 3, |tail| tail[1] = 7);
+        assert_eq!(&buffer[..], &[1, 2, 0, 7, 0]);
+    }
+
+    #[test]
+    fn test_fragments() {
+        let payload = [1, 2, 3, 4, 5];
+        assert_eq!(
+            fragments(&payload, 2).collect::<Vec<_>>(),
+            vec![
+                (Boundary::First, &[1, 2][..]),
+                (Boundary::Continuation, &[3, 4][..]),
+                (Boundary::Last, &[5][..]),
+            ]
+        );
+        assert_eq!(fragments(&[1], 2).collect::<Vec<_>>(), vec![(Boundary::Complete, &[1][..])]);
+        assert_eq!(fragments(&[], 2).count(), 0);
+    }
+
+    #[test]
+    fn test_reassembler() {
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.push(Boundary::First, &[1, 2]), Ok(None));
+        assert_eq!(
+            reassembler.push(Boundary::Last, &[3]).unwrap().as_deref(),
+            Some(&[1, 2, 3][..])
+        );
+        assert_eq!(
+            reassembler.push(Boundary::Continuation, &[4]),
+            Err(ReassemblyError::UnexpectedContinuation)
+        );
+
+        // The length is read from the first byte, as the L2CAP length of
+        // ACL payloads.
+        let mut reassembler =
+            Reassembler::with_length(|buffer| buffer.first().map(|b| *b as usize));
+        assert_eq!(reassembler.push(Boundary::First, &[3, 1]), Ok(None));
+        assert_eq!(
+            reassembler.push(Boundary::Continuation, &[2]).unwrap().as_deref(),
+            Some(&[3, 1, 2][..])
+        );
+        assert_eq!(reassembler.push(Boundary::First, &[4, 1]), Ok(None));
+        assert_eq!(reassembler.push(Boundary::First, &[3]), Ok(None));
+        assert_eq!(reassembler.dropped(), 1);
+        assert_eq!(
+            reassembler.push(Boundary::Continuation, &[1, 2, 3]),
+            Err(ReassemblyError::Overflow { wanted: 3, got: 4 })
+        );
+    }
+}
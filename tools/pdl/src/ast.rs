@@ -1,8 +1,14 @@
 use codespan_reporting::diagnostic;
 use codespan_reporting::files;
 use serde::Serialize;
+use std::borrow::Borrow;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
 use std::ops;
+use std::sync::Arc;
 
 /// File identfiier.
 /// References a source file in the source database.
@@ -12,6 +18,14 @@ pub type FileId = usize;
 /// Stores the source file contents for reference.
 pub type SourceDatabase = files::SimpleFiles<String, String>;
 
+/// Interned identifier.
+///
+/// Symbols created from the same string share a single allocation:
+/// cloning a symbol copies a pointer, and comparing two symbols
+/// compares pointers first.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(Arc<str>);
+
 #[derive(Debug, Copy, Clone, Serialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SourceLocation {
     /// Byte offset into the file (counted from zero).
@@ -29,32 +43,34 @@ pub struct SourceRange {
     pub end: SourceLocation,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind", rename = "comment")]
 pub struct Comment {
     pub loc: SourceRange,
     pub text: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum EndiannessValue {
     LittleEndian,
     BigEndian,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind", rename = "endianness_declaration")]
 pub struct Endianness {
     pub loc: SourceRange,
     pub value: EndiannessValue,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind")]
+// Unary and binary expressions are not produced by the parser yet.
+#[allow(dead_code)]
 pub enum Expr {
     #[serde(rename = "identifier")]
-    Identifier { loc: SourceRange, name: String },
+    Identifier { loc: SourceRange, name: Symbol },
     #[serde(rename = "integer")]
     Integer { loc: SourceRange, value: usize },
     #[serde(rename = "unary_expr")]
@@ -63,33 +79,33 @@ pub enum Expr {
     Binary { loc: SourceRange, op: String, operands: Box<(Expr, Expr)> },
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind", rename = "tag")]
 pub struct Tag {
-    pub id: String,
+    pub id: Symbol,
     pub loc: SourceRange,
     pub value: usize,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind", rename = "constraint")]
 pub struct Constraint {
-    pub id: String,
+    pub id: Symbol,
     pub loc: SourceRange,
     pub value: Expr,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind")]
 pub enum Field {
     #[serde(rename = "checksum_field")]
-    Checksum { loc: SourceRange, field_id: String },
+    Checksum { loc: SourceRange, field_id: Symbol },
     #[serde(rename = "padding_field")]
     Padding { loc: SourceRange, width: usize },
     #[serde(rename = "size_field")]
-    Size { loc: SourceRange, field_id: String, width: usize },
+    Size { loc: SourceRange, field_id: Symbol, width: usize },
     #[serde(rename = "count_field")]
-    Count { loc: SourceRange, field_id: String, width: usize },
+    Count { loc: SourceRange, field_id: Symbol, width: usize },
     #[serde(rename = "body_field")]
     Body { loc: SourceRange },
     #[serde(rename = "payload_field")]
@@ -99,73 +115,210 @@ pub enum Field {
         loc: SourceRange,
         width: Option<usize>,
         value: Option<usize>,
-        enum_id: Option<String>,
-        tag_id: Option<String>,
+        enum_id: Option<Symbol>,
+        tag_id: Option<Symbol>,
     },
     #[serde(rename = "reserved_field")]
     Reserved { loc: SourceRange, width: usize },
     #[serde(rename = "array_field")]
     Array {
         loc: SourceRange,
-        id: String,
+        id: Symbol,
         width: Option<usize>,
-        type_id: Option<String>,
+        type_id: Option<Symbol>,
         size_modifier: Option<String>,
         size: Option<usize>,
     },
     #[serde(rename = "scalar_field")]
-    Scalar { loc: SourceRange, id: String, width: usize },
+    Scalar { loc: SourceRange, id: Symbol, width: usize },
     #[serde(rename = "typedef_field")]
-    Typedef { loc: SourceRange, id: String, type_id: String },
+    Typedef { loc: SourceRange, id: Symbol, type_id: Symbol },
     #[serde(rename = "group_field")]
-    Group { loc: SourceRange, group_id: String, constraints: Vec<Constraint> },
+    Group { loc: SourceRange, group_id: Symbol, constraints: Vec<Constraint> },
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind", rename = "test_case")]
 pub struct TestCase {
     pub loc: SourceRange,
     pub input: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 #[serde(tag = "kind")]
 pub enum Decl {
     #[serde(rename = "checksum_declaration")]
-    Checksum { id: String, loc: SourceRange, function: String, width: usize },
+    Checksum { id: Symbol, loc: SourceRange, function: String, width: usize },
     #[serde(rename = "custom_field_declaration")]
-    CustomField { id: String, loc: SourceRange, width: Option<usize>, function: String },
+    CustomField { id: Symbol, loc: SourceRange, width: Option<usize>, function: String },
     #[serde(rename = "enum_declaration")]
-    Enum { id: String, loc: SourceRange, tags: Vec<Tag>, width: usize },
+    Enum { id: Symbol, loc: SourceRange, tags: Vec<Tag>, width: usize },
     #[serde(rename = "packet_declaration")]
     Packet {
-        id: String,
+        id: Symbol,
         loc: SourceRange,
         constraints: Vec<Constraint>,
         fields: Vec<Field>,
-        parent_id: Option<String>,
+        parent_id: Option<Symbol>,
     },
     #[serde(rename = "struct_declaration")]
     Struct {
-        id: String,
+        id: Symbol,
         loc: SourceRange,
         constraints: Vec<Constraint>,
         fields: Vec<Field>,
-        parent_id: Option<String>,
+        parent_id: Option<Symbol>,
     },
+    /// The constraints of a group declaration are default values of
+    /// its fields, overridden by the constraints of the group fields
+    /// inserting it.
     #[serde(rename = "group_declaration")]
-    Group { id: String, loc: SourceRange, fields: Vec<Field> },
+    Group { id: Symbol, loc: SourceRange, constraints: Vec<Constraint>, fields: Vec<Field> },
     #[serde(rename = "test_declaration")]
-    Test { loc: SourceRange, type_id: String, test_cases: Vec<TestCase> },
+    Test { loc: SourceRange, type_id: Symbol, test_cases: Vec<TestCase> },
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Grammar {
     pub version: String,
     pub file: FileId,
     pub comments: Vec<Comment>,
     pub endianness: Option<Endianness>,
-    pub declarations: Vec<Decl>,
+    pub declarations: Arena<Decl>,
+}
+
+/// Typed arena of AST nodes.
+/// Nodes are stored contiguously in allocation order, and designated by
+/// their [`Id`]. Identifiers remain valid as long as no node is removed,
+/// and designate the same nodes in clones of the arena.
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub struct Arena<T> {
+    nodes: Vec<T>,
+}
+
+/// Identifier of a node allocated in an [`Arena`].
+pub struct Id<T> {
+    index: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+/// Identifier of a declaration of a grammar.
+pub type DeclId = Id<Decl>;
+
+/// Interned strings, with the number of strings above which the unused
+/// strings are released.
+struct Interner {
+    symbols: HashSet<Arc<str>>,
+    threshold: usize,
+}
+
+thread_local! {
+    static INTERNER: RefCell<Interner> =
+        RefCell::new(Interner { symbols: HashSet::new(), threshold: 1024 });
+}
+
+impl Symbol {
+    /// Intern a string.
+    pub fn new(text: &str) -> Symbol {
+        INTERNER.with(|interner| {
+            let mut interner = interner.borrow_mut();
+            if let Some(symbol) = interner.symbols.get(text) {
+                return Symbol(symbol.clone());
+            }
+            if interner.symbols.len() >= interner.threshold {
+                interner.symbols.retain(|symbol| Arc::strong_count(symbol) > 1);
+                interner.threshold = (2 * interner.symbols.len()).max(1024);
+            }
+            let symbol: Arc<str> = Arc::from(text);
+            interner.symbols.insert(symbol.clone());
+            Symbol(symbol)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ops::Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Symbol {
+        Symbol::new(text)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(text: String) -> Symbol {
+        Symbol::new(&text)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl PartialEq<String> for Symbol {
+    fn eq(&self, other: &String) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl PartialEq<Symbol> for str {
+    fn eq(&self, other: &Symbol) -> bool {
+        self == &*other.0
+    }
+}
+
+impl PartialEq<Symbol> for String {
+    fn eq(&self, other: &Symbol) -> bool {
+        **self == *other.0
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl Serialize for Symbol {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
 }
 
 impl SourceLocation {
@@ -228,12 +381,108 @@ impl Grammar {
             version: "1,0".to_owned(),
             comments: vec![],
             endianness: None,
-            declarations: vec![],
+            declarations: Arena::new(),
             file,
         }
     }
 }
 
+impl<T> Id<T> {
+    /// Return the allocation index of the node.
+    pub fn index(self) -> usize {
+        self.index
+    }
+}
+
+impl<T> Clone for Id<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Id<T> {}
+
+impl<T> PartialEq for Id<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Id<T> {}
+
+impl<T> Hash for Id<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state)
+    }
+}
+
+impl<T> fmt::Debug for Id<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Id({})", self.index)
+    }
+}
+
+impl<T> Arena<T> {
+    pub fn new() -> Arena<T> {
+        Arena { nodes: vec![] }
+    }
+
+    /// Allocate a node, and return its identifier.
+    pub fn alloc(&mut self, node: T) -> Id<T> {
+        self.nodes.push(node);
+        Id { index: self.nodes.len() - 1, marker: PhantomData }
+    }
+
+    /// Iterate over the nodes with their identifiers, in allocation
+    /// order.
+    pub fn iter_ids(&self) -> impl Iterator<Item = (Id<T>, &T)> {
+        self.nodes.iter().enumerate().map(|(index, node)| (Id { index, marker: PhantomData }, node))
+    }
+
+    /// Remove the nodes rejected by the predicate. The identifiers
+    /// previously returned by the arena are invalidated.
+    pub fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        self.nodes.retain(f)
+    }
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Arena::new()
+    }
+}
+
+impl<T> ops::Deref for Arena<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.nodes
+    }
+}
+
+impl<T> ops::Index<Id<T>> for Arena<T> {
+    type Output = T;
+
+    fn index(&self, id: Id<T>) -> &T {
+        &self.nodes[id.index]
+    }
+}
+
+impl<T> ops::IndexMut<Id<T>> for Arena<T> {
+    fn index_mut(&mut self, id: Id<T>) -> &mut T {
+        &mut self.nodes[id.index]
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Arena<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.nodes.iter()
+    }
+}
+
 impl Decl {
     pub fn loc(&self) -> &SourceRange {
         match self {
@@ -247,7 +496,7 @@ impl Decl {
         }
     }
 
-    pub fn id(&self) -> Option<&String> {
+    pub fn id(&self) -> Option<&Symbol> {
         match self {
             Decl::Test { .. } => None,
             Decl::Checksum { id, .. }
@@ -258,6 +507,18 @@ impl Decl {
             | Decl::Group { id, .. } => Some(id),
         }
     }
+
+    pub fn kind(&self) -> &str {
+        match self {
+            Decl::Checksum { .. } => "checksum",
+            Decl::CustomField { .. } => "custom field",
+            Decl::Enum { .. } => "enum",
+            Decl::Packet { .. } => "packet",
+            Decl::Struct { .. } => "struct",
+            Decl::Group { .. } => "group",
+            Decl::Test { .. } => "test",
+        }
+    }
 }
 
 impl Field {
@@ -278,7 +539,7 @@ impl Field {
         }
     }
 
-    pub fn id(&self) -> Option<&String> {
+    pub fn id(&self) -> Option<&Symbol> {
         match self {
             Field::Checksum { .. }
             | Field::Padding { .. }
@@ -330,4 +591,26 @@ mod tests {
         let loc = SourceLocation::new(100, &[]);
         assert_eq!(loc, SourceLocation { offset: 100, line: 0, column: 100 });
     }
+
+    #[test]
+    fn arena_ids_survive_clone() {
+        let mut arena = Arena::new();
+        let a = arena.alloc("a");
+        let b = arena.alloc("b");
+        let copy = arena.clone();
+        assert_ne!(a, b);
+        assert_eq!(copy[b], "b");
+        arena[a] = "c";
+        assert_eq!(arena.iter_ids().collect::<Vec<_>>(), vec![(a, &"c"), (b, &"b")]);
+    }
+
+    #[test]
+    fn symbols_share_storage() {
+        let a = Symbol::new("foo");
+        let b = Symbol::from(String::from("foo"));
+        assert_eq!(a, b);
+        assert_eq!(a, "foo");
+        assert!(std::ptr::eq(a.as_str(), b.as_str()));
+        assert!(!std::ptr::eq(a.as_str(), Symbol::new("bar").as_str()));
+    }
 }
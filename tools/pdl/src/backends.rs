@@ -0,0 +1,250 @@
+//! Code and documentation generators.
+//!
+//! Every generator implements the [`Backend`] trait. Downstream
+//! projects can implement their own generators and add them to a
+//! [`Registry`], or pass them to [`crate::Config::backend`] from a
+//! build script.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::ast;
+use crate::clock::Instant;
+use crate::lint;
+use crate::vectors;
+
+pub mod csv;
+pub mod diagram;
+pub mod hashes;
+pub mod json;
+pub mod mermaid;
+pub mod scapy;
+pub mod template;
+
+/// Options of the built-in backends. Backends ignore the options
+/// which do not apply to them, see [`Backend::with_options`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Record the wire format hash of every packet and struct in the
+    /// Scapy output, see [`scapy::ScapyBackend`].
+    pub wire_hashes: bool,
+}
+
+/// Time spent generating the output of each declaration, see
+/// [`Backend::generate`].
+#[derive(Debug, Default, Clone)]
+pub struct GenerateTimings {
+    /// Time spent generating each named declaration.
+    pub declarations: BTreeMap<String, Duration>,
+}
+
+impl GenerateTimings {
+    /// Run the generation of a declaration, and add its elapsed time
+    /// to the declaration total. Declarations without a name are not
+    /// recorded.
+    pub fn time<T>(&mut self, decl: &ast::Decl, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        if let Some(id) = decl.id() {
+            *self.declarations.entry(id.to_string()).or_default() += start.elapsed();
+        }
+        result
+    }
+}
+
+/// Code or documentation generator.
+pub trait Backend {
+    /// Name used to select the backend, e.g. with `--output-format`.
+    fn name(&self) -> &str;
+
+    /// Extension of the generated files, without the leading dot.
+    fn extension(&self) -> &str;
+
+    /// Version of the generated output, recorded in the stamps. The
+    /// stamps also record the hash of the compiler executable, so the
+    /// outputs are regenerated whenever the compiler is rebuilt.
+    fn version(&self) -> u64 {
+        1
+    }
+
+    /// Description of the backend configuration and output version,
+    /// recorded in the stamps of the generated files, see
+    /// [`crate::stamp`].
+    fn fingerprint(&self) -> String {
+        format!("{}:{}", self.name(), self.version())
+    }
+
+    /// Return the backend configured with the options, or `None` if
+    /// the backend has no options.
+    fn with_options(&self, _options: &Options) -> Option<Box<dyn Backend>> {
+        None
+    }
+
+    /// Files read by the backend, besides the grammar, recorded in
+    /// the build dependencies of the generated files.
+    fn inputs(&self) -> Vec<PathBuf> {
+        vec![]
+    }
+
+    /// Generate the output for the grammar, and record the time spent
+    /// generating each declaration to `timings`. `sources` holds the
+    /// source file of the grammar, and `scope` is the scope of the
+    /// grammar, or `None` if the grammar is invalid. The scope is
+    /// passed by the caller so that the grammar is analyzed once when
+    /// it is compiled to several formats.
+    fn generate(
+        &self,
+        sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        scope: Option<&lint::Scope>,
+        output: &mut dyn io::Write,
+        timings: &mut GenerateTimings,
+    ) -> io::Result<()>;
+
+    /// Generate conformance tests of the generated code, which check
+    /// that the test vectors are encoded and decoded as expected, see
+    /// [`crate::vectors`]. Backends which do not generate code return
+    /// an error.
+    fn generate_tests(
+        &self,
+        _sources: &ast::SourceDatabase,
+        _grammar: &ast::Grammar,
+        _vectors: &[vectors::TestVector],
+        _output: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the {} backend does not generate conformance tests", self.name()),
+        ))
+    }
+}
+
+/// Collection of backends, indexed by name.
+pub struct Registry {
+    backends: Vec<Box<dyn Backend>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    /// Create a registry with the built-in backends.
+    pub fn new() -> Registry {
+        Registry::with_options(&Options::default())
+    }
+
+    /// Create a registry with the built-in backends, configured with
+    /// the options.
+    pub fn with_options(options: &Options) -> Registry {
+        let backends: Vec<Box<dyn Backend>> = vec![
+            Box::new(json::JsonBackend),
+            Box::new(mermaid::MermaidBackend),
+            Box::new(diagram::DiagramBackend),
+            Box::new(scapy::ScapyBackend::default()),
+            Box::new(csv::CsvBackend),
+            Box::new(hashes::HashesBackend),
+        ];
+        Registry {
+            backends: backends
+                .into_iter()
+                .map(|backend| backend.with_options(options).unwrap_or(backend))
+                .collect(),
+        }
+    }
+
+    /// Add a backend, replacing the backend with the same name if any.
+    pub fn register(&mut self, backend: Box<dyn Backend>) {
+        self.backends.retain(|registered| registered.name() != backend.name());
+        self.backends.push(backend);
+    }
+
+    /// Return the backend with this name, ignoring the case.
+    pub fn get(&self, name: &str) -> Option<&dyn Backend> {
+        self.backends
+            .iter()
+            .find(|backend| backend.name().eq_ignore_ascii_case(name))
+            .map(|backend| backend.as_ref())
+    }
+
+    /// Return the names of the registered backends.
+    pub fn names(&self) -> Vec<&str> {
+        self.backends.iter().map(|backend| backend.name()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    struct Count;
+
+    impl Backend for Count {
+        fn name(&self) -> &str {
+            "count"
+        }
+
+        fn extension(&self) -> &str {
+            "txt"
+        }
+
+        fn generate(
+            &self,
+            _sources: &ast::SourceDatabase,
+            grammar: &ast::Grammar,
+            _scope: Option<&lint::Scope>,
+            output: &mut dyn io::Write,
+            _timings: &mut GenerateTimings,
+        ) -> io::Result<()> {
+            writeln!(output, "{}", grammar.declarations.len())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = Registry::new();
+        assert_eq!(registry.names(), vec!["json", "mermaid", "diagram", "scapy", "csv", "hashes"]);
+        assert_eq!(registry.get("JSON").map(|backend| backend.extension()), Some("json"));
+        assert!(registry.get("count").is_none());
+
+        let configured = Registry::with_options(&Options { wire_hashes: true });
+        let fingerprint = |registry: &Registry| registry.get("scapy").unwrap().fingerprint();
+        assert_ne!(fingerprint(&configured), fingerprint(&registry));
+
+        registry.register(Box::new(Count));
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(&mut db, "little_endian_packets\npacket A {}\npacket B {}\n");
+        let mut output = vec![];
+        let mut timings = GenerateTimings::default();
+        let backend = registry.get("count").unwrap();
+        backend.generate(&db, &grammar, None, &mut output, &mut timings).unwrap();
+        assert_eq!(output, b"2\n");
+    }
+
+    #[test]
+    fn test_generate_timings() {
+        let mut db = ast::SourceDatabase::new();
+        let grammar =
+            grammar!(&mut db, "little_endian_packets\nenum E : 8 { X = 1 }\npacket A { e: E }\n");
+        let scope = lint::Scope::new(&grammar).ok();
+        let registry = Registry::new();
+        for name in registry.names() {
+            let mut output = vec![];
+            let mut timings = GenerateTimings::default();
+            let backend = registry.get(name).unwrap();
+            backend.generate(&db, &grammar, scope.as_ref(), &mut output, &mut timings).unwrap();
+            assert!(timings.declarations.contains_key("A"), "{}", name);
+        }
+
+        let mut output = vec![];
+        let mut timings = GenerateTimings::default();
+        Count.generate(&db, &grammar, scope.as_ref(), &mut output, &mut timings).unwrap();
+        assert_eq!(output, b"2\n");
+        assert!(timings.declarations.is_empty());
+    }
+}
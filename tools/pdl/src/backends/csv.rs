@@ -0,0 +1,233 @@
+//! CSV field layout generator.
+//!
+//! Produces one row per field of every packet and struct declaration,
+//! with the columns:
+//!
+//! ```text
+//! packet,field,bit offset,bit width,type,description
+//! ```
+//!
+//! Bit offsets are counted from the start of the outermost parent
+//! declaration, so that the fields of a child packet are located
+//! inside the payload of its parent. Offsets following a variable
+//! size field, and widths of variable size fields, are left empty.
+//! Group fields are inlined, see [`crate::layout`]. The description is made of the value of
+//! fixed or constrained fields, and of the comment found at the end
+//! of the field line, if any.
+
+use std::io;
+
+use crate::ast;
+use crate::backends::{Backend, GenerateTimings};
+use crate::layout::{FieldLayout, Layout};
+use crate::lint;
+
+/// Field of a declaration, with groups inlined.
+pub(crate) struct Row {
+    pub(crate) decl: String,
+    pub(crate) field: String,
+    pub(crate) offset: Option<usize>,
+    pub(crate) width: Option<usize>,
+    pub(crate) ty: String,
+    pub(crate) description: String,
+}
+
+pub(crate) struct Generator<'d> {
+    layout: Layout<'d>,
+    comments: &'d [ast::Comment],
+}
+
+/// Quote a CSV value if it contains separators or quotes.
+fn escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Return the text of a comment without its delimiters.
+fn comment_text(comment: &ast::Comment) -> &str {
+    let text = comment.text.as_str();
+    let text = match text.strip_prefix("/*") {
+        Some(text) => text.strip_suffix("*/").unwrap_or(text),
+        None => text.strip_prefix("//").unwrap_or(text),
+    };
+    text.trim()
+}
+
+impl<'d> Generator<'d> {
+    pub(crate) fn new(grammar: &'d ast::Grammar) -> Self {
+        Generator { layout: Layout::new(grammar), comments: &grammar.comments }
+    }
+
+    /// Return the comment found after the field on the same line.
+    fn trailing_comment(&self, field: &ast::Field) -> Option<&'d str> {
+        let loc = field.loc();
+        self.comments
+            .iter()
+            .find(|c| {
+                c.loc.file == loc.file
+                    && c.loc.start.line == loc.end.line
+                    && c.loc.start.offset >= loc.end.offset
+            })
+            .map(comment_text)
+    }
+
+    /// Return the fields of a packet or struct declaration.
+    pub(crate) fn rows(&self, decl: &'d ast::Decl) -> Vec<Row> {
+        let id = match decl {
+            ast::Decl::Packet { id, .. } | ast::Decl::Struct { id, .. } => id,
+            _ => return vec![],
+        };
+        let mut rows = vec![];
+        for FieldLayout { field, value, offset, width } in self.layout.fields(decl) {
+            let offset = offset.and_then(|offset| offset.static_offset());
+            let (name, ty, value) = match field {
+                ast::Field::Checksum { field_id, .. } => {
+                    (format!("_checksum_start_({})", field_id), "checksum".to_owned(), None)
+                }
+                ast::Field::Padding { width, .. } => {
+                    (format!("_padding_[{}]", width), "padding".to_owned(), None)
+                }
+                ast::Field::Size { field_id, .. } => {
+                    (format!("_size_({})", field_id), "size".to_owned(), None)
+                }
+                ast::Field::Count { field_id, .. } => {
+                    (format!("_count_({})", field_id), "count".to_owned(), None)
+                }
+                ast::Field::Body { .. } => ("_body_".to_owned(), "body".to_owned(), None),
+                ast::Field::Payload { .. } => ("_payload_".to_owned(), "payload".to_owned(), None),
+                ast::Field::Fixed { value: Some(value), .. } => {
+                    ("_fixed_".to_owned(), "scalar".to_owned(), Some(format!("{:#x}", value)))
+                }
+                ast::Field::Fixed { enum_id, tag_id, .. } => (
+                    "_fixed_".to_owned(),
+                    enum_id.as_deref().unwrap_or_default().to_owned(),
+                    tag_id.as_deref().map(str::to_owned),
+                ),
+                ast::Field::Reserved { .. } => {
+                    ("_reserved_".to_owned(), "reserved".to_owned(), None)
+                }
+                ast::Field::Array { id, type_id, size, size_modifier, .. } => {
+                    let element = type_id.as_deref().unwrap_or("scalar");
+                    let size = match (size, size_modifier) {
+                        (Some(size), _) => size.to_string(),
+                        (_, Some(modifier)) => modifier.clone(),
+                        _ => String::new(),
+                    };
+                    (id.to_string(), format!("{}[{}]", element, size), value)
+                }
+                ast::Field::Scalar { id, .. } => (id.to_string(), "scalar".to_owned(), value),
+                ast::Field::Typedef { id, type_id, .. } => {
+                    (id.to_string(), type_id.to_string(), value)
+                }
+                ast::Field::Group { group_id, .. } => {
+                    (group_id.to_string(), "group".to_owned(), None)
+                }
+            };
+            let description = value
+                .map(|value| format!("= {}", value))
+                .into_iter()
+                .chain(self.trailing_comment(field).map(str::to_owned))
+                .collect::<Vec<_>>()
+                .join("; ");
+            rows.push(Row { decl: id.to_string(), field: name, offset, width, ty, description });
+        }
+        rows
+    }
+}
+
+/// Generate the CSV field layout of all packet and struct declarations.
+pub fn generate(grammar: &ast::Grammar) -> String {
+    generate_with_timings(grammar, &mut GenerateTimings::default())
+}
+
+/// Generate the CSV field layout, and record the time spent on each
+/// declaration to `timings`.
+fn generate_with_timings(grammar: &ast::Grammar, timings: &mut GenerateTimings) -> String {
+    let generator = Generator::new(grammar);
+    let mut out = String::from("packet,field,bit offset,bit width,type,description\n");
+    for decl in &grammar.declarations {
+        timings.time(decl, || {
+            for row in generator.rows(decl) {
+                let columns = [
+                    row.decl,
+                    row.field,
+                    row.offset.map(|o| o.to_string()).unwrap_or_default(),
+                    row.width.map(|w| w.to_string()).unwrap_or_default(),
+                    row.ty,
+                    row.description,
+                ];
+                out.push_str(&columns.iter().map(|c| escape(c)).collect::<Vec<_>>().join(","));
+                out.push('\n');
+            }
+        });
+    }
+    out
+}
+
+/// CSV field layout backend, see [`generate`].
+pub struct CsvBackend;
+
+impl Backend for CsvBackend {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    fn extension(&self) -> &str {
+        "csv"
+    }
+
+    fn generate(
+        &self,
+        _sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        _scope: Option<&lint::Scope>,
+        output: &mut dyn io::Write,
+        timings: &mut GenerateTimings,
+    ) -> io::Result<()> {
+        output.write_all(generate_with_timings(grammar, timings).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_generate() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            struct Handle { value: 12, _reserved_: 4 }
+            packet Command {
+                op: Op, // Operation code, see "Op"
+                _size_(_payload_): 8,
+                _payload_,
+            }
+            packet Read : Command (op = READ) {
+                handle: Handle,
+                data: 8[],
+                crc: 16,
+            }
+            "#,
+        );
+
+        assert_eq!(
+            generate(&grammar),
+            r#"packet,field,bit offset,bit width,type,description
+Handle,value,0,12,scalar,
+Handle,_reserved_,12,4,reserved,
+Command,op,0,8,Op,"Operation code, see ""Op"""
+Command,_size_(_payload_),8,8,size,
+Command,_payload_,16,,payload,
+Read,handle,16,16,Handle,
+Read,data,32,,scalar[],
+Read,crc,,16,scalar,
+"#
+        );
+    }
+}
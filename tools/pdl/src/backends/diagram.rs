@@ -0,0 +1,301 @@
+//! RFC style ASCII packet diagram generator.
+//!
+//! Every packet and struct is drawn as a bit ruler with 32 columns and
+//! one box per field, similar to the figures of the Bluetooth Core
+//! Specification:
+//!
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! |    opcode     |  len  |  ...  |             handle            |
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! /                           _payload_                           /
+//! +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+//! ```
+//!
+//! Fields with a variable size (payloads, dynamic arrays, structs
+//! containing such fields) are drawn as a full row delimited by `/`;
+//! fields following a variable size field restart at column 0.
+//! Group fields are inlined, see [`crate::layout`], and fields set by
+//! a group constraint are labelled with their value.
+
+use std::io;
+
+use crate::ast;
+use crate::backends::{Backend, GenerateTimings};
+use crate::layout::{FieldLayout, Layout};
+use crate::lint;
+
+/// Number of bits drawn per row.
+const ROW_WIDTH: usize = 32;
+
+/// Flattened field, ready to be drawn.
+enum Item {
+    /// Field with a statically known bit width.
+    Static { label: String, width: usize },
+    /// Field with a variable size.
+    Variable { label: String },
+}
+
+/// Row of the diagram.
+enum Row {
+    /// Row of static fields segments (width, label).
+    Bits(Vec<(usize, String)>),
+    /// Variable size field.
+    Variable(String),
+}
+
+impl Row {
+    fn width(&self) -> usize {
+        match self {
+            Row::Bits(segments) => segments.iter().map(|(width, _)| width).sum(),
+            Row::Variable(_) => ROW_WIDTH,
+        }
+    }
+}
+
+/// Convert a flattened field to a diagram item. Checksum start
+/// markers and invalid fixed fields are not drawn.
+fn item(field: &FieldLayout) -> Option<Item> {
+    let label = |label: String| match &field.value {
+        Some(value) => format!("{} = {}", label, value),
+        None => label,
+    };
+    let label = match field.field {
+        ast::Field::Checksum { .. } => return None,
+        ast::Field::Padding { width, .. } => format!("_padding_ [{}]", width),
+        ast::Field::Size { field_id, .. } => format!("_size_({})", field_id),
+        ast::Field::Count { field_id, .. } => format!("_count_({})", field_id),
+        ast::Field::Body { .. } => "_body_".to_owned(),
+        ast::Field::Payload { .. } => "_payload_".to_owned(),
+        ast::Field::Fixed { width: Some(_), value: Some(value), .. } => format!("{:#x}", value),
+        ast::Field::Fixed { enum_id: Some(_), tag_id: Some(tag_id), .. } => tag_id.to_string(),
+        ast::Field::Fixed { .. } => return None,
+        ast::Field::Reserved { .. } => "_reserved_".to_owned(),
+        ast::Field::Array { id, size: Some(size), .. } => label(format!("{}[{}]", id, size)),
+        ast::Field::Array { id, .. } => format!("{}[]", id),
+        ast::Field::Scalar { id, .. } | ast::Field::Typedef { id, .. } => label(id.to_string()),
+        // Undeclared or recursive groups are reported by the linter.
+        ast::Field::Group { group_id, .. } => group_id.to_string(),
+    };
+    Some(match field.width {
+        Some(width) => Item::Static { label, width },
+        None => Item::Variable { label },
+    })
+}
+
+/// Split the flattened fields into rows of at most `ROW_WIDTH` bits.
+/// Fields spanning multiple rows are labelled on their largest segment.
+fn layout(items: Vec<Item>) -> Vec<Row> {
+    let mut rows = vec![];
+    let mut current = vec![];
+    let mut column = 0;
+    for item in items {
+        match item {
+            Item::Static { label, width } => {
+                let mut segments = vec![];
+                let mut remaining = width;
+                while remaining > 0 {
+                    let segment = remaining.min(ROW_WIDTH - column);
+                    segments.push(segment);
+                    remaining -= segment;
+                    column += segment;
+                    if column == ROW_WIDTH {
+                        segments.push(0);
+                        column = 0;
+                    }
+                }
+                let labelled = segments
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(index, width)| (**width, usize::MAX - index))
+                    .map(|(index, _)| index);
+                for (index, width) in segments.into_iter().enumerate() {
+                    if width == 0 {
+                        rows.push(Row::Bits(std::mem::take(&mut current)));
+                    } else if Some(index) == labelled {
+                        current.push((width, label.clone()));
+                    } else {
+                        current.push((width, String::new()));
+                    }
+                }
+            }
+            Item::Variable { label } => {
+                if !current.is_empty() {
+                    rows.push(Row::Bits(std::mem::take(&mut current)));
+                }
+                rows.push(Row::Variable(label));
+                column = 0;
+            }
+        }
+    }
+    if !current.is_empty() {
+        rows.push(Row::Bits(current));
+    }
+    rows
+}
+
+/// Center `label` in a box of `width` characters, truncating
+/// the label if it does not fit.
+fn center(label: &str, width: usize) -> String {
+    let label: String = label.chars().take(width).collect();
+    let padding = width - label.chars().count();
+    format!("{}{}{}", " ".repeat(padding / 2), label, " ".repeat(padding - padding / 2))
+}
+
+fn separator(width: usize) -> String {
+    format!("+{}", "-+".repeat(width))
+}
+
+/// Draw the layout of the selected fields.
+fn draw(rows: &[Row]) -> Vec<String> {
+    let mut lines = vec![
+        " 0                   1                   2                   3".to_owned(),
+        " 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1".to_owned(),
+    ];
+    let mut previous_width = rows.first().map(|row| row.width()).unwrap_or(0);
+    for row in rows {
+        lines.push(separator(previous_width.max(row.width())));
+        lines.push(match row {
+            Row::Bits(segments) => {
+                let mut line = String::from("|");
+                for (width, label) in segments {
+                    line.push_str(&center(label, 2 * width - 1));
+                    line.push('|');
+                }
+                line
+            }
+            Row::Variable(label) => format!("/{}/", center(label, 2 * ROW_WIDTH - 1)),
+        });
+        previous_width = row.width();
+    }
+    lines.push(separator(previous_width));
+    lines
+}
+
+/// Draw the diagram of a single packet or struct declaration.
+/// Returns `None` for other declarations.
+pub fn decl_diagram(grammar: &ast::Grammar, decl: &ast::Decl) -> Option<Vec<String>> {
+    match decl {
+        ast::Decl::Packet { .. } | ast::Decl::Struct { .. } => {
+            let items = Layout::new(grammar).fields(decl).iter().filter_map(item).collect();
+            Some(draw(&layout(items)))
+        }
+        _ => None,
+    }
+}
+
+/// Draw the diagram of a declaration as a comment block, for
+/// embedding into generated code. Each line is prefixed with
+/// `prefix`, e.g. `"// "` or `"# "`.
+pub fn decl_comment(grammar: &ast::Grammar, decl: &ast::Decl, prefix: &str) -> Option<String> {
+    decl_diagram(grammar, decl).map(|lines| {
+        lines.iter().map(|line| format!("{}{}\n", prefix, line)).collect::<Vec<_>>().concat()
+    })
+}
+
+/// Generate the diagrams of all packet and struct declarations.
+pub fn generate(grammar: &ast::Grammar) -> String {
+    generate_with_timings(grammar, &mut GenerateTimings::default())
+}
+
+/// Generate the diagrams, and record the time spent on each
+/// declaration to `timings`.
+fn generate_with_timings(grammar: &ast::Grammar, timings: &mut GenerateTimings) -> String {
+    let mut out = String::new();
+    for decl in &grammar.declarations {
+        timings.time(decl, || write_decl(&mut out, grammar, decl));
+    }
+    out
+}
+
+/// Write the header and diagram of a packet or struct declaration.
+fn write_decl(out: &mut String, grammar: &ast::Grammar, decl: &ast::Decl) {
+    let header = match decl {
+        ast::Decl::Packet { id, parent_id: Some(parent_id), .. } => {
+            format!("packet {} : {}", id, parent_id)
+        }
+        ast::Decl::Packet { id, .. } => format!("packet {}", id),
+        ast::Decl::Struct { id, parent_id: Some(parent_id), .. } => {
+            format!("struct {} : {}", id, parent_id)
+        }
+        ast::Decl::Struct { id, .. } => format!("struct {}", id),
+        _ => return,
+    };
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&header);
+    out.push_str("\n\n");
+    for line in decl_diagram(grammar, decl).unwrap() {
+        out.push_str(&line);
+        out.push('\n');
+    }
+}
+
+/// packet diagram backend, see [`generate`].
+pub struct DiagramBackend;
+
+impl Backend for DiagramBackend {
+    fn name(&self) -> &str {
+        "diagram"
+    }
+
+    fn extension(&self) -> &str {
+        "txt"
+    }
+
+    fn generate(
+        &self,
+        _sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        _scope: Option<&lint::Scope>,
+        output: &mut dyn io::Write,
+        timings: &mut GenerateTimings,
+    ) -> io::Result<()> {
+        output.write_all(generate_with_timings(grammar, timings).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_generate() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            group Header { op: Op, length: 4 }
+            packet Command {
+                Header { length = 2 },
+                _reserved_: 4,
+                handle: 24,
+                _payload_,
+                crc: 8,
+            }
+            "#,
+        );
+
+        assert_eq!(
+            generate(&grammar),
+            r#"packet Command
+
+ 0                   1                   2                   3
+ 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
++-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+|      op       |length |_reserv|            handle             |
++-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+|               |
++-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/                           _payload_                           /
++-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+|      crc      |
++-+-+-+-+-+-+-+-+
+"#
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Wire format hash manifest generator.
+//!
+//! Produces one line per packet and struct declaration, with the
+//! declaration name and its wire format hash in hexadecimal:
+//!
+//! ```text
+//! Command 3b2f0c6d9a41e587
+//! ```
+//!
+//! The hashes are computed from the flattened wire layout of the
+//! declarations, see [`crate::layout::Layout::wire_hash`]. Comparing
+//! the manifests generated by two builds reveals the declarations
+//! whose wire format changed; renaming fields or types does not
+//! change the hashes.
+
+use std::io;
+
+use crate::ast;
+use crate::backends::{Backend, GenerateTimings};
+use crate::layout::Layout;
+use crate::lint;
+
+/// Generate the wire format hash manifest of all packet and struct
+/// declarations.
+pub fn generate(grammar: &ast::Grammar) -> String {
+    generate_with_timings(grammar, &mut GenerateTimings::default())
+}
+
+/// Generate the wire format hash manifest, and record the time spent
+/// on each declaration to `timings`.
+fn generate_with_timings(grammar: &ast::Grammar, timings: &mut GenerateTimings) -> String {
+    let layout = Layout::new(grammar);
+    let mut out = String::new();
+    for decl in &grammar.declarations {
+        timings.time(decl, || {
+            if let (ast::Decl::Packet { id, .. } | ast::Decl::Struct { id, .. }, Some(hash)) =
+                (decl, layout.wire_hash(decl))
+            {
+                out.push_str(&format!("{} {:016x}\n", id, hash));
+            }
+        });
+    }
+    out
+}
+
+/// Wire format hash manifest backend, see [`generate`].
+pub struct HashesBackend;
+
+impl Backend for HashesBackend {
+    fn name(&self) -> &str {
+        "hashes"
+    }
+
+    fn extension(&self) -> &str {
+        "hashes"
+    }
+
+    fn generate(
+        &self,
+        _sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        _scope: Option<&lint::Scope>,
+        output: &mut dyn io::Write,
+        timings: &mut GenerateTimings,
+    ) -> io::Result<()> {
+        output.write_all(generate_with_timings(grammar, timings).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_generate() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            struct Handle { value: 12, _reserved_: 4 }
+            packet Command { op: Op, _payload_ }
+            packet Read : Command (op = READ) { handle: Handle }
+            "#,
+        );
+
+        let manifest = generate(&grammar);
+        let names: Vec<_> = manifest.lines().map(|line| line.split(' ').next().unwrap()).collect();
+        assert_eq!(names, vec!["Handle", "Command", "Read"]);
+        let layout = Layout::new(&grammar);
+        let hash = layout.wire_hash(grammar.declarations.get(3).unwrap()).unwrap();
+        assert!(manifest.ends_with(&format!("Read {:016x}\n", hash)));
+    }
+}
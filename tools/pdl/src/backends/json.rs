@@ -0,0 +1,722 @@
+//! Versioned JSON representation of the AST.
+//!
+//! The JSON output is consumed by external tools, so it is produced
+//! from an explicit schema rather than from the internal AST types:
+//! refactoring the AST must not change the output. Any change to the
+//! layout below must bump [`SCHEMA_VERSION`] and be reflected in the
+//! golden files under `tests/json/`. The representation is loaded
+//! back by [`from_value`].
+//!
+//! # Schema, version 2
+//!
+//! All objects are emitted with their keys in lexicographic order.
+//! Optional values are always present and set to `null` when absent.
+//! String literals (checksum and custom field functions, test case
+//! inputs) are emitted without their surrounding quotes.
+//! Version 2 added the default values of the group declarations.
+//!
+//! ```text
+//! file := {
+//!     "comments": [comment],
+//!     "declarations": [declaration],
+//!     "endianness": endianness | null,
+//!     "file": string,           // name of the source file
+//!     "version": 2,
+//! }
+//!
+//! loc := {
+//!     "end": position,
+//!     "file": string,
+//!     "start": position,
+//! }
+//!
+//! position := {
+//!     "column": integer,        // counted from zero
+//!     "line": integer,          // counted from zero
+//!     "offset": integer,        // byte offset, counted from zero
+//! }
+//! ```
+//!
+//! Every other object has a `"kind"` key and a `"loc"` key:
+//!
+//! | kind                       | additional keys                                             |
+//! |----------------------------|-------------------------------------------------------------|
+//! | `comment`                  | `text`                                                      |
+//! | `endianness_declaration`   | `value` (`"little_endian"` or `"big_endian"`)               |
+//! | `checksum_declaration`     | `id`, `function`, `width`                                   |
+//! | `custom_field_declaration` | `id`, `function`, `width` (nullable)                        |
+//! | `enum_declaration`         | `id`, `tags`, `width`                                       |
+//! | `packet_declaration`       | `id`, `constraints`, `fields`, `parent_id` (nullable)       |
+//! | `struct_declaration`       | `id`, `constraints`, `fields`, `parent_id` (nullable)       |
+//! | `group_declaration`        | `id`, `constraints` (default values), `fields`              |
+//! | `test_declaration`         | `type_id`, `test_cases`                                     |
+//! | `tag`                      | `id`, `value`                                               |
+//! | `constraint`               | `id`, `value` (expression)                                  |
+//! | `test_case`                | `input`                                                     |
+//! | `checksum_field`           | `field_id`                                                  |
+//! | `padding_field`            | `width`                                                     |
+//! | `size_field`               | `field_id`, `width`                                         |
+//! | `count_field`              | `field_id`, `width`                                         |
+//! | `body_field`               |                                                             |
+//! | `payload_field`            | `size_modifier` (nullable)                                  |
+//! | `fixed_field`              | `width`, `value`, `enum_id`, `tag_id` (all nullable)        |
+//! | `reserved_field`           | `width`                                                     |
+//! | `array_field`              | `id`, `width`, `type_id`, `size_modifier`, `size` (nullable)|
+//! | `scalar_field`             | `id`, `width`                                               |
+//! | `typedef_field`            | `id`, `type_id`                                             |
+//! | `group_field`              | `group_id`, `constraints`                                   |
+//! | `identifier`               | `name`                                                      |
+//! | `integer`                  | `value`                                                     |
+//! | `unary_expr`               | `op`, `operand`                                             |
+//! | `binary_expr`              | `op`, `operands` (array of two expressions)                 |
+
+use codespan_reporting::files::Files;
+use serde_json::{Map, Value};
+use std::io;
+
+use crate::ast;
+use crate::backends::{Backend, GenerateTimings};
+use crate::lint;
+
+/// Version of the JSON schema, emitted as the top-level `"version"`, and
+/// recorded in the stamps of the generated files.
+pub const SCHEMA_VERSION: usize = 2;
+
+/// Build a JSON object.
+///
+/// The keys must be listed in lexicographic order: this keeps the
+/// output identical whether or not `serde_json` preserves the
+/// insertion order of its maps.
+fn object(entries: Vec<(&str, Value)>) -> Value {
+    debug_assert!(entries.windows(2).all(|w| w[0].0 < w[1].0), "unsorted keys");
+    let mut map = Map::new();
+    for (key, value) in entries {
+        map.insert(key.to_owned(), value);
+    }
+    Value::Object(map)
+}
+
+fn string(value: &str) -> Value {
+    Value::from(value)
+}
+
+/// Strip the quotes surrounding a string literal.
+fn literal(value: &str) -> Value {
+    string(value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value))
+}
+
+fn optional<T, F: Fn(&T) -> Value>(value: &Option<T>, f: F) -> Value {
+    value.as_ref().map(f).unwrap_or(Value::Null)
+}
+
+fn list<T, F: Fn(&T) -> Value>(values: &[T], f: F) -> Value {
+    Value::Array(values.iter().map(f).collect())
+}
+
+struct Generator<'a> {
+    sources: &'a ast::SourceDatabase,
+}
+
+impl Generator<'_> {
+    fn file(&self, file: ast::FileId) -> Value {
+        string(&self.sources.name(file).unwrap())
+    }
+
+    fn position(&self, position: &ast::SourceLocation) -> Value {
+        object(vec![
+            ("column", Value::from(position.column)),
+            ("line", Value::from(position.line)),
+            ("offset", Value::from(position.offset)),
+        ])
+    }
+
+    fn loc(&self, loc: &ast::SourceRange) -> Value {
+        object(vec![
+            ("end", self.position(&loc.end)),
+            ("file", self.file(loc.file)),
+            ("start", self.position(&loc.start)),
+        ])
+    }
+
+    fn comment(&self, comment: &ast::Comment) -> Value {
+        object(vec![
+            ("kind", string("comment")),
+            ("loc", self.loc(&comment.loc)),
+            ("text", string(&comment.text)),
+        ])
+    }
+
+    fn endianness(&self, endianness: &ast::Endianness) -> Value {
+        let value = match endianness.value {
+            ast::EndiannessValue::LittleEndian => "little_endian",
+            ast::EndiannessValue::BigEndian => "big_endian",
+        };
+        object(vec![
+            ("kind", string("endianness_declaration")),
+            ("loc", self.loc(&endianness.loc)),
+            ("value", string(value)),
+        ])
+    }
+
+    fn expr(&self, expr: &ast::Expr) -> Value {
+        match expr {
+            ast::Expr::Identifier { loc, name } => object(vec![
+                ("kind", string("identifier")),
+                ("loc", self.loc(loc)),
+                ("name", string(name)),
+            ]),
+            ast::Expr::Integer { loc, value } => object(vec![
+                ("kind", string("integer")),
+                ("loc", self.loc(loc)),
+                ("value", Value::from(*value)),
+            ]),
+            ast::Expr::Unary { loc, op, operand } => object(vec![
+                ("kind", string("unary_expr")),
+                ("loc", self.loc(loc)),
+                ("op", string(op)),
+                ("operand", self.expr(operand)),
+            ]),
+            ast::Expr::Binary { loc, op, operands } => object(vec![
+                ("kind", string("binary_expr")),
+                ("loc", self.loc(loc)),
+                ("op", string(op)),
+                ("operands", Value::Array(vec![self.expr(&operands.0), self.expr(&operands.1)])),
+            ]),
+        }
+    }
+
+    fn tag(&self, tag: &ast::Tag) -> Value {
+        object(vec![
+            ("id", string(&tag.id)),
+            ("kind", string("tag")),
+            ("loc", self.loc(&tag.loc)),
+            ("value", Value::from(tag.value)),
+        ])
+    }
+
+    fn constraint(&self, constraint: &ast::Constraint) -> Value {
+        object(vec![
+            ("id", string(&constraint.id)),
+            ("kind", string("constraint")),
+            ("loc", self.loc(&constraint.loc)),
+            ("value", self.expr(&constraint.value)),
+        ])
+    }
+
+    fn test_case(&self, test_case: &ast::TestCase) -> Value {
+        object(vec![
+            ("input", literal(&test_case.input)),
+            ("kind", string("test_case")),
+            ("loc", self.loc(&test_case.loc)),
+        ])
+    }
+
+    fn field(&self, field: &ast::Field) -> Value {
+        let loc = ("loc", self.loc(field.loc()));
+        match field {
+            ast::Field::Checksum { field_id, .. } => object(vec![
+                ("field_id", string(field_id)),
+                ("kind", string("checksum_field")),
+                loc,
+            ]),
+            ast::Field::Padding { width, .. } => {
+                object(vec![("kind", string("padding_field")), loc, ("width", Value::from(*width))])
+            }
+            ast::Field::Size { field_id, width, .. } => object(vec![
+                ("field_id", string(field_id)),
+                ("kind", string("size_field")),
+                loc,
+                ("width", Value::from(*width)),
+            ]),
+            ast::Field::Count { field_id, width, .. } => object(vec![
+                ("field_id", string(field_id)),
+                ("kind", string("count_field")),
+                loc,
+                ("width", Value::from(*width)),
+            ]),
+            ast::Field::Body { .. } => object(vec![("kind", string("body_field")), loc]),
+            ast::Field::Payload { size_modifier, .. } => object(vec![
+                ("kind", string("payload_field")),
+                loc,
+                ("size_modifier", optional(size_modifier, |m| string(m))),
+            ]),
+            ast::Field::Fixed { width, value, enum_id, tag_id, .. } => object(vec![
+                ("enum_id", optional(enum_id, |id| string(id))),
+                ("kind", string("fixed_field")),
+                loc,
+                ("tag_id", optional(tag_id, |id| string(id))),
+                ("value", optional(value, |v| Value::from(*v))),
+                ("width", optional(width, |w| Value::from(*w))),
+            ]),
+            ast::Field::Reserved { width, .. } => object(vec![
+                ("kind", string("reserved_field")),
+                loc,
+                ("width", Value::from(*width)),
+            ]),
+            ast::Field::Array { id, width, type_id, size_modifier, size, .. } => object(vec![
+                ("id", string(id)),
+                ("kind", string("array_field")),
+                loc,
+                ("size", optional(size, |s| Value::from(*s))),
+                ("size_modifier", optional(size_modifier, |m| string(m))),
+                ("type_id", optional(type_id, |id| string(id))),
+                ("width", optional(width, |w| Value::from(*w))),
+            ]),
+            ast::Field::Scalar { id, width, .. } => object(vec![
+                ("id", string(id)),
+                ("kind", string("scalar_field")),
+                loc,
+                ("width", Value::from(*width)),
+            ]),
+            ast::Field::Typedef { id, type_id, .. } => object(vec![
+                ("id", string(id)),
+                ("kind", string("typedef_field")),
+                loc,
+                ("type_id", string(type_id)),
+            ]),
+            ast::Field::Group { group_id, constraints, .. } => object(vec![
+                ("constraints", list(constraints, |c| self.constraint(c))),
+                ("group_id", string(group_id)),
+                ("kind", string("group_field")),
+                loc,
+            ]),
+        }
+    }
+
+    fn decl(&self, decl: &ast::Decl) -> Value {
+        let loc = ("loc", self.loc(decl.loc()));
+        match decl {
+            ast::Decl::Checksum { id, function, width, .. } => object(vec![
+                ("function", literal(function)),
+                ("id", string(id)),
+                ("kind", string("checksum_declaration")),
+                loc,
+                ("width", Value::from(*width)),
+            ]),
+            ast::Decl::CustomField { id, width, function, .. } => object(vec![
+                ("function", literal(function)),
+                ("id", string(id)),
+                ("kind", string("custom_field_declaration")),
+                loc,
+                ("width", optional(width, |w| Value::from(*w))),
+            ]),
+            ast::Decl::Enum { id, tags, width, .. } => object(vec![
+                ("id", string(id)),
+                ("kind", string("enum_declaration")),
+                loc,
+                ("tags", list(tags, |t| self.tag(t))),
+                ("width", Value::from(*width)),
+            ]),
+            ast::Decl::Packet { id, constraints, fields, parent_id, .. }
+            | ast::Decl::Struct { id, constraints, fields, parent_id, .. } => {
+                let kind = match decl {
+                    ast::Decl::Packet { .. } => "packet_declaration",
+                    _ => "struct_declaration",
+                };
+                object(vec![
+                    ("constraints", list(constraints, |c| self.constraint(c))),
+                    ("fields", list(fields, |f| self.field(f))),
+                    ("id", string(id)),
+                    ("kind", string(kind)),
+                    loc,
+                    ("parent_id", optional(parent_id, |id| string(id))),
+                ])
+            }
+            ast::Decl::Group { id, constraints, fields, .. } => object(vec![
+                ("constraints", list(constraints, |c| self.constraint(c))),
+                ("fields", list(fields, |f| self.field(f))),
+                ("id", string(id)),
+                ("kind", string("group_declaration")),
+                loc,
+            ]),
+            ast::Decl::Test { type_id, test_cases, .. } => object(vec![
+                ("kind", string("test_declaration")),
+                loc,
+                ("test_cases", list(test_cases, |t| self.test_case(t))),
+                ("type_id", string(type_id)),
+            ]),
+        }
+    }
+
+    fn grammar(&self, grammar: &ast::Grammar, timings: &mut GenerateTimings) -> Value {
+        let declarations =
+            grammar.declarations.iter().map(|d| timings.time(d, || self.decl(d))).collect();
+        object(vec![
+            ("comments", list(&grammar.comments, |c| self.comment(c))),
+            ("declarations", Value::Array(declarations)),
+            ("endianness", optional(&grammar.endianness, |e| self.endianness(e))),
+            ("file", self.file(grammar.file)),
+            ("version", Value::from(SCHEMA_VERSION)),
+        ])
+    }
+}
+
+/// Convert the grammar to a JSON value following the versioned
+/// schema described in the module documentation.
+pub fn to_value(sources: &ast::SourceDatabase, grammar: &ast::Grammar) -> Value {
+    Generator { sources }.grammar(grammar, &mut GenerateTimings::default())
+}
+
+/// Return the value of a key of a JSON object.
+fn get<'v>(value: &'v Value, key: &str) -> Result<&'v Value, String> {
+    value.get(key).ok_or_else(|| format!("missing key '{}'", key))
+}
+
+fn get_str(value: &Value, key: &str) -> Result<String, String> {
+    get(value, key)?.as_str().map(str::to_owned).ok_or_else(|| format!("'{}' is not a string", key))
+}
+
+fn get_symbol(value: &Value, key: &str) -> Result<ast::Symbol, String> {
+    get(value, key)?
+        .as_str()
+        .map(ast::Symbol::new)
+        .ok_or_else(|| format!("'{}' is not a string", key))
+}
+
+fn get_usize(value: &Value, key: &str) -> Result<usize, String> {
+    get(value, key)?
+        .as_u64()
+        .map(|value| value as usize)
+        .ok_or_else(|| format!("'{}' is not an integer", key))
+}
+
+/// Return the value of a nullable key, converted with `f`.
+fn get_optional<T>(
+    value: &Value,
+    key: &str,
+    f: impl Fn(&Value, &str) -> Result<T, String>,
+) -> Result<Option<T>, String> {
+    match get(value, key)? {
+        Value::Null => Ok(None),
+        _ => f(value, key).map(Some),
+    }
+}
+
+/// Return the elements of a list key, converted with `f`.
+fn get_list<T>(
+    value: &Value,
+    key: &str,
+    f: impl Fn(&Value) -> Result<T, String>,
+) -> Result<Vec<T>, String> {
+    get(value, key)?
+        .as_array()
+        .ok_or_else(|| format!("'{}' is not a list", key))?
+        .iter()
+        .map(f)
+        .collect()
+}
+
+/// Add the quotes stripped by [`literal`].
+fn quoted(value: String) -> String {
+    format!("\"{}\"", value)
+}
+
+/// Inverse of [`Generator`].
+struct Loader {
+    file: ast::FileId,
+}
+
+impl Loader {
+    fn position(&self, position: &Value) -> Result<ast::SourceLocation, String> {
+        Ok(ast::SourceLocation {
+            column: get_usize(position, "column")?,
+            line: get_usize(position, "line")?,
+            offset: get_usize(position, "offset")?,
+        })
+    }
+
+    /// All the locations are attributed to the loaded file.
+    fn loc(&self, value: &Value) -> Result<ast::SourceRange, String> {
+        let loc = get(value, "loc")?;
+        Ok(ast::SourceRange {
+            file: self.file,
+            start: self.position(get(loc, "start")?)?,
+            end: self.position(get(loc, "end")?)?,
+        })
+    }
+
+    fn comment(&self, value: &Value) -> Result<ast::Comment, String> {
+        Ok(ast::Comment { loc: self.loc(value)?, text: get_str(value, "text")? })
+    }
+
+    fn endianness(&self, value: &Value) -> Result<ast::Endianness, String> {
+        let value_ = match get_str(value, "value")?.as_str() {
+            "little_endian" => ast::EndiannessValue::LittleEndian,
+            "big_endian" => ast::EndiannessValue::BigEndian,
+            other => return Err(format!("invalid endianness '{}'", other)),
+        };
+        Ok(ast::Endianness { loc: self.loc(value)?, value: value_ })
+    }
+
+    fn expr(&self, value: &Value) -> Result<ast::Expr, String> {
+        let loc = self.loc(value)?;
+        match get_str(value, "kind")?.as_str() {
+            "identifier" => Ok(ast::Expr::Identifier { loc, name: get_symbol(value, "name")? }),
+            "integer" => Ok(ast::Expr::Integer { loc, value: get_usize(value, "value")? }),
+            "unary_expr" => Ok(ast::Expr::Unary {
+                loc,
+                op: get_str(value, "op")?,
+                operand: Box::new(self.expr(get(value, "operand")?)?),
+            }),
+            "binary_expr" => match get_list(value, "operands", |e| self.expr(e))?.as_slice() {
+                [lhs, rhs] => Ok(ast::Expr::Binary {
+                    loc,
+                    op: get_str(value, "op")?,
+                    operands: Box::new((lhs.clone(), rhs.clone())),
+                }),
+                _ => Err("binary expressions take two operands".to_owned()),
+            },
+            kind => Err(format!("invalid expression kind '{}'", kind)),
+        }
+    }
+
+    fn tag(&self, value: &Value) -> Result<ast::Tag, String> {
+        Ok(ast::Tag {
+            id: get_symbol(value, "id")?,
+            loc: self.loc(value)?,
+            value: get_usize(value, "value")?,
+        })
+    }
+
+    fn constraint(&self, value: &Value) -> Result<ast::Constraint, String> {
+        Ok(ast::Constraint {
+            id: get_symbol(value, "id")?,
+            loc: self.loc(value)?,
+            value: self.expr(get(value, "value")?)?,
+        })
+    }
+
+    fn test_case(&self, value: &Value) -> Result<ast::TestCase, String> {
+        Ok(ast::TestCase { loc: self.loc(value)?, input: quoted(get_str(value, "input")?) })
+    }
+
+    fn field(&self, value: &Value) -> Result<ast::Field, String> {
+        let loc = self.loc(value)?;
+        Ok(match get_str(value, "kind")?.as_str() {
+            "checksum_field" => {
+                ast::Field::Checksum { loc, field_id: get_symbol(value, "field_id")? }
+            }
+            "padding_field" => ast::Field::Padding { loc, width: get_usize(value, "width")? },
+            "size_field" => ast::Field::Size {
+                loc,
+                field_id: get_symbol(value, "field_id")?,
+                width: get_usize(value, "width")?,
+            },
+            "count_field" => ast::Field::Count {
+                loc,
+                field_id: get_symbol(value, "field_id")?,
+                width: get_usize(value, "width")?,
+            },
+            "body_field" => ast::Field::Body { loc },
+            "payload_field" => ast::Field::Payload {
+                loc,
+                size_modifier: get_optional(value, "size_modifier", get_str)?,
+            },
+            "fixed_field" => ast::Field::Fixed {
+                loc,
+                width: get_optional(value, "width", get_usize)?,
+                value: get_optional(value, "value", get_usize)?,
+                enum_id: get_optional(value, "enum_id", get_symbol)?,
+                tag_id: get_optional(value, "tag_id", get_symbol)?,
+            },
+            "reserved_field" => ast::Field::Reserved { loc, width: get_usize(value, "width")? },
+            "array_field" => ast::Field::Array {
+                loc,
+                id: get_symbol(value, "id")?,
+                width: get_optional(value, "width", get_usize)?,
+                type_id: get_optional(value, "type_id", get_symbol)?,
+                size_modifier: get_optional(value, "size_modifier", get_str)?,
+                size: get_optional(value, "size", get_usize)?,
+            },
+            "scalar_field" => ast::Field::Scalar {
+                loc,
+                id: get_symbol(value, "id")?,
+                width: get_usize(value, "width")?,
+            },
+            "typedef_field" => ast::Field::Typedef {
+                loc,
+                id: get_symbol(value, "id")?,
+                type_id: get_symbol(value, "type_id")?,
+            },
+            "group_field" => ast::Field::Group {
+                loc,
+                group_id: get_symbol(value, "group_id")?,
+                constraints: get_list(value, "constraints", |c| self.constraint(c))?,
+            },
+            kind => return Err(format!("invalid field kind '{}'", kind)),
+        })
+    }
+
+    fn decl(&self, value: &Value) -> Result<ast::Decl, String> {
+        let loc = self.loc(value)?;
+        Ok(match get_str(value, "kind")?.as_str() {
+            "checksum_declaration" => ast::Decl::Checksum {
+                id: get_symbol(value, "id")?,
+                loc,
+                function: quoted(get_str(value, "function")?),
+                width: get_usize(value, "width")?,
+            },
+            "custom_field_declaration" => ast::Decl::CustomField {
+                id: get_symbol(value, "id")?,
+                loc,
+                width: get_optional(value, "width", get_usize)?,
+                function: quoted(get_str(value, "function")?),
+            },
+            "enum_declaration" => ast::Decl::Enum {
+                id: get_symbol(value, "id")?,
+                loc,
+                tags: get_list(value, "tags", |t| self.tag(t))?,
+                width: get_usize(value, "width")?,
+            },
+            "packet_declaration" => ast::Decl::Packet {
+                id: get_symbol(value, "id")?,
+                loc,
+                constraints: get_list(value, "constraints", |c| self.constraint(c))?,
+                fields: get_list(value, "fields", |f| self.field(f))?,
+                parent_id: get_optional(value, "parent_id", get_symbol)?,
+            },
+            "struct_declaration" => ast::Decl::Struct {
+                id: get_symbol(value, "id")?,
+                loc,
+                constraints: get_list(value, "constraints", |c| self.constraint(c))?,
+                fields: get_list(value, "fields", |f| self.field(f))?,
+                parent_id: get_optional(value, "parent_id", get_symbol)?,
+            },
+            "group_declaration" => ast::Decl::Group {
+                id: get_symbol(value, "id")?,
+                loc,
+                constraints: get_list(value, "constraints", |c| self.constraint(c))?,
+                fields: get_list(value, "fields", |f| self.field(f))?,
+            },
+            "test_declaration" => ast::Decl::Test {
+                loc,
+                type_id: get_symbol(value, "type_id")?,
+                test_cases: get_list(value, "test_cases", |t| self.test_case(t))?,
+            },
+            kind => return Err(format!("invalid declaration kind '{}'", kind)),
+        })
+    }
+
+    fn grammar(&self, value: &Value) -> Result<ast::Grammar, String> {
+        let mut grammar = ast::Grammar::new(self.file);
+        grammar.comments = get_list(value, "comments", |c| self.comment(c))?;
+        grammar.endianness = get_optional(value, "endianness", |v, k| self.endianness(get(v, k)?))?;
+        for decl in get_list(value, "declarations", |d| self.decl(d))? {
+            grammar.declarations.alloc(decl);
+        }
+        Ok(grammar)
+    }
+}
+
+/// Load a grammar from its JSON representation, see [`to_value`].
+///
+/// The source file is added to the source database without its
+/// contents: the locations are preserved, but the diagnostics
+/// reported against the grammar cannot quote the source.
+pub fn from_value(
+    sources: &mut ast::SourceDatabase,
+    value: &Value,
+) -> Result<ast::Grammar, String> {
+    let version = get_usize(value, "version")?;
+    if version != SCHEMA_VERSION {
+        return Err(format!("unsupported schema version {}, expected {}", version, SCHEMA_VERSION));
+    }
+    let file = sources.add(get_str(value, "file")?, String::new());
+    Loader { file }.grammar(value)
+}
+
+/// Generate the pretty-printed JSON representation of the grammar.
+pub fn generate(sources: &ast::SourceDatabase, grammar: &ast::Grammar) -> String {
+    pretty(&to_value(sources, grammar))
+}
+
+/// Generate the JSON representation, and record the time spent
+/// converting each declaration to `timings`.
+fn generate_with_timings(
+    sources: &ast::SourceDatabase,
+    grammar: &ast::Grammar,
+    timings: &mut GenerateTimings,
+) -> String {
+    pretty(&Generator { sources }.grammar(grammar, timings))
+}
+
+fn pretty(value: &Value) -> String {
+    let mut out = serde_json::to_string_pretty(value).unwrap();
+    out.push('\n');
+    out
+}
+
+/// JSON representation backend, see [`generate`].
+pub struct JsonBackend;
+
+impl Backend for JsonBackend {
+    fn name(&self) -> &str {
+        "json"
+    }
+
+    fn extension(&self) -> &str {
+        "json"
+    }
+
+    fn version(&self) -> u64 {
+        SCHEMA_VERSION as u64
+    }
+
+    fn generate(
+        &self,
+        sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        _scope: Option<&lint::Scope>,
+        output: &mut dyn io::Write,
+        timings: &mut GenerateTimings,
+    ) -> io::Result<()> {
+        output.write_all(generate_with_timings(sources, grammar, timings).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_file;
+    use crate::test_utils::assert_snapshot_eq;
+
+    fn check_golden(name: &str) {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = parse_file(&mut db, format!("test/{}.pdl", name)).expect("parsing failure");
+        assert_snapshot_eq(format!("tests/json/{}.json", name), &generate(&db, &grammar));
+    }
+
+    #[test]
+    fn test_golden_example() {
+        check_golden("example");
+    }
+
+    #[test]
+    fn test_golden_group_constraint() {
+        check_golden("group-constraint");
+    }
+
+    #[test]
+    fn test_version() {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = parse_file(&mut db, "test/packet.pdl".to_owned()).expect("parsing failure");
+        let value = to_value(&db, &grammar);
+        assert_eq!(value.get("version").and_then(Value::as_u64), Some(2));
+        assert_eq!(value.get("file").and_then(Value::as_str), Some("test/packet.pdl"));
+        assert_eq!(JsonBackend.fingerprint(), format!("json:{}", SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn test_from_value() {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = parse_file(&mut db, "test/example.pdl".to_owned()).expect("parsing failure");
+        let value = to_value(&db, &grammar);
+        let mut loaded_db = ast::SourceDatabase::new();
+        let loaded = from_value(&mut loaded_db, &value).expect("loading failure");
+        assert_eq!(to_value(&loaded_db, &loaded), value);
+
+        let mut invalid = value.as_object().unwrap().clone();
+        invalid.insert("version".to_owned(), Value::from(0));
+        assert!(from_value(&mut loaded_db, &Value::Object(invalid)).is_err());
+    }
+}
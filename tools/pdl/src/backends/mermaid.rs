@@ -0,0 +1,222 @@
+//! Mermaid class diagram generator.
+//!
+//! The generated diagram can be pasted into a ```` ```mermaid ```` fenced
+//! block of any Markdown document. Declarations are mapped as follows:
+//!
+//! - packets, structs, and groups become classes listing their fields,
+//! - enums become `<<enumeration>>` classes listing their tags,
+//! - inheritance becomes a `Parent <|-- Child` relation, labelled with
+//!   the child constraints,
+//! - struct fields become a composition `Decl *-- Struct`,
+//! - enum, custom field, and checksum fields become a dependency
+//!   `Decl ..> Type`,
+//! - group insertions become an aggregation `Decl o-- Group`.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+
+use crate::ast;
+use crate::backends::{Backend, GenerateTimings};
+use crate::layout;
+use crate::lint;
+
+fn constraints_label(constraints: &[ast::Constraint]) -> String {
+    constraints
+        .iter()
+        .map(|c| format!("{} = {}", c.id, layout::constraint_value(&c.value)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Render a field as a class member.
+fn field_member(field: &ast::Field) -> String {
+    match field {
+        ast::Field::Checksum { field_id, .. } => format!("_checksum_start_[{}]", field_id),
+        ast::Field::Padding { width, .. } => format!("_padding_[{}]", width),
+        ast::Field::Size { field_id, width, .. } => format!("_size_[{}] : {}", field_id, width),
+        ast::Field::Count { field_id, width, .. } => format!("_count_[{}] : {}", field_id, width),
+        ast::Field::Body { .. } => "_body_".to_owned(),
+        ast::Field::Payload { size_modifier: Some(modifier), .. } => {
+            format!("_payload_ : [{}]", modifier)
+        }
+        ast::Field::Payload { .. } => "_payload_".to_owned(),
+        ast::Field::Fixed { width: Some(width), value: Some(value), .. } => {
+            format!("_fixed_ = {} : {}", value, width)
+        }
+        ast::Field::Fixed { enum_id: Some(enum_id), tag_id: Some(tag_id), .. } => {
+            format!("_fixed_ = {} : {}", tag_id, enum_id)
+        }
+        ast::Field::Fixed { .. } => "_fixed_".to_owned(),
+        ast::Field::Reserved { width, .. } => format!("_reserved_ : {}", width),
+        ast::Field::Array { id, width, type_id, size_modifier, size, .. } => {
+            let element = match (width, type_id) {
+                (Some(width), _) => width.to_string(),
+                (_, Some(type_id)) => type_id.to_string(),
+                _ => "?".to_owned(),
+            };
+            let size = match (size, size_modifier) {
+                (Some(size), _) => size.to_string(),
+                (_, Some(modifier)) => modifier.clone(),
+                _ => String::new(),
+            };
+            format!("{} : {}[{}]", id, element, size)
+        }
+        ast::Field::Scalar { id, width, .. } => format!("{} : {}", id, width),
+        ast::Field::Typedef { id, type_id, .. } => format!("{} : {}", id, type_id),
+        ast::Field::Group { group_id, constraints, .. } if constraints.is_empty() => {
+            group_id.to_string()
+        }
+        ast::Field::Group { group_id, constraints, .. } => {
+            format!("{} [{}]", group_id, constraints_label(constraints))
+        }
+    }
+}
+
+/// Generate the relations originating from a field.
+fn field_relation(
+    typedefs: &HashMap<&str, &ast::Decl>,
+    decl_id: &str,
+    field: &ast::Field,
+) -> Option<String> {
+    let (type_id, label) = match field {
+        ast::Field::Typedef { id, type_id, .. } => (type_id, id),
+        ast::Field::Array { id, type_id: Some(type_id), .. } => (type_id, id),
+        ast::Field::Group { group_id, .. } => {
+            return Some(format!("{} o-- {}", decl_id, group_id));
+        }
+        _ => return None,
+    };
+    match typedefs.get(type_id.as_str()) {
+        Some(ast::Decl::Struct { .. }) => Some(format!("{} *-- {} : {}", decl_id, type_id, label)),
+        Some(_) => Some(format!("{} ..> {} : {}", decl_id, type_id, label)),
+        // Undeclared types are reported by the linter.
+        None => None,
+    }
+}
+
+/// Generate a Mermaid class diagram for the packet hierarchies
+/// of the input grammar.
+pub fn generate(grammar: &ast::Grammar) -> String {
+    generate_with_timings(grammar, &mut GenerateTimings::default())
+}
+
+/// Generate the class diagram, and record the time spent on each
+/// declaration to `timings`.
+fn generate_with_timings(grammar: &ast::Grammar, timings: &mut GenerateTimings) -> String {
+    let typedefs: HashMap<&str, &ast::Decl> = grammar
+        .declarations
+        .iter()
+        .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+        .collect();
+
+    let mut classes = String::new();
+    let mut relations = vec![];
+    for decl in &grammar.declarations {
+        timings.time(decl, || {
+            let (id, stereotype, members) = match decl {
+                ast::Decl::Enum { id, tags, .. } => (
+                    id,
+                    "enumeration",
+                    tags.iter().map(|t| format!("{} = {}", t.id, t.value)).collect::<Vec<_>>(),
+                ),
+                ast::Decl::Packet { id, fields, .. }
+                | ast::Decl::Struct { id, fields, .. }
+                | ast::Decl::Group { id, fields, .. } => {
+                    relations
+                        .extend(fields.iter().filter_map(|f| field_relation(&typedefs, id, f)));
+                    (id, decl.kind(), fields.iter().map(field_member).collect())
+                }
+                // Checksum and custom field declarations have no
+                // layout; they are only drawn when referenced.
+                ast::Decl::Checksum { id, .. } => (id, "checksum", vec![]),
+                ast::Decl::CustomField { id, .. } => (id, "custom_field", vec![]),
+                ast::Decl::Test { .. } => return,
+            };
+
+            writeln!(&mut classes, "    class {} {{", id).unwrap();
+            writeln!(&mut classes, "        <<{}>>", stereotype).unwrap();
+            for member in members {
+                writeln!(&mut classes, "        {}", member).unwrap();
+            }
+            writeln!(&mut classes, "    }}").unwrap();
+
+            match decl {
+                ast::Decl::Packet { id, parent_id: Some(parent_id), constraints, .. }
+                | ast::Decl::Struct { id, parent_id: Some(parent_id), constraints, .. }
+                    if constraints.is_empty() =>
+                {
+                    relations.push(format!("{} <|-- {}", parent_id, id))
+                }
+                ast::Decl::Packet { id, parent_id: Some(parent_id), constraints, .. }
+                | ast::Decl::Struct { id, parent_id: Some(parent_id), constraints, .. } => {
+                    relations.push(format!(
+                        "{} <|-- {} : {}",
+                        parent_id,
+                        id,
+                        constraints_label(constraints)
+                    ))
+                }
+                _ => (),
+            }
+        });
+    }
+
+    let mut out = String::from("classDiagram\n");
+    out.push_str(&classes);
+    for relation in relations {
+        writeln!(&mut out, "    {}", relation).unwrap();
+    }
+    out
+}
+
+/// Mermaid class diagram backend, see [`generate`].
+pub struct MermaidBackend;
+
+impl Backend for MermaidBackend {
+    fn name(&self) -> &str {
+        "mermaid"
+    }
+
+    fn extension(&self) -> &str {
+        "mmd"
+    }
+
+    fn generate(
+        &self,
+        _sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        _scope: Option<&lint::Scope>,
+        output: &mut dyn io::Write,
+        timings: &mut GenerateTimings,
+    ) -> io::Result<()> {
+        output.write_all(generate_with_timings(grammar, timings).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_generate() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            struct Handle { value: 12, _reserved_: 4 }
+            packet Command { op: Op, handle: Handle, _payload_ }
+            packet Read : Command (op = READ) { length: 16 }
+            "#,
+        );
+
+        let diagram = generate(&grammar);
+        assert!(diagram.starts_with("classDiagram\n"));
+        assert!(diagram.contains("    class Op {\n        <<enumeration>>\n        READ = 1\n"));
+        assert!(diagram.contains("        _reserved_ : 4\n"));
+        assert!(diagram.contains("    Command <|-- Read : op = READ\n"));
+        assert!(diagram.contains("    Command *-- Handle : handle\n"));
+        assert!(diagram.contains("    Command ..> Op : op\n"));
+    }
+}
@@ -0,0 +1,1216 @@
+//! Scapy layer generator.
+//!
+//! Every packet and struct declaration is converted to a subclass of
+//! `scapy.packet.Packet`. Child packets only declare their own fields
+//! and are attached to their parent with `bind_layers`, using the
+//! child constraints as binding conditions.
+//!
+//! Bit fields follow the Scapy conventions: in little endian mode,
+//! consecutive bit fields forming a whole number of octets are read
+//! as a single little endian integer (`tot_size`/`end_tot_size`) and
+//! listed from the most significant to the least significant field.
+//!
+//! Enums and classes are preceded by a `# source: FILE:LINE` comment
+//! giving the location of their declaration.
+//!
+//! The conformance tests of test vectors are generated as a `unittest`
+//! test case appended to the layers, see [`generate_tests`].
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+
+use crate::ast;
+use crate::backends::diagram;
+use crate::backends::{Backend, GenerateTimings, Options};
+use crate::encoder;
+use crate::layout::{self, FlatField, Layout};
+use crate::lint;
+use crate::vectors;
+
+/// Style of the generated tests.
+#[derive(Clone, Copy)]
+enum Style {
+    /// Methods of a `unittest.TestCase`.
+    Unittest,
+    /// Module level pytest functions.
+    Pytest,
+}
+
+impl Style {
+    fn def(&self, name: &str) -> String {
+        match self {
+            Style::Unittest => format!("    def {}(self):", name),
+            Style::Pytest => format!("def {}():", name),
+        }
+    }
+
+    fn indent(&self) -> &'static str {
+        match self {
+            Style::Unittest => "        ",
+            Style::Pytest => "    ",
+        }
+    }
+
+    fn assert_eq(&self, left: &str, right: &str) -> String {
+        match self {
+            Style::Unittest => format!("self.assertEqual({}, {})", left, right),
+            Style::Pytest => format!("assert {} == {}", left, right),
+        }
+    }
+
+    fn assert_in(&self, member: &str, container: &str) -> String {
+        match self {
+            Style::Unittest => format!("self.assertIn({}, {})", member, container),
+            Style::Pytest => format!("assert {} in {}", member, container),
+        }
+    }
+
+    fn skip(&self, reason: &str) -> String {
+        match self {
+            Style::Unittest => format!("self.skipTest(\"{}\")", reason),
+            Style::Pytest => format!("pytest.skip(\"{}\")", reason),
+        }
+    }
+}
+
+/// Role of an integer field, used to select the Scapy field class.
+#[derive(Clone)]
+enum Role {
+    Value,
+    Enum(String),
+    Size(String),
+    Count(String),
+    PayloadSize(Option<String>),
+}
+
+/// Flattened field, ready to be converted to a Scapy field.
+enum Item {
+    /// Integer field of known width.
+    Int { name: String, width: usize, default: Option<usize>, role: Role },
+    /// Any other field, already converted.
+    Other(String),
+    /// Payload or body field, handled by Scapy layering.
+    Payload,
+    /// Field that cannot be represented, with an explanation.
+    Unsupported(String),
+}
+
+struct Generator<'d> {
+    grammar: &'d ast::Grammar,
+    /// Name of the source file, for the source map comments.
+    file: &'d str,
+    typedefs: HashMap<&'d str, &'d ast::Decl>,
+    scope: Option<&'d lint::Scope<'d>>,
+    little_endian: bool,
+    /// Layout of the declarations, when the classes record their wire
+    /// format hash.
+    wire_hashes: Option<Layout<'d>>,
+}
+
+fn python_name(id: &str) -> String {
+    match id {
+        "from" | "import" | "class" | "def" | "global" | "in" | "is" | "lambda" | "pass" => {
+            format!("{}_", id)
+        }
+        _ => id.to_owned(),
+    }
+}
+
+impl<'d> Generator<'d> {
+    fn new(grammar: &'d ast::Grammar, file: &'d str, scope: Option<&'d lint::Scope<'d>>) -> Self {
+        Generator {
+            grammar,
+            file,
+            typedefs: grammar
+                .declarations
+                .iter()
+                .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+                .collect(),
+            scope,
+            little_endian: !matches!(
+                grammar.endianness,
+                Some(ast::Endianness { value: ast::EndiannessValue::BigEndian, .. })
+            ),
+            wire_hashes: None,
+        }
+    }
+
+    /// Return the value of an enum tag.
+    fn tag_value(&self, enum_id: &str, tag_id: &str) -> Option<usize> {
+        match self.typedefs.get(enum_id) {
+            Some(ast::Decl::Enum { tags, .. }) => {
+                tags.iter().find(|tag| tag.id == tag_id).map(|tag| tag.value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolve a constraint value to an integer, using the enum
+    /// type of the constrained field when the value is a tag.
+    fn constraint_value(&self, enum_id: Option<&str>, value: &ast::Expr) -> Option<usize> {
+        match (value, enum_id) {
+            (ast::Expr::Integer { value, .. }, _) => Some(*value),
+            (ast::Expr::Identifier { name, .. }, Some(enum_id)) => self.tag_value(enum_id, name),
+            _ => None,
+        }
+    }
+
+    /// Return the Python expression for a fixed width integer field,
+    /// or `None` if the width has no dedicated Scapy field class.
+    fn int_class(&self, width: usize, role: &Role) -> Option<&'static str> {
+        let le = self.little_endian;
+        Some(match (role, width) {
+            (Role::Enum(_), 8) => "ByteEnumField",
+            (Role::Enum(_), 16) if le => "LEShortEnumField",
+            (Role::Enum(_), 16) => "ShortEnumField",
+            (Role::Enum(_), 32) if le => "LEIntEnumField",
+            (Role::Enum(_), 32) => "IntEnumField",
+            (Role::Enum(_), _) => return None,
+            (_, 8) => "ByteField",
+            (_, 16) if le => "LEShortField",
+            (_, 16) => "ShortField",
+            (_, 24) if le => "LEThreeBytesField",
+            (_, 24) => "ThreeBytesField",
+            (_, 32) if le => "LEIntField",
+            (_, 32) => "IntField",
+            (_, 64) if le => "LELongField",
+            (_, 64) => "LongField",
+            _ => return None,
+        })
+    }
+
+    /// Return the `struct` format of an integer field, used by length
+    /// fields.
+    fn int_format(&self, width: usize) -> Option<String> {
+        let code = match width {
+            8 => return Some("B".to_owned()),
+            16 => "H",
+            32 => "I",
+            64 => "Q",
+            _ => return None,
+        };
+        Some(format!("{}{}", if self.little_endian { "<" } else { ">" }, code))
+    }
+
+    /// Generate the Scapy field for an array element.
+    fn element_field(&self, width: Option<usize>, type_id: Option<&ast::Symbol>) -> Option<String> {
+        match (width, type_id.map(|id| (id, self.typedefs.get(id.as_str())))) {
+            (Some(width), _) => self
+                .int_class(width, &Role::Value)
+                .map(|class| format!("fields.{}(\"\", 0)", class)),
+            (_, Some((id, Some(ast::Decl::Enum { width, .. })))) => self
+                .int_class(*width, &Role::Enum(id.to_string()))
+                .map(|class| format!("fields.{}(\"\", 0, {})", class, id)),
+            (_, Some((_, Some(ast::Decl::CustomField { width: Some(width), .. })))) => {
+                Some(format!("fields.StrFixedLenField(\"\", b\"\", length={})", width / 8))
+            }
+            _ => None,
+        }
+    }
+
+    /// Flatten the fields of a declaration into Scapy fields,
+    /// inlining groups.
+    fn flatten(&self, fields: &'d [ast::Field]) -> Vec<Item> {
+        let flattened = layout::flatten(&self.typedefs, fields);
+        let fields: Vec<_> = flattened.iter().map(|flat| flat.field).collect();
+
+        // Sizes and counts, indexed by the sized field.
+        let mut sizes = HashMap::new();
+        for field in &fields {
+            match field {
+                ast::Field::Size { field_id, .. } | ast::Field::Count { field_id, .. } => {
+                    sizes.insert(field_id.as_str(), *field)
+                }
+                _ => None,
+            };
+        }
+
+        let mut items = vec![];
+        let mut reserved = 0;
+        for FlatField { field, value: constraint } in flattened {
+            let item = match field {
+                ast::Field::Checksum { .. } => continue,
+                ast::Field::Padding { width, .. } => {
+                    Item::Unsupported(format!("padding to {} octets", width))
+                }
+                ast::Field::Size { field_id, width, .. }
+                    if field_id == "_payload_" || field_id == "_body_" =>
+                {
+                    let modifier = fields.iter().find_map(|f| match f {
+                        ast::Field::Payload { size_modifier, .. } => size_modifier.clone(),
+                        _ => None,
+                    });
+                    Item::Int {
+                        name: format!("{}_size", field_id.trim_matches('_')),
+                        width: *width,
+                        default: None,
+                        role: Role::PayloadSize(modifier),
+                    }
+                }
+                ast::Field::Size { field_id, width, .. } => Item::Int {
+                    name: format!("{}_size", field_id),
+                    width: *width,
+                    default: None,
+                    role: Role::Size(field_id.to_string()),
+                },
+                ast::Field::Count { field_id, width, .. } => Item::Int {
+                    name: format!("{}_count", field_id),
+                    width: *width,
+                    default: None,
+                    role: Role::Count(field_id.to_string()),
+                },
+                ast::Field::Body { .. } | ast::Field::Payload { .. } => Item::Payload,
+                ast::Field::Fixed { width: Some(width), value, .. } => {
+                    let name = format!("fixed_{}", reserved);
+                    reserved += 1;
+                    Item::Int { name, width: *width, default: *value, role: Role::Value }
+                }
+                ast::Field::Fixed { enum_id: Some(enum_id), tag_id: Some(tag_id), .. } => {
+                    let name = format!("fixed_{}", reserved);
+                    reserved += 1;
+                    match layout::scalar_type_width(&self.typedefs, enum_id) {
+                        Some(width) => Item::Int {
+                            name,
+                            width,
+                            default: self.tag_value(enum_id, tag_id),
+                            role: Role::Enum(enum_id.to_string()),
+                        },
+                        None => Item::Unsupported(format!("fixed field {}", tag_id)),
+                    }
+                }
+                ast::Field::Fixed { .. } => continue,
+                ast::Field::Reserved { width, .. } => {
+                    let name = format!("reserved_{}", reserved);
+                    reserved += 1;
+                    Item::Int { name, width: *width, default: Some(0), role: Role::Value }
+                }
+                ast::Field::Scalar { id, width, .. } => Item::Int {
+                    name: python_name(id),
+                    width: *width,
+                    default: constraint.and_then(|value| self.constraint_value(None, value)),
+                    role: Role::Value,
+                },
+                ast::Field::Typedef { id, type_id, .. } => {
+                    match self.typedefs.get(type_id.as_str()) {
+                        Some(ast::Decl::Enum { width, tags, .. }) => Item::Int {
+                            name: python_name(id),
+                            width: *width,
+                            default: constraint
+                                .and_then(|value| self.constraint_value(Some(type_id), value))
+                                .or_else(|| tags.first().map(|tag| tag.value)),
+                            role: Role::Enum(type_id.to_string()),
+                        },
+                        Some(ast::Decl::Checksum { width, .. }) => Item::Int {
+                            name: python_name(id),
+                            width: *width,
+                            default: None,
+                            role: Role::Value,
+                        },
+                        Some(ast::Decl::CustomField { width: Some(width), .. }) => {
+                            Item::Other(format!(
+                                "fields.StrFixedLenField(\"{}\", b\"\", length={})",
+                                python_name(id),
+                                width / 8
+                            ))
+                        }
+                        Some(ast::Decl::Struct { .. }) => Item::Other(format!(
+                            "fields.PacketField(\"{}\", {}(), {})",
+                            python_name(id),
+                            type_id,
+                            type_id
+                        )),
+                        _ => Item::Unsupported(format!("field {}", id)),
+                    }
+                }
+                ast::Field::Array { id, width, type_id, size, size_modifier, .. } => {
+                    let bound = match (size, sizes.get(id.as_str())) {
+                        (Some(size), _) => format!(", count_from=lambda pkt: {}", size),
+                        (_, Some(ast::Field::Count { .. })) => {
+                            format!(", count_from=lambda pkt: pkt.{}_count", id)
+                        }
+                        (_, Some(ast::Field::Size { .. })) => {
+                            let modifier = size_modifier
+                                .as_ref()
+                                .map(|modifier| format!(" - ({})", modifier))
+                                .unwrap_or_default();
+                            format!(", length_from=lambda pkt: pkt.{}_size{}", id, modifier)
+                        }
+                        _ => String::new(),
+                    };
+                    let struct_id = type_id.as_ref().filter(|id| {
+                        matches!(self.typedefs.get(id.as_str()), Some(ast::Decl::Struct { .. }))
+                    });
+                    match (struct_id, self.element_field(*width, type_id.as_ref())) {
+                        (Some(struct_id), _) => Item::Other(format!(
+                            "fields.PacketListField(\"{}\", [], {}{})",
+                            python_name(id),
+                            struct_id,
+                            bound
+                        )),
+                        (None, Some(element)) => Item::Other(format!(
+                            "fields.FieldListField(\"{}\", [], {}{})",
+                            python_name(id),
+                            element,
+                            bound
+                        )),
+                        (None, None) => Item::Unsupported(format!("array {}", id)),
+                    }
+                }
+                // Undeclared or recursive groups are reported by the
+                // linter.
+                ast::Field::Group { .. } => continue,
+            };
+            items.push(item);
+        }
+        items
+    }
+
+    /// Convert integer fields to Scapy fields. Integer fields that are
+    /// not octet aligned are grouped into bit field runs.
+    fn convert(&self, items: Vec<Item>) -> Vec<String> {
+        let mut out = vec![];
+        let mut run: Vec<(String, usize, Option<usize>, Role)> = vec![];
+        let mut run_width = 0;
+        let mut after_payload = vec![];
+        let mut payload = false;
+
+        for item in items {
+            if payload {
+                match item {
+                    Item::Int { name, .. } => after_payload.push(name),
+                    Item::Other(field) | Item::Unsupported(field) => after_payload.push(field),
+                    Item::Payload => (),
+                }
+                continue;
+            }
+            match item {
+                Item::Int { name, width, default, role } => {
+                    let class = self.int_class(width, &role);
+                    if run.is_empty() && class.is_some() {
+                        out.push(self.typed_field(&name, width, default, &role));
+                    } else {
+                        run.push((name, width, default, role));
+                        run_width += width;
+                        if run_width % 8 == 0 {
+                            out.extend(self.bit_fields(std::mem::take(&mut run), run_width));
+                            run_width = 0;
+                        }
+                    }
+                }
+                Item::Other(field) => {
+                    if !run.is_empty() {
+                        out.push(
+                            "# /!\\ the following bit fields are not octet aligned".to_owned(),
+                        );
+                        out.extend(self.bit_fields(std::mem::take(&mut run), 0));
+                        run_width = 0;
+                    }
+                    out.push(field)
+                }
+                // Fields declared after the payload cannot be
+                // represented.
+                Item::Payload => payload = true,
+                Item::Unsupported(what) => {
+                    out.push(format!("# /!\\ {} cannot be represented", what))
+                }
+            }
+        }
+        if !run.is_empty() {
+            out.push("# /!\\ the following bit fields are not octet aligned".to_owned());
+            out.extend(self.bit_fields(run, 0));
+        }
+        if !after_payload.is_empty() {
+            out.push(format!(
+                "# /!\\ fields following the payload cannot be represented: {}",
+                after_payload.join(", ")
+            ));
+        }
+        out
+    }
+
+    fn default(default: Option<usize>) -> String {
+        default.map(|value| value.to_string()).unwrap_or_else(|| "None".to_owned())
+    }
+
+    /// Generate an octet aligned integer field.
+    fn typed_field(&self, name: &str, width: usize, default: Option<usize>, role: &Role) -> String {
+        let class = self.int_class(width, role).unwrap();
+        let format = self.int_format(width);
+        match (role, format) {
+            (Role::Enum(enum_id), _) => {
+                format!("fields.{}(\"{}\", {}, {})", class, name, Self::default(default), enum_id)
+            }
+            (Role::Size(field_id), Some(format)) => format!(
+                "fields.FieldLenField(\"{}\", None, length_of=\"{}\", fmt=\"{}\")",
+                name, field_id, format
+            ),
+            (Role::Count(field_id), Some(format)) => format!(
+                "fields.FieldLenField(\"{}\", None, count_of=\"{}\", fmt=\"{}\")",
+                name, field_id, format
+            ),
+            (Role::PayloadSize(modifier), Some(format)) => format!(
+                "fields.LenField(\"{}\", None, fmt=\"{}\"{})",
+                name,
+                format,
+                modifier
+                    .as_ref()
+                    .map(|modifier| format!(", adjust=lambda x: x {}", modifier))
+                    .unwrap_or_default()
+            ),
+            (Role::Value, _) => {
+                format!("fields.{}(\"{}\", {})", class, name, default.unwrap_or(0))
+            }
+            _ => format!("fields.{}(\"{}\", 0)", class, name),
+        }
+    }
+
+    /// Generate a run of bit fields. `total_width` is the width of the
+    /// run when octet aligned, or zero.
+    fn bit_fields(
+        &self,
+        mut run: Vec<(String, usize, Option<usize>, Role)>,
+        total_width: usize,
+    ) -> Vec<String> {
+        let total_size = total_width / 8;
+        let little_endian = self.little_endian && total_size > 0;
+        if little_endian {
+            // Scapy reads little endian bit fields from the most
+            // significant bit of the assembled integer.
+            run.reverse();
+        }
+        let count = run.len();
+        run.into_iter()
+            .enumerate()
+            .map(|(index, (name, width, default, role))| {
+                let mut extra = String::new();
+                if little_endian && index == 0 {
+                    write!(&mut extra, ", tot_size=-{}", total_size).unwrap();
+                }
+                if little_endian && index + 1 == count {
+                    write!(&mut extra, ", end_tot_size=-{}", total_size).unwrap();
+                }
+                match role {
+                    Role::Enum(enum_id) => format!(
+                        "fields.BitEnumField(\"{}\", {}, {}, {}{})",
+                        name,
+                        Self::default(default),
+                        width,
+                        enum_id,
+                        extra
+                    ),
+                    Role::Size(field_id) => format!(
+                        "fields.BitFieldLenField(\"{}\", None, {}, length_of=\"{}\"{})",
+                        name, width, field_id, extra
+                    ),
+                    Role::Count(field_id) => format!(
+                        "fields.BitFieldLenField(\"{}\", None, {}, count_of=\"{}\"{})",
+                        name, width, field_id, extra
+                    ),
+                    Role::PayloadSize(_) | Role::Value => format!(
+                        "fields.BitField(\"{}\", {}, {}{})",
+                        name,
+                        default.unwrap_or(0),
+                        width,
+                        extra
+                    ),
+                }
+            })
+            .collect()
+    }
+
+    /// Generate the class of a packet or struct declaration.
+    fn class(&self, out: &mut String, decl: &'d ast::Decl) {
+        let (id, fields, is_struct) = match decl {
+            ast::Decl::Packet { id, fields, .. } => (id, fields, false),
+            ast::Decl::Struct { id, fields, .. } => (id, fields, true),
+            _ => return,
+        };
+
+        let items = self.flatten(fields);
+
+        if let Some(comment) = diagram::decl_comment(self.grammar, decl, "# ") {
+            out.push_str(&comment);
+        }
+        self.source_comment(out, decl);
+        writeln!(out, "class {}(packet.Packet):", id).unwrap();
+        writeln!(out, "    name = \"{}\"", id).unwrap();
+        if let Some(hash) = self.wire_hashes.as_ref().and_then(|layout| layout.wire_hash(decl)) {
+            writeln!(out, "    _wire_hash = 0x{:016x}", hash).unwrap();
+        }
+        writeln!(out, "    fields_desc = [").unwrap();
+        for field in self.convert(items) {
+            if field.starts_with('#') {
+                writeln!(out, "        {}", field).unwrap();
+            } else {
+                writeln!(out, "        {},", field).unwrap();
+            }
+        }
+        writeln!(out, "    ]").unwrap();
+        if is_struct {
+            // Structs are embedded in other packets and never
+            // carry a payload.
+            writeln!(out).unwrap();
+            writeln!(out, "    def extract_padding(self, s):").unwrap();
+            writeln!(out, "        return b\"\", s").unwrap();
+        }
+        writeln!(out).unwrap();
+        writeln!(out).unwrap();
+    }
+
+    /// Generate a comment locating the declaration in the source file,
+    /// so that errors in the generated code can be traced back to it.
+    fn source_comment(&self, out: &mut String, decl: &ast::Decl) {
+        writeln!(out, "# source: {}:{}", self.file, decl.loc().start.line + 1).unwrap();
+    }
+
+    /// Generate the `bind_layers` call of a child declaration.
+    fn bind_layers(&self, out: &mut String, decl: &'d ast::Decl) {
+        let (id, parent_id, constraints) = match decl {
+            ast::Decl::Packet { id, parent_id: Some(parent_id), constraints, .. }
+            | ast::Decl::Struct { id, parent_id: Some(parent_id), constraints, .. } => {
+                (id, parent_id, constraints)
+            }
+            _ => return,
+        };
+        let values = self.scope.as_ref().and_then(|scope| scope.constraint_values(id));
+        let mut conditions = vec![];
+        for constraint in constraints {
+            match values.as_ref().and_then(|values| values.get(constraint.id.as_str())) {
+                Some(value) => {
+                    conditions.push(format!(", {}={}", python_name(&constraint.id), value.value()))
+                }
+                None => writeln!(
+                    out,
+                    "# /!\\ constraint {} of {} cannot be represented",
+                    constraint.id, id
+                )
+                .unwrap(),
+            }
+        }
+        writeln!(out, "packet.bind_layers({}, {}{})", parent_id, id, conditions.concat()).unwrap();
+    }
+
+    /// Find a field of a declaration by name, inlining groups.
+    fn find_field(&self, fields: &'d [ast::Field], id: &str) -> Option<&'d ast::Field> {
+        layout::flatten(&self.typedefs, fields)
+            .into_iter()
+            .map(|flat| flat.field)
+            .find(|field| field.id().map(ast::Symbol::as_str) == Some(id))
+    }
+
+    /// Return the Python expression of a test vector value, for a field
+    /// of type `type_id`.
+    fn python_value(&self, type_id: Option<&str>, value: &serde_json::Value) -> Option<String> {
+        match value {
+            serde_json::Value::Array(elements) => {
+                let elements: Option<Vec<_>> =
+                    elements.iter().map(|element| self.python_value(type_id, element)).collect();
+                Some(format!("[{}]", elements?.join(", ")))
+            }
+            serde_json::Value::Object(fields) => {
+                let type_id = type_id?;
+                let fields: Option<Vec<_>> = fields
+                    .iter()
+                    .map(|(id, value)| {
+                        let field = match self.typedefs.get(type_id) {
+                            Some(ast::Decl::Struct { fields, .. }) => self.find_field(fields, id),
+                            _ => None,
+                        };
+                        let value = self.python_value(field_type(field?), value)?;
+                        Some(format!("{}={}", python_name(id), value))
+                    })
+                    .collect();
+                Some(format!("{}({})", type_id, fields?.join(", ")))
+            }
+            serde_json::Value::String(tag) if encoder::integer(value).is_none() => {
+                Some(format!("\"{}\"", tag))
+            }
+            _ => encoder::integer(value).map(|value| value.to_string()),
+        }
+    }
+
+    /// Return the declarations from the root to the declaration `id`.
+    fn lineage(&self, id: &str) -> Vec<&'d ast::Decl> {
+        let mut lineage = vec![];
+        let mut id = Some(id);
+        while let Some(decl) = id.and_then(|id| self.typedefs.get(id)) {
+            if lineage.iter().any(|other: &&ast::Decl| other.id() == decl.id()) {
+                break;
+            }
+            lineage.insert(0, *decl);
+            id = match decl {
+                ast::Decl::Packet { parent_id, .. } | ast::Decl::Struct { parent_id, .. } => {
+                    parent_id.as_deref()
+                }
+                _ => None,
+            };
+        }
+        lineage
+    }
+
+    /// Generate the conformance test of a test vector.
+    fn test(&self, out: &mut String, vector: &vectors::TestVector, style: Style) {
+        let lineage = self.lineage(&vector.packet);
+        let test_name: String =
+            vector.name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+        writeln!(out, "{}", style.def(&format!("test_{}", test_name))).unwrap();
+        let indent = style.indent();
+        let (root_id, packet_id) = match (lineage.first(), lineage.last()) {
+            (Some(root), Some(packet)) => (root.id().unwrap(), packet.id().unwrap()),
+            _ => {
+                writeln!(
+                    out,
+                    "{}{}",
+                    indent,
+                    style.skip(&format!("unknown packet {}", vector.packet))
+                )
+                .unwrap();
+                writeln!(out).unwrap();
+                return;
+            }
+        };
+
+        let hex: String = vector.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let mut layers = vec![];
+        let mut checks = vec![];
+        for decl in &lineage {
+            let fields = match decl {
+                ast::Decl::Packet { fields, .. } | ast::Decl::Struct { fields, .. } => fields,
+                _ => continue,
+            };
+            let mut arguments = vec![];
+            for (id, value) in &vector.fields {
+                let field = match self.find_field(fields, id) {
+                    Some(field) => field,
+                    None => continue,
+                };
+                match self.python_value(field_type(field), value) {
+                    Some(python_value) => {
+                        // Decoded scalar and enum values are compared as
+                        // integers.
+                        let expected = match (field, value) {
+                            (ast::Field::Scalar { .. }, _) => encoder::integer(value),
+                            (
+                                ast::Field::Typedef { type_id, .. },
+                                serde_json::Value::String(tag),
+                            ) if encoder::integer(value).is_none() => {
+                                self.tag_value(type_id, tag).map(|value| value as u64)
+                            }
+                            (ast::Field::Typedef { type_id, .. }, _)
+                                if layout::scalar_type_width(&self.typedefs, type_id).is_some() =>
+                            {
+                                encoder::integer(value)
+                            }
+                            _ => None,
+                        };
+                        if let Some(expected) = expected {
+                            checks.push((decl.id().unwrap(), python_name(id), expected));
+                        }
+                        arguments.push(format!("{}={}", python_name(id), python_value))
+                    }
+                    None => writeln!(out, "{}# /!\\ value of {} cannot be represented", indent, id)
+                        .unwrap(),
+                }
+            }
+            layers.push(format!("{}({})", decl.id().unwrap(), arguments.join(", ")));
+        }
+        if let Some(payload) = vector.fields.get("_payload_").and_then(|value| value.as_str()) {
+            layers.push(format!("packet.Raw(bytes.fromhex(\"{}\"))", payload));
+        }
+
+        writeln!(out, "{}data = bytes.fromhex(\"{}\")", indent, hex).unwrap();
+        writeln!(out, "{}pkt = {}", indent, layers.join(" / ")).unwrap();
+        writeln!(out, "{}{}", indent, style.assert_eq("bytes(pkt)", "data")).unwrap();
+        writeln!(out, "{}decoded = {}(data)", indent, root_id).unwrap();
+        writeln!(out, "{}{}", indent, style.assert_in(packet_id, "decoded")).unwrap();
+        for (layer, name, value) in checks {
+            let field = format!("decoded[{}].{}", layer, name);
+            writeln!(out, "{}{}", indent, style.assert_eq(&field, &value.to_string())).unwrap();
+        }
+        writeln!(out, "{}{}", indent, style.assert_eq("bytes(decoded)", "data")).unwrap();
+        writeln!(out).unwrap();
+    }
+
+    /// Return the round-trip cases of a packet or struct: the layers
+    /// from the root to the declaration built with default values, with
+    /// the scalar fields of the declaration at their maximum value, and
+    /// with each tag of its enum fields. Returns no case if a field of
+    /// the lineage cannot be represented.
+    fn round_trip_cases(&self, decl: &'d ast::Decl) -> Vec<(String, String)> {
+        let lineage = self.lineage(decl.id().unwrap());
+        let mut layers = vec![];
+        for decl in &lineage {
+            let fields = match decl {
+                ast::Decl::Packet { fields, .. } | ast::Decl::Struct { fields, .. } => fields,
+                _ => return vec![],
+            };
+            let items = self.flatten(fields);
+            if self.convert(items).iter().any(|field| field.starts_with("# /!\\")) {
+                return vec![];
+            }
+            // The children are selected by the values bound to the
+            // layers.
+            let mut binding = String::new();
+            self.bind_layers(&mut binding, decl);
+            if binding.starts_with("# /!\\") {
+                return vec![];
+            }
+            layers.push(decl.id().unwrap().as_str());
+        }
+        let (id, fields) = match decl {
+            ast::Decl::Packet { id, fields, .. } | ast::Decl::Struct { id, fields, .. } => {
+                (id, fields)
+            }
+            _ => return vec![],
+        };
+        let build = |arguments: &str| {
+            let parents: String = layers[..layers.len() - 1]
+                .iter()
+                .map(|parent| format!("{}() / ", parent))
+                .collect();
+            format!("{}{}({})", parents, id, arguments)
+        };
+
+        let mut cases = vec![(id.to_string(), build(""))];
+        let maximums: Vec<String> = fields
+            .iter()
+            .filter_map(|field| match field {
+                ast::Field::Scalar { id, width, .. } if *width <= 64 => {
+                    Some(format!("{}={}", python_name(id), u64::MAX >> (64 - width)))
+                }
+                _ => None,
+            })
+            .collect();
+        if !maximums.is_empty() {
+            cases.push((format!("{}-max", id), build(&maximums.join(", "))));
+        }
+        for field in fields {
+            if let ast::Field::Typedef { id: field_id, type_id, .. } = field {
+                if let Some(ast::Decl::Enum { tags, .. }) = self.typedefs.get(type_id.as_str()) {
+                    for tag in tags {
+                        cases.push((
+                            format!("{}-{}-{}", id, field_id, tag.id),
+                            build(&format!("{}={}", python_name(field_id), tag.value)),
+                        ));
+                    }
+                }
+            }
+        }
+        cases
+    }
+
+    /// Emit struct declarations before the declarations using them.
+    fn order(&self) -> Vec<&'d ast::Decl> {
+        match &self.scope {
+            Some(scope) => scope
+                .ordered_declarations()
+                .filter(|decl| !matches!(decl, ast::Decl::Group { .. }))
+                .collect(),
+            None => self
+                .grammar
+                .declarations
+                .iter()
+                .filter(|decl| matches!(decl, ast::Decl::Packet { .. } | ast::Decl::Struct { .. }))
+                .collect(),
+        }
+    }
+}
+
+/// Generate Scapy layers for the input grammar.
+pub fn generate(sources: &ast::SourceDatabase, grammar: &ast::Grammar) -> String {
+    let scope = lint::Scope::new(grammar).ok();
+    generate_with_scope(sources, grammar, scope.as_ref(), false, &mut GenerateTimings::default())
+}
+
+/// Generate Scapy layers for the input grammar, with its scope if it
+/// is valid. With `wire_hashes`, every class records the wire format
+/// hash of its declaration in the `_wire_hash` attribute, see
+/// [`Layout::wire_hash`]. The time spent on each declaration is
+/// recorded to `timings`.
+fn generate_with_scope(
+    sources: &ast::SourceDatabase,
+    grammar: &ast::Grammar,
+    scope: Option<&lint::Scope>,
+    wire_hashes: bool,
+    timings: &mut GenerateTimings,
+) -> String {
+    let source = sources.get(grammar.file).expect("could not read source");
+    let mut generator = Generator::new(grammar, source.name(), scope);
+    if wire_hashes {
+        generator.wire_hashes = Some(Layout::new(grammar));
+    }
+    let mut out = String::new();
+
+    writeln!(&mut out, "# File generated from {}, with the command:", source.name()).unwrap();
+    writeln!(&mut out, "#  pdl compile --output-format scapy {}", source.name()).unwrap();
+    writeln!(&mut out, "# /!\\ Do not edit by hand.").unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out, "from scapy import fields, packet").unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out).unwrap();
+
+    for decl in &grammar.declarations {
+        if let ast::Decl::Enum { id, tags, .. } = decl {
+            timings.time(decl, || {
+                let tags: Vec<_> =
+                    tags.iter().map(|tag| format!("{}: \"{}\"", tag.value, tag.id)).collect();
+                generator.source_comment(&mut out, decl);
+                writeln!(&mut out, "{} = {{{}}}", id, tags.join(", ")).unwrap();
+            });
+        }
+    }
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out).unwrap();
+
+    let order = generator.order();
+    for decl in &order {
+        timings.time(decl, || generator.class(&mut out, decl));
+    }
+    for decl in &order {
+        timings.time(decl, || generator.bind_layers(&mut out, decl));
+    }
+    out
+}
+
+/// Return the type of a typedef or array field.
+fn field_type(field: &ast::Field) -> Option<&str> {
+    match field {
+        ast::Field::Typedef { type_id, .. } => Some(type_id),
+        ast::Field::Array { type_id, .. } => type_id.as_deref(),
+        _ => None,
+    }
+}
+
+/// Generate Scapy layers for the input grammar, followed by a
+/// `unittest` test case checking that the layers encode and decode
+/// the test vectors.
+pub fn generate_tests(
+    sources: &ast::SourceDatabase,
+    grammar: &ast::Grammar,
+    vectors: &[vectors::TestVector],
+) -> String {
+    let scope = lint::Scope::new(grammar).ok();
+    let mut out = generate_with_scope(
+        sources,
+        grammar,
+        scope.as_ref(),
+        false,
+        &mut GenerateTimings::default(),
+    );
+    let source = sources.get(grammar.file).expect("could not read source");
+    let generator = Generator::new(grammar, source.name(), scope.as_ref());
+
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out, "import unittest").unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out, "class ConformanceTest(unittest.TestCase):").unwrap();
+    for vector in vectors {
+        generator.test(&mut out, vector, Style::Unittest);
+    }
+    if vectors.is_empty() {
+        writeln!(&mut out, "    pass").unwrap();
+        writeln!(&mut out).unwrap();
+    }
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out, "if __name__ == \"__main__\":").unwrap();
+    writeln!(&mut out, "    unittest.main()").unwrap();
+    out
+}
+
+/// Generate a pytest module testing the Scapy layers of the input
+/// grammar, imported from the Python module `module`. The module has a
+/// round-trip test for each packet and struct, which builds the layers
+/// from the root to the declaration, decodes their bytes with the root
+/// layer and checks that the decoded layers encode to the same bytes,
+/// and a conformance test for each test vector.
+pub fn generate_pytest(
+    sources: &ast::SourceDatabase,
+    grammar: &ast::Grammar,
+    module: &str,
+    vectors: &[vectors::TestVector],
+) -> String {
+    let scope = lint::Scope::new(grammar).ok();
+    let source = sources.get(grammar.file).expect("could not read source");
+    let generator = Generator::new(grammar, source.name(), scope.as_ref());
+    let mut out = String::new();
+
+    writeln!(&mut out, "# File generated from {}, with the command:", source.name()).unwrap();
+    writeln!(
+        &mut out,
+        "#  pdl test --output-format scapy --pytest {} --grammar {} VECTORS",
+        module,
+        source.name()
+    )
+    .unwrap();
+    writeln!(&mut out, "# /!\\ Do not edit by hand.").unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out, "import pytest").unwrap();
+    writeln!(&mut out, "from scapy import packet").unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out, "from {} import *", module).unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out).unwrap();
+
+    writeln!(&mut out, "ROUND_TRIP = [").unwrap();
+    for decl in generator.order() {
+        if matches!(decl, ast::Decl::Packet { .. } | ast::Decl::Struct { .. }) {
+            for (name, layers) in generator.round_trip_cases(decl) {
+                let root = generator.lineage(decl.id().unwrap())[0].id().unwrap();
+                writeln!(
+                    &mut out,
+                    "    pytest.param(lambda: {}, {}, {}, id=\"{}\"),",
+                    layers,
+                    root,
+                    decl.id().unwrap(),
+                    name
+                )
+                .unwrap();
+            }
+        }
+    }
+    writeln!(&mut out, "]").unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out).unwrap();
+    writeln!(&mut out, "@pytest.mark.parametrize(\"build, root, layer\", ROUND_TRIP)").unwrap();
+    writeln!(&mut out, "def test_round_trip(build, root, layer):").unwrap();
+    writeln!(&mut out, "    data = bytes(build())").unwrap();
+    writeln!(&mut out, "    decoded = root(data)").unwrap();
+    writeln!(&mut out, "    assert layer in decoded").unwrap();
+    writeln!(&mut out, "    assert bytes(decoded) == data").unwrap();
+    for vector in vectors {
+        writeln!(&mut out).unwrap();
+        writeln!(&mut out).unwrap();
+        generator.test(&mut out, vector, Style::Pytest);
+        out.pop();
+    }
+    out
+}
+
+/// Scapy layer backend, see [`generate`].
+#[derive(Default)]
+pub struct ScapyBackend {
+    /// Record the wire format hash of the declarations in the classes.
+    pub wire_hashes: bool,
+}
+
+impl Backend for ScapyBackend {
+    fn name(&self) -> &str {
+        "scapy"
+    }
+
+    fn fingerprint(&self) -> String {
+        if self.wire_hashes {
+            format!("scapy:{} wire-hashes", self.version())
+        } else {
+            format!("scapy:{}", self.version())
+        }
+    }
+
+    fn extension(&self) -> &str {
+        "py"
+    }
+
+    fn with_options(&self, options: &Options) -> Option<Box<dyn Backend>> {
+        Some(Box::new(ScapyBackend { wire_hashes: options.wire_hashes }))
+    }
+
+    fn generate(
+        &self,
+        sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        scope: Option<&lint::Scope>,
+        output: &mut dyn io::Write,
+        timings: &mut GenerateTimings,
+    ) -> io::Result<()> {
+        let out = generate_with_scope(sources, grammar, scope, self.wire_hashes, timings);
+        output.write_all(out.as_bytes())
+    }
+
+    fn generate_tests(
+        &self,
+        sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        vectors: &[vectors::TestVector],
+        output: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        output.write_all(generate_tests(sources, grammar, vectors).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    fn generate_for(text: &str) -> String {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(&mut db, text);
+        generate(&db, &grammar)
+    }
+
+    #[test]
+    fn test_little_endian_bit_fields() {
+        let out = generate_for(
+            r#"
+            little_endian_packets
+            packet Acl {
+                handle: 12,
+                packet_boundary_flag: 2,
+                broadcast_flag: 2,
+                _size_(_payload_): 16,
+                _payload_,
+            }
+            "#,
+        );
+        assert!(out.contains(
+            r#"    fields_desc = [
+        fields.BitField("broadcast_flag", 0, 2, tot_size=-2),
+        fields.BitField("packet_boundary_flag", 0, 2),
+        fields.BitField("handle", 0, 12, end_tot_size=-2),
+        fields.LenField("payload_size", None, fmt="<H"),
+    ]
+"#
+        ));
+    }
+
+    #[test]
+    fn test_bind_layers() {
+        let out = generate_for(
+            r#"
+            big_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            struct Handle { value: 16 }
+            packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+            packet Read : Command (op = READ) { handle: Handle, length: 16 }
+            "#,
+        );
+        assert!(out.contains("Op = {1: \"READ\", 2: \"WRITE\"}\n"));
+        assert!(out.contains("        fields.ByteEnumField(\"op\", 1, Op),\n"));
+        assert!(out.contains("        fields.PacketField(\"handle\", Handle(), Handle),\n"));
+        assert!(out.contains("        fields.ShortField(\"length\", 0),\n"));
+        assert!(out.contains("packet.bind_layers(Command, Read, op=1)\n"));
+        assert!(out.contains("# source: stdin:3\nOp = {"));
+        assert!(out.contains("# source: stdin:6\nclass Read(packet.Packet):\n"));
+        assert!(out.find("class Handle").unwrap() < out.find("class Read").unwrap());
+    }
+
+    #[test]
+    fn test_generate_with_scope() {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            packet Command { op: Op, _payload_ }
+            packet Read : Command (op = READ) { addr: 16 }
+            "#,
+        );
+        let scope = lint::Scope::new(&grammar).unwrap_or_else(|_| panic!("invalid grammar"));
+        let mut output = vec![];
+        let mut timings = GenerateTimings::default();
+        let backend = ScapyBackend::default();
+        backend.generate(&db, &grammar, Some(&scope), &mut output, &mut timings).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), generate(&db, &grammar));
+
+        let mut output = vec![];
+        let backend = ScapyBackend::default().with_options(&Options { wire_hashes: true }).unwrap();
+        backend.generate(&db, &grammar, Some(&scope), &mut output, &mut timings).unwrap();
+        let hash = Layout::new(&grammar).wire_hash(grammar.declarations.get(2).unwrap()).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains(&format!("    name = \"Read\"\n    _wire_hash = 0x{:016x}\n", hash)));
+        assert_ne!(backend.fingerprint(), ScapyBackend::default().fingerprint());
+    }
+
+    #[test]
+    fn test_generate_tests() {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+            packet Write : Command (op = WRITE) { addr: 16, data: 8[] }
+            "#,
+        );
+        let vectors = vectors::parse(
+            r#"{
+                "version": 1,
+                "vectors": [
+                    { "name": "write", "packet": "Write",
+                      "fields": { "addr": "0x1234", "data": [7] }, "bytes": "0203341207" },
+                    { "packet": "Command", "fields": { "op": "READ", "_payload_": "01" },
+                      "bytes": "010101" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let out = generate_tests(&db, &grammar, &vectors.vectors);
+        assert!(out.starts_with(&generate(&db, &grammar)));
+        assert!(out.contains(
+            r#"    def test_write(self):
+        data = bytes.fromhex("0203341207")
+        pkt = Command() / Write(addr=4660, data=[7])
+        self.assertEqual(bytes(pkt), data)
+        decoded = Command(data)
+        self.assertIn(Write, decoded)
+        self.assertEqual(decoded[Write].addr, 4660)
+"#
+        ));
+        assert!(out.contains("pkt = Command(op=\"READ\") / packet.Raw(bytes.fromhex(\"01\"))\n"));
+        assert!(out.contains("        self.assertEqual(decoded[Command].op, 1)\n"));
+    }
+
+    #[test]
+    fn test_generate_pytest() {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+            packet Write : Command (op = WRITE) { addr: 16, data: 8[] }
+            packet Padded { _padding_ [4] }
+            "#,
+        );
+        let vectors = vectors::parse(
+            r#"{
+                "version": 1,
+                "vectors": [
+                    { "name": "write", "packet": "Write",
+                      "fields": { "addr": "0x1234", "data": [7] }, "bytes": "0203341207" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let out = generate_pytest(&db, &grammar, "test_packets", &vectors.vectors);
+        assert!(out.contains("from test_packets import *\n"));
+        assert!(out.contains(
+            r#"ROUND_TRIP = [
+    pytest.param(lambda: Command(), Command, Command, id="Command"),
+    pytest.param(lambda: Command(op=1), Command, Command, id="Command-op-READ"),
+    pytest.param(lambda: Command(op=2), Command, Command, id="Command-op-WRITE"),
+    pytest.param(lambda: Command() / Write(), Command, Write, id="Write"),
+    pytest.param(lambda: Command() / Write(addr=65535), Command, Write, id="Write-max"),
+]
+"#
+        ));
+        assert!(out.ends_with(
+            r#"def test_write():
+    data = bytes.fromhex("0203341207")
+    pkt = Command() / Write(addr=4660, data=[7])
+    assert bytes(pkt) == data
+    decoded = Command(data)
+    assert Write in decoded
+    assert decoded[Write].addr == 4660
+    assert bytes(decoded) == data
+"#
+        ));
+    }
+}
@@ -0,0 +1,579 @@
+//! Template generator.
+//!
+//! Renders a user provided template against a description of the
+//! grammar, for one-off artifacts that do not justify a backend. The
+//! templates use a subset of the Tera (Jinja) syntax:
+//!
+//! ```text
+//! {{ path }}                        value at path
+//! {{ path | filter | ... }}         value at path, with filters applied:
+//!                                   upper, lower, hex, length, json
+//! {% for x in path %}...{% endfor %}
+//!                                   loop over an array; `loop.index`,
+//!                                   `loop.first` and `loop.last` are
+//!                                   defined in the loop body
+//! {% if path %}...{% elif path %}...{% else %}...{% endif %}
+//!                                   conditional; the condition can be
+//!                                   `path`, `not path`, or `path == literal`
+//!                                   and `path != literal` with a JSON literal
+//! {# comment #}
+//! ```
+//!
+//! Paths are dot separated keys or array indices, starting with a loop
+//! variable or a key of the context. A `-` inside a delimiter, e.g.
+//! `{%- ... -%}`, removes the whitespace before or after it. Null
+//! values are rendered as empty strings, and false values are null,
+//! `false`, `0`, `""`, `[]` and `{}`.
+//!
+//! # Context
+//!
+//! ```text
+//! context := {
+//!     "file": string,           // name of the source file
+//!     "endianness": "little_endian" | "big_endian" | null,
+//!     "declarations": [declaration],
+//! }
+//!
+//! declaration := {
+//!     "id": string | null,      // null for test declarations
+//!     "kind": "checksum" | "custom_field" | "enum" | "packet"
+//!           | "struct" | "group" | "test",
+//!     "line": integer,          // line of the declaration in `file`
+//!     "width": integer | null,  // bit width of enum, checksum,
+//!                               // custom field, and static size
+//!                               // struct declarations
+//!     "parent": string | null,
+//!     "payload_offset": integer | null, // bit offset of the payload
+//!                               // from the start of the outermost
+//!                               // parent, if static
+//!     "children": [string],     // identifiers of the direct children
+//!     "tags": [{ "id": string, "value": integer }],
+//!     "fields": [field],        // packet and struct fields
+//! }
+//!
+//! field := {
+//!     "name": string,           // identifier, or `_payload_`, ...
+//!     "type": string,           // as in the CSV backend
+//!     "offset": integer | null, // bit offset from the start of the
+//!                               // outermost parent
+//!     "width": integer | null,  // bit width, if static
+//!     "description": string,
+//! }
+//! ```
+//!
+//! Groups are inlined in the fields of packets and structs.
+
+use codespan_reporting::files::Files;
+use serde_json::{Map, Value};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ast;
+use crate::backends::csv;
+use crate::backends::{Backend, GenerateTimings};
+use crate::layout::Layout;
+use crate::lint;
+
+/// Condition of an `if` or `elif` tag.
+#[derive(Debug)]
+enum Condition {
+    Truthy(String),
+    Not(String),
+    Equal(String, Value),
+    NotEqual(String, Value),
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Output { path: String, filters: Vec<String> },
+    For { var: String, path: String, body: Vec<Node> },
+    If { branches: Vec<(Condition, Vec<Node>)>, otherwise: Vec<Node> },
+}
+
+#[derive(Debug)]
+enum Token {
+    Text(String),
+    Output(String),
+    Tag(String, usize),
+}
+
+/// Tag ending a block, with its line.
+type End<'t> = Option<(&'t str, usize)>;
+
+/// Split the template into text, `{{ }}` and `{% %}` tokens, dropping
+/// comments and applying the whitespace control markers.
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = source;
+    let mut trim_next = false;
+    loop {
+        let start = ["{{", "{%", "{#"].iter().filter_map(|open| rest.find(open)).min();
+        let (text, tail) = match start {
+            Some(start) => rest.split_at(start),
+            None => (rest, ""),
+        };
+        let text = if trim_next { text.trim_start() } else { text };
+        tokens.push(Token::Text(text.to_owned()));
+        if tail.is_empty() {
+            return Ok(tokens);
+        }
+
+        let line = source[..source.len() - tail.len()].matches('\n').count() + 1;
+        let close = match &tail[..2] {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let end = tail
+            .find(close)
+            .ok_or_else(|| format!("line {}: unterminated '{}'", line, &tail[..2]))?;
+        let inner = &tail[2..end];
+        let (inner, trim_previous) = match inner.strip_prefix('-') {
+            Some(inner) => (inner, true),
+            None => (inner, false),
+        };
+        let (inner, trim) = match inner.strip_suffix('-') {
+            Some(inner) => (inner, true),
+            None => (inner, false),
+        };
+        if trim_previous {
+            if let Some(Token::Text(text)) = tokens.last_mut() {
+                text.truncate(text.trim_end().len());
+            }
+        }
+        trim_next = trim;
+        match close {
+            "}}" => tokens.push(Token::Output(inner.trim().to_owned())),
+            "%}" => tokens.push(Token::Tag(inner.trim().to_owned(), line)),
+            _ => (),
+        }
+        rest = &tail[end + 2..];
+    }
+}
+
+fn parse_condition(text: &str, line: usize) -> Result<Condition, String> {
+    let text = text.trim();
+    let literal = |text: &str| {
+        serde_json::from_str(text.trim())
+            .map_err(|_| format!("line {}: invalid literal '{}'", line, text.trim()))
+    };
+    if let Some((path, value)) = text.split_once("==") {
+        Ok(Condition::Equal(path.trim().to_owned(), literal(value)?))
+    } else if let Some((path, value)) = text.split_once("!=") {
+        Ok(Condition::NotEqual(path.trim().to_owned(), literal(value)?))
+    } else if let Some(path) = text.strip_prefix("not ") {
+        Ok(Condition::Not(path.trim().to_owned()))
+    } else {
+        Ok(Condition::Truthy(text.trim().to_owned()))
+    }
+}
+
+/// Parse the nodes up to one of the `terminators` tags, and return the
+/// nodes with the terminator tag found, or `None` at the end of the
+/// template.
+fn parse_nodes<'t>(
+    tokens: &'t [Token],
+    position: &mut usize,
+    terminators: &[&str],
+) -> Result<(Vec<Node>, End<'t>), String> {
+    let mut nodes = vec![];
+    while let Some(token) = tokens.get(*position) {
+        *position += 1;
+        match token {
+            Token::Text(text) if text.is_empty() => (),
+            Token::Text(text) => nodes.push(Node::Text(text.clone())),
+            Token::Output(expr) => {
+                let mut parts = expr.split('|').map(str::trim);
+                let path = parts.next().unwrap_or_default().to_owned();
+                nodes.push(Node::Output { path, filters: parts.map(str::to_owned).collect() });
+            }
+            Token::Tag(tag, line) => {
+                let keyword = tag.split_whitespace().next().unwrap_or_default();
+                if terminators.contains(&keyword) {
+                    return Ok((nodes, Some((tag, *line))));
+                }
+                nodes.push(parse_tag(tokens, position, tag, *line)?);
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn parse_tag(
+    tokens: &[Token],
+    position: &mut usize,
+    tag: &str,
+    line: usize,
+) -> Result<Node, String> {
+    let words: Vec<&str> = tag.split_whitespace().collect();
+    match words.as_slice() {
+        ["for", var, "in", path] => match parse_nodes(tokens, position, &["endfor"])? {
+            (body, Some(_)) => Ok(Node::For { var: var.to_string(), path: path.to_string(), body }),
+            (_, None) => Err(format!("line {}: missing 'endfor'", line)),
+        },
+        ["if", ..] => {
+            let mut branches = vec![];
+            let mut condition = parse_condition(&tag[2..], line)?;
+            loop {
+                let terminators = ["elif", "else", "endif"];
+                let (body, end) = parse_nodes(tokens, position, &terminators)?;
+                branches.push((condition, body));
+                match end {
+                    Some((end, line)) if end.starts_with("elif") => {
+                        condition = parse_condition(&end[4..], line)?
+                    }
+                    Some(("else", _)) => {
+                        return match parse_nodes(tokens, position, &["endif"])? {
+                            (otherwise, Some(_)) => Ok(Node::If { branches, otherwise }),
+                            (_, None) => Err(format!("line {}: missing 'endif'", line)),
+                        }
+                    }
+                    Some(_) => return Ok(Node::If { branches, otherwise: vec![] }),
+                    None => return Err(format!("line {}: missing 'endif'", line)),
+                }
+            }
+        }
+        _ => Err(format!("line {}: unexpected tag '{}'", line, tag)),
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(value) => *value,
+        Value::Number(value) => value.as_f64() != Some(0.0),
+        Value::String(value) => !value.is_empty(),
+        Value::Array(value) => !value.is_empty(),
+        Value::Object(value) => !value.is_empty(),
+    }
+}
+
+fn filter(value: Value, filter: &str) -> Result<Value, String> {
+    match (filter, &value) {
+        ("upper", Value::String(text)) => Ok(Value::String(text.to_uppercase())),
+        ("lower", Value::String(text)) => Ok(Value::String(text.to_lowercase())),
+        ("hex", Value::Number(number)) if number.as_u64().is_some() => {
+            Ok(Value::String(format!("{:#x}", number.as_u64().unwrap())))
+        }
+        ("length", Value::String(text)) => Ok(Value::from(text.len() as u64)),
+        ("length", Value::Array(elements)) => Ok(Value::from(elements.len() as u64)),
+        ("json", _) => Ok(Value::String(serde_json::to_string(&value).unwrap_or_default())),
+        _ => Err(format!("cannot apply the filter '{}' to {}", filter, value)),
+    }
+}
+
+struct Renderer<'c> {
+    context: &'c Value,
+    scopes: Vec<(String, Value)>,
+}
+
+impl Renderer<'_> {
+    fn lookup(&self, path: &str) -> Result<Value, String> {
+        let mut keys = path.split('.');
+        let first = keys.next().unwrap_or_default();
+        let mut value = match self.scopes.iter().rev().find(|(var, _)| var == first) {
+            Some((_, value)) => value,
+            None => self.context.get(first).ok_or_else(|| format!("undefined '{}'", first))?,
+        };
+        for key in keys {
+            let next = match key.parse::<usize>() {
+                Ok(index) => value.get(index),
+                Err(_) => value.get(key),
+            };
+            value = next.ok_or_else(|| format!("undefined '{}'", path))?;
+        }
+        Ok(value.clone())
+    }
+
+    fn condition(&self, condition: &Condition) -> Result<bool, String> {
+        Ok(match condition {
+            Condition::Truthy(path) => is_truthy(&self.lookup(path)?),
+            Condition::Not(path) => !is_truthy(&self.lookup(path)?),
+            Condition::Equal(path, value) => self.lookup(path)? == *value,
+            Condition::NotEqual(path, value) => self.lookup(path)? != *value,
+        })
+    }
+
+    fn render(&mut self, nodes: &[Node], out: &mut String) -> Result<(), String> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Output { path, filters } => {
+                    let mut value = self.lookup(path)?;
+                    for name in filters {
+                        value = filter(value, name)?;
+                    }
+                    match value {
+                        Value::Null => (),
+                        Value::String(text) => out.push_str(&text),
+                        value => out.push_str(&value.to_string()),
+                    }
+                }
+                Node::For { var, path, body } => {
+                    let elements = match self.lookup(path)? {
+                        Value::Array(elements) => elements,
+                        Value::Null => vec![],
+                        _ => return Err(format!("'{}' is not an array", path)),
+                    };
+                    let length = elements.len();
+                    for (index, element) in elements.into_iter().enumerate() {
+                        let mut state = Map::new();
+                        state.insert("index".to_owned(), Value::from(index as u64 + 1));
+                        state.insert("first".to_owned(), Value::Bool(index == 0));
+                        state.insert("last".to_owned(), Value::Bool(index + 1 == length));
+                        self.scopes.push(("loop".to_owned(), Value::Object(state)));
+                        self.scopes.push((var.clone(), element));
+                        let result = self.render(body, out);
+                        self.scopes.truncate(self.scopes.len() - 2);
+                        result?;
+                    }
+                }
+                Node::If { branches, otherwise } => {
+                    let mut taken = None;
+                    for (condition, body) in branches {
+                        if self.condition(condition)? {
+                            taken = Some(body);
+                            break;
+                        }
+                    }
+                    self.render(taken.unwrap_or(otherwise), out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn optional<T: Into<Value>>(value: Option<T>) -> Value {
+    value.map(Into::into).unwrap_or(Value::Null)
+}
+
+/// Return the template context of the grammar, with its scope if it
+/// is valid.
+fn context(file: &str, grammar: &ast::Grammar, scope: Option<&lint::Scope>) -> Value {
+    let generator = csv::Generator::new(grammar);
+    let layout = Layout::new(grammar);
+    let declarations = grammar.declarations.iter().map(|decl| {
+        let (width, parent, tags) = match decl {
+            ast::Decl::Enum { width, tags, .. } => (Some(*width), None, tags.as_slice()),
+            ast::Decl::Checksum { width, .. } => (Some(*width), None, &[][..]),
+            ast::Decl::CustomField { width, .. } => (*width, None, &[][..]),
+            ast::Decl::Struct { id, parent_id, .. } => {
+                (layout.type_width(id), parent_id.clone(), &[][..])
+            }
+            ast::Decl::Packet { parent_id, .. } => (None, parent_id.clone(), &[][..]),
+            _ => (None, None, &[][..]),
+        };
+        let payload_offset = match decl {
+            ast::Decl::Packet { id, .. } | ast::Decl::Struct { id, .. } => {
+                layout.payload_offset(id).and_then(|offset| offset.static_offset())
+            }
+            _ => None,
+        };
+        let children = scope.iter().zip(decl.id()).flat_map(|(scope, id)| scope.iter_children(id));
+        let tags = tags.iter().map(|tag| {
+            let mut object = Map::new();
+            object.insert("id".to_owned(), Value::String(tag.id.to_string()));
+            object.insert("value".to_owned(), Value::from(tag.value as u64));
+            Value::Object(object)
+        });
+        let fields = generator.rows(decl).into_iter().map(|row| {
+            let mut object = Map::new();
+            object.insert("name".to_owned(), Value::String(row.field));
+            object.insert("type".to_owned(), Value::String(row.ty));
+            object.insert("offset".to_owned(), optional(row.offset.map(|o| o as u64)));
+            object.insert("width".to_owned(), optional(row.width.map(|w| w as u64)));
+            object.insert("description".to_owned(), Value::String(row.description));
+            Value::Object(object)
+        });
+
+        let mut object = Map::new();
+        object.insert("id".to_owned(), optional(decl.id().map(|id| id.to_string())));
+        object.insert("kind".to_owned(), Value::String(decl.kind().replace(' ', "_")));
+        object.insert("line".to_owned(), Value::from(decl.loc().start.line as u64 + 1));
+        object.insert("width".to_owned(), optional(width.map(|w| w as u64)));
+        object.insert("parent".to_owned(), optional(parent.map(|id| id.to_string())));
+        object.insert("payload_offset".to_owned(), optional(payload_offset.map(|o| o as u64)));
+        object.insert(
+            "children".to_owned(),
+            Value::Array(
+                children
+                    .filter_map(|child| child.id())
+                    .map(|id| Value::from(id.as_str()))
+                    .collect(),
+            ),
+        );
+        object.insert("tags".to_owned(), Value::Array(tags.collect()));
+        object.insert("fields".to_owned(), Value::Array(fields.collect()));
+        Value::Object(object)
+    });
+
+    let mut object = Map::new();
+    object.insert("file".to_owned(), Value::String(file.to_owned()));
+    object.insert(
+        "endianness".to_owned(),
+        optional(grammar.endianness.as_ref().map(|endianness| match endianness.value {
+            ast::EndiannessValue::LittleEndian => "little_endian",
+            ast::EndiannessValue::BigEndian => "big_endian",
+        })),
+    );
+    object.insert("declarations".to_owned(), Value::Array(declarations.collect()));
+    Value::Object(object)
+}
+
+/// Template backend, rendering a template against the grammar.
+pub struct TemplateBackend {
+    source: String,
+    nodes: Vec<Node>,
+    extension: String,
+    path: Option<PathBuf>,
+}
+
+impl TemplateBackend {
+    /// Parse a template. `extension` is the extension of the
+    /// generated files.
+    pub fn new(source: &str, extension: &str) -> Result<TemplateBackend, String> {
+        let tokens = tokenize(source)?;
+        let mut position = 0;
+        match parse_nodes(&tokens, &mut position, &[])? {
+            (nodes, None) => Ok(TemplateBackend {
+                source: source.to_owned(),
+                nodes,
+                extension: extension.to_owned(),
+                path: None,
+            }),
+            (_, Some((tag, line))) => Err(format!("line {}: unexpected tag '{}'", line, tag)),
+        }
+    }
+
+    /// Read and parse a template file. The extension of the generated
+    /// files is taken from the file name without its `.tera` suffix,
+    /// e.g. `registers.h.tera` generates `.h` files.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<TemplateBackend, String> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = name.strip_suffix(".tera").unwrap_or(&name);
+        let extension = Path::new(name).extension().unwrap_or_default().to_string_lossy();
+        let backend =
+            TemplateBackend::new(&source, if extension.is_empty() { "txt" } else { &extension })
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(TemplateBackend { path: Some(path.to_owned()), ..backend })
+    }
+
+    /// Render the template against the grammar.
+    pub fn render(
+        &self,
+        sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+    ) -> Result<String, String> {
+        let scope = lint::Scope::new(grammar).ok();
+        self.render_with_scope(sources, grammar, scope.as_ref())
+    }
+
+    fn render_with_scope(
+        &self,
+        sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        scope: Option<&lint::Scope>,
+    ) -> Result<String, String> {
+        let file = sources.name(grammar.file).map_err(|err| err.to_string())?;
+        let context = context(&file, grammar, scope);
+        let mut out = String::new();
+        Renderer { context: &context, scopes: vec![] }.render(&self.nodes, &mut out)?;
+        Ok(out)
+    }
+}
+
+impl Backend for TemplateBackend {
+    fn name(&self) -> &str {
+        "template"
+    }
+
+    fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    fn fingerprint(&self) -> String {
+        format!("template:{}:{}:{}", self.version(), self.extension, self.source)
+    }
+
+    fn inputs(&self) -> Vec<PathBuf> {
+        self.path.iter().cloned().collect()
+    }
+
+    fn generate(
+        &self,
+        sources: &ast::SourceDatabase,
+        grammar: &ast::Grammar,
+        scope: Option<&lint::Scope>,
+        output: &mut dyn io::Write,
+        _timings: &mut GenerateTimings,
+    ) -> io::Result<()> {
+        let out = self
+            .render_with_scope(sources, grammar, scope)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        output.write_all(out.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    fn render(template: &str, text: &str) -> Result<String, String> {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(&mut db, text);
+        TemplateBackend::new(template, "txt")?.render(&db, &grammar)
+    }
+
+    #[test]
+    fn test_render() {
+        let grammar = r#"
+            little_endian_packets
+            enum Opcode : 8 { READ = 1, WRITE = 2 }
+            packet Command { opcode: Opcode, _size_(_payload_): 8, _payload_ }
+            packet Write : Command (opcode = WRITE) { handle: 16, value: 8[] }
+        "#;
+        let template = r#"// {{ file }} {{ endianness | upper }}
+{%- for decl in declarations %}
+{%- if decl.kind == "enum" %}
+enum {{ decl.id }} : {{ decl.width }} {
+{%- for tag in decl.tags %} {{ tag.id | lower }}={{ tag.value | hex }}{% if not loop.last %},{% endif %}{% endfor %} }
+{%- elif decl.parent %}
+{{ decl.id }} < {{ decl.parent }} (line {{ decl.line }}):{% for f in decl.fields %} {{ f.name }}@{{ f.offset }}{% endfor %}
+{%- else %}
+{{ decl.id }} ({{ decl.children | length }} {# comment #}children, payload@{{ decl.payload_offset }}):
+{%- for f in decl.fields %} {{ f.name }}@{{ f.offset }}/{{ f.width }}{% endfor %}
+{%- endif %}
+{%- endfor %}
+"#;
+        assert_eq!(
+            render(template, grammar).unwrap(),
+            r#"// stdin LITTLE_ENDIAN
+enum Opcode : 8 { read=0x1, write=0x2 }
+Command (1 children, payload@16): opcode@0/8 _size_(_payload_)@8/8 _payload_@16/
+Write < Command (line 5): handle@16 value@32
+"#
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let grammar = "little_endian_packets\npacket A {}\n";
+        assert_eq!(render("{% for x in y %}", grammar), Err("line 1: missing 'endfor'".to_owned()));
+        assert_eq!(render("\n{{ x", grammar), Err("line 2: unterminated '{{'".to_owned()));
+        assert_eq!(
+            render("{% endif %}", grammar),
+            Err("line 1: unexpected tag 'endif'".to_owned())
+        );
+        assert_eq!(render("{{ x }}", grammar), Err("undefined 'x'".to_owned()));
+        assert_eq!(
+            render("{{ declarations.0.id | hex }}", grammar),
+            Err("cannot apply the filter 'hex' to \"A\"".to_owned())
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Field-level comparison of decoded packets.
+//!
+//! Compares two packets decoded as the same declaration, and lists the
+//! fields whose values differ, recursing into structs, arrays and child
+//! declarations. Fields are named by their path from the declaration
+//! that contains them, e.g. `LeMetaEvent.subevent_code` or
+//! `Inquiry.responses[2].address`, so that a length change does not
+//! shift the comparison of the following fields.
+
+use std::fmt;
+
+use crate::decoder::{Packet, Value};
+
+/// Field whose value differs between the two packets.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Difference {
+    pub path: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.path, self.old, self.new)
+    }
+}
+
+/// Return a one-line summary of a value which is not compared field
+/// by field.
+fn summary(value: &Value) -> String {
+    match value {
+        Value::Array(elements) => format!("{} elements", elements.len()),
+        Value::Struct(packet) => packet.id.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn diff_values(path: String, old: &Value, new: &Value, differences: &mut Vec<Difference>) {
+    match (old, new) {
+        (Value::Struct(old), Value::Struct(new)) if old.id == new.id => {
+            diff_fields(&path, old, new, differences)
+        }
+        (Value::Array(old_elements), Value::Array(new_elements)) => {
+            if old_elements.len() != new_elements.len() {
+                differences.push(Difference {
+                    path: path.clone(),
+                    old: summary(old),
+                    new: summary(new),
+                });
+            }
+            for (index, (old, new)) in old_elements.iter().zip(new_elements).enumerate() {
+                diff_values(format!("{}[{}]", path, index), old, new, differences);
+            }
+        }
+        (old, new) if old != new => {
+            differences.push(Difference { path, old: summary(old), new: summary(new) })
+        }
+        _ => (),
+    }
+}
+
+/// Compare the fields of two packets decoded as the same declaration,
+/// then their children.
+fn diff_fields(prefix: &str, old: &Packet, new: &Packet, differences: &mut Vec<Difference>) {
+    for ((id, old_value), (_, new_value)) in old.fields.iter().zip(&new.fields) {
+        diff_values(format!("{}.{}", prefix, id), old_value, new_value, differences);
+    }
+    match (&old.child, &new.child) {
+        (Some(old), Some(new)) if old.id == new.id => diff_fields(&old.id, old, new, differences),
+        (None, None) => (),
+        (old_child, new_child) => {
+            let id = |child: &Option<Box<Packet>>| {
+                child.as_ref().map_or_else(|| "none".to_owned(), |child| child.id.clone())
+            };
+            differences.push(Difference {
+                path: format!("{}.child", prefix),
+                old: id(old_child),
+                new: id(new_child),
+            })
+        }
+    }
+}
+
+/// Return the fields whose values differ between two packets decoded
+/// as the same declaration.
+pub fn diff(old: &Packet, new: &Packet) -> Vec<Difference> {
+    let mut differences = vec![];
+    diff_fields(&old.id, old, new, &mut differences);
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::decode;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_diff() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            struct Handle { value: 8 }
+            packet Command { op: Op, _size_(handles): 8, handles: Handle[], _payload_ }
+            packet Read : Command (op = READ) { offset: 8 }
+            packet Write : Command (op = WRITE) { }
+            "#,
+        );
+        let decode = |bytes: &[u8]| decode(&grammar, "Command", bytes).unwrap();
+        let read = decode(&[0x01, 0x02, 0x0a, 0x0b, 0x10]);
+        let differences: Vec<String> = diff(&read, &decode(&[0x01, 0x01, 0x0a, 0x11]))
+            .iter()
+            .map(Difference::to_string)
+            .collect();
+        assert_eq!(
+            differences,
+            vec![
+                "Command.handles: 2 elements -> 1 elements",
+                "Read.offset: 16 (0x10) -> 17 (0x11)"
+            ]
+        );
+        let differences: Vec<String> = diff(&read, &decode(&[0x01, 0x02, 0x0a, 0x0c, 0x10]))
+            .iter()
+            .map(Difference::to_string)
+            .collect();
+        assert_eq!(differences, vec!["Command.handles[1].value: 11 (0xb) -> 12 (0xc)"]);
+        let differences: Vec<String> = diff(&read, &decode(&[0x02, 0x02, 0x0a, 0x0b]))
+            .iter()
+            .map(Difference::to_string)
+            .collect();
+        assert_eq!(
+            differences,
+            vec!["Command.op: READ (0x1) -> WRITE (0x2)", "Command.child: Read -> Write"]
+        );
+        assert!(diff(&read, &read).is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+//! Build script integration.
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     pdl_build::Config::new()
+//!         .file("hci.pdl")
+//!         .backend(pdl_build::backends::json::JsonBackend)
+//!         .compile()
+//!         .unwrap();
+//! }
+//! ```
+//!
+//! Each file is parsed, linted and generated to `OUT_DIR/<stem>.<ext>`,
+//! unless the output is up to date, see [`crate::stamp`].
+//! The instructions `cargo:rerun-if-changed=<file>` are printed to
+//! stdout for the input files and the files read by the backend, e.g.
+//! templates, so that cargo runs the build script again when a file
+//! changes.
+
+use codespan_reporting::diagnostic::{Diagnostic, Severity};
+use codespan_reporting::term::{self, termcolor};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast;
+use crate::backends::{self, Backend};
+use crate::lint::{self, Lintable};
+use crate::parser;
+use crate::stamp::Stamp;
+
+/// Error returned by [`Config::compile`].
+#[derive(Debug)]
+pub enum Error {
+    /// No output directory was configured, and `OUT_DIR` is not set.
+    MissingOutDir,
+    /// An input file could not be parsed or has lint errors. The
+    /// diagnostics are rendered without colors.
+    Invalid { file: PathBuf, diagnostics: String },
+    /// An output file could not be written.
+    Io { file: PathBuf, error: std::io::Error },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingOutDir => write!(f, "no output directory, and OUT_DIR is not set"),
+            Error::Invalid { file, diagnostics } => {
+                write!(f, "invalid input file {}:\n{}", file.display(), diagnostics)
+            }
+            Error::Io { file, error } => write!(f, "failed to write {}: {}", file.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Configuration of the compilation of PDL files.
+#[derive(Clone)]
+pub struct Config {
+    files: Vec<PathBuf>,
+    backend: Rc<dyn Backend>,
+    options: backends::Options,
+    out_dir: Option<PathBuf>,
+    cargo_metadata: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    /// Create a configuration with no input files, the JSON backend,
+    /// and cargo metadata enabled.
+    pub fn new() -> Config {
+        Config {
+            files: vec![],
+            backend: Rc::new(backends::json::JsonBackend),
+            options: backends::Options::default(),
+            out_dir: None,
+            cargo_metadata: true,
+        }
+    }
+
+    /// Add an input file.
+    pub fn file<P: AsRef<Path>>(&mut self, file: P) -> &mut Config {
+        self.files.push(file.as_ref().to_owned());
+        self
+    }
+
+    /// Select the backend, built-in or custom.
+    pub fn backend<B: Backend + 'static>(&mut self, backend: B) -> &mut Config {
+        self.backend = Rc::new(backend);
+        self
+    }
+
+    /// Set the options of the backend, see [`backends::Options`].
+    pub fn options(&mut self, options: backends::Options) -> &mut Config {
+        self.options = options;
+        self
+    }
+
+    /// Set the output directory. Defaults to the `OUT_DIR` environment
+    /// variable set by cargo.
+    pub fn out_dir<P: AsRef<Path>>(&mut self, out_dir: P) -> &mut Config {
+        self.out_dir = Some(out_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Enable or disable the `cargo:rerun-if-changed` instructions.
+    pub fn cargo_metadata(&mut self, cargo_metadata: bool) -> &mut Config {
+        self.cargo_metadata = cargo_metadata;
+        self
+    }
+
+    /// Compile the input files, and return the paths of the generated
+    /// files in the order of the input files.
+    pub fn compile(&self) -> Result<Vec<PathBuf>, Error> {
+        let out_dir = match &self.out_dir {
+            Some(out_dir) => out_dir.clone(),
+            None => std::env::var_os("OUT_DIR").map(PathBuf::from).ok_or(Error::MissingOutDir)?,
+        };
+        let configured = self.backend.with_options(&self.options);
+        let backend = configured.as_deref().unwrap_or_else(|| self.backend.as_ref());
+        if self.cargo_metadata {
+            for input in backend.inputs() {
+                println!("cargo:rerun-if-changed={}", input.display());
+            }
+        }
+        let mut outputs = vec![];
+        for file in &self.files {
+            if self.cargo_metadata {
+                println!("cargo:rerun-if-changed={}", file.display());
+            }
+            let output = self.compile_file(file, &out_dir, backend)?;
+            outputs.push(output);
+        }
+        Ok(outputs)
+    }
+
+    fn compile_file(
+        &self,
+        file: &Path,
+        out_dir: &Path,
+        backend: &dyn Backend,
+    ) -> Result<PathBuf, Error> {
+        let mut sources = ast::SourceDatabase::new();
+        let invalid = |sources: &ast::SourceDatabase, diagnostics: &[Diagnostic<ast::FileId>]| {
+            Error::Invalid { file: file.to_owned(), diagnostics: render(sources, diagnostics) }
+        };
+        let stem = file.file_stem().unwrap_or(file.as_os_str());
+        let output = out_dir.join(stem).with_extension(backend.extension());
+        let (name, source) = parser::read_source(&file.display().to_string()).map_err(|err| {
+            let err = Diagnostic::error().with_message(format!(
+                "failed to read input file '{}': {}",
+                file.display(),
+                err
+            ));
+            invalid(&sources, &[err])
+        })?;
+        let fingerprint = backend.fingerprint();
+        let stamp = Stamp::new(&output, &[fingerprint.as_bytes(), source.as_bytes()]);
+        if stamp.is_fresh() {
+            return Ok(output);
+        }
+
+        let grammar = parser::parse_inline(&mut sources, name, source)
+            .map_err(|err| invalid(&sources, &[err]))?;
+        let lint = grammar.lint();
+        if lint.diagnostics.iter().any(|d| matches!(d.severity, Severity::Error | Severity::Bug)) {
+            return Err(invalid(&sources, &lint.diagnostics));
+        }
+
+        let io_error = |error| Error::Io { file: output.clone(), error };
+        let mut writer = std::fs::File::create(&output).map_err(io_error)?;
+        let scope = lint::Scope::new(&grammar).ok();
+        let mut timings = backends::GenerateTimings::default();
+        backend
+            .generate(&sources, &grammar, scope.as_ref(), &mut writer, &mut timings)
+            .map_err(io_error)?;
+        stamp.write().map_err(io_error)?;
+        Ok(output)
+    }
+}
+
+/// Render diagnostics without colors.
+pub(crate) fn render(
+    sources: &ast::SourceDatabase,
+    diagnostics: &[Diagnostic<ast::FileId>],
+) -> String {
+    let mut writer = termcolor::NoColor::new(vec![]);
+    let config = term::Config::default();
+    for diagnostic in diagnostics {
+        let _ = term::emit(&mut writer, &config, sources, diagnostic);
+    }
+    String::from_utf8_lossy(&writer.into_inner()).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_compile() {
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"little_endian_packets\npacket Foo { a: 8 }\n").unwrap();
+        let outputs = Config::new()
+            .file(file.path())
+            .backend(backends::csv::CsvBackend)
+            .out_dir(out_dir.path())
+            .cargo_metadata(false)
+            .compile()
+            .unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].extension().unwrap(), "csv");
+        assert!(std::fs::read_to_string(&outputs[0]).unwrap().contains("Foo"));
+
+        // The output is up to date, and is not written again.
+        let modified = std::fs::metadata(&outputs[0]).unwrap().modified().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let mut config = Config::new();
+        config.file(file.path()).backend(backends::csv::CsvBackend).out_dir(out_dir.path());
+        assert_eq!(config.cargo_metadata(false).compile().unwrap(), outputs);
+        assert_eq!(std::fs::metadata(&outputs[0]).unwrap().modified().unwrap(), modified);
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"little_endian_packets\npacket Foo : Bar { a: 8 }\n").unwrap();
+        let result =
+            Config::new().file(file.path()).out_dir(out_dir.path()).cargo_metadata(false).compile();
+        assert!(matches!(result, Err(Error::Invalid { .. })));
+    }
+
+    #[test]
+    fn test_compile_options() {
+        let out_dir = tempfile::tempdir().unwrap();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"little_endian_packets\npacket Foo { a: 8 }\n").unwrap();
+        let mut config = Config::new();
+        config
+            .file(file.path())
+            .backend(backends::scapy::ScapyBackend::default())
+            .out_dir(out_dir.path())
+            .cargo_metadata(false);
+        let outputs = config.compile().unwrap();
+        assert!(!std::fs::read_to_string(&outputs[0]).unwrap().contains("_wire_hash"));
+
+        // The options change the fingerprint, and the output is generated
+        // again.
+        let outputs = config.options(backends::Options { wire_hashes: true }).compile().unwrap();
+        assert!(std::fs::read_to_string(&outputs[0]).unwrap().contains("_wire_hash"));
+    }
+}
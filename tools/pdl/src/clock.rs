@@ -0,0 +1,29 @@
+//! Clock of the timings reports.
+//!
+//! `std::time::Instant` panics on `wasm32-unknown-unknown`, which has
+//! no clock: there the elapsed times are always zero.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub use self::wasm::Instant;
+
+#[cfg(target_arch = "wasm32")]
+mod wasm {
+    use std::time::Duration;
+
+    /// Instant which never advances.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Instant;
+
+    impl Instant {
+        pub fn now() -> Instant {
+            Instant
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Duration::ZERO
+        }
+    }
+}
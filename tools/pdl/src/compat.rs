@@ -0,0 +1,418 @@
+//! Wire compatibility checker.
+//!
+//! Compares two revisions of a grammar and reports the changes of the
+//! wire format. Changes are breaking if packets encoded with one
+//! revision are decoded differently with the other: removed
+//! declarations and enum tags, reordered, resized, added or removed
+//! fields, changed fixed values, constraints and parents. Added
+//! declarations and enum tags, and renamed fields, are compatible.
+//! Groups are compared through the declarations which use them.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast;
+use crate::layout::{self, FlatField};
+
+/// Change between two revisions of a grammar.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Change {
+    pub breaking: bool,
+    pub message: String,
+}
+
+/// Field of a declaration, with groups inlined: the field identifier,
+/// if any, a description of its wire format, and its static bit width.
+pub(crate) struct Slot<'d> {
+    pub id: Option<&'d str>,
+    pub layout: String,
+    pub width: Option<usize>,
+}
+
+/// Return the wire layout of a field.
+pub(crate) fn layout(field: &ast::Field) -> String {
+    match field {
+        ast::Field::Checksum { field_id, .. } => format!("_checksum_start_({})", field_id),
+        ast::Field::Padding { width, .. } => format!("_padding_[{}]", width),
+        ast::Field::Size { field_id, width, .. } => format!("_size_({}) : {}", field_id, width),
+        ast::Field::Count { field_id, width, .. } => format!("_count_({}) : {}", field_id, width),
+        ast::Field::Body { .. } => "_body_".to_owned(),
+        ast::Field::Payload { size_modifier, .. } => {
+            format!("_payload_{}", size_modifier.as_deref().unwrap_or_default())
+        }
+        ast::Field::Fixed { width: Some(width), value: Some(value), .. } => {
+            format!("_fixed_ = {:#x} : {}", value, width)
+        }
+        ast::Field::Fixed { enum_id, tag_id, .. } => format!(
+            "_fixed_ = {} : {}",
+            tag_id.as_deref().unwrap_or_default(),
+            enum_id.as_deref().unwrap_or_default()
+        ),
+        ast::Field::Reserved { width, .. } => format!("_reserved_ : {}", width),
+        ast::Field::Array { width, type_id, size_modifier, size, .. } => format!(
+            "{}[{}]",
+            match (width, type_id) {
+                (Some(width), _) => width.to_string(),
+                (_, Some(type_id)) => type_id.to_string(),
+                _ => String::new(),
+            },
+            match (size, size_modifier) {
+                (Some(size), _) => size.to_string(),
+                (_, Some(modifier)) => modifier.clone(),
+                _ => String::new(),
+            }
+        ),
+        ast::Field::Scalar { width, .. } => format!("{}", width),
+        ast::Field::Typedef { type_id, .. } => type_id.to_string(),
+        ast::Field::Group { group_id, .. } => group_id.to_string(),
+    }
+}
+
+pub(crate) struct Revision<'d> {
+    pub typedefs: HashMap<&'d str, &'d ast::Decl>,
+}
+
+impl<'d> Revision<'d> {
+    pub fn new(grammar: &'d ast::Grammar) -> Self {
+        Revision {
+            typedefs: grammar
+                .declarations
+                .iter()
+                .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+                .collect(),
+        }
+    }
+
+    /// Return the static bit width of a field, or `None` if the
+    /// field has a variable size or its type is a struct.
+    fn width(&self, field: &ast::Field) -> Option<usize> {
+        let type_width = |type_id: &str| match self.typedefs.get(type_id) {
+            Some(ast::Decl::Enum { width, .. }) | Some(ast::Decl::Checksum { width, .. }) => {
+                Some(*width)
+            }
+            Some(ast::Decl::CustomField { width, .. }) => *width,
+            _ => None,
+        };
+        match field {
+            ast::Field::Checksum { .. } => Some(0),
+            ast::Field::Size { width, .. }
+            | ast::Field::Count { width, .. }
+            | ast::Field::Reserved { width, .. }
+            | ast::Field::Scalar { width, .. }
+            | ast::Field::Fixed { width: Some(width), .. } => Some(*width),
+            ast::Field::Fixed { enum_id: Some(type_id), .. }
+            | ast::Field::Typedef { type_id, .. } => type_width(type_id),
+            ast::Field::Array { width: Some(width), size: Some(size), .. } => Some(width * size),
+            ast::Field::Array { type_id: Some(type_id), size: Some(size), .. } => {
+                type_width(type_id).map(|width| width * size)
+            }
+            _ => None,
+        }
+    }
+
+    /// Flatten the fields of a declaration, inlining groups. Fields
+    /// constrained by a group are described with their value.
+    pub fn slots(&self, fields: &'d [ast::Field]) -> Vec<Slot<'d>> {
+        layout::flatten(&self.typedefs, fields)
+            .into_iter()
+            .map(|FlatField { field, value }| {
+                let layout = match value {
+                    Some(value) => {
+                        format!("{} = {}", layout(field), layout::constraint_value(value))
+                    }
+                    None => layout(field),
+                };
+                Slot { id: field.id().map(ast::Symbol::as_str), layout, width: self.width(field) }
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn constraints(constraints: &[ast::Constraint]) -> String {
+    let mut constraints: Vec<String> = constraints
+        .iter()
+        .map(|c| format!("{} = {}", c.id, layout::constraint_value(&c.value)))
+        .collect();
+    constraints.sort();
+    constraints.join(", ")
+}
+
+fn slot_name(slot: &Slot) -> String {
+    match slot.id {
+        Some(id) => format!("'{}'", id),
+        None => format!("'{}'", slot.layout),
+    }
+}
+
+struct Checker {
+    changes: Vec<Change>,
+}
+
+impl Checker {
+    fn breaking(&mut self, decl: &ast::Decl, message: String) {
+        let message = format!("{} {}: {}", decl.kind(), decl.id().unwrap(), message);
+        self.changes.push(Change { breaking: true, message });
+    }
+
+    fn compatible(&mut self, decl: &ast::Decl, message: String) {
+        let message = format!("{} {}: {}", decl.kind(), decl.id().unwrap(), message);
+        self.changes.push(Change { breaking: false, message });
+    }
+
+    fn enums(&mut self, decl: &ast::Decl, old_tags: &[ast::Tag], new_tags: &[ast::Tag]) {
+        for old in old_tags {
+            match new_tags.iter().find(|new| new.id == old.id) {
+                Some(new) if new.value != old.value => self.breaking(
+                    decl,
+                    format!(
+                        "value of tag '{}' changed from {} to {}",
+                        old.id, old.value, new.value
+                    ),
+                ),
+                Some(_) => (),
+                None => self.breaking(decl, format!("tag '{}' removed", old.id)),
+            }
+        }
+        for new in new_tags {
+            if !old_tags.iter().any(|old| old.id == new.id) {
+                self.compatible(decl, format!("tag '{}' added", new.id));
+            }
+        }
+    }
+
+    fn fields(&mut self, decl: &ast::Decl, old: &[Slot], new: &[Slot]) {
+        for index in 0..old.len().max(new.len()) {
+            match (old.get(index), new.get(index)) {
+                (Some(old_slot), Some(new_slot)) if old_slot.layout == new_slot.layout => {
+                    // Swapping fields of identical layouts is not a
+                    // rename, the values are assigned to other fields.
+                    if old_slot.id != new_slot.id && new.iter().any(|slot| slot.id == old_slot.id) {
+                        self.breaking(decl, format!("field {} moved", slot_name(old_slot)));
+                    } else if old_slot.id != new_slot.id {
+                        self.compatible(
+                            decl,
+                            format!(
+                                "field {} renamed to {}",
+                                slot_name(old_slot),
+                                slot_name(new_slot)
+                            ),
+                        );
+                    }
+                }
+                (Some(old_slot), Some(new_slot)) if old_slot.id == new_slot.id => {
+                    let message = match old_slot.id {
+                        Some(id) => format!(
+                            "field '{}' changed from '{}' to '{}'",
+                            id, old_slot.layout, new_slot.layout
+                        ),
+                        None => format!(
+                            "field '{}' at position {} changed to '{}'",
+                            old_slot.layout, index, new_slot.layout
+                        ),
+                    };
+                    self.breaking(decl, message)
+                }
+                (Some(old_slot), new_slot) => {
+                    let moved =
+                        old_slot.id.is_some() && new.iter().any(|slot| slot.id == old_slot.id);
+                    let message = match new_slot {
+                        _ if moved => format!("field {} moved", slot_name(old_slot)),
+                        Some(new_slot) => format!(
+                            "field {} at position {} replaced by {}",
+                            slot_name(old_slot),
+                            index,
+                            slot_name(new_slot)
+                        ),
+                        None => format!("field {} removed", slot_name(old_slot)),
+                    };
+                    self.breaking(decl, message);
+                }
+                (None, Some(new_slot)) => {
+                    self.breaking(decl, format!("field {} added", slot_name(new_slot)))
+                }
+                (None, None) => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Compare two revisions of a grammar, and return the changes of the
+/// wire format, in declaration order.
+pub fn check(old: &ast::Grammar, new: &ast::Grammar) -> Vec<Change> {
+    let old_revision = Revision::new(old);
+    let new_revision = Revision::new(new);
+    let mut checker = Checker { changes: vec![] };
+
+    let endianness = |grammar: &ast::Grammar| {
+        matches!(
+            grammar.endianness,
+            Some(ast::Endianness { value: ast::EndiannessValue::BigEndian, .. })
+        )
+    };
+    if endianness(old) != endianness(new) {
+        checker.changes.push(Change { breaking: true, message: "endianness changed".to_owned() });
+    }
+
+    for old_decl in &old.declarations {
+        let id = match old_decl {
+            ast::Decl::Group { .. } | ast::Decl::Test { .. } => continue,
+            _ => old_decl.id().unwrap().as_str(),
+        };
+        let new_decl = match new_revision.typedefs.get(id) {
+            Some(new_decl) if new_decl.kind() == old_decl.kind() => new_decl,
+            Some(new_decl) => {
+                checker.breaking(old_decl, format!("changed to {}", new_decl.kind()));
+                continue;
+            }
+            None => {
+                checker.breaking(old_decl, "removed".to_owned());
+                continue;
+            }
+        };
+        match (old_decl, new_decl) {
+            (
+                ast::Decl::Enum { width: old_width, tags: old_tags, .. },
+                ast::Decl::Enum { width: new_width, tags: new_tags, .. },
+            ) => {
+                if old_width != new_width {
+                    checker.breaking(
+                        old_decl,
+                        format!("width changed from {} to {}", old_width, new_width),
+                    );
+                }
+                checker.enums(old_decl, old_tags, new_tags);
+            }
+            (
+                ast::Decl::Checksum { width: old_width, .. },
+                ast::Decl::Checksum { width: new_width, .. },
+            ) if old_width != new_width => checker
+                .breaking(old_decl, format!("width changed from {} to {}", old_width, new_width)),
+            (
+                ast::Decl::CustomField { width: old_width, .. },
+                ast::Decl::CustomField { width: new_width, .. },
+            ) if old_width != new_width => checker.breaking(
+                old_decl,
+                format!("width changed from {:?} to {:?}", old_width, new_width),
+            ),
+            (
+                ast::Decl::Packet {
+                    fields: old_fields,
+                    constraints: old_constraints,
+                    parent_id: old_parent,
+                    ..
+                }
+                | ast::Decl::Struct {
+                    fields: old_fields,
+                    constraints: old_constraints,
+                    parent_id: old_parent,
+                    ..
+                },
+                ast::Decl::Packet {
+                    fields: new_fields,
+                    constraints: new_constraints,
+                    parent_id: new_parent,
+                    ..
+                }
+                | ast::Decl::Struct {
+                    fields: new_fields,
+                    constraints: new_constraints,
+                    parent_id: new_parent,
+                    ..
+                },
+            ) => {
+                if old_parent != new_parent {
+                    checker.breaking(
+                        old_decl,
+                        format!(
+                            "parent changed from '{}' to '{}'",
+                            old_parent.as_deref().unwrap_or_default(),
+                            new_parent.as_deref().unwrap_or_default()
+                        ),
+                    );
+                }
+                let (old_constraints, new_constraints) =
+                    (constraints(old_constraints), constraints(new_constraints));
+                if old_constraints != new_constraints {
+                    checker.breaking(
+                        old_decl,
+                        format!(
+                            "constraints changed from ({}) to ({})",
+                            old_constraints, new_constraints
+                        ),
+                    );
+                }
+                let old_slots = old_revision.slots(old_fields);
+                let new_slots = new_revision.slots(new_fields);
+                checker.fields(old_decl, &old_slots, &new_slots);
+            }
+            _ => (),
+        }
+    }
+
+    for new_decl in &new.declarations {
+        match new_decl {
+            ast::Decl::Group { .. } | ast::Decl::Test { .. } => (),
+            _ if !old_revision.typedefs.contains_key(new_decl.id().unwrap().as_str()) => {
+                checker.compatible(new_decl, "added".to_owned())
+            }
+            _ => (),
+        }
+    }
+    checker.changes
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = if self.breaking { "breaking" } else { "compatible" };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_check() {
+        let old = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2, ERASE = 3 }
+            group Header { op: Op, length: 8 }
+            packet Command { Header, _payload_ }
+            packet Read : Command (op = READ) { handle: 16, offset: 16 }
+            packet Write : Command (op = WRITE) { _fixed_ = 1 : 8, handle: 16, data: 8[] }
+            packet Erase : Command (op = ERASE) { handle: 16 }
+            "#,
+        );
+        let new = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 4, FLUSH = 5 }
+            group Header { op: Op, length: 16 }
+            packet Command { Header, _payload_ }
+            packet Read : Command (op = READ) { offset: 16, handle: 16 }
+            packet Write : Command (op = WRITE) { _fixed_ = 2 : 8, attribute: 16, data: 8[] }
+            packet Flush : Command (op = FLUSH) { }
+            "#,
+        );
+        let changes: Vec<String> = check(&old, &new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "breaking: enum Op: value of tag 'WRITE' changed from 2 to 4",
+                "breaking: enum Op: tag 'ERASE' removed",
+                "compatible: enum Op: tag 'FLUSH' added",
+                "breaking: packet Command: field 'length' changed from '8' to '16'",
+                "breaking: packet Read: field 'handle' moved",
+                "breaking: packet Read: field 'offset' moved",
+                "breaking: packet Write: field '_fixed_ = 0x1 : 8' at position 0 changed to '_fixed_ = 0x2 : 8'",
+                "compatible: packet Write: field 'handle' renamed to 'attribute'",
+                "breaking: packet Erase: removed",
+                "compatible: packet Flush: added",
+            ]
+        );
+        assert!(check(&old, &old).is_empty());
+    }
+}
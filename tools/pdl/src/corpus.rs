@@ -0,0 +1,260 @@
+//! Random test vector corpus.
+//!
+//! Generates test vectors with random field values for the packets of
+//! a grammar, see [`crate::vectors`]. The bytes and the decoded values
+//! of the vectors are produced by the dynamic encoder and decoder,
+//! which serve as the reference: converting the same corpus to the
+//! conformance tests of several backends with `pdl test
+//! --output-format` checks that the backends agree on the bytes and
+//! the decoded values of every vector.
+//!
+//! The generation is deterministic for a given seed. Vectors which the
+//! dynamic encoder and decoder cannot check, e.g. because a random
+//! payload selects another child, are discarded.
+
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+use crate::ast;
+use crate::decoder;
+use crate::encoder;
+use crate::layout::{self, FlatField};
+use crate::vectors::{self, TestVector};
+
+/// Maximum nesting of the generated struct values.
+const MAX_DEPTH: usize = 8;
+
+/// Maximum number of elements of the generated arrays and bytes of
+/// the generated payloads.
+const MAX_LEN: usize = 4;
+
+/// Pseudo-random generator, xorshift64*.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // The state must not be zero.
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Return an integer below `bound`, which must not be zero.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    /// Return an integer of `width` bits. The bounds of the range are
+    /// drawn more often than the other values.
+    fn bits(&mut self, width: usize) -> u64 {
+        let max = if width >= 64 { u64::MAX } else { (1 << width) - 1 };
+        match self.below(8) {
+            0 => 0,
+            1 => max,
+            _ => self.next() & max,
+        }
+    }
+}
+
+struct Generator<'d> {
+    typedefs: HashMap<&'d str, &'d ast::Decl>,
+    rng: Rng,
+}
+
+impl<'d> Generator<'d> {
+    /// Return the fields, constraints and parent of a packet or struct.
+    fn decl(&self, id: &str) -> Option<(&'d [ast::Field], &'d [ast::Constraint], Option<&'d str>)> {
+        match self.typedefs.get(id) {
+            Some(ast::Decl::Packet { fields, constraints, parent_id, .. })
+            | Some(ast::Decl::Struct { fields, constraints, parent_id, .. }) => {
+                Some((fields, constraints, parent_id.as_deref()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Return a random value of an enum, checksum or custom field
+    /// type, or `None` for struct types.
+    fn typed_value(&mut self, type_id: &str) -> Option<Value> {
+        match self.typedefs.get(type_id) {
+            Some(ast::Decl::Enum { tags, width, .. }) if tags.is_empty() => {
+                Some(Value::from(self.rng.bits(*width)))
+            }
+            Some(ast::Decl::Enum { tags, .. }) => {
+                Some(Value::String(tags[self.rng.below(tags.len())].id.to_string()))
+            }
+            Some(ast::Decl::CustomField { width: Some(width), .. })
+            | Some(ast::Decl::Checksum { width, .. }) => Some(Value::from(self.rng.bits(*width))),
+            _ => None,
+        }
+    }
+
+    /// Return random field values for a packet or struct, and for the
+    /// fields of its parents which are not constrained. Returns `None`
+    /// if a field cannot take random values.
+    fn values(&mut self, id: &str, depth: usize) -> Option<Map<String, Value>> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let mut path = vec![];
+        let mut current = Some(id);
+        while let Some(id) = current {
+            if path.contains(&id) {
+                return None;
+            }
+            path.push(id);
+            current = self.decl(id)?.2;
+        }
+
+        let mut constrained = HashSet::new();
+        let mut flattened = vec![];
+        for (index, id) in path.iter().enumerate() {
+            let (fields, constraints, _) = self.decl(id)?;
+            constrained.extend(constraints.iter().map(|c| c.id.as_str()));
+            for FlatField { field, value } in layout::flatten(&self.typedefs, fields) {
+                if let (Some(id), Some(_)) = (field.id(), value) {
+                    constrained.insert(id.as_str());
+                }
+                // Only the payload of the declaration itself takes a
+                // value; the payloads of the parents hold their children.
+                if index == 0
+                    || !matches!(field, ast::Field::Payload { .. } | ast::Field::Body { .. })
+                {
+                    flattened.push(field);
+                }
+            }
+        }
+
+        let mut values = Map::new();
+        for field in flattened {
+            let (key, value) = match field {
+                ast::Field::Scalar { id, .. } | ast::Field::Typedef { id, .. }
+                    if constrained.contains(id.as_str()) =>
+                {
+                    continue
+                }
+                ast::Field::Scalar { id, width, .. } => (id, Value::from(self.rng.bits(*width))),
+                ast::Field::Typedef { id, type_id, .. } => {
+                    match self.typedefs.get(type_id.as_str()) {
+                        // Checksums are computed by the encoder.
+                        Some(ast::Decl::Checksum { .. }) => continue,
+                        Some(ast::Decl::Packet { .. }) | Some(ast::Decl::Struct { .. }) => {
+                            (id, Value::Object(self.values(type_id, depth + 1)?))
+                        }
+                        _ => (id, self.typed_value(type_id)?),
+                    }
+                }
+                ast::Field::Array { id, width, type_id, size, .. } => {
+                    let count = size.unwrap_or_else(|| self.rng.below(MAX_LEN + 1));
+                    let mut elements = vec![];
+                    for _ in 0..count {
+                        elements.push(match (width, type_id) {
+                            (Some(width), _) => Value::from(self.rng.bits(*width)),
+                            (None, Some(type_id)) => match self.typed_value(type_id) {
+                                Some(value) => value,
+                                None => Value::Object(self.values(type_id, depth + 1)?),
+                            },
+                            (None, None) => return None,
+                        });
+                    }
+                    (id, Value::Array(elements))
+                }
+                ast::Field::Payload { .. } | ast::Field::Body { .. } => {
+                    let key = if matches!(field, ast::Field::Body { .. }) {
+                        "_body_"
+                    } else {
+                        "_payload_"
+                    };
+                    let len = self.rng.below(MAX_LEN + 1);
+                    let bytes: String =
+                        (0..len).map(|_| format!("{:02x}", self.rng.bits(8))).collect();
+                    values.insert(key.to_owned(), Value::String(bytes));
+                    continue;
+                }
+                _ => continue,
+            };
+            values.insert(key.to_string(), value);
+        }
+        Some(values)
+    }
+}
+
+/// Generate `count` random test vectors for each packet of the
+/// grammar. The vectors give the decoded field values, and are checked
+/// with [`vectors::check`].
+pub fn generate(grammar: &ast::Grammar, count: usize, seed: u64) -> Vec<TestVector> {
+    let mut generator = Generator {
+        typedefs: grammar
+            .declarations
+            .iter()
+            .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+            .collect(),
+        rng: Rng::new(seed),
+    };
+    let mut corpus = vec![];
+    for decl in &grammar.declarations {
+        let packet = match decl {
+            ast::Decl::Packet { id, .. } => id,
+            _ => continue,
+        };
+        for index in 0..count {
+            let fields = match generator.values(packet, 0) {
+                Some(fields) => fields,
+                None => break,
+            };
+            let bytes = match encoder::encode(grammar, packet, &Value::Object(fields)) {
+                Ok(bytes) => bytes,
+                Err(_) => continue,
+            };
+            let mut fields = Map::new();
+            match decoder::decode(grammar, packet, &bytes) {
+                Ok(decoded) if decoded.add_fields(&mut fields) == *packet => (),
+                _ => continue,
+            }
+            let vector = TestVector {
+                name: format!("{}_{}", packet, index),
+                packet: packet.to_string(),
+                fields,
+                bytes,
+            };
+            if vectors::check(grammar, &vector).is_ok() {
+                corpus.push(vector);
+            }
+        }
+    }
+    corpus
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_generate() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            struct Range { start: 16, end: 16 }
+            packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+            packet Write : Command (op = WRITE) { addr: 16, data: 8[] }
+            packet Read : Command (op = READ) { ranges: Range[2], _reserved_: 4, flags: 4 }
+            "#,
+        );
+        let corpus = generate(&grammar, 16, 1);
+        assert_eq!(corpus, generate(&grammar, 16, 1));
+        assert_ne!(corpus, generate(&grammar, 16, 2));
+        for packet in ["Write", "Read"] {
+            assert!(corpus.iter().any(|vector| vector.packet == packet));
+        }
+        for vector in &corpus {
+            assert_eq!(vectors::check(&grammar, vector), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Definition coverage.
+//!
+//! Counts the packets, structs and enum tags of a grammar exercised by
+//! a set of decoded packets, e.g. the test vectors of a grammar or the
+//! records of a btsnoop log, and reports the declarations that were
+//! never exercised. The JSON report has the layout:
+//!
+//! ```text
+//! coverage := {
+//!     "version": 1,
+//!     "decoded": integer,         // packets decoded
+//!     "failed": integer,          // packets which failed to decode
+//!     "declarations": [declaration],
+//!     "tags": [tag],
+//! }
+//!
+//! declaration := {
+//!     "id": string,
+//!     "kind": "packet" | "struct",
+//!     "count": integer,           // times the declaration was decoded
+//!     "children": [string],       // child declarations never decoded
+//! }
+//!
+//! tag := {
+//!     "enum": string,
+//!     "id": string,
+//!     "count": integer,
+//! }
+//! ```
+//!
+//! Declarations and tags are listed in declaration order. An enum tag
+//! is exercised when a field of the enum type decodes to the tag.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::ast;
+use crate::decoder;
+use crate::layout::{self, FlatField};
+
+/// Version of the JSON coverage layout.
+const COVERAGE_VERSION: u64 = 1;
+
+/// Coverage of a packet or struct declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeclCoverage<'d> {
+    pub id: &'d str,
+    pub kind: &'static str,
+    pub parent_id: Option<&'d str>,
+    pub count: usize,
+}
+
+/// Coverage of an enum tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagCoverage<'d> {
+    pub enum_id: &'d str,
+    pub id: &'d str,
+    pub count: usize,
+}
+
+/// Coverage of the declarations of a grammar.
+pub struct Coverage<'d> {
+    pub decls: Vec<DeclCoverage<'d>>,
+    pub tags: Vec<TagCoverage<'d>>,
+    pub decoded: usize,
+    pub failed: usize,
+    /// Enum type of the enum fields, by declaration and field.
+    enum_fields: HashMap<(&'d str, &'d str), &'d str>,
+}
+
+/// Add the enum fields of a declaration to `enum_fields`, inlining the
+/// groups.
+fn add_enum_fields<'d>(
+    decl_id: &'d str,
+    fields: &'d [ast::Field],
+    typedefs: &HashMap<&'d str, &'d ast::Decl>,
+    enum_fields: &mut HashMap<(&'d str, &'d str), &'d str>,
+) {
+    for FlatField { field, .. } in layout::flatten(typedefs, fields) {
+        if let ast::Field::Typedef { id, type_id, .. }
+        | ast::Field::Array { id, type_id: Some(type_id), .. } = field
+        {
+            if let Some(ast::Decl::Enum { .. }) = typedefs.get(type_id.as_str()) {
+                enum_fields.insert((decl_id, id), type_id);
+            }
+        }
+    }
+}
+
+impl<'d> Coverage<'d> {
+    pub fn new(grammar: &'d ast::Grammar) -> Self {
+        let typedefs: HashMap<&str, &ast::Decl> = grammar
+            .declarations
+            .iter()
+            .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+            .collect();
+        let mut decls = vec![];
+        let mut tags = vec![];
+        let mut enum_fields = HashMap::new();
+        for decl in &grammar.declarations {
+            match decl {
+                ast::Decl::Packet { id, fields, parent_id, .. }
+                | ast::Decl::Struct { id, fields, parent_id, .. } => {
+                    let kind =
+                        if matches!(decl, ast::Decl::Packet { .. }) { "packet" } else { "struct" };
+                    decls.push(DeclCoverage {
+                        id,
+                        kind,
+                        parent_id: parent_id.as_deref(),
+                        count: 0,
+                    });
+                    add_enum_fields(id, fields, &typedefs, &mut enum_fields);
+                }
+                ast::Decl::Enum { id, tags: enum_tags, .. } => {
+                    tags.extend(enum_tags.iter().map(|tag| TagCoverage {
+                        enum_id: id,
+                        id: &tag.id,
+                        count: 0,
+                    }))
+                }
+                _ => (),
+            }
+        }
+        Coverage { decls, tags, decoded: 0, failed: 0, enum_fields }
+    }
+
+    /// Count the declarations and tags exercised by a decoded packet.
+    pub fn add(&mut self, packet: &decoder::Packet) {
+        self.decoded += 1;
+        self.add_packet(packet);
+    }
+
+    /// Count a packet which failed to decode.
+    pub fn add_failure(&mut self) {
+        self.failed += 1;
+    }
+
+    fn add_packet(&mut self, packet: &decoder::Packet) {
+        if let Some(decl) = self.decls.iter_mut().find(|decl| decl.id == packet.id) {
+            decl.count += 1;
+        }
+        for (field_id, value) in &packet.fields {
+            self.add_value(&packet.id, field_id, value);
+        }
+        if let Some(child) = &packet.child {
+            self.add_packet(child);
+        }
+    }
+
+    fn add_value(&mut self, decl_id: &str, field_id: &str, value: &decoder::Value) {
+        match value {
+            decoder::Value::Tag(_, Some(tag_id)) => {
+                if let Some(enum_id) = self.enum_fields.get(&(decl_id, field_id)) {
+                    if let Some(tag) =
+                        self.tags.iter_mut().find(|tag| tag.enum_id == *enum_id && tag.id == tag_id)
+                    {
+                        tag.count += 1;
+                    }
+                }
+            }
+            decoder::Value::Array(elements) => {
+                for element in elements {
+                    self.add_value(decl_id, field_id, element);
+                }
+            }
+            decoder::Value::Struct(packet) => self.add_packet(packet),
+            _ => (),
+        }
+    }
+
+    /// Return the child declarations of `id` which were never decoded.
+    fn missing_children(&self, id: &str) -> Vec<&'d str> {
+        self.decls
+            .iter()
+            .filter(|decl| decl.parent_id == Some(id) && decl.count == 0)
+            .map(|decl| decl.id)
+            .collect()
+    }
+
+    /// Render the coverage as text: the counts of the declarations and
+    /// tags, followed by the summary.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let count = |count: usize| match count {
+            0 => "never".to_owned(),
+            _ => count.to_string(),
+        };
+        for decl in &self.decls {
+            out.push_str(&format!("{} {}: {}\n", decl.kind, decl.id, count(decl.count)));
+            let children = self.missing_children(decl.id);
+            if !children.is_empty() {
+                out.push_str(&format!("  children never decoded: {}\n", children.join(", ")));
+            }
+        }
+        for tag in &self.tags {
+            out.push_str(&format!("tag {}::{}: {}\n", tag.enum_id, tag.id, count(tag.count)));
+        }
+        let exercised = |counts: Vec<usize>| {
+            format!("{} of {}", counts.iter().filter(|count| **count > 0).count(), counts.len())
+        };
+        out.push_str(&format!(
+            "{} packets decoded, {} failed; declarations exercised: {}, tags exercised: {}\n",
+            self.decoded,
+            self.failed,
+            exercised(self.decls.iter().map(|decl| decl.count).collect()),
+            exercised(self.tags.iter().map(|tag| tag.count).collect()),
+        ));
+        out
+    }
+
+    /// Render the coverage in JSON.
+    pub fn to_json(&self) -> String {
+        let decls = self.decls.iter().map(|decl| {
+            let mut object = Map::new();
+            object.insert("id".to_owned(), Value::String(decl.id.to_owned()));
+            object.insert("kind".to_owned(), Value::String(decl.kind.to_owned()));
+            object.insert("count".to_owned(), Value::from(decl.count));
+            let children = self.missing_children(decl.id).into_iter().map(Value::from).collect();
+            object.insert("children".to_owned(), Value::Array(children));
+            Value::Object(object)
+        });
+        let tags = self.tags.iter().map(|tag| {
+            let mut object = Map::new();
+            object.insert("enum".to_owned(), Value::String(tag.enum_id.to_owned()));
+            object.insert("id".to_owned(), Value::String(tag.id.to_owned()));
+            object.insert("count".to_owned(), Value::from(tag.count));
+            Value::Object(object)
+        });
+
+        let mut object = Map::new();
+        object.insert("version".to_owned(), Value::from(COVERAGE_VERSION));
+        object.insert("decoded".to_owned(), Value::from(self.decoded));
+        object.insert("failed".to_owned(), Value::from(self.failed));
+        object.insert("declarations".to_owned(), Value::Array(decls.collect()));
+        object.insert("tags".to_owned(), Value::Array(tags.collect()));
+        let mut out = serde_json::to_string_pretty(&Value::Object(object)).unwrap();
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_coverage() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2, ERASE = 3 }
+            packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+            packet Write : Command (op = WRITE) { addr: 16 }
+            packet Read : Command (op = READ) { addr: 16 }
+            packet Erase : Command (op = ERASE) { ops: Op[] }
+            "#,
+        );
+        let mut coverage = Coverage::new(&grammar);
+        for bytes in [&[0x02, 0x02, 0x34, 0x12][..], &[0x03, 0x02, 0x01, 0x01]] {
+            coverage.add(&decoder::decode(&grammar, "Command", bytes).unwrap());
+        }
+        coverage.add_failure();
+
+        let counts: Vec<_> = coverage.decls.iter().map(|decl| (decl.id, decl.count)).collect();
+        assert_eq!(counts, vec![("Command", 2), ("Write", 1), ("Read", 0), ("Erase", 1)]);
+        let counts: Vec<_> = coverage.tags.iter().map(|tag| (tag.id, tag.count)).collect();
+        assert_eq!(counts, vec![("READ", 2), ("WRITE", 1), ("ERASE", 1)]);
+        assert!(coverage.to_text().contains("  children never decoded: Read\n"));
+        assert!(coverage.to_text().ends_with(
+            "2 packets decoded, 1 failed; declarations exercised: 3 of 4, tags exercised: 3 of 3\n"
+        ));
+    }
+}
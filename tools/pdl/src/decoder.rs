@@ -0,0 +1,841 @@
+//! Dynamic packet decoder.
+//!
+//! Decodes raw bytes against the declarations of a grammar, without
+//! generating code. Scalar fields are grouped in byte aligned chunks,
+//! read with the endianness of the grammar; the first field of a chunk
+//! occupies its least significant bits. The payload of a packet is
+//! decoded as the first child declaration whose constraints are
+//! satisfied by the values decoded so far.
+
+use serde_json::Map;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast;
+use crate::layout::{self, FlatField};
+
+/// Decoded value of a field.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Value {
+    Integer(u64),
+    /// Enum value, with the matching tag if any.
+    Tag(u64, Option<String>),
+    /// Payload which could not be matched to a child declaration.
+    Bytes(Vec<u8>),
+    Array(Vec<Value>),
+    Struct(Packet),
+}
+
+/// Decoded packet or struct.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub id: String,
+    pub fields: Vec<(String, Value)>,
+    pub child: Option<Box<Packet>>,
+}
+
+/// Parse a hexadecimal string. Whitespace and `:` separators are
+/// ignored, as well as an optional `0x` prefix.
+pub fn parse_hex(input: &str) -> Result<Vec<u8>, String> {
+    let input = input.trim();
+    let input = input.strip_prefix("0x").unwrap_or(input);
+    let digits: Vec<char> = input.chars().filter(|c| !c.is_whitespace() && *c != ':').collect();
+    if digits.len() & 1 != 0 {
+        return Err("odd number of hexadecimal digits".to_owned());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16)
+                .map_err(|_| format!("invalid hexadecimal byte '{}'", byte))
+        })
+        .collect()
+}
+
+/// Return the byte count added by a size modifier, e.g. `+2`.
+fn modifier_bytes(modifier: &Option<String>) -> u64 {
+    modifier.as_deref().and_then(|m| m.trim_start_matches('+').parse().ok()).unwrap_or(0)
+}
+
+/// Byte reader over the input data.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    fn take(&mut self, len: usize, what: &str) -> Result<&'a [u8], String> {
+        if self.remaining() < len {
+            return Err(format!(
+                "{}: expected {} bytes at offset {}, but only {} remain",
+                what,
+                len,
+                self.offset,
+                self.remaining()
+            ));
+        }
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(bytes)
+    }
+}
+
+/// Values decoded so far, visible to the constraints of child
+/// declarations: the integer value and the enum tag, if any.
+type Scope<'d> = HashMap<&'d str, (u64, Option<String>)>;
+
+struct Decoder<'d> {
+    typedefs: HashMap<&'d str, &'d ast::Decl>,
+    declarations: &'d [ast::Decl],
+    big_endian: bool,
+}
+
+impl<'d> Decoder<'d> {
+    fn new(grammar: &'d ast::Grammar) -> Self {
+        Decoder {
+            typedefs: grammar
+                .declarations
+                .iter()
+                .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+                .collect(),
+            declarations: &grammar.declarations,
+            big_endian: matches!(
+                grammar.endianness,
+                Some(ast::Endianness { value: ast::EndiannessValue::BigEndian, .. })
+            ),
+        }
+    }
+
+    /// Return the fields, constraints and parent of a packet or struct.
+    #[allow(clippy::type_complexity)]
+    fn decl(
+        &self,
+        id: &str,
+    ) -> Result<(&'d [ast::Field], &'d [ast::Constraint], Option<&'d str>), String> {
+        match self.typedefs.get(id) {
+            Some(ast::Decl::Packet { fields, constraints, parent_id, .. })
+            | Some(ast::Decl::Struct { fields, constraints, parent_id, .. }) => {
+                Ok((fields, constraints, parent_id.as_deref()))
+            }
+            Some(decl) => Err(format!("'{}' is not a packet or struct, but a {}", id, decl.kind())),
+            None => Err(format!("undeclared packet or struct '{}'", id)),
+        }
+    }
+
+    /// Flatten the fields of a declaration, inlining groups. Group
+    /// constraints are added to `constraints`.
+    fn flatten(
+        &self,
+        fields: &'d [ast::Field],
+        constraints: &mut HashMap<&'d str, &'d ast::Expr>,
+    ) -> Result<Vec<&'d ast::Field>, String> {
+        let mut flattened = vec![];
+        for FlatField { field, value } in layout::flatten(&self.typedefs, fields) {
+            match (field, value) {
+                (ast::Field::Group { group_id, .. }, _) => {
+                    return Err(format!("invalid group '{}'", group_id))
+                }
+                (_, Some(value)) => {
+                    constraints.entry(field.id().unwrap()).or_insert(value);
+                }
+                _ => (),
+            }
+            flattened.push(field);
+        }
+        Ok(flattened)
+    }
+
+    /// Return the tags of an enum declaration.
+    fn enum_tags(&self, type_id: &str) -> Option<&'d [ast::Tag]> {
+        match self.typedefs.get(type_id) {
+            Some(ast::Decl::Enum { tags, .. }) => Some(tags),
+            _ => None,
+        }
+    }
+
+    /// Return the bit width of a field read from a chunk of scalars,
+    /// or `None` for fields of variable or byte aligned size.
+    fn scalar_width(&self, field: &ast::Field) -> Option<usize> {
+        match field {
+            ast::Field::Checksum { .. } => Some(0),
+            ast::Field::Size { width, .. }
+            | ast::Field::Count { width, .. }
+            | ast::Field::Reserved { width, .. }
+            | ast::Field::Scalar { width, .. }
+            | ast::Field::Fixed { width: Some(width), .. } => Some(*width),
+            ast::Field::Fixed { enum_id: Some(type_id), .. }
+            | ast::Field::Typedef { type_id, .. } => {
+                layout::scalar_type_width(&self.typedefs, type_id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the static byte size of a list of fields, used to locate
+    /// the end of a payload or array of unknown size.
+    fn static_size(&self, fields: &[&'d ast::Field]) -> Result<usize, String> {
+        let mut bits = 0;
+        for field in fields {
+            bits += match field {
+                ast::Field::Padding { .. } => 0,
+                ast::Field::Array { width: Some(width), size: Some(size), .. } => width * size,
+                ast::Field::Array { type_id: Some(type_id), size: Some(size), .. } => {
+                    layout::scalar_type_width(&self.typedefs, type_id)
+                        .map(|width| width * size)
+                        .ok_or_else(|| {
+                            format!(
+                                "cannot determine the size of the fields following '{}'",
+                                type_id
+                            )
+                        })?
+                }
+                _ => self.scalar_width(field).ok_or_else(|| {
+                    "cannot determine the size of the fields following a variable size field"
+                        .to_owned()
+                })?,
+            }
+        }
+        Ok(bits / 8)
+    }
+
+    /// Read a chunk of scalar fields and add them to the decoded
+    /// packet.
+    fn read_chunk(
+        &self,
+        reader: &mut Reader,
+        chunk: &mut Vec<(&'d ast::Field, usize)>,
+        state: &mut DeclState<'d>,
+        scope: &mut Scope<'d>,
+    ) -> Result<(), String> {
+        let bits: usize = chunk.iter().map(|(_, width)| width).sum();
+        if bits > 64 {
+            return Err(format!("unsupported chunk of {} bits", bits));
+        }
+        let bytes = reader.take(bits / 8, "scalar fields")?;
+        let mut value: u64 = 0;
+        for (index, byte) in bytes.iter().enumerate() {
+            if self.big_endian {
+                value = (value << 8) | *byte as u64;
+            } else {
+                value |= (*byte as u64) << (8 * index);
+            }
+        }
+        for (field, width) in chunk.drain(..) {
+            let mask = if width == 64 { u64::MAX } else { (1 << width) - 1 };
+            let field_value = value & mask;
+            value = value.checked_shr(width as u32).unwrap_or(0);
+            state.scalar(self, field, field_value, scope)?;
+        }
+        Ok(())
+    }
+
+    /// Decode a packet or struct and its children. `path` lists the
+    /// child declarations to select, from the outermost. `stack` lists
+    /// the declarations being decoded: recursive declarations are
+    /// rejected by the linter, and are reported as an error instead of
+    /// being decoded.
+    fn decode(
+        &self,
+        id: &'d str,
+        data: &[u8],
+        scope: &mut Scope<'d>,
+        path: &[&'d str],
+        stack: &mut Vec<&'d str>,
+    ) -> Result<(Packet, usize), String> {
+        if stack.contains(&id) {
+            return Err(format!("recursive declaration '{}'", id));
+        }
+        stack.push(id);
+        let result = self.decode_fields(id, data, scope, path, stack);
+        stack.pop();
+        result
+    }
+
+    fn decode_fields(
+        &self,
+        id: &'d str,
+        data: &[u8],
+        scope: &mut Scope<'d>,
+        path: &[&'d str],
+        stack: &mut Vec<&'d str>,
+    ) -> Result<(Packet, usize), String> {
+        let (fields, constraints, _) = self.decl(id)?;
+        let mut group_constraints = HashMap::new();
+        let flattened = self.flatten(fields, &mut group_constraints)?;
+
+        let mut reader = Reader { data, offset: 0 };
+        let mut state = DeclState {
+            packet: Packet { id: id.to_owned(), fields: vec![], child: None },
+            sizes: HashMap::new(),
+            counts: HashMap::new(),
+            constraints: group_constraints,
+            payload: None,
+        };
+        let mut chunk = vec![];
+        let mut chunk_bits = 0;
+
+        for (index, field) in flattened.iter().enumerate() {
+            if let Some(width) = self.scalar_width(field) {
+                chunk.push((*field, width));
+                chunk_bits += width;
+                if chunk_bits % 8 == 0 {
+                    self.read_chunk(&mut reader, &mut chunk, &mut state, scope)?;
+                    chunk_bits = 0;
+                }
+                continue;
+            }
+            if chunk_bits != 0 {
+                return Err(format!("{}: field at {} is not byte aligned", id, field.loc()));
+            }
+            let following = &flattened[index + 1..];
+            match field {
+                ast::Field::Payload { .. } | ast::Field::Body { .. } => {
+                    let modifier = match field {
+                        ast::Field::Payload { size_modifier, .. } => modifier_bytes(size_modifier),
+                        _ => 0,
+                    };
+                    let size = match state.sizes.get("_payload_").or(state.sizes.get("_body_")) {
+                        Some(size) => size
+                            .checked_sub(modifier)
+                            .ok_or_else(|| format!("{}: invalid payload size", id))?
+                            as usize,
+                        None => reader.remaining().saturating_sub(self.static_size(following)?),
+                    };
+                    state.payload = Some(reader.take(size, "payload")?.to_vec());
+                }
+                ast::Field::Array {
+                    id: field_id,
+                    width,
+                    type_id,
+                    size_modifier: modifier,
+                    size,
+                    ..
+                } => {
+                    let padded = match following.first() {
+                        Some(ast::Field::Padding { width, .. }) => Some(*width),
+                        _ => None,
+                    };
+                    let count = size
+                        .map(|size| size as u64)
+                        .or_else(|| state.counts.get(field_id.as_str()).copied());
+                    let byte_size = match (count, state.sizes.get(field_id.as_str())) {
+                        (Some(_), _) => None,
+                        (None, Some(size)) => {
+                            Some(size.checked_sub(modifier_bytes(modifier)).ok_or_else(|| {
+                                format!("{}: invalid size for '{}'", id, field_id)
+                            })?)
+                        }
+                        (None, None) => Some(match padded {
+                            Some(padded) => padded as u64,
+                            None => reader.remaining().saturating_sub(self.static_size(following)?)
+                                as u64,
+                        }),
+                    };
+                    let start = reader.offset;
+                    let elements = self.array(
+                        &mut reader,
+                        field_id,
+                        *width,
+                        type_id,
+                        count,
+                        byte_size,
+                        stack,
+                    )?;
+                    if let Some(padded) = padded {
+                        let used = reader.offset - start;
+                        if used > padded {
+                            return Err(format!(
+                                "{}: array '{}' overflows its padding",
+                                id, field_id
+                            ));
+                        }
+                        reader.take(padded - used, "padding")?;
+                    }
+                    state.packet.fields.push((field_id.to_string(), Value::Array(elements)));
+                }
+                ast::Field::Typedef { id: field_id, type_id, .. } => {
+                    let data = &data[reader.offset..];
+                    let (value, len) = self.decode(type_id, data, &mut Scope::new(), &[], stack)?;
+                    reader.offset += len;
+                    state.packet.fields.push((field_id.to_string(), Value::Struct(value)));
+                }
+                ast::Field::Padding { .. } => (),
+                _ => return Err(format!("{}: unsupported field at {}", id, field.loc())),
+            }
+        }
+        if chunk_bits != 0 {
+            return Err(format!("{}: declaration is not byte aligned", id));
+        }
+
+        for constraint in constraints {
+            state.check(&constraint.id, &constraint.value, scope)?;
+        }
+
+        if let Some(payload) = state.payload.take() {
+            match self.child(id, scope, path)? {
+                Some(child_id) => {
+                    let rest = if path.is_empty() { path } else { &path[1..] };
+                    let (child, len) = self.decode(child_id, &payload, scope, rest, stack)?;
+                    if len != payload.len() {
+                        return Err(format!(
+                            "{}: {} unexpected trailing bytes in payload",
+                            child_id,
+                            payload.len() - len
+                        ));
+                    }
+                    state.packet.child = Some(Box::new(child));
+                }
+                None => state.packet.fields.push(("_payload_".to_owned(), Value::Bytes(payload))),
+            }
+        }
+        Ok((state.packet, reader.offset))
+    }
+
+    /// Decode the elements of an array, from either the element count
+    /// or the byte size of the array.
+    #[allow(clippy::too_many_arguments)]
+    fn array(
+        &self,
+        reader: &mut Reader,
+        field_id: &str,
+        width: Option<usize>,
+        type_id: &'d Option<ast::Symbol>,
+        count: Option<u64>,
+        byte_size: Option<u64>,
+        stack: &mut Vec<&'d str>,
+    ) -> Result<Vec<Value>, String> {
+        let end = match byte_size {
+            Some(size) => {
+                if (reader.remaining() as u64) < size {
+                    return Err(format!("{}: array size exceeds the remaining bytes", field_id));
+                }
+                reader.offset + size as usize
+            }
+            None => reader.data.len(),
+        };
+        let element_width = match (width, type_id) {
+            (Some(width), _) => Some(width),
+            (_, Some(type_id)) => layout::scalar_type_width(&self.typedefs, type_id),
+            _ => None,
+        };
+        let mut elements = vec![];
+        while count.map_or(reader.offset < end, |count| (elements.len() as u64) < count) {
+            let element = match element_width {
+                Some(0) => return Err(format!("{}: empty array element", field_id)),
+                Some(width) if width % 8 == 0 && width <= 64 => {
+                    let bytes = reader.take(width / 8, field_id)?;
+                    let mut value: u64 = 0;
+                    for (index, byte) in bytes.iter().enumerate() {
+                        if self.big_endian {
+                            value = (value << 8) | *byte as u64;
+                        } else {
+                            value |= (*byte as u64) << (8 * index);
+                        }
+                    }
+                    match type_id.as_deref().and_then(|type_id| self.enum_tags(type_id)) {
+                        Some(tags) => Value::Tag(value, tag_id(tags, value)),
+                        None => Value::Integer(value),
+                    }
+                }
+                Some(width) => {
+                    return Err(format!("{}: unsupported array element width {}", field_id, width))
+                }
+                None => {
+                    let type_id = type_id.as_deref().unwrap_or_default();
+                    let (value, len) = self.decode(
+                        type_id,
+                        &reader.data[reader.offset..end],
+                        &mut Scope::new(),
+                        &[],
+                        stack,
+                    )?;
+                    if len == 0 {
+                        return Err(format!("{}: empty array element", field_id));
+                    }
+                    reader.offset += len;
+                    Value::Struct(value)
+                }
+            };
+            elements.push(element);
+        }
+        if reader.offset > end {
+            return Err(format!("{}: array elements exceed the array size", field_id));
+        }
+        Ok(elements)
+    }
+
+    /// Select the child declaration used to decode the payload of the
+    /// declaration `id`.
+    fn child(
+        &self,
+        id: &str,
+        scope: &Scope<'d>,
+        path: &[&'d str],
+    ) -> Result<Option<&'d str>, String> {
+        let children = self.declarations.iter().filter_map(|decl| match decl {
+            ast::Decl::Packet { id: child_id, parent_id: Some(parent_id), constraints, .. }
+            | ast::Decl::Struct { id: child_id, parent_id: Some(parent_id), constraints, .. }
+                if parent_id == id =>
+            {
+                Some((child_id.as_str(), constraints))
+            }
+            _ => None,
+        });
+        if let Some(child_id) = path.first() {
+            return Ok(Some(child_id));
+        }
+        Ok(children
+            .filter(|(_, constraints)| {
+                constraints.iter().all(|c| matches_constraint(scope, &c.id, &c.value))
+            })
+            .map(|(child_id, _)| child_id)
+            .next())
+    }
+}
+
+/// Return the tag of an enum matching the value, if any.
+fn tag_id(tags: &[ast::Tag], value: u64) -> Option<String> {
+    tags.iter().find(|tag| tag.value as u64 == value).map(|tag| tag.id.to_string())
+}
+
+/// Check if a decoded value satisfies a constraint.
+fn matches_constraint(scope: &Scope, id: &str, expected: &ast::Expr) -> bool {
+    match (scope.get(id), expected) {
+        (Some((value, _)), ast::Expr::Integer { value: expected, .. }) => {
+            *value == *expected as u64
+        }
+        (Some((_, Some(tag))), ast::Expr::Identifier { name, .. }) => tag == name,
+        _ => false,
+    }
+}
+
+/// State of the declaration being decoded.
+struct DeclState<'d> {
+    packet: Packet,
+    /// Values of the size fields, indexed by sized field.
+    sizes: HashMap<&'d str, u64>,
+    /// Values of the count fields, indexed by counted field.
+    counts: HashMap<&'d str, u64>,
+    /// Constraints of the inlined groups.
+    constraints: HashMap<&'d str, &'d ast::Expr>,
+    payload: Option<Vec<u8>>,
+}
+
+impl<'d> DeclState<'d> {
+    /// Record the value of a field read from a chunk of scalars.
+    fn scalar(
+        &mut self,
+        decoder: &Decoder<'d>,
+        field: &'d ast::Field,
+        value: u64,
+        scope: &mut Scope<'d>,
+    ) -> Result<(), String> {
+        match field {
+            ast::Field::Size { field_id, .. } => {
+                self.sizes.insert(field_id, value);
+            }
+            ast::Field::Count { field_id, .. } => {
+                self.counts.insert(field_id, value);
+            }
+            ast::Field::Fixed { value: Some(expected), .. } if value != *expected as u64 => {
+                return Err(format!(
+                    "{}: invalid fixed value {:#x} at {}, expected {:#x}",
+                    self.packet.id,
+                    value,
+                    field.loc(),
+                    expected
+                ))
+            }
+            ast::Field::Fixed { enum_id: Some(enum_id), tag_id: Some(expected), .. } => {
+                let tags = decoder.enum_tags(enum_id).unwrap_or_default();
+                if tag_id(tags, value).as_deref() != Some(expected.as_str()) {
+                    return Err(format!(
+                        "{}: invalid fixed value {:#x} at {}, expected {}",
+                        self.packet.id,
+                        value,
+                        field.loc(),
+                        expected
+                    ));
+                }
+            }
+            ast::Field::Scalar { id, .. } => {
+                scope.insert(id, (value, None));
+                self.packet.fields.push((id.to_string(), Value::Integer(value)));
+                if let Some(expected) = self.constraints.get(id.as_str()) {
+                    self.check(id, expected, scope)?;
+                }
+            }
+            ast::Field::Typedef { id, type_id, .. } => {
+                let decoded = match decoder.enum_tags(type_id) {
+                    Some(tags) => {
+                        let tag = tag_id(tags, value);
+                        scope.insert(id, (value, tag.clone()));
+                        Value::Tag(value, tag)
+                    }
+                    None => {
+                        scope.insert(id, (value, None));
+                        Value::Integer(value)
+                    }
+                };
+                self.packet.fields.push((id.to_string(), decoded));
+                if let Some(expected) = self.constraints.get(id.as_str()) {
+                    self.check(id, expected, scope)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Check that a decoded field satisfies a constraint.
+    fn check(&self, id: &str, expected: &ast::Expr, scope: &Scope) -> Result<(), String> {
+        if matches_constraint(scope, id, expected) {
+            return Ok(());
+        }
+        let expected = match expected {
+            ast::Expr::Integer { value, .. } => value.to_string(),
+            ast::Expr::Identifier { name, .. } => name.to_string(),
+            _ => "?".to_owned(),
+        };
+        Err(format!("{}: constraint {} = {} is not satisfied", self.packet.id, id, expected))
+    }
+}
+
+/// Decode a prefix of `data` as the packet or struct `id`, and return
+/// the decoded packet and the number of bytes decoded. See [`decode`].
+pub fn decode_prefix(
+    grammar: &ast::Grammar,
+    id: &str,
+    data: &[u8],
+) -> Result<(Packet, usize), String> {
+    let decoder = Decoder::new(grammar);
+    let mut path = vec![];
+    let mut current = decoder.typedefs.get_key_value(id).map(|(id, _)| *id);
+    while let Some(id) = current {
+        if path.contains(&id) {
+            return Err(format!("recursive declaration '{}'", id));
+        }
+        path.push(id);
+        current = decoder
+            .decl(id)?
+            .2
+            .and_then(|parent| decoder.typedefs.get_key_value(parent).map(|(id, _)| *id));
+    }
+    let root = match path.pop() {
+        Some(root) => root,
+        None => return Err(format!("undeclared packet or struct '{}'", id)),
+    };
+    path.reverse();
+    decoder.decode(root, data, &mut Scope::new(), &path, &mut vec![])
+}
+
+/// Decode `data` as the packet or struct `id`.
+///
+/// If the declaration has a parent, the data is decoded from the
+/// outermost parent, selecting the declaration `id` on the way; the
+/// returned packet is the outermost parent.
+pub fn decode(grammar: &ast::Grammar, id: &str, data: &[u8]) -> Result<Packet, String> {
+    let (packet, len) = decode_prefix(grammar, id, data)?;
+    if len != data.len() {
+        return Err(format!("{} unexpected trailing bytes", data.len() - len));
+    }
+    Ok(packet)
+}
+
+impl Packet {
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        writeln!(f, "{} {{", self.id)?;
+        for (id, value) in &self.fields {
+            write!(f, "{:indent$}{}: ", "", id, indent = indent + 2)?;
+            value.fmt_indented(f, indent + 2)?;
+            writeln!(f)?;
+        }
+        if let Some(child) = &self.child {
+            write!(f, "{:indent$}", "", indent = indent + 2)?;
+            child.fmt_indented(f, indent + 2)?;
+            writeln!(f)?;
+        }
+        write!(f, "{:indent$}}}", "", indent = indent)
+    }
+}
+
+impl Packet {
+    /// Add the fields of the packet and of its children to `fields`, in
+    /// the JSON format of the encoder, and return the identifier of the
+    /// innermost child.
+    pub fn add_fields(&self, fields: &mut Map<String, serde_json::Value>) -> String {
+        for (id, value) in &self.fields {
+            fields.insert(id.clone(), value.to_json());
+        }
+        match &self.child {
+            Some(child) => child.add_fields(fields),
+            None => self.id.clone(),
+        }
+    }
+}
+
+impl Value {
+    /// Convert the value to the JSON format of the encoder.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Integer(value) | Value::Tag(value, None) => serde_json::Value::from(*value),
+            Value::Tag(_, Some(tag)) => serde_json::Value::String(tag.clone()),
+            Value::Bytes(bytes) => {
+                serde_json::Value::String(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+            }
+            Value::Array(elements) => {
+                serde_json::Value::Array(elements.iter().map(Value::to_json).collect())
+            }
+            Value::Struct(packet) => {
+                let mut fields = Map::new();
+                packet.add_fields(&mut fields);
+                serde_json::Value::Object(fields)
+            }
+        }
+    }
+
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{} ({:#x})", value, value),
+            Value::Tag(value, Some(tag)) => write!(f, "{} ({:#x})", tag, value),
+            Value::Tag(value, None) => write!(f, "{:#x} (unknown tag)", value),
+            Value::Bytes(bytes) => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                write!(f, "[{}]", bytes.join(" "))
+            }
+            Value::Array(elements) => {
+                if elements.is_empty() {
+                    return write!(f, "[]");
+                }
+                writeln!(f, "[")?;
+                for element in elements {
+                    write!(f, "{:indent$}", "", indent = indent + 2)?;
+                    element.fmt_indented(f, indent + 2)?;
+                    writeln!(f, ",")?;
+                }
+                write!(f, "{:indent$}]", "", indent = indent)
+            }
+            Value::Struct(packet) => packet.fmt_indented(f, indent),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("043e 0a:ff"), Ok(vec![0x04, 0x3e, 0x0a, 0xff]));
+        assert_eq!(parse_hex("0x0102"), Ok(vec![0x01, 0x02]));
+        assert!(parse_hex("043").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum EventCode : 8 { LE_META_EVENT = 0x3e }
+            enum SubeventCode : 8 { CONNECTION_COMPLETE = 0x01, ADVERTISING_REPORT = 0x02 }
+            packet Event {
+                event_code: EventCode,
+                _size_(_payload_): 8,
+                _payload_,
+            }
+            packet LeMetaEvent : Event (event_code = LE_META_EVENT) {
+                subevent_code: SubeventCode,
+                _payload_,
+            }
+            packet LeConnectionComplete : LeMetaEvent (subevent_code = CONNECTION_COMPLETE) {
+                status: 8,
+                connection_handle: 12,
+                _reserved_: 4,
+                _count_(data): 8,
+                data: 16[],
+            }
+            "#,
+        );
+
+        let packet =
+            decode(&grammar, "LeMetaEvent", &parse_hex("3e09 01 00 3412 02 aaaa bbbb").unwrap())
+                .unwrap();
+        assert_eq!(
+            packet.to_string(),
+            r#"Event {
+  event_code: LE_META_EVENT (0x3e)
+  LeMetaEvent {
+    subevent_code: CONNECTION_COMPLETE (0x1)
+    LeConnectionComplete {
+      status: 0 (0x0)
+      connection_handle: 564 (0x234)
+      data: [
+        43690 (0xaaaa),
+        48059 (0xbbbb),
+      ]
+    }
+  }
+}"#
+        );
+
+        // Unknown child: the payload is kept as bytes.
+        let packet = decode(&grammar, "Event", &parse_hex("3e02 0200").unwrap()).unwrap();
+        assert_eq!(
+            packet.to_string(),
+            r#"Event {
+  event_code: LE_META_EVENT (0x3e)
+  LeMetaEvent {
+    subevent_code: ADVERTISING_REPORT (0x2)
+    _payload_: [00]
+  }
+}"#
+        );
+
+        assert!(decode(&grammar, "Event", &parse_hex("3e05 01").unwrap()).is_err());
+        assert!(decode(&grammar, "Event", &parse_hex("3e01 02 ff").unwrap()).is_err());
+        assert!(decode(&grammar, "Unknown", &[]).is_err());
+    }
+
+    #[test]
+    fn test_decode_recursive() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            struct A { a: A }
+            packet P { x: 8, a: A }
+            "#,
+        );
+        assert_eq!(decode(&grammar, "P", &[0x01]), Err("recursive declaration 'A'".to_owned()));
+    }
+
+    #[test]
+    fn test_decode_empty_array_element() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            packet Q { z: 0[] }
+            "#,
+        );
+        assert_eq!(decode(&grammar, "Q", &[0x00]), Err("z: empty array element".to_owned()));
+        assert!(decode(&grammar, "Q", &[]).is_ok());
+    }
+}
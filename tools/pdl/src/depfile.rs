@@ -0,0 +1,61 @@
+//! Make dependency files.
+//!
+//! A depfile `<output>.d` lists the files read to generate an output,
+//! in the Make syntax understood by ninja and Soong:
+//!
+//! ```text
+//! out/hci.json: hci.pdl templates/hci.json.tera
+//! ```
+//!
+//! The build system reads it after the generation, and runs the
+//! generation again when any of the listed files changes.
+
+use std::io;
+use std::path::Path;
+
+/// Escape a path for a Make rule.
+fn escape(path: &str) -> String {
+    let mut escaped = String::new();
+    for c in path.chars() {
+        match c {
+            ' ' | '#' => escaped.push('\\'),
+            '$' => escaped.push('$'),
+            _ => (),
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Return the rule for `target`, depending on `inputs`.
+pub fn rule(target: &str, inputs: &[String]) -> String {
+    let mut rule = format!("{}:", escape(target));
+    for input in inputs {
+        rule.push(' ');
+        rule.push_str(&escape(input));
+    }
+    rule.push('\n');
+    rule
+}
+
+/// Write the depfile of `target`.
+pub fn write(path: &Path, target: &str, inputs: &[String]) -> io::Result<()> {
+    std::fs::write(path, rule(target, inputs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rule() {
+        assert_eq!(
+            rule("out/hci.json", &["hci.pdl".to_owned(), "hci.json.tera".to_owned()]),
+            "out/hci.json: hci.pdl hci.json.tera\n"
+        );
+        assert_eq!(
+            rule("out/a b.json", &["$dir/#1.pdl".to_owned()]),
+            "out/a\\ b.json: $$dir/\\#1.pdl\n"
+        );
+    }
+}
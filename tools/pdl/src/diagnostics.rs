@@ -0,0 +1,422 @@
+//! Structured diagnostics.
+//!
+//! [`Diagnostic`] is the representation of the parser and linter
+//! diagnostics exposed to library consumers, independent of
+//! `codespan_reporting`. Diagnostics are converted to:
+//!  - the terminal, with [`emit`], which renders them with
+//!    `codespan_reporting`,
+//!  - JSON, with [`Diagnostic::to_json`], one object per diagnostic,
+//!  - SARIF 2.1.0, with [`sarif_results`] and [`sarif_log`], for code
+//!    scanning tools,
+//!  - LSP, with [`Diagnostic::to_lsp`], for `publishDiagnostics`.
+//!
+//! Locations are byte ranges in the source files of a
+//! [`ast::SourceDatabase`]. Lines and columns are counted from 1 in
+//! JSON and SARIF, and from 0 in LSP, where columns are counted in
+//! UTF-16 code units.
+
+use codespan_reporting::diagnostic as codespan;
+use codespan_reporting::files::Files;
+use codespan_reporting::term::{self, termcolor};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::ops::Range;
+
+use crate::ast;
+
+/// Severity of a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+/// Source range attached to a diagnostic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    /// True for the location of the diagnostic, false for related
+    /// locations.
+    pub primary: bool,
+    pub file: ast::FileId,
+    pub range: Range<usize>,
+    pub message: String,
+}
+
+/// Replacement of a source range fixing a diagnostic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub message: String,
+    pub file: ast::FileId,
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+/// Parser or linter diagnostic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub code: Option<String>,
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        })
+    }
+}
+
+impl From<&codespan::Diagnostic<ast::FileId>> for Diagnostic {
+    fn from(diagnostic: &codespan::Diagnostic<ast::FileId>) -> Diagnostic {
+        let severity = match diagnostic.severity {
+            codespan::Severity::Bug | codespan::Severity::Error => Severity::Error,
+            codespan::Severity::Warning => Severity::Warning,
+            codespan::Severity::Note => Severity::Note,
+            codespan::Severity::Help => Severity::Help,
+        };
+        let labels = diagnostic
+            .labels
+            .iter()
+            .map(|label| Label {
+                primary: matches!(label.style, codespan::LabelStyle::Primary),
+                file: label.file_id,
+                range: label.range.clone(),
+                message: label.message.clone(),
+            })
+            .collect();
+        Diagnostic {
+            code: diagnostic.code.clone(),
+            severity,
+            message: diagnostic.message.clone(),
+            labels,
+            notes: diagnostic.notes.clone(),
+            suggestions: vec![],
+        }
+    }
+}
+
+/// Return the line and column of a byte offset, counted from 1.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let line = text[..line_start].matches('\n').count();
+    (line + 1, text[line_start..offset].chars().count() + 1)
+}
+
+/// Convert a byte offset to an LSP position. Columns are counted in
+/// UTF-16 code units.
+pub fn lsp_position(text: &str, offset: usize) -> Value {
+    let offset = offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let line = text[..line_start].matches('\n').count();
+    let character: usize = text[line_start..offset].chars().map(char::len_utf16).sum();
+    json!({ "line": line, "character": character })
+}
+
+/// Convert a byte range to an LSP range.
+pub fn lsp_range(text: &str, range: Range<usize>) -> Value {
+    json!({ "start": lsp_position(text, range.start), "end": lsp_position(text, range.end) })
+}
+
+fn source(sources: &ast::SourceDatabase, file: ast::FileId) -> &str {
+    sources.source(file).unwrap_or_default()
+}
+
+fn file_name(sources: &ast::SourceDatabase, file: ast::FileId) -> String {
+    sources.name(file).unwrap_or_default()
+}
+
+impl Diagnostic {
+    /// Return the primary label, or the first label if none is primary.
+    pub fn primary(&self) -> Option<&Label> {
+        self.labels.iter().find(|label| label.primary).or_else(|| self.labels.first())
+    }
+
+    /// Render the diagnostic in one line, in the GCC format
+    /// `file:line:column: severity[code]: message`, located at the
+    /// primary label.
+    pub fn to_oneline(&self, sources: &ast::SourceDatabase) -> String {
+        let location = match self.primary() {
+            Some(label) => {
+                let (line, column) = line_column(source(sources, label.file), label.range.start);
+                format!("{}:{}:{}: ", file_name(sources, label.file), line, column)
+            }
+            None => String::new(),
+        };
+        let code = self.code.as_ref().map(|code| format!("[{}]", code)).unwrap_or_default();
+        format!("{}{}{}: {}", location, self.severity, code, self.message)
+    }
+
+    /// Convert to a `codespan_reporting` diagnostic, for rendering.
+    /// Suggestions are rendered as notes.
+    pub fn to_codespan(&self) -> codespan::Diagnostic<ast::FileId> {
+        let severity = match self.severity {
+            Severity::Error => codespan::Severity::Error,
+            Severity::Warning => codespan::Severity::Warning,
+            Severity::Note => codespan::Severity::Note,
+            Severity::Help => codespan::Severity::Help,
+        };
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| {
+                let style = match label.primary {
+                    true => codespan::LabelStyle::Primary,
+                    false => codespan::LabelStyle::Secondary,
+                };
+                codespan::Label::new(style, label.file, label.range.clone())
+                    .with_message(label.message.clone())
+            })
+            .collect();
+        let suggestions = self.suggestions.iter().map(|suggestion| {
+            format!("help: {}: `{}`", suggestion.message, suggestion.replacement)
+        });
+        let mut diagnostic = codespan::Diagnostic::new(severity)
+            .with_message(self.message.clone())
+            .with_labels(labels)
+            .with_notes(self.notes.iter().cloned().chain(suggestions).collect());
+        if let Some(code) = &self.code {
+            diagnostic = diagnostic.with_code(code.clone());
+        }
+        diagnostic
+    }
+
+    /// Convert to a JSON object:
+    ///
+    /// ```text
+    /// {
+    ///     "code": string | null,
+    ///     "severity": "error" | "warning" | "note" | "help",
+    ///     "message": string,
+    ///     "labels": [{ "primary": bool, "message": string, location... }],
+    ///     "notes": [string],
+    ///     "suggestions": [{ "message": string, "replacement": string,
+    ///                       location... }],
+    /// }
+    /// ```
+    ///
+    /// where the location is made of the fields `file`, `start` and `end`,
+    /// the latter two holding the `offset`, `line` and `column` of the
+    /// range bounds.
+    pub fn to_json(&self, sources: &ast::SourceDatabase) -> Value {
+        let location = |file: ast::FileId, range: &Range<usize>| {
+            let text = source(sources, file);
+            let bound = |offset: usize| {
+                let (line, column) = line_column(text, offset);
+                json!({ "offset": offset, "line": line, "column": column })
+            };
+            (file_name(sources, file), bound(range.start), bound(range.end))
+        };
+        let labels: Vec<Value> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let (file, start, end) = location(label.file, &label.range);
+                json!({
+                    "primary": label.primary,
+                    "message": label.message,
+                    "file": file,
+                    "start": start,
+                    "end": end,
+                })
+            })
+            .collect();
+        let suggestions: Vec<Value> = self
+            .suggestions
+            .iter()
+            .map(|suggestion| {
+                let (file, start, end) = location(suggestion.file, &suggestion.range);
+                json!({
+                    "message": suggestion.message,
+                    "replacement": suggestion.replacement,
+                    "file": file,
+                    "start": start,
+                    "end": end,
+                })
+            })
+            .collect();
+        json!({
+            "code": self.code,
+            "severity": self.severity.to_string(),
+            "message": self.message,
+            "labels": labels,
+            "notes": self.notes,
+            "suggestions": suggestions,
+        })
+    }
+
+    /// Convert to an LSP diagnostic of the document `uri`, whose
+    /// content is `text`. Secondary labels are reported as related
+    /// information, and notes are appended to the message.
+    pub fn to_lsp(&self, uri: &str, text: &str) -> Value {
+        let severity = match self.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+            Severity::Note => 3,
+            Severity::Help => 4,
+        };
+        let primary = self.primary().map_or(0..0, |label| label.range.clone());
+        let related: Vec<Value> = self
+            .labels
+            .iter()
+            .filter(|label| !label.primary)
+            .map(|label| {
+                json!({
+                    "location": { "uri": uri, "range": lsp_range(text, label.range.clone()) },
+                    "message": label.message,
+                })
+            })
+            .collect();
+        let mut message = self.message.clone();
+        for note in &self.notes {
+            message.push('\n');
+            message.push_str(note);
+        }
+        let mut diagnostic = Map::new();
+        diagnostic.insert("range".to_owned(), lsp_range(text, primary));
+        diagnostic.insert("severity".to_owned(), Value::from(severity));
+        if let Some(code) = &self.code {
+            diagnostic.insert("code".to_owned(), Value::String(code.clone()));
+        }
+        diagnostic.insert("source".to_owned(), Value::from("pdl"));
+        diagnostic.insert("message".to_owned(), Value::String(message));
+        diagnostic.insert("relatedInformation".to_owned(), Value::Array(related));
+        Value::Object(diagnostic)
+    }
+}
+
+/// Render diagnostics on a terminal writer.
+pub fn emit(
+    writer: &mut dyn termcolor::WriteColor,
+    config: &term::Config,
+    sources: &ast::SourceDatabase,
+    diagnostics: &[Diagnostic],
+) {
+    for diagnostic in diagnostics {
+        _ = term::emit(writer, config, sources, &diagnostic.to_codespan());
+    }
+}
+
+/// Convert diagnostics to SARIF 2.1.0 results, to be gathered in a log
+/// with [`sarif_log`]. Diagnostics without a code are reported with the
+/// rule `pdl`.
+pub fn sarif_results(sources: &ast::SourceDatabase, diagnostics: &[Diagnostic]) -> Vec<Value> {
+    let region = |file: ast::FileId, range: &Range<usize>| {
+        let text = source(sources, file);
+        let (start_line, start_column) = line_column(text, range.start);
+        let (end_line, end_column) = line_column(text, range.end);
+        json!({
+            "artifactLocation": { "uri": file_name(sources, file) },
+            "region": {
+                "startLine": start_line,
+                "startColumn": start_column,
+                "endLine": end_line,
+                "endColumn": end_column,
+            },
+        })
+    };
+    diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let level = match diagnostic.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Note | Severity::Help => "note",
+            };
+            let locations: Vec<Value> = diagnostic
+                .primary()
+                .map(|label| json!({ "physicalLocation": region(label.file, &label.range) }))
+                .into_iter()
+                .collect();
+            let related: Vec<Value> = diagnostic
+                .labels
+                .iter()
+                .filter(|label| !label.primary)
+                .map(|label| {
+                    json!({
+                        "physicalLocation": region(label.file, &label.range),
+                        "message": { "text": label.message },
+                    })
+                })
+                .collect();
+            let fixes: Vec<Value> = diagnostic
+                .suggestions
+                .iter()
+                .map(|suggestion| {
+                    let location = region(suggestion.file, &suggestion.range);
+                    json!({
+                        "description": { "text": suggestion.message },
+                        "artifactChanges": [{
+                            "artifactLocation": location["artifactLocation"],
+                            "replacements": [{
+                                "deletedRegion": location["region"],
+                                "insertedContent": { "text": suggestion.replacement },
+                            }],
+                        }],
+                    })
+                })
+                .collect();
+            json!({
+                "ruleId": diagnostic.code.as_deref().unwrap_or("pdl"),
+                "level": level,
+                "message": { "text": diagnostic.message },
+                "locations": locations,
+                "relatedLocations": related,
+                "fixes": fixes,
+            })
+        })
+        .collect()
+}
+
+/// Return a SARIF 2.1.0 log with a single run of `pdl`.
+pub fn sarif_log(results: Vec<Value>) -> Value {
+    json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": { "driver": { "name": "pdl" } },
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let mut sources = ast::SourceDatabase::new();
+        let file = sources.add("test.pdl".to_owned(), "packet A {\n  a: 8,\n}\n".to_owned());
+        let diagnostic: Diagnostic = (&codespan::Diagnostic::warning()
+            .with_code("E1")
+            .with_message("unused field")
+            .with_labels(vec![
+                codespan::Label::primary(file, 13..14),
+                codespan::Label::secondary(file, 7..8).with_message("declared here"),
+            ]))
+            .into();
+        assert_eq!(diagnostic.severity, Severity::Warning);
+        assert_eq!(diagnostic.primary().map(|label| label.range.clone()), Some(13..14));
+        assert_eq!(Diagnostic::from(&diagnostic.to_codespan()), diagnostic);
+
+        let json = diagnostic.to_json(&sources);
+        assert_eq!(json["labels"][0]["start"]["line"], 2);
+        assert_eq!(json["labels"][0]["start"]["column"], 3);
+        let lsp = diagnostic.to_lsp("file:///test.pdl", "packet A {\n  a: 8,\n}\n");
+        assert_eq!(lsp["range"]["start"], json!({ "line": 1, "character": 2 }));
+        let sarif = sarif_log(sarif_results(&sources, &[diagnostic]));
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "E1");
+        assert_eq!(result["locations"][0]["physicalLocation"]["region"]["startLine"], 2);
+    }
+}
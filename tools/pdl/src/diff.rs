@@ -0,0 +1,295 @@
+//! Semantic diff between two grammars.
+//!
+//! Lists the declarations added (`+`), removed (`-`) or changed (`~`)
+//! between two grammars and, for changed declarations, the differences
+//! of their fields, enum tags, parent, and constraints. Fields are
+//! flattened with groups inlined and matched in order; the bit offset
+//! of a field, counted from the start of its declaration, is reported
+//! when known and changed.
+
+use std::fmt::Write;
+
+use crate::ast;
+use crate::compat::{constraints, Revision, Slot};
+
+/// Return the description of a field.
+fn describe(slot: &Slot) -> String {
+    match slot.id {
+        Some(id) => format!("{}: {}", id, slot.layout),
+        None => slot.layout.clone(),
+    }
+}
+
+/// Return the bit offsets of the fields, when known.
+fn offsets(slots: &[Slot]) -> Vec<Option<usize>> {
+    let mut offset = Some(0);
+    slots
+        .iter()
+        .map(|slot| {
+            let current = offset;
+            offset = offset.zip(slot.width).map(|(offset, width)| offset + width);
+            current
+        })
+        .collect()
+}
+
+fn offset(offset: Option<usize>) -> String {
+    offset.map(|offset| format!(" @{}", offset)).unwrap_or_default()
+}
+
+/// Diff the fields of two revisions of a declaration, matching the
+/// longest common subsequence of fields.
+fn diff_fields(old: &[Slot], new: &[Slot], lines: &mut Vec<String>) {
+    let same = |a: &Slot, b: &Slot| a.id == b.id && a.layout == b.layout;
+    let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if same(&old[i], &new[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (old_offsets, new_offsets) = (offsets(old), offsets(new));
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same(&old[i], &new[j]) {
+            if old_offsets[i] != new_offsets[j] {
+                lines.push(format!(
+                    "~ {} (bit offset {} -> {})",
+                    describe(&old[i]),
+                    old_offsets[i].map_or("?".to_owned(), |o| o.to_string()),
+                    new_offsets[j].map_or("?".to_owned(), |o| o.to_string())
+                ));
+            }
+            i += 1;
+            j += 1;
+        } else if i < old.len()
+            && j < new.len()
+            && old[i].id.is_some()
+            && old[i].id == new[j].id
+            && lengths[i + 1][j + 1] == lengths[i][j]
+        {
+            lines.push(format!(
+                "~ {}: {} -> {}{}",
+                old[i].id.unwrap_or_default(),
+                old[i].layout,
+                new[j].layout,
+                offset(new_offsets[j])
+            ));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            lines.push(format!("- {}{}", describe(&old[i]), offset(old_offsets[i])));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}{}", describe(&new[j]), offset(new_offsets[j])));
+            j += 1;
+        }
+    }
+}
+
+/// Diff two revisions of a declaration.
+fn diff_decl(
+    old_revision: &Revision,
+    new_revision: &Revision,
+    old: &ast::Decl,
+    new: &ast::Decl,
+) -> Vec<String> {
+    let mut lines = vec![];
+    match (old, new) {
+        (
+            ast::Decl::Enum { width: old_width, tags: old_tags, .. },
+            ast::Decl::Enum { width: new_width, tags: new_tags, .. },
+        ) => {
+            if old_width != new_width {
+                lines.push(format!("~ width: {} -> {}", old_width, new_width));
+            }
+            for old_tag in old_tags {
+                match new_tags.iter().find(|tag| tag.id == old_tag.id) {
+                    Some(new_tag) if new_tag.value != old_tag.value => lines.push(format!(
+                        "~ {}: {:#x} -> {:#x}",
+                        old_tag.id, old_tag.value, new_tag.value
+                    )),
+                    Some(_) => (),
+                    None => lines.push(format!("- {} = {:#x}", old_tag.id, old_tag.value)),
+                }
+            }
+            for new_tag in new_tags {
+                if !old_tags.iter().any(|tag| tag.id == new_tag.id) {
+                    lines.push(format!("+ {} = {:#x}", new_tag.id, new_tag.value));
+                }
+            }
+        }
+        (
+            ast::Decl::Checksum { width: old_width, function: old_function, .. },
+            ast::Decl::Checksum { width: new_width, function: new_function, .. },
+        ) => {
+            if old_width != new_width {
+                lines.push(format!("~ width: {} -> {}", old_width, new_width));
+            }
+            if old_function != new_function {
+                lines.push(format!("~ function: {} -> {}", old_function, new_function));
+            }
+        }
+        (
+            ast::Decl::CustomField { width: old_width, function: old_function, .. },
+            ast::Decl::CustomField { width: new_width, function: new_function, .. },
+        ) => {
+            if old_width != new_width {
+                lines.push(format!("~ width: {:?} -> {:?}", old_width, new_width));
+            }
+            if old_function != new_function {
+                lines.push(format!("~ function: {} -> {}", old_function, new_function));
+            }
+        }
+        (
+            ast::Decl::Packet {
+                fields: old_fields,
+                constraints: old_constraints,
+                parent_id: old_parent,
+                ..
+            }
+            | ast::Decl::Struct {
+                fields: old_fields,
+                constraints: old_constraints,
+                parent_id: old_parent,
+                ..
+            },
+            ast::Decl::Packet {
+                fields: new_fields,
+                constraints: new_constraints,
+                parent_id: new_parent,
+                ..
+            }
+            | ast::Decl::Struct {
+                fields: new_fields,
+                constraints: new_constraints,
+                parent_id: new_parent,
+                ..
+            },
+        ) => {
+            if old_parent != new_parent {
+                lines.push(format!(
+                    "~ parent: {} -> {}",
+                    old_parent.as_deref().unwrap_or("none"),
+                    new_parent.as_deref().unwrap_or("none")
+                ));
+            }
+            let (old_constraints, new_constraints) =
+                (constraints(old_constraints), constraints(new_constraints));
+            if old_constraints != new_constraints {
+                lines
+                    .push(format!("~ constraints: ({}) -> ({})", old_constraints, new_constraints));
+            }
+            let old_slots = old_revision.slots(old_fields);
+            let new_slots = new_revision.slots(new_fields);
+            diff_fields(&old_slots, &new_slots, &mut lines);
+        }
+        (
+            ast::Decl::Group { constraints: old_constraints, fields: old_fields, .. },
+            ast::Decl::Group { constraints: new_constraints, fields: new_fields, .. },
+        ) => {
+            let (old_constraints, new_constraints) =
+                (constraints(old_constraints), constraints(new_constraints));
+            if old_constraints != new_constraints {
+                lines.push(format!("~ defaults: ({}) -> ({})", old_constraints, new_constraints));
+            }
+            let old_slots = old_revision.slots(old_fields);
+            let new_slots = new_revision.slots(new_fields);
+            diff_fields(&old_slots, &new_slots, &mut lines);
+        }
+        _ => lines.push(format!("~ kind: {} -> {}", old.kind(), new.kind())),
+    }
+    lines
+}
+
+/// Return the semantic diff between two grammars, in the order of the
+/// declarations of the old grammar, followed by the added declarations.
+pub fn diff(old: &ast::Grammar, new: &ast::Grammar) -> String {
+    let old_revision = Revision::new(old);
+    let new_revision = Revision::new(new);
+    let mut out = String::new();
+
+    if old.endianness.as_ref().map(|e| format!("{:?}", e.value))
+        != new.endianness.as_ref().map(|e| format!("{:?}", e.value))
+    {
+        writeln!(out, "~ endianness").unwrap();
+    }
+    for old_decl in &old.declarations {
+        let id = match old_decl.id() {
+            Some(id) => id,
+            None => continue,
+        };
+        match new_revision.typedefs.get(id.as_str()) {
+            None => writeln!(out, "- {} {}", old_decl.kind(), id).unwrap(),
+            Some(new_decl) => {
+                let lines = diff_decl(&old_revision, &new_revision, old_decl, new_decl);
+                if !lines.is_empty() {
+                    writeln!(out, "~ {} {}", old_decl.kind(), id).unwrap();
+                    for line in lines {
+                        writeln!(out, "    {}", line).unwrap();
+                    }
+                }
+            }
+        }
+    }
+    for new_decl in &new.declarations {
+        match new_decl.id() {
+            Some(id) if !old_revision.typedefs.contains_key(id.as_str()) => {
+                writeln!(out, "+ {} {}", new_decl.kind(), id).unwrap()
+            }
+            _ => (),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_diff() {
+        let old = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2, ERASE = 3 }
+            packet Command { op: Op, length: 8, _payload_ }
+            packet Read : Command (op = READ) { handle: 16, offset: 16 }
+            packet Write : Command (op = WRITE) { handle: 16, data: 8[] }
+            packet Erase : Command (op = ERASE) { handle: 16 }
+            "#,
+        );
+        let new = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 4, FLUSH = 5 }
+            packet Command { op: Op, length: 16, _payload_ }
+            packet Read : Command (op = READ) { handle: 16, _reserved_: 8, offset: 16 }
+            packet Write : Command (op = WRITE) { handle: 16, data: 8[] }
+            packet Flush : Command (op = FLUSH) { }
+            "#,
+        );
+        assert_eq!(
+            diff(&old, &new),
+            r#"~ enum Op
+    ~ WRITE: 0x2 -> 0x4
+    - ERASE = 0x3
+    + FLUSH = 0x5
+~ packet Command
+    ~ length: 8 -> 16 @8
+    ~ _payload_ (bit offset 16 -> 24)
+~ packet Read
+    + _reserved_ : 8 @16
+    ~ offset: 16 (bit offset 16 -> 24)
+- packet Erase
++ packet Flush
+"#
+        );
+        assert_eq!(diff(&old, &old), "");
+    }
+}
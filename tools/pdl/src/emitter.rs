@@ -0,0 +1,302 @@
+//! Diagnostics rendering.
+//!
+//! Diagnostics are rendered on stderr in one of five formats:
+//!  - `full`: source snippets with the labels and notes,
+//!  - `short`: the source lines of the labels only,
+//!  - `oneline`: one line per diagnostic, in the GCC format
+//!    `file:line:column: severity[code]: message` parsed by editors,
+//!    e.g. with the vim `errorformat` or the emacs compilation mode,
+//!  - `json`: one JSON object per line and diagnostic, see
+//!    [`diagnostics::Diagnostic::to_json`],
+//!  - `sarif`: a single SARIF log gathering the diagnostics of the
+//!    run, written by [`Emitter::finish`].
+//!
+//! In the terminal formats, errors beyond the `--max-errors` limit are
+//! not rendered. With a limit, the `full` and `short` formats also
+//! fold diagnostics similar to [`MAX_SIMILAR`] diagnostics already
+//! rendered into a single note. The totals are printed by
+//! [`Emitter::finish`] when diagnostics were hidden. The formats
+//! parsed by tools, `oneline`, JSON and SARIF, never fold diagnostics,
+//! and JSON and SARIF always include every diagnostic.
+
+use codespan_reporting::diagnostic::{Diagnostic, Severity};
+use codespan_reporting::term::{self, termcolor};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::IsTerminal;
+
+use crate::ast;
+use crate::diagnostics;
+use crate::report::diagnostic_key;
+
+/// Number of similar diagnostics rendered before folding the others.
+pub const MAX_SIMILAR: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Full,
+    Short,
+    Oneline,
+    Json,
+    Sarif,
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "full" => Ok(Self::Full),
+            "short" => Ok(Self::Short),
+            "oneline" => Ok(Self::Oneline),
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            _ => Err(format!(
+                "could not parse {:?}, valid options are 'full', 'short', 'oneline', 'json', \
+                 'sarif'.",
+                input
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Always,
+    Never,
+    Auto,
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            "auto" => Ok(Self::Auto),
+            _ => Err(format!(
+                "could not parse {:?}, valid options are 'always', 'never', 'auto'.",
+                input
+            )),
+        }
+    }
+}
+
+/// Number of diagnostics emitted, rendered or not.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Totals {
+    errors: usize,
+    warnings: usize,
+    rendered_errors: usize,
+    hidden: usize,
+}
+
+/// Number of folded diagnostics, by diagnostic key.
+type Folded = BTreeMap<String, (Severity, usize)>;
+
+/// Renderer of the diagnostics on stderr.
+pub struct Emitter {
+    format: ErrorFormat,
+    config: term::Config,
+    color: termcolor::ColorChoice,
+    /// Maximum number of errors rendered.
+    max_errors: Option<usize>,
+    /// Fold the diagnostics similar to [`MAX_SIMILAR`] diagnostics
+    /// already rendered.
+    fold_similar: bool,
+    /// SARIF results of the diagnostics emitted so far.
+    sarif: RefCell<Vec<Value>>,
+    totals: RefCell<Totals>,
+    /// Number of diagnostics emitted so far, by diagnostic key.
+    similar: RefCell<HashMap<String, usize>>,
+}
+
+fn severity_name(severity: Severity, count: usize) -> String {
+    let name = match severity {
+        Severity::Bug => "bug",
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    };
+    if count == 1 {
+        name.to_owned()
+    } else {
+        format!("{}s", name)
+    }
+}
+
+impl Emitter {
+    pub fn new(format: ErrorFormat, color: Color, max_errors: Option<usize>) -> Emitter {
+        let display_style = match format {
+            ErrorFormat::Full | ErrorFormat::Json | ErrorFormat::Sarif => term::DisplayStyle::Rich,
+            ErrorFormat::Short => term::DisplayStyle::Medium,
+            ErrorFormat::Oneline => term::DisplayStyle::Short,
+        };
+        // termcolor does not check whether the stream is a terminal.
+        let color = match color {
+            Color::Always => termcolor::ColorChoice::Always,
+            Color::Auto if std::io::stderr().is_terminal() => termcolor::ColorChoice::Auto,
+            Color::Never | Color::Auto => termcolor::ColorChoice::Never,
+        };
+        Emitter {
+            format,
+            config: term::Config { display_style, ..Default::default() },
+            color,
+            max_errors,
+            fold_similar: max_errors.is_some()
+                && matches!(format, ErrorFormat::Full | ErrorFormat::Short),
+            sarif: RefCell::new(vec![]),
+            totals: RefCell::new(Totals::default()),
+            similar: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Select the diagnostics to render, updating the totals. Returns
+    /// the selected diagnostics, and the number of folded diagnostics
+    /// by diagnostic key.
+    fn select<'a>(
+        &self,
+        diagnostics: &'a [Diagnostic<ast::FileId>],
+    ) -> (Vec<&'a Diagnostic<ast::FileId>>, Folded) {
+        let mut totals = self.totals.borrow_mut();
+        let mut similar = self.similar.borrow_mut();
+        let mut selected = vec![];
+        let mut folded = BTreeMap::new();
+        for diagnostic in diagnostics {
+            let error = matches!(diagnostic.severity, Severity::Bug | Severity::Error);
+            match diagnostic.severity {
+                Severity::Bug | Severity::Error => totals.errors += 1,
+                Severity::Warning => totals.warnings += 1,
+                _ => (),
+            }
+            if error && matches!(self.max_errors, Some(max) if totals.rendered_errors >= max) {
+                totals.hidden += 1;
+                continue;
+            }
+            if self.fold_similar {
+                let key = diagnostic_key(diagnostic);
+                let count = similar.entry(key.clone()).or_insert(0);
+                *count += 1;
+                if *count > MAX_SIMILAR {
+                    folded.entry(key).or_insert((diagnostic.severity, 0)).1 += 1;
+                    totals.hidden += 1;
+                    continue;
+                }
+            }
+            if error {
+                totals.rendered_errors += 1;
+            }
+            selected.push(diagnostic);
+        }
+        (selected, folded)
+    }
+
+    /// Render the diagnostics on stderr.
+    pub fn emit(&self, sources: &ast::SourceDatabase, diagnostics: &[Diagnostic<ast::FileId>]) {
+        if !matches!(self.format, ErrorFormat::Json | ErrorFormat::Sarif) {
+            return self.emit_terminal(sources, diagnostics);
+        }
+        let diagnostics: Vec<_> = diagnostics.iter().map(diagnostics::Diagnostic::from).collect();
+        match self.format {
+            ErrorFormat::Json => {
+                for diagnostic in diagnostics {
+                    eprintln!("{}", diagnostic.to_json(sources));
+                }
+            }
+            ErrorFormat::Sarif => {
+                self.sarif.borrow_mut().extend(diagnostics::sarif_results(sources, &diagnostics))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Render the diagnostics of a terminal format, folding similar
+    /// diagnostics and skipping errors beyond the limit.
+    fn emit_terminal(
+        &self,
+        sources: &ast::SourceDatabase,
+        diagnostics: &[Diagnostic<ast::FileId>],
+    ) {
+        let (selected, folded) = self.select(diagnostics);
+        let diagnostics: Vec<_> = selected.into_iter().map(diagnostics::Diagnostic::from).collect();
+        let writer = termcolor::StandardStream::stderr(self.color);
+        diagnostics::emit(&mut writer.lock(), &self.config, sources, &diagnostics);
+        for (key, (severity, count)) in folded {
+            eprintln!(
+                "note: and {} more similar {} ({})",
+                count,
+                severity_name(severity, count),
+                key
+            );
+        }
+    }
+
+    /// Write the SARIF log of the run, when the diagnostics are
+    /// rendered in SARIF, or the totals when diagnostics were hidden.
+    pub fn finish(&self) {
+        if self.format == ErrorFormat::Sarif {
+            let results = self.sarif.take();
+            eprintln!("{}", diagnostics::sarif_log(results));
+        }
+        let totals = *self.totals.borrow();
+        if totals.hidden > 0 {
+            eprintln!(
+                "{} {} and {} {} emitted, {} not shown",
+                totals.errors,
+                severity_name(Severity::Error, totals.errors),
+                totals.warnings,
+                severity_name(Severity::Warning, totals.warnings),
+                totals.hidden
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!("oneline".parse::<ErrorFormat>(), Ok(ErrorFormat::Oneline));
+        assert_eq!("Full".parse::<ErrorFormat>(), Ok(ErrorFormat::Full));
+        assert_eq!("sarif".parse::<ErrorFormat>(), Ok(ErrorFormat::Sarif));
+        assert!("gcc".parse::<ErrorFormat>().is_err());
+        assert_eq!("never".parse::<Color>(), Ok(Color::Never));
+        assert!("yes".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let emitter = Emitter::new(ErrorFormat::Short, Color::Never, Some(2));
+        let mut diagnostics = vec![];
+        for index in 0..7 {
+            diagnostics
+                .push(Diagnostic::warning().with_message(format!("unused field `f{}`", index)));
+        }
+        for _ in 0..3 {
+            diagnostics.push(Diagnostic::error().with_message("undeclared type"));
+        }
+        let (selected, folded) = emitter.select(&diagnostics);
+        assert_eq!(selected.len(), MAX_SIMILAR + 2);
+        assert_eq!(folded.get("unused field `_`"), Some(&(Severity::Warning, 2)));
+        assert_eq!(
+            *emitter.totals.borrow(),
+            Totals { errors: 3, warnings: 7, rendered_errors: 2, hidden: 3 }
+        );
+
+        // Without a limit, or in the oneline format, the similar
+        // diagnostics are not folded.
+        for emitter in [
+            Emitter::new(ErrorFormat::Short, Color::Never, None),
+            Emitter::new(ErrorFormat::Oneline, Color::Never, Some(10)),
+        ] {
+            let (selected, folded) = emitter.select(&diagnostics);
+            assert_eq!(selected.len(), 10);
+            assert!(folded.is_empty());
+        }
+    }
+}
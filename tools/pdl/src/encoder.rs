@@ -0,0 +1,549 @@
+//! Dynamic packet encoder.
+//!
+//! Builds the bytes of a packet from a JSON object giving the values
+//! of its fields, and of the fields of its parents:
+//!
+//! ```json
+//! { "subevent_code": "CONNECTION_COMPLETE", "status": 0, "data": [1, 2] }
+//! ```
+//!
+//! Scalar fields take integers, or strings with a `0x` prefix; enum
+//! fields take tag names or integers; struct fields take objects, and
+//! arrays take lists of elements. Payloads which are not described by
+//! a child declaration take hexadecimal strings.
+//!
+//! Fixed and reserved fields, and fields constrained by the selected
+//! child declarations, are filled in automatically, as well as size
+//! and count fields, and checksums with a known algorithm. The value of
+//! any size or count field can be forced with keys of the form
+//! `_size_(field)` or `_count_(field)`, e.g. to craft invalid packets.
+
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+use crate::ast;
+use crate::decoder::parse_hex;
+use crate::layout::{self, FlatField};
+
+/// Compute the checksum of the named declaration, for the algorithms
+/// used in the Bluetooth packet definitions.
+fn checksum(id: &str, data: &[u8]) -> Option<u64> {
+    match id {
+        // L2CAP frame check sequence, CRC-16 with polynomial
+        // x^16 + x^15 + x^2 + 1.
+        "Fcs" => {
+            let mut crc: u16 = 0;
+            for byte in data {
+                crc ^= *byte as u16;
+                for _ in 0..8 {
+                    crc = if crc & 1 != 0 { (crc >> 1) ^ 0xa001 } else { crc >> 1 };
+                }
+            }
+            Some(crc as u64)
+        }
+        "SimpleSum" => Some(data.iter().map(|b| *b as u64).sum::<u64>() & 0xffff),
+        _ => None,
+    }
+}
+
+/// Return the integer value of a JSON number or `0x` prefixed string.
+pub fn integer(value: &Value) -> Option<u64> {
+    match value {
+        Value::String(s) => u64::from_str_radix(s.strip_prefix("0x")?, 16).ok(),
+        _ => value.as_u64(),
+    }
+}
+
+/// Byte writer, packing scalar fields in byte aligned chunks.
+struct Writer {
+    bytes: Vec<u8>,
+    chunk: u64,
+    chunk_bits: usize,
+    big_endian: bool,
+}
+
+impl Writer {
+    fn write_bits(&mut self, value: u64, width: usize) -> Result<(), String> {
+        if self.chunk_bits + width > 64 {
+            return Err(format!("unsupported chunk of {} bits", self.chunk_bits + width));
+        }
+        self.chunk |= value.checked_shl(self.chunk_bits as u32).unwrap_or(0);
+        self.chunk_bits += width;
+        let (len, remainder) = (self.chunk_bits / 8, self.chunk_bits % 8);
+        if remainder == 0 {
+            for index in 0..len {
+                let shift = if self.big_endian { len - 1 - index } else { index };
+                self.bytes.push((self.chunk >> (8 * shift)) as u8);
+            }
+            self.chunk = 0;
+            self.chunk_bits = 0;
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8], what: &str) -> Result<(), String> {
+        if self.chunk_bits != 0 {
+            return Err(format!("{} is not byte aligned", what));
+        }
+        self.bytes.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+struct Encoder<'d> {
+    typedefs: HashMap<&'d str, &'d ast::Decl>,
+    big_endian: bool,
+}
+
+impl<'d> Encoder<'d> {
+    fn new(grammar: &'d ast::Grammar) -> Self {
+        Encoder {
+            typedefs: grammar
+                .declarations
+                .iter()
+                .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+                .collect(),
+            big_endian: matches!(
+                grammar.endianness,
+                Some(ast::Endianness { value: ast::EndiannessValue::BigEndian, .. })
+            ),
+        }
+    }
+
+    fn writer(&self) -> Writer {
+        Writer { bytes: vec![], chunk: 0, chunk_bits: 0, big_endian: self.big_endian }
+    }
+
+    /// Return the fields, constraints and parent of a packet or struct.
+    #[allow(clippy::type_complexity)]
+    fn decl(
+        &self,
+        id: &str,
+    ) -> Result<(&'d [ast::Field], &'d [ast::Constraint], Option<&'d str>), String> {
+        match self.typedefs.get(id) {
+            Some(ast::Decl::Packet { fields, constraints, parent_id, .. })
+            | Some(ast::Decl::Struct { fields, constraints, parent_id, .. }) => {
+                Ok((fields, constraints, parent_id.as_deref()))
+            }
+            Some(decl) => Err(format!("'{}' is not a packet or struct, but a {}", id, decl.kind())),
+            None => Err(format!("undeclared packet or struct '{}'", id)),
+        }
+    }
+
+    /// Flatten the fields of a declaration, inlining groups and adding
+    /// the group constraints to `constraints`, unless already set by a
+    /// child declaration.
+    fn flatten(
+        &self,
+        fields: &'d [ast::Field],
+        constraints: &mut HashMap<&'d str, &'d ast::Expr>,
+    ) -> Result<Vec<&'d ast::Field>, String> {
+        let mut flattened = vec![];
+        for FlatField { field, value } in layout::flatten(&self.typedefs, fields) {
+            match (field, value) {
+                (ast::Field::Group { group_id, .. }, _) => {
+                    return Err(format!("invalid group '{}'", group_id))
+                }
+                (_, Some(value)) => {
+                    constraints.entry(field.id().unwrap()).or_insert(value);
+                }
+                _ => (),
+            }
+            flattened.push(field);
+        }
+        Ok(flattened)
+    }
+
+    /// Return the integer value of an enum tag or integer.
+    fn enum_value(&self, type_id: &str, value: &Value) -> Result<u64, String> {
+        let tags = match self.typedefs.get(type_id) {
+            Some(ast::Decl::Enum { tags, .. }) => tags,
+            _ => {
+                return integer(value).ok_or_else(|| format!("invalid {} value {}", type_id, value))
+            }
+        };
+        match value.as_str().and_then(|name| tags.iter().find(|tag| tag.id == name)) {
+            Some(tag) => Ok(tag.value as u64),
+            None => integer(value).ok_or_else(|| format!("invalid {} value {}", type_id, value)),
+        }
+    }
+
+    /// Encode the elements of an array.
+    fn array(
+        &self,
+        field_id: &str,
+        width: Option<usize>,
+        type_id: &Option<ast::Symbol>,
+        value: &Value,
+    ) -> Result<(Vec<u8>, usize), String> {
+        let elements = match value {
+            Value::Array(elements) => elements,
+            Value::String(hex) if width == Some(8) => {
+                let bytes = parse_hex(hex)?;
+                let count = bytes.len();
+                return Ok((bytes, count));
+            }
+            _ => return Err(format!("expected a list of elements for '{}'", field_id)),
+        };
+        let element_width = match (width, type_id) {
+            (Some(width), _) => Some(width),
+            (_, Some(type_id)) => layout::scalar_type_width(&self.typedefs, type_id),
+            _ => None,
+        };
+        let mut writer = self.writer();
+        for element in elements {
+            match (element_width, type_id) {
+                (Some(width), _) => {
+                    let value = match type_id {
+                        Some(type_id) => self.enum_value(type_id, element)?,
+                        None => integer(element).ok_or_else(|| {
+                            format!("invalid element {} in '{}'", element, field_id)
+                        })?,
+                    };
+                    check_width(field_id, value, width)?;
+                    writer.write_bits(value, width)?;
+                }
+                (None, Some(type_id)) => {
+                    let bytes = self.encode(type_id, element)?;
+                    writer.write_bytes(&bytes, field_id)?;
+                }
+                (None, None) => unreachable!(),
+            }
+        }
+        if writer.chunk_bits != 0 {
+            return Err(format!("array '{}' is not byte aligned", field_id));
+        }
+        Ok((writer.bytes, elements.len()))
+    }
+
+    /// Encode the fields of a single declaration, with the payload
+    /// encoded from its child, if any.
+    fn encode_fields(
+        &self,
+        id: &str,
+        values: &Map<String, Value>,
+        used: &mut HashSet<String>,
+        constraints: &mut HashMap<&'d str, &'d ast::Expr>,
+        payload: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, String> {
+        let (fields, _, _) = self.decl(id)?;
+        let flattened = self.flatten(fields, constraints)?;
+
+        // Encode the variable size fields first, to fill in size and
+        // count fields.
+        let mut encoded: HashMap<&str, (Vec<u8>, usize)> = HashMap::new();
+        let mut payload = payload;
+        for field in &flattened {
+            match field {
+                ast::Field::Payload { .. } | ast::Field::Body { .. } if payload.is_none() => {
+                    let key = if matches!(field, ast::Field::Body { .. }) {
+                        "_body_"
+                    } else {
+                        "_payload_"
+                    };
+                    used.insert(key.to_owned());
+                    payload = Some(match values.get(key) {
+                        Some(Value::String(hex)) => parse_hex(hex)?,
+                        Some(value) => {
+                            return Err(format!(
+                                "expected a hexadecimal string for {}, got {}",
+                                key, value
+                            ))
+                        }
+                        None => vec![],
+                    });
+                }
+                ast::Field::Array { id: field_id, width, type_id, .. } => {
+                    used.insert(field_id.to_string());
+                    let value = values
+                        .get(field_id.as_str())
+                        .ok_or_else(|| format!("{}: missing value for '{}'", id, field_id))?;
+                    encoded.insert(field_id, self.array(field_id, *width, type_id, value)?);
+                }
+                ast::Field::Typedef { id: field_id, type_id, .. }
+                    if layout::scalar_type_width(&self.typedefs, type_id).is_none() =>
+                {
+                    used.insert(field_id.to_string());
+                    let value = values
+                        .get(field_id.as_str())
+                        .ok_or_else(|| format!("{}: missing value for '{}'", id, field_id))?;
+                    let bytes = self.encode(type_id, value)?;
+                    encoded.insert(field_id, (bytes, 1));
+                }
+                _ => (),
+            }
+        }
+        let payload = payload.unwrap_or_default();
+
+        let mut writer = self.writer();
+        let mut checksum_start = None;
+        let mut padding_start = 0;
+        for field in &flattened {
+            match field {
+                ast::Field::Checksum { .. } => {
+                    if writer.chunk_bits != 0 {
+                        return Err(format!("{}: checksum start is not byte aligned", id));
+                    }
+                    checksum_start = Some(writer.bytes.len());
+                }
+                ast::Field::Padding { width, .. } => {
+                    let used_bytes = writer.bytes.len() - padding_start;
+                    if used_bytes > *width {
+                        return Err(format!("{}: array overflows its padding", id));
+                    }
+                    writer.write_bytes(&vec![0; width - used_bytes], "padding")?;
+                }
+                ast::Field::Size { field_id, width, .. }
+                | ast::Field::Count { field_id, width, .. } => {
+                    let is_size = matches!(field, ast::Field::Size { .. });
+                    let key = format!("_{}_({})", if is_size { "size" } else { "count" }, field_id);
+                    let value = match values.get(&key) {
+                        Some(value) => {
+                            used.insert(key.clone());
+                            integer(value).ok_or_else(|| format!("invalid value for {}", key))?
+                        }
+                        None => {
+                            let (bytes, count) = match field_id.as_str() {
+                                "_payload_" | "_body_" => (&payload, 0),
+                                _ => encoded
+                                    .get(field_id.as_str())
+                                    .map(|(bytes, count)| (bytes, *count))
+                                    .ok_or_else(|| format!("{}: cannot compute {}", id, key))?,
+                            };
+                            let modifier = flattened
+                                .iter()
+                                .find_map(|f| match f {
+                                    ast::Field::Payload { size_modifier, .. }
+                                        if matches!(field_id.as_str(), "_payload_") =>
+                                    {
+                                        size_modifier.as_ref()
+                                    }
+                                    ast::Field::Array { id, size_modifier, .. }
+                                        if id == field_id =>
+                                    {
+                                        size_modifier.as_ref()
+                                    }
+                                    _ => None,
+                                })
+                                .and_then(|m| m.trim_start_matches('+').parse::<usize>().ok())
+                                .unwrap_or(0);
+                            if is_size {
+                                (bytes.len() + modifier) as u64
+                            } else {
+                                count as u64
+                            }
+                        }
+                    };
+                    check_width(&key, value, *width)?;
+                    writer.write_bits(value, *width)?;
+                }
+                ast::Field::Payload { .. } | ast::Field::Body { .. } => {
+                    writer.write_bytes(&payload, "payload")?;
+                }
+                ast::Field::Fixed { width: Some(width), value: Some(value), .. } => {
+                    writer.write_bits(*value as u64, *width)?;
+                }
+                ast::Field::Fixed { enum_id: Some(enum_id), tag_id: Some(tag_id), .. } => {
+                    let value = self.enum_value(enum_id, &Value::String(tag_id.to_string()))?;
+                    let width =
+                        layout::scalar_type_width(&self.typedefs, enum_id).unwrap_or_default();
+                    writer.write_bits(value, width)?;
+                }
+                ast::Field::Reserved { width, .. } => writer.write_bits(0, *width)?,
+                ast::Field::Array { id: field_id, .. } => {
+                    padding_start = writer.bytes.len();
+                    writer.write_bytes(&encoded[field_id.as_str()].0, field_id)?;
+                }
+                ast::Field::Typedef { id: field_id, type_id, .. } => {
+                    match (self.typedefs.get(type_id.as_str()), encoded.get(field_id.as_str())) {
+                        (_, Some((bytes, _))) => writer.write_bytes(bytes, field_id)?,
+                        (Some(ast::Decl::Checksum { width, .. }), _)
+                            if !values.contains_key(field_id.as_str()) =>
+                        {
+                            let start = checksum_start
+                                .ok_or_else(|| format!("{}: missing checksum start", id))?;
+                            if writer.chunk_bits != 0 {
+                                return Err(format!("{}: checksum is not byte aligned", id));
+                            }
+                            let value = checksum(type_id, &writer.bytes[start..]).ok_or_else(|| {
+                                format!("unknown checksum algorithm for '{}', provide the value of '{}'", type_id, field_id)
+                            })?;
+                            writer.write_bits(value, *width)?;
+                        }
+                        _ => {
+                            let value = self.value(id, field_id, values, used, constraints)?;
+                            let value = self.enum_value(type_id, &value)?;
+                            let width = layout::scalar_type_width(&self.typedefs, type_id)
+                                .unwrap_or_default();
+                            check_width(field_id, value, width)?;
+                            writer.write_bits(value, width)?;
+                        }
+                    }
+                }
+                ast::Field::Scalar { id: field_id, width, .. } => {
+                    let value = self.value(id, field_id, values, used, constraints)?;
+                    let value = integer(&value)
+                        .ok_or_else(|| format!("invalid value {} for '{}'", value, field_id))?;
+                    check_width(field_id, value, *width)?;
+                    writer.write_bits(value, *width)?;
+                }
+                _ => return Err(format!("{}: unsupported field at {}", id, field.loc())),
+            }
+        }
+        if writer.chunk_bits != 0 {
+            return Err(format!("{}: declaration is not byte aligned", id));
+        }
+        Ok(writer.bytes)
+    }
+
+    /// Return the value of a field, from the JSON object or from the
+    /// constraints of the child declarations.
+    fn value(
+        &self,
+        id: &str,
+        field_id: &str,
+        values: &Map<String, Value>,
+        used: &mut HashSet<String>,
+        constraints: &HashMap<&'d str, &'d ast::Expr>,
+    ) -> Result<Value, String> {
+        used.insert(field_id.to_owned());
+        match (values.get(field_id), constraints.get(field_id)) {
+            (Some(value), _) => Ok(value.clone()),
+            (None, Some(ast::Expr::Integer { value, .. })) => Ok(Value::from(*value as u64)),
+            (None, Some(ast::Expr::Identifier { name, .. })) => Ok(Value::String(name.to_string())),
+            _ => Err(format!("{}: missing value for '{}'", id, field_id)),
+        }
+    }
+
+    /// Encode a packet or struct from a JSON object. If the declaration
+    /// has a parent, the parents are encoded as well, with the fields
+    /// constrained by their children filled in.
+    fn encode(&self, id: &str, value: &Value) -> Result<Vec<u8>, String> {
+        let values = value.as_object().ok_or_else(|| format!("expected an object for '{}'", id))?;
+        let mut path = vec![];
+        let mut current = Some(id);
+        while let Some(id) = current {
+            if path.contains(&id) {
+                return Err(format!("recursive declaration '{}'", id));
+            }
+            path.push(id);
+            current = self.decl(id)?.2;
+        }
+
+        let mut constraints = HashMap::new();
+        for id in &path {
+            for constraint in self.decl(id)?.1 {
+                constraints.insert(constraint.id.as_str(), &constraint.value);
+            }
+        }
+
+        let mut used = HashSet::new();
+        let mut payload = None;
+        for id in &path {
+            payload = Some(self.encode_fields(id, values, &mut used, &mut constraints, payload)?);
+        }
+        if let Some(key) = values.keys().find(|key| !used.contains(key.as_str())) {
+            return Err(format!("unknown field '{}' for '{}'", key, id));
+        }
+        Ok(payload.unwrap_or_default())
+    }
+}
+
+fn check_width(id: &str, value: u64, width: usize) -> Result<(), String> {
+    if width < 64 && value >> width != 0 {
+        return Err(format!("value {:#x} of '{}' overflows {} bits", value, id, width));
+    }
+    Ok(())
+}
+
+/// Encode the packet or struct `id` from the JSON object `fields`, and
+/// return the encoded bytes.
+pub fn encode(grammar: &ast::Grammar, id: &str, fields: &Value) -> Result<Vec<u8>, String> {
+    Encoder::new(grammar).encode(id, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::decode;
+    use crate::test_utils::grammar;
+    use serde_json::json;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum("Fcs", b"123456789"), Some(0xbb3d));
+        assert_eq!(checksum("SimpleSum", &[0xff, 0xff, 0x02]), Some(0x200));
+        assert_eq!(checksum("Unknown", &[]), None);
+    }
+
+    #[test]
+    fn test_encode() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            checksum Fcs : 16 "l2cap/"
+            enum EventCode : 8 { LE_META_EVENT = 0x3e }
+            enum SubeventCode : 8 { CONNECTION_COMPLETE = 0x01 }
+            packet Event {
+                event_code: EventCode,
+                _size_(_payload_): 8,
+                _payload_,
+            }
+            packet LeMetaEvent : Event (event_code = LE_META_EVENT) {
+                subevent_code: SubeventCode,
+                _payload_,
+            }
+            packet LeConnectionComplete : LeMetaEvent (subevent_code = CONNECTION_COMPLETE) {
+                _checksum_start_(fcs),
+                status: 8,
+                connection_handle: 12,
+                _reserved_: 4,
+                _count_(data): 8,
+                data: 16[],
+                fcs: Fcs,
+            }
+            "#,
+        );
+
+        let fields = json!({ "status": 0, "connection_handle": "0x234", "data": [0xaaaa, 0xbbbb] });
+        let bytes = encode(&grammar, "LeConnectionComplete", &fields).unwrap();
+        let fcs = checksum("Fcs", &bytes[3..11]).unwrap();
+        assert_eq!(
+            bytes,
+            vec![
+                0x3e,
+                0x0b,
+                0x01,
+                0x00,
+                0x34,
+                0x02,
+                0x02,
+                0xaa,
+                0xaa,
+                0xbb,
+                0xbb,
+                fcs as u8,
+                (fcs >> 8) as u8
+            ]
+        );
+        assert!(decode(&grammar, "LeConnectionComplete", &bytes).is_ok());
+
+        // Forced count field.
+        let fields = json!({ "status": 0, "connection_handle": 0, "_count_(data)": 5, "data": [], "fcs": 0 });
+        assert_eq!(
+            encode(&grammar, "LeConnectionComplete", &fields).unwrap(),
+            vec![0x3e, 0x07, 0x01, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00]
+        );
+
+        // Payload of a packet without children.
+        let fields = json!({ "subevent_code": 2, "_payload_": "0102" });
+        assert_eq!(
+            encode(&grammar, "LeMetaEvent", &fields).unwrap(),
+            vec![0x3e, 0x03, 0x02, 0x01, 0x02]
+        );
+
+        assert!(encode(&grammar, "LeMetaEvent", &json!({ "subevent_code": 256 })).is_err());
+        assert!(encode(&grammar, "LeMetaEvent", &json!({ "subevent": 1 })).is_err());
+        assert!(encode(&grammar, "Event", &json!({})).is_err());
+    }
+}
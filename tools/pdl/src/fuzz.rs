@@ -0,0 +1,217 @@
+//! Structured fuzzing of the compiler.
+//!
+//! Random bytes seldom form a valid PDL grammar, and exercise little
+//! beyond the parser. [`grammar_source`] converts the fuzzer input to
+//! pseudo-PDL instead: declarations drawn from a small set of
+//! identifiers, so that they reference, derive from, and include each
+//! other, often recursively, with fields of arbitrary widths and
+//! constraints. [`check`] runs the parser, the linter, the scope
+//! analysis and the backends on a source; they must report
+//! diagnostics on invalid input, and never panic.
+//!
+//! The fuzz targets are in `fuzz/`, and are built by Soong with
+//! libFuzzer.
+
+use codespan_reporting::diagnostic::Severity;
+
+use crate::ast;
+use crate::backends::{GenerateTimings, Registry};
+use crate::lint::{self, Lintable};
+use crate::parser;
+
+/// Identifiers of the declarations.
+const DECLS: [&str; 6] = ["A", "B", "C", "D", "E", "F"];
+
+/// Identifiers of the fields and tags.
+const FIELDS: [&str; 4] = ["a", "b", "c", "d"];
+
+/// Integers used as widths, sizes and values.
+const INTEGERS: [&str; 12] = ["0", "1", "2", "4", "7", "8", "16", "24", "64", "65", "0xff", "1000"];
+
+/// Reader of the fuzzer input. Returns zeros once the input is
+/// exhausted.
+struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((byte, data)) => {
+                self.data = data;
+                *byte
+            }
+            None => 0,
+        }
+    }
+
+    fn below(&mut self, bound: usize) -> usize {
+        self.byte() as usize % bound
+    }
+
+    fn pick(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+fn constraints(input: &mut Input, out: &mut String) {
+    let count = 1 + input.below(2);
+    for index in 0..count {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        let field = input.pick(&FIELDS);
+        let value = if input.below(2) == 0 { input.pick(&INTEGERS) } else { input.pick(&FIELDS) };
+        out.push_str(&format!("{} = {}", field, value));
+    }
+}
+
+fn field(input: &mut Input, out: &mut String) {
+    let field = input.pick(&FIELDS);
+    let decl = input.pick(&DECLS);
+    let integer = input.pick(&INTEGERS);
+    match input.below(16) {
+        0 => out.push_str(&format!("_checksum_start_({})", field)),
+        1 => out.push_str(&format!("_padding_ [{}]", integer)),
+        2 => out.push_str(&format!("_size_({}): {}", field, integer)),
+        3 => out.push_str(&format!("_size_(_payload_): {}", integer)),
+        4 => out.push_str(&format!("_count_({}): {}", field, integer)),
+        5 => out.push_str("_body_"),
+        6 => out.push_str("_payload_"),
+        7 => out.push_str("_payload_ : [+2]"),
+        8 => out.push_str(&format!("_fixed_ = {} : {}", integer, input.pick(&INTEGERS))),
+        9 => out.push_str(&format!("_fixed_ = {} : {}", field, decl)),
+        10 => out.push_str(&format!("_reserved_ : {}", integer)),
+        11 => out.push_str(&format!("{}: {}[]", field, decl)),
+        12 => out.push_str(&format!("{}: {}[{}]", field, integer, input.pick(&INTEGERS))),
+        13 => out.push_str(&format!("{}: {}", field, decl)),
+        14 => {
+            out.push_str(&format!("{} {{ ", decl));
+            constraints(input, out);
+            out.push_str(" }");
+        }
+        _ => out.push_str(&format!("{}: {}", field, integer)),
+    }
+}
+
+fn fields(input: &mut Input, out: &mut String) {
+    let count = input.below(6);
+    for index in 0..count {
+        if index > 0 {
+            out.push_str(", ");
+        }
+        field(input, out);
+    }
+}
+
+fn declaration(input: &mut Input, out: &mut String) {
+    let id = input.pick(&DECLS);
+    match input.below(8) {
+        0 => {
+            out.push_str(&format!("enum {} : {} {{ ", id, input.pick(&INTEGERS)));
+            let count = 1 + input.below(3);
+            for index in 0..count {
+                if index > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&format!("{} = {}", input.pick(&FIELDS), input.pick(&INTEGERS)));
+            }
+            out.push_str(" }");
+        }
+        1 => out.push_str(&format!("checksum {} : {} \"checksum\"", id, input.pick(&INTEGERS))),
+        2 => out.push_str(&format!("custom_field {} : {} \"custom\"", id, input.pick(&INTEGERS))),
+        3 => {
+            out.push_str(&format!("group {} {{ ", id));
+            field(input, out);
+            out.push_str(" }");
+        }
+        4 => out.push_str(&format!("test {} {{ \"0102\" }}", id)),
+        kind => {
+            out.push_str(if kind == 5 { "struct " } else { "packet " });
+            out.push_str(id);
+            if input.below(2) == 0 {
+                out.push_str(&format!(" : {}", input.pick(&DECLS)));
+                if input.below(2) == 0 {
+                    out.push_str(" (");
+                    constraints(input, out);
+                    out.push(')');
+                }
+            }
+            out.push_str(" { ");
+            fields(input, out);
+            out.push_str(" }");
+        }
+    }
+    out.push('\n');
+}
+
+/// Convert fuzzer input to a pseudo-PDL grammar.
+pub fn grammar_source(data: &[u8]) -> String {
+    let mut input = Input { data };
+    let mut out = String::new();
+    match input.below(4) {
+        0 => (),
+        1 => out.push_str("big_endian_packets\n"),
+        _ => out.push_str("little_endian_packets\n"),
+    }
+    while !input.is_empty() {
+        declaration(&mut input, &mut out);
+    }
+    out
+}
+
+/// Parse, lint and analyze the source, and generate the outputs of the
+/// built-in backends if the source is valid. Panics are left to the
+/// fuzzer to report.
+pub fn check(source: &str) {
+    let mut sources = ast::SourceDatabase::new();
+    let grammar = match parser::parse_inline(&mut sources, "fuzz.pdl".to_owned(), source.to_owned())
+    {
+        Ok(grammar) => grammar,
+        Err(_) => return,
+    };
+    let diagnostics = grammar.lint().diagnostics;
+    let scope = match lint::Scope::new(&grammar) {
+        Ok(scope) => scope,
+        Err(_) => return,
+    };
+    if diagnostics.iter().any(|d| matches!(d.severity, Severity::Error | Severity::Bug)) {
+        return;
+    }
+    let registry = Registry::new();
+    for name in registry.names() {
+        if let Some(backend) = registry.get(name) {
+            let mut output = std::io::sink();
+            let mut timings = GenerateTimings::default();
+            let _ = backend.generate(&sources, &grammar, Some(&scope), &mut output, &mut timings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        assert_eq!(grammar_source(&[]), "");
+        assert_eq!(grammar_source(&[1, 0, 0, 0]), "big_endian_packets\nenum A : 0 { a = 0 }\n");
+
+        // Run a short deterministic campaign, as a smoke test of the
+        // fuzz targets.
+        let mut state: u32 = 1;
+        for len in 0..512 {
+            let data: Vec<u8> = (0..len % 64)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                    (state >> 16) as u8
+                })
+                .collect();
+            check(&grammar_source(&data));
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Golden-file tests.
+//!
+//! A golden directory holds small grammar fixtures `<name>.pdl`, each
+//! checked in together with its expected results:
+//!  - `<name>.stderr`: the diagnostics of the parser and linter, one
+//!    per line in the `oneline` error format, absent if the fixture
+//!    has none,
+//!  - `<name>.<extension>`: the output of each tested backend, absent
+//!    if the fixture has errors.
+//!
+//! [`run`] compiles the fixtures and compares the results with the
+//! golden files, or writes the golden files when blessing. The harness
+//! is run by `pdl test <directory>`, and by downstream projects on
+//! their own backends.
+
+use codespan_reporting::diagnostic::Severity;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ast;
+use crate::backends::{Backend, GenerateTimings};
+use crate::diagnostics;
+use crate::lint::{self, Lintable};
+use crate::parser;
+
+/// Golden file whose content differs from the result of its fixture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub golden_file: PathBuf,
+    /// Content of the golden file, `None` if the file does not exist.
+    pub expected: Option<String>,
+    /// Result of the fixture, `None` if no file is expected.
+    pub actual: Option<String>,
+}
+
+impl Mismatch {
+    /// Describe the mismatch in one line.
+    pub fn describe(&self) -> String {
+        let golden_file = self.golden_file.display();
+        match (&self.expected, &self.actual) {
+            (None, _) => format!("{} is missing", golden_file),
+            (_, None) => format!("{} is not expected", golden_file),
+            (Some(expected), Some(actual)) => {
+                let line = expected
+                    .lines()
+                    .zip(actual.lines())
+                    .position(|(expected, actual)| expected != actual)
+                    .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()));
+                format!("{} differs from line {}", golden_file, line + 1)
+            }
+        }
+    }
+}
+
+/// Result of a fixture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub fixture: PathBuf,
+    /// Golden files that differ from the results. When blessing, the
+    /// golden files that were updated.
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Compile a fixture, and return its results, by golden file
+/// extension.
+fn compile(fixture: &Path, backends: &[&dyn Backend]) -> io::Result<Vec<(String, Option<String>)>> {
+    let source = fs::read_to_string(fixture)?;
+    // Diagnostics refer to the fixture by its file name, so that the
+    // golden files do not depend on the working directory.
+    let name = fixture.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let mut sources = ast::SourceDatabase::new();
+    let (grammar, lint) = match parser::parse_inline(&mut sources, name, source) {
+        Ok(grammar) => {
+            let lint = grammar.lint();
+            (Some(grammar), lint.diagnostics)
+        }
+        Err(err) => (None, vec![err]),
+    };
+
+    let stderr: String = lint
+        .iter()
+        .map(|diagnostic| diagnostics::Diagnostic::from(diagnostic).to_oneline(&sources) + "\n")
+        .collect();
+    let mut results = vec![("stderr".to_owned(), Some(stderr).filter(|stderr| !stderr.is_empty()))];
+
+    let grammar = grammar
+        .filter(|_| !lint.iter().any(|d| matches!(d.severity, Severity::Error | Severity::Bug)));
+    let scope = grammar.as_ref().and_then(|grammar| lint::Scope::new(grammar).ok());
+    for backend in backends {
+        let output = match &grammar {
+            Some(grammar) => {
+                let mut output = vec![];
+                let mut timings = GenerateTimings::default();
+                backend.generate(&sources, grammar, scope.as_ref(), &mut output, &mut timings)?;
+                Some(String::from_utf8_lossy(&output).into_owned())
+            }
+            None => None,
+        };
+        results.push((backend.extension().to_owned(), output));
+    }
+    Ok(results)
+}
+
+/// Run the fixtures of the directory with the backends, in file name
+/// order. With `bless`, the golden files that differ are written, or
+/// removed if no longer expected, and reported as mismatches.
+pub fn run(directory: &Path, backends: &[&dyn Backend], bless: bool) -> io::Result<Vec<Outcome>> {
+    let mut fixtures = vec![];
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().map(|extension| extension == "pdl") == Some(true) {
+            fixtures.push(path);
+        }
+    }
+    fixtures.sort();
+
+    let mut outcomes = vec![];
+    for fixture in fixtures {
+        let mut mismatches = vec![];
+        for (extension, actual) in compile(&fixture, backends)? {
+            let golden_file = fixture.with_extension(extension);
+            let expected = match fs::read_to_string(&golden_file) {
+                Ok(expected) => Some(expected),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
+            };
+            if expected == actual {
+                continue;
+            }
+            if bless {
+                match &actual {
+                    Some(actual) => fs::write(&golden_file, actual)?,
+                    None => fs::remove_file(&golden_file)?,
+                }
+            }
+            mismatches.push(Mismatch { golden_file, expected, actual });
+        }
+        outcomes.push(Outcome { fixture, mismatches });
+    }
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backends::json::JsonBackend;
+
+    #[test]
+    fn test_golden_directory() {
+        let mismatches: Vec<_> = run(Path::new("tests/golden"), &[&JsonBackend], false)
+            .unwrap()
+            .iter()
+            .flat_map(|outcome| outcome.mismatches.iter().map(Mismatch::describe))
+            .collect();
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn test_bless() {
+        let directory = tempfile::tempdir().unwrap();
+        let fixture = directory.path().join("foo.pdl");
+        fs::write(&fixture, "little_endian_packets\npacket Foo { a: 8 }\n").unwrap();
+        let outcomes = run(directory.path(), &[&JsonBackend], false).unwrap();
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(
+            outcomes[0].mismatches[0].describe(),
+            format!("{} is missing", directory.path().join("foo.json").display())
+        );
+
+        // Blessing writes the output; the fixture has no diagnostics.
+        assert_eq!(run(directory.path(), &[&JsonBackend], true).unwrap()[0].mismatches.len(), 1);
+        assert!(run(directory.path(), &[&JsonBackend], false).unwrap()[0].mismatches.is_empty());
+
+        // An invalid fixture expects diagnostics, and no output.
+        fs::write(&fixture, "little_endian_packets\npacket Foo { a: Bar }\n").unwrap();
+        let outcomes = run(directory.path(), &[&JsonBackend], true).unwrap();
+        assert_eq!(outcomes[0].mismatches.len(), 2);
+        assert!(directory.path().join("foo.stderr").exists());
+        assert!(!directory.path().join("foo.json").exists());
+    }
+}
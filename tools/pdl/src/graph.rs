@@ -0,0 +1,438 @@
+//! Declaration dependency graph.
+//!
+//! Lists the dependencies between the declarations of a grammar: the
+//! parent of packets and structs, and the types used by their fields.
+//! The JSON export has the layout:
+//!
+//! ```text
+//! graph := {
+//!     "version": 1,
+//!     "declarations": [{ "id": string, "kind": string }],
+//!     "dependencies": [dependency],
+//! }
+//!
+//! dependency := {
+//!     "from": string,     // dependent declaration
+//!     "to": string,       // declaration used, possibly undeclared
+//!     "kind": "parent"    // `to` is the parent of `from`
+//!           | "typedef"   // `from` has a field of type `to`
+//!           | "array"     // `from` has an array field of type `to`
+//!           | "fixed"     // `from` has a fixed field of enum `to`
+//!           | "group",    // `from` inlines the group `to`
+//! }
+//! ```
+//!
+//! Dependencies are listed in declaration and field order, without
+//! duplicates.
+//!
+//! The graph is also used to select the declarations of a grammar to
+//! generate: the declarations reachable from a set of roots, for
+//! outputs that only need a few packet families, and the declarations
+//! matching include and exclude glob patterns. The selected
+//! declarations are checked to not depend on removed ones.
+
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+use crate::ast;
+use crate::visit::{walk_decl, Visitor};
+
+/// Version of the JSON graph layout.
+const GRAPH_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+    Parent,
+    Typedef,
+    Array,
+    Fixed,
+    Group,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency<'d> {
+    pub from: &'d str,
+    pub to: &'d str,
+    pub kind: DependencyKind,
+}
+
+impl DependencyKind {
+    fn name(&self) -> &'static str {
+        match self {
+            DependencyKind::Parent => "parent",
+            DependencyKind::Typedef => "typedef",
+            DependencyKind::Array => "array",
+            DependencyKind::Fixed => "fixed",
+            DependencyKind::Group => "group",
+        }
+    }
+}
+
+/// Visitor collecting the dependencies of the declarations.
+struct Collector<'d> {
+    from: Option<&'d str>,
+    dependencies: Vec<Dependency<'d>>,
+}
+
+impl<'d> Collector<'d> {
+    fn push(&mut self, to: &'d str, kind: DependencyKind) {
+        if let Some(from) = self.from {
+            let dependency = Dependency { from, to, kind };
+            if !self.dependencies.contains(&dependency) {
+                self.dependencies.push(dependency);
+            }
+        }
+    }
+}
+
+impl<'d> Visitor<'d> for Collector<'d> {
+    fn visit_decl(&mut self, decl: &'d ast::Decl) {
+        self.from = match decl {
+            ast::Decl::Packet { .. } | ast::Decl::Struct { .. } | ast::Decl::Group { .. } => {
+                decl.id().map(ast::Symbol::as_str)
+            }
+            _ => None,
+        };
+        match decl {
+            ast::Decl::Packet { parent_id: Some(parent_id), .. }
+            | ast::Decl::Struct { parent_id: Some(parent_id), .. } => {
+                self.push(parent_id, DependencyKind::Parent)
+            }
+            _ => (),
+        }
+        walk_decl(self, decl)
+    }
+
+    fn visit_field(&mut self, field: &'d ast::Field) {
+        match field {
+            ast::Field::Typedef { type_id, .. } => self.push(type_id, DependencyKind::Typedef),
+            ast::Field::Array { type_id: Some(type_id), .. } => {
+                self.push(type_id, DependencyKind::Array)
+            }
+            ast::Field::Fixed { enum_id: Some(enum_id), .. } => {
+                self.push(enum_id, DependencyKind::Fixed)
+            }
+            ast::Field::Group { group_id, .. } => self.push(group_id, DependencyKind::Group),
+            _ => (),
+        }
+    }
+}
+
+/// Return the dependencies of the declarations of the grammar.
+pub fn dependencies(grammar: &ast::Grammar) -> Vec<Dependency<'_>> {
+    let mut collector = Collector { from: None, dependencies: vec![] };
+    collector.visit_grammar(grammar);
+    collector.dependencies
+}
+
+/// Return the identifiers of the declarations reachable from the
+/// roots: the roots, the declarations deriving from them, and
+/// transitively the declarations these depend on.
+pub fn reachable<'d>(grammar: &'d ast::Grammar, roots: &[String]) -> HashSet<&'d str> {
+    let dependencies = dependencies(grammar);
+    let mut reachable: HashSet<&str> = grammar
+        .declarations
+        .iter()
+        .filter_map(|decl| decl.id())
+        .filter(|id| roots.iter().any(|root| root == id.as_str()))
+        .map(ast::Symbol::as_str)
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for dependency in &dependencies {
+            if dependency.kind == DependencyKind::Parent
+                && reachable.contains(dependency.to)
+                && reachable.insert(dependency.from)
+            {
+                changed = true;
+            }
+        }
+    }
+    let mut queue: Vec<&str> = reachable.iter().copied().collect();
+    while let Some(id) = queue.pop() {
+        for dependency in dependencies.iter().filter(|dependency| dependency.from == id) {
+            if reachable.insert(dependency.to) {
+                queue.push(dependency.to);
+            }
+        }
+    }
+    reachable
+}
+
+/// Remove the declarations that are not reachable from the roots,
+/// and the tests of the removed declarations. Returns an error if a
+/// root is not declared.
+pub fn prune(grammar: &mut ast::Grammar, roots: &[String]) -> Result<(), String> {
+    for root in roots {
+        if !grammar.declarations.iter().any(|decl| matches!(decl.id(), Some(id) if id == root)) {
+            return Err(format!("root `{}` is not declared", root));
+        }
+    }
+    let reachable: HashSet<String> =
+        reachable(grammar, roots).into_iter().map(str::to_owned).collect();
+    grammar.declarations.retain(|decl| match decl {
+        ast::Decl::Test { type_id, .. } => reachable.contains(type_id.as_str()),
+        _ => matches!(decl.id(), Some(id) if reachable.contains(id.as_str())),
+    });
+    Ok(())
+}
+
+/// Return true if `id` matches the glob `pattern`, where `*` matches
+/// any sequence of characters and `?` any single character.
+pub fn glob_match(pattern: &str, id: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let id: Vec<char> = id.chars().collect();
+    // Position in the pattern and the identifier to resume from on a
+    // mismatch, after the last `*`.
+    let mut backtrack = None;
+    let (mut p, mut i) = (0, 0);
+    while i < id.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, i));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == id[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    i = start + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Selection of the declarations to generate.
+#[derive(Debug, Default, Clone)]
+pub struct Selection {
+    /// Keep only the declarations reachable from these declarations.
+    pub roots: Vec<String>,
+    /// Keep only the declarations matching one of these patterns.
+    pub only: Vec<String>,
+    /// Remove the declarations matching one of these patterns.
+    pub exclude: Vec<String>,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty() && self.only.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Return a description of the selection, for output stamps.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "roots={} only={} exclude={}",
+            self.roots.join(","),
+            self.only.join(","),
+            self.exclude.join(",")
+        )
+    }
+
+    /// Return the reason why a declaration is filtered out by the
+    /// patterns, if it is.
+    fn filtered(&self, id: &str) -> Option<String> {
+        if !self.only.is_empty() && !self.only.iter().any(|pattern| glob_match(pattern, id)) {
+            return Some("not included by --only".to_owned());
+        }
+        self.exclude
+            .iter()
+            .find(|pattern| glob_match(pattern, id))
+            .map(|pattern| format!("excluded by --exclude '{}'", pattern))
+    }
+
+    /// Remove the declarations that are not selected, and the tests of
+    /// the removed declarations. Returns the list of errors if a root
+    /// is not declared, or a selected declaration uses a declaration
+    /// removed by the filters.
+    pub fn apply(&self, grammar: &mut ast::Grammar) -> Result<(), Vec<String>> {
+        if !self.roots.is_empty() {
+            prune(grammar, &self.roots).map_err(|err| vec![err])?;
+        }
+        let removed: Vec<(String, String)> = grammar
+            .declarations
+            .iter()
+            .filter_map(|decl| decl.id())
+            .filter_map(|id| Some((id.to_string(), self.filtered(id)?)))
+            .collect();
+        let errors: Vec<String> = dependencies(grammar)
+            .iter()
+            .filter(|dependency| removed.iter().all(|(id, _)| id != dependency.from))
+            .filter_map(|dependency| {
+                let (_, reason) = removed.iter().find(|(id, _)| id == dependency.to)?;
+                Some(format!("`{}` uses `{}`, {}", dependency.from, dependency.to, reason))
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let removed = |id: &str| removed.iter().any(|(removed, _)| removed == id);
+        grammar.declarations.retain(|decl| match decl {
+            ast::Decl::Test { type_id, .. } => !removed(type_id),
+            _ => !matches!(decl.id(), Some(id) if removed(id)),
+        });
+        Ok(())
+    }
+}
+
+/// Return the dependency graph in the JSON format.
+pub fn to_json(grammar: &ast::Grammar) -> String {
+    let declarations = grammar.declarations.iter().filter_map(|decl| {
+        let mut object = Map::new();
+        object.insert("id".to_owned(), Value::String(decl.id()?.to_string()));
+        object.insert("kind".to_owned(), Value::String(decl.kind().replace(' ', "_")));
+        Some(Value::Object(object))
+    });
+    let dependencies = dependencies(grammar).into_iter().map(|dependency| {
+        let mut object = Map::new();
+        object.insert("from".to_owned(), Value::String(dependency.from.to_owned()));
+        object.insert("to".to_owned(), Value::String(dependency.to.to_owned()));
+        object.insert("kind".to_owned(), Value::String(dependency.kind.name().to_owned()));
+        Value::Object(object)
+    });
+
+    let mut object = Map::new();
+    object.insert("version".to_owned(), Value::from(GRAPH_VERSION));
+    object.insert("declarations".to_owned(), Value::Array(declarations.collect()));
+    object.insert("dependencies".to_owned(), Value::Array(dependencies.collect()));
+    let mut out = serde_json::to_string_pretty(&Value::Object(object)).unwrap();
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_dependencies() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1 }
+            struct Handle { value: 16 }
+            group Header { _fixed_ = READ : Op }
+            packet Command { Header, op: Op, _payload_ }
+            packet Read : Command { handle: Handle, handles: Handle[], op: Op }
+            "#,
+        );
+        let dependency = |from, to, kind| Dependency { from, to, kind };
+        assert_eq!(
+            dependencies(&grammar),
+            vec![
+                dependency("Header", "Op", DependencyKind::Fixed),
+                dependency("Command", "Header", DependencyKind::Group),
+                dependency("Command", "Op", DependencyKind::Typedef),
+                dependency("Read", "Command", DependencyKind::Parent),
+                dependency("Read", "Handle", DependencyKind::Typedef),
+                dependency("Read", "Handle", DependencyKind::Array),
+                dependency("Read", "Op", DependencyKind::Typedef),
+            ]
+        );
+        let json: Value = serde_json::from_str(&to_json(&grammar)).unwrap();
+        assert_eq!(json["declarations"][2]["kind"], "group");
+        assert_eq!(json["dependencies"][3]["kind"], "parent");
+    }
+
+    #[test]
+    fn test_prune() {
+        let text = r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            struct Handle { value: 16 }
+            struct Data { value: 8 }
+            packet Command { op: Op, _payload_ }
+            packet Read : Command (op = READ) { handle: Handle }
+            packet Write : Command (op = WRITE) { data: Data }
+            packet Reset { }
+            test Write { "\x02\x00" }
+            "#;
+        let pruned = |roots: &[&str]| {
+            let mut grammar = grammar!(text);
+            let roots: Vec<String> = roots.iter().map(|root| root.to_string()).collect();
+            prune(&mut grammar, &roots).map(|()| {
+                grammar
+                    .declarations
+                    .iter()
+                    .map(|decl| {
+                        decl.id().map_or_else(|| decl.kind().to_owned(), |id| id.to_string())
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(pruned(&["Read"]).unwrap(), vec!["Op", "Handle", "Command", "Read"]);
+        assert_eq!(
+            pruned(&["Command"]).unwrap(),
+            vec!["Op", "Handle", "Data", "Command", "Read", "Write", "test"]
+        );
+        assert_eq!(pruned(&["Reset", "Handle"]).unwrap(), vec!["Handle", "Reset"]);
+        assert!(pruned(&["Missing"]).is_err());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("Vendor*", "VendorCommand"));
+        assert!(glob_match("Vendor*", "Vendor"));
+        assert!(!glob_match("Vendor*", "LeVendorCommand"));
+        assert!(glob_match("*Vendor*", "LeVendorCommand"));
+        assert!(glob_match("Le?etaEvent", "LeMetaEvent"));
+        assert!(glob_match("*Command*Complete", "CommandCompleteCommandComplete"));
+        assert!(!glob_match("*Complete", "CommandCompleted"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_selection() {
+        let text = r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, VENDOR = 0xff }
+            struct VendorData { value: 8 }
+            packet Command { op: Op, _payload_ }
+            packet Read : Command (op = READ) { }
+            packet VendorCommand : Command (op = VENDOR) { data: VendorData }
+            test VendorCommand { "\xff\x00" }
+            "#;
+        let selected = |selection: Selection| {
+            let mut grammar = grammar!(text);
+            selection.apply(&mut grammar).map(|()| {
+                grammar
+                    .declarations
+                    .iter()
+                    .map(|decl| {
+                        decl.id().map_or_else(|| decl.kind().to_owned(), |id| id.to_string())
+                    })
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            selected(Selection { exclude: vec!["Vendor*".to_owned()], ..Default::default() })
+                .unwrap(),
+            vec!["Op", "Command", "Read"]
+        );
+        assert_eq!(
+            selected(Selection { exclude: vec!["Op".to_owned()], ..Default::default() }),
+            Err(vec!["`Command` uses `Op`, excluded by --exclude 'Op'".to_owned()])
+        );
+        assert_eq!(
+            selected(Selection { only: vec!["Command".to_owned()], ..Default::default() }),
+            Err(vec!["`Command` uses `Op`, not included by --only".to_owned()])
+        );
+        assert_eq!(
+            selected(Selection {
+                roots: vec!["Command".to_owned()],
+                exclude: vec!["Vendor*".to_owned()],
+                ..Default::default()
+            })
+            .unwrap(),
+            vec!["Op", "Command", "Read"]
+        );
+    }
+}
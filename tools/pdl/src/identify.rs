@@ -0,0 +1,159 @@
+//! Packet identification.
+//!
+//! Decodes bytes of unknown type as every root packet of a grammar,
+//! selecting the child declarations from the decoded values, and ranks
+//! the candidates by how cleanly they decode: candidates without
+//! trailing bytes first, then by decreasing score. The score is the
+//! number of fixed fields and constraints matched, minus the number of
+//! unknown enum values and of payloads not matched to a child
+//! declaration.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast;
+use crate::decoder::{self, Packet, Value};
+use crate::layout;
+
+/// Successful decoding of the bytes as a root packet.
+#[derive(Debug)]
+pub struct Candidate {
+    pub packet: Packet,
+    /// Number of bytes left after the packet.
+    pub trailing_bytes: usize,
+    /// Number of enum values without a tag.
+    pub unknown_tags: usize,
+    /// Number of non-empty payloads not matched to a child
+    /// declaration.
+    pub undecoded_payloads: usize,
+    /// Number of fixed fields and constraints checked.
+    pub matched: usize,
+}
+
+/// Return the number of fixed fields in the fields, including the
+/// fields of inlined groups.
+fn fixed_fields<'d>(decls: &HashMap<&'d str, &'d ast::Decl>, fields: &'d [ast::Field]) -> usize {
+    layout::flatten(decls, fields)
+        .iter()
+        .filter(|flat| matches!(flat.field, ast::Field::Fixed { .. }))
+        .count()
+}
+
+impl Candidate {
+    fn new(decls: &HashMap<&str, &ast::Decl>, packet: Packet, trailing_bytes: usize) -> Self {
+        let mut packets = vec![&packet];
+        let (mut unknown_tags, mut undecoded_payloads, mut matched) = (0, 0, 0);
+        while let Some(packet) = packets.pop() {
+            match decls.get(packet.id.as_str()) {
+                Some(ast::Decl::Packet { fields, constraints, .. })
+                | Some(ast::Decl::Struct { fields, constraints, .. }) => {
+                    matched += constraints.len() + fixed_fields(decls, fields)
+                }
+                _ => (),
+            }
+            let mut values: Vec<&Value> = packet.fields.iter().map(|(_, value)| value).collect();
+            while let Some(value) = values.pop() {
+                match value {
+                    Value::Tag(_, None) => unknown_tags += 1,
+                    Value::Bytes(bytes) if !bytes.is_empty() => undecoded_payloads += 1,
+                    Value::Array(elements) => values.extend(elements),
+                    Value::Struct(packet) => packets.push(packet),
+                    _ => (),
+                }
+            }
+            packets.extend(packet.child.as_deref());
+        }
+        Candidate { packet, trailing_bytes, unknown_tags, undecoded_payloads, matched }
+    }
+
+    /// Return the number of fixed fields and constraints matched,
+    /// minus the number of unknown enum values and undecoded payloads.
+    pub fn score(&self) -> isize {
+        self.matched as isize - (self.unknown_tags + self.undecoded_payloads) as isize
+    }
+
+    /// Return the identifiers of the decoded declaration and its
+    /// parents, from the outermost.
+    pub fn path(&self) -> Vec<&str> {
+        let mut path = vec![];
+        let mut packet = Some(&self.packet);
+        while let Some(current) = packet {
+            path.push(current.id.as_str());
+            packet = current.child.as_deref();
+        }
+        path
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} matched", self.path().join(" > "), self.matched)?;
+        if self.unknown_tags > 0 {
+            write!(f, ", {} unknown enum values", self.unknown_tags)?;
+        }
+        if self.undecoded_payloads > 0 {
+            write!(f, ", {} undecoded payloads", self.undecoded_payloads)?;
+        }
+        if self.trailing_bytes > 0 {
+            write!(f, ", {} trailing bytes", self.trailing_bytes)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Decode the bytes as every root packet, and return the successful
+/// candidates, best first. Candidates with the same rank are listed
+/// in declaration order.
+pub fn identify(grammar: &ast::Grammar, data: &[u8]) -> Vec<Candidate> {
+    let decls: HashMap<&str, &ast::Decl> = grammar
+        .declarations
+        .iter()
+        .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+        .collect();
+    let mut candidates: Vec<Candidate> = grammar
+        .declarations
+        .iter()
+        .filter_map(|decl| match decl {
+            ast::Decl::Packet { id, parent_id: None, .. } => {
+                let (packet, len) = decoder::decode_prefix(grammar, id, data).ok()?;
+                Some(Candidate::new(&decls, packet, data.len() - len))
+            }
+            _ => None,
+        })
+        .collect();
+    candidates.sort_by_key(|candidate| (candidate.trailing_bytes > 0, Reverse(candidate.score())));
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_identify() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            packet Command { _fixed_ = 0x01 : 8, op: Op, _payload_ }
+            packet Read : Command (op = READ) { handle: 8 }
+            packet Event { code: 8, _payload_ }
+            packet Raw { data: 8[] }
+            "#,
+        );
+        let candidates = identify(&grammar, &[0x01, 0x01, 0x42]);
+        let paths: Vec<Vec<&str>> = candidates.iter().map(Candidate::path).collect();
+        assert_eq!(paths, vec![vec!["Command", "Read"], vec!["Raw"], vec!["Event"]]);
+        assert_eq!(candidates[0].matched, 2);
+        assert_eq!(candidates[2].undecoded_payloads, 1);
+        assert_eq!(candidates[0].to_string(), "Command > Read (2 matched)");
+
+        let candidates = identify(&grammar, &[0x01, 0x03]);
+        let paths: Vec<Vec<&str>> = candidates.iter().map(Candidate::path).collect();
+        assert_eq!(paths, vec![vec!["Command"], vec!["Raw"], vec!["Event"]]);
+        assert_eq!(candidates[0].unknown_tags, 1);
+        assert_eq!(candidates[0].score(), 0);
+    }
+}
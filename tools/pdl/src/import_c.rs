@@ -0,0 +1,576 @@
+//! Import of C struct definitions.
+//!
+//! Converts the packed struct definitions of a C header, as found in
+//! vendor HALs, to draft PDL struct declarations, e.g.
+//!
+//! ```c
+//! typedef struct {
+//!     uint8_t opcode;
+//!     uint16_t handle : 12;
+//!     uint16_t flags : 4;
+//!     uint8_t data[4];
+//! } __attribute__((packed)) vendor_cmd_t;
+//! ```
+//!
+//! is converted to
+//!
+//! ```text
+//! struct VendorCmd {
+//!     opcode: 8,
+//!     handle: 12,
+//!     flags: 4,
+//!     data: 8[4],
+//! }
+//! ```
+//!
+//! The declarations are named in camel case, without the `_t` suffix.
+//! Fixed width integers, `char`, `short`, `int` and `bool` are
+//! converted to scalar fields, structs defined earlier in the header to
+//! typedef fields, and one-dimensional arrays to array fields. Fields
+//! which cannot be inferred, e.g. unions, pointers, enums, or types
+//! defined in other headers, are left as `TODO` comments in the
+//! declarations and reported as issues. The preprocessor is not run:
+//! macros are not expanded, and conditional sections are all imported.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Punct(char),
+}
+
+/// Field which could not be converted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// Line of the field in the header, counted from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Draft PDL declarations converted from a header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub source: String,
+    pub issues: Vec<Issue>,
+}
+
+/// Split the header in tokens, with their line. Comments are skipped,
+/// and the preprocessor lines are returned separately.
+fn tokenize(header: &str) -> Vec<(usize, Result<Token, String>)> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = header.chars().collect();
+    let mut line = 1;
+    let mut index = 0;
+    let mut line_start = true;
+    while index < chars.len() {
+        let c = chars[index];
+        if c == '\n' {
+            line += 1;
+            line_start = true;
+            index += 1;
+        } else if c.is_whitespace() {
+            index += 1;
+        } else if c == '/' && chars.get(index + 1) == Some(&'/') {
+            while index < chars.len() && chars[index] != '\n' {
+                index += 1;
+            }
+        } else if c == '/' && chars.get(index + 1) == Some(&'*') {
+            index += 2;
+            while index < chars.len()
+                && !(chars[index] == '*' && chars.get(index + 1) == Some(&'/'))
+            {
+                line += (chars[index] == '\n') as usize;
+                index += 1;
+            }
+            index += 2;
+        } else if c == '#' && line_start {
+            // Preprocessor directive, with its continuation lines.
+            let start = index;
+            while index < chars.len() && !(chars[index] == '\n' && chars[index - 1] != '\\') {
+                line += (chars[index] == '\n') as usize;
+                index += 1;
+            }
+            tokens.push((line, Err(chars[start..index].iter().collect())));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = index;
+            while index < chars.len()
+                && (chars[index].is_ascii_alphanumeric() || chars[index] == '_')
+            {
+                index += 1;
+            }
+            tokens.push((line, Ok(Token::Ident(chars[start..index].iter().collect()))));
+            line_start = false;
+        } else if c.is_ascii_digit() {
+            let start = index;
+            while index < chars.len() && chars[index].is_ascii_alphanumeric() {
+                index += 1;
+            }
+            tokens.push((line, Ok(Token::Number(chars[start..index].iter().collect()))));
+            line_start = false;
+        } else {
+            tokens.push((line, Ok(Token::Punct(c))));
+            line_start = false;
+            index += 1;
+        }
+    }
+    tokens
+}
+
+/// Parse a C integer literal, ignoring the suffixes.
+fn parse_number(number: &str) -> Option<usize> {
+    let number = number.trim_end_matches(['u', 'U', 'l', 'L']);
+    match number.strip_prefix("0x").or_else(|| number.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => number.parse().ok(),
+    }
+}
+
+/// Return the width of a C integer type, or the reason it cannot be
+/// converted.
+fn type_width(words: &[&str]) -> Result<usize, String> {
+    let name = words
+        .iter()
+        .filter(|word| !matches!(**word, "const" | "volatile" | "signed" | "unsigned"))
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+    match name.as_str() {
+        "uint8_t" | "int8_t" | "u8" | "s8" | "__u8" | "__s8" | "char" | "bool" | "_Bool" => Ok(8),
+        "uint16_t" | "int16_t" | "u16" | "s16" | "__u16" | "__s16" | "__le16" | "short"
+        | "short int" => Ok(16),
+        "uint32_t" | "int32_t" | "u32" | "s32" | "__u32" | "__s32" | "__le32" | "int" | "" => {
+            Ok(32)
+        }
+        "uint64_t" | "int64_t" | "u64" | "s64" | "__u64" | "__s64" | "__le64" | "long long"
+        | "long long int" => Ok(64),
+        "__be16" | "__be32" | "__be64" => Err(format!("big endian type `{}`", name)),
+        "long" | "long int" | "size_t" => Err(format!("platform dependent type `{}`", name)),
+        "float" | "double" => Err(format!("floating point type `{}`", name)),
+        _ => Err(format!("unknown type `{}`", name)),
+    }
+}
+
+/// Convert a C type name to a PDL declaration name, e.g.
+/// `vendor_cmd_t` to `VendorCmd`.
+fn decl_name(name: &str) -> String {
+    let name = name.strip_suffix("_t").unwrap_or(name);
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+                .collect::<String>()
+        })
+        .collect()
+}
+
+struct Importer {
+    tokens: Vec<(usize, Result<Token, String>)>,
+    index: usize,
+    /// PDL names of the structs imported so far, by C name.
+    structs: HashMap<String, String>,
+    /// `#pragma pack(1)` is in effect.
+    pragma_packed: bool,
+    out: String,
+    issues: Vec<Issue>,
+}
+
+impl Importer {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).and_then(|(_, token)| token.as_ref().ok())
+    }
+
+    fn line(&self) -> usize {
+        self.tokens.get(self.index).or_else(|| self.tokens.last()).map_or(0, |(line, _)| *line)
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(name)) if name == ident)
+    }
+
+    fn is_punct(&self, punct: char) -> bool {
+        self.peek() == Some(&Token::Punct(punct))
+    }
+
+    /// Skip a balanced group of parentheses, brackets or braces, and
+    /// return the identifiers it contains.
+    fn skip_group(&mut self) -> Vec<String> {
+        let mut idents = vec![];
+        let mut depth = 0;
+        while let Some((_, token)) = self.tokens.get(self.index) {
+            self.index += 1;
+            match token {
+                Ok(Token::Punct('(' | '[' | '{')) => depth += 1,
+                Ok(Token::Punct(')' | ']' | '}')) => depth -= 1,
+                Ok(Token::Ident(ident)) => idents.push(ident.clone()),
+                _ => (),
+            }
+            if depth <= 0 {
+                break;
+            }
+        }
+        idents
+    }
+
+    /// Skip the attributes, and return true if one of them is `packed`.
+    fn attributes(&mut self) -> bool {
+        let mut packed = false;
+        while self.is_ident("__attribute__") || self.is_ident("__packed") {
+            packed |= self.is_ident("__packed");
+            self.index += 1;
+            if self.is_punct('(') {
+                packed |= self
+                    .skip_group()
+                    .iter()
+                    .any(|ident| ident == "packed" || ident == "__packed__");
+            }
+        }
+        packed
+    }
+
+    fn issue(&mut self, line: usize, message: String) {
+        self.issues.push(Issue { line, message });
+    }
+
+    /// Convert the fields of a struct body, from the opening brace to
+    /// the closing brace included.
+    fn fields(&mut self, struct_name: &str) -> Vec<String> {
+        let mut fields = vec![];
+        self.index += 1;
+        while self.peek().is_some() && !self.is_punct('}') {
+            let line = self.line();
+            if self.is_ident("union") || self.is_ident("struct") && self.is_inline_struct() {
+                let kind = if self.is_ident("union") { "union" } else { "inline struct" };
+                while self.peek().is_some() && !self.is_punct('{') && !self.is_punct(';') {
+                    self.index += 1;
+                }
+                if self.is_punct('{') {
+                    self.skip_group();
+                }
+                let mut name = vec![];
+                while let Some(token) = self.peek().cloned() {
+                    self.index += 1;
+                    match token {
+                        Token::Punct(';') => break,
+                        Token::Ident(ident) => name.push(ident),
+                        _ => (),
+                    }
+                }
+                let name = name.join(" ");
+                let message = format!("{}.{}: unsupported {}", struct_name, name, kind);
+                fields.push(format!("// TODO: {} {}", kind, name));
+                self.issue(line, message);
+                continue;
+            }
+
+            // Gather the declaration up to the semicolon.
+            let mut declaration = vec![];
+            while let Some(token) = self.peek().cloned() {
+                self.index += 1;
+                match token {
+                    Token::Punct(';') => break,
+                    Token::Punct('(') => {
+                        self.index -= 1;
+                        self.skip_group();
+                        declaration.push(Token::Punct('('));
+                    }
+                    token => declaration.push(token),
+                }
+            }
+            for field in self.declaration(struct_name, line, &declaration) {
+                fields.push(field);
+            }
+        }
+        self.index += 1;
+        fields
+    }
+
+    /// Return true if the `struct` keyword starts an inline struct
+    /// definition.
+    fn is_inline_struct(&self) -> bool {
+        let next = |offset: usize| {
+            self.tokens.get(self.index + offset).and_then(|(_, token)| token.as_ref().ok())
+        };
+        next(1) == Some(&Token::Punct('{')) || next(2) == Some(&Token::Punct('{'))
+    }
+
+    /// Convert a field declaration, possibly declaring several fields.
+    fn declaration(&mut self, struct_name: &str, line: usize, tokens: &[Token]) -> Vec<String> {
+        let text = tokens
+            .iter()
+            .map(|token| match token {
+                Token::Ident(ident) | Token::Number(ident) => ident.clone(),
+                Token::Punct(c) => c.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let declarators: Vec<&[Token]> =
+            tokens.split(|token| *token == Token::Punct(',')).collect();
+        let first = declarators[0];
+        let type_len = first
+            .iter()
+            .position(|token| !matches!(token, Token::Ident(_)))
+            .unwrap_or(first.len())
+            .saturating_sub(1);
+        let type_words: Vec<&str> = first[..type_len]
+            .iter()
+            .filter_map(|token| match token {
+                Token::Ident(ident) => Some(ident.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        let mut fields = vec![];
+        for (index, declarator) in declarators.iter().enumerate() {
+            let declarator = if index == 0 { &first[type_len..] } else { declarator };
+            let result = self.field(&type_words, declarator);
+            match result {
+                Ok(field) => fields.push(field),
+                Err(reason) => {
+                    self.issue(line, format!("{}: {}: {}", struct_name, text, reason));
+                    fields.push(format!("// TODO: {}; {}", text, reason));
+                    break;
+                }
+            }
+        }
+        fields
+    }
+
+    /// Convert a field declarator, e.g. `data[4]` or `flags : 4`.
+    fn field(&self, type_words: &[&str], declarator: &[Token]) -> Result<String, String> {
+        if declarator.contains(&Token::Punct('*')) {
+            return Err("pointer".to_owned());
+        }
+        if declarator.contains(&Token::Punct('(')) {
+            return Err("function pointer or macro".to_owned());
+        }
+        let name = match declarator.first() {
+            Some(Token::Ident(name)) => name.trim_start_matches('_'),
+            _ => return Err("missing field name".to_owned()),
+        };
+        if name.is_empty() {
+            return Err("invalid field name".to_owned());
+        }
+
+        let element = match type_words {
+            ["struct", type_name] | ["const", "struct", type_name] | [type_name]
+                if self.structs.contains_key(*type_name) =>
+            {
+                self.structs[*type_name].clone()
+            }
+            ["struct", type_name] => return Err(format!("unknown struct `{}`", type_name)),
+            ["enum", ..] => return Err("enum of implementation defined width".to_owned()),
+            ["union", ..] => return Err("union".to_owned()),
+            _ => type_width(type_words)?.to_string(),
+        };
+
+        match &declarator[1..] {
+            [] => Ok(format!("{}: {},", name, element)),
+            [Token::Punct(':'), Token::Number(width)] => match parse_number(width) {
+                Some(width) if element.parse::<usize>().is_ok() => {
+                    Ok(format!("{}: {},", name, width))
+                }
+                _ => Err("invalid bit field".to_owned()),
+            },
+            [Token::Punct('['), Token::Punct(']')] => Ok(format!("{}: {}[],", name, element)),
+            [Token::Punct('['), Token::Number(count), Token::Punct(']')] => {
+                match parse_number(count) {
+                    Some(0) => Ok(format!("{}: {}[],", name, element)),
+                    Some(count) => Ok(format!("{}: {}[{}],", name, element, count)),
+                    None => Err("invalid array size".to_owned()),
+                }
+            }
+            [Token::Punct('['), Token::Ident(_), Token::Punct(']')] => {
+                Err("array size given by a macro".to_owned())
+            }
+            _ => Err("unsupported declarator".to_owned()),
+        }
+    }
+
+    /// Convert a struct definition, from the `struct` keyword, and
+    /// return true if the struct was imported.
+    fn struct_definition(&mut self, typedef: bool) -> bool {
+        let line = self.line();
+        self.index += 1;
+        let mut packed = self.attributes();
+        let tag = match self.peek() {
+            Some(Token::Ident(tag)) => {
+                let tag = tag.clone();
+                self.index += 1;
+                Some(tag)
+            }
+            _ => None,
+        };
+        packed |= self.attributes();
+        if !self.is_punct('{') {
+            // Declaration or use of the struct, not a definition.
+            return false;
+        }
+        let c_name = tag.clone().unwrap_or_default();
+        let fields = self.fields(&decl_name(&c_name));
+        packed |= self.attributes();
+        let mut names: Vec<String> = tag.into_iter().collect();
+        if typedef {
+            // Typedef names, excluding pointer types.
+            let mut pointer = false;
+            while let Some(token) = self.peek().cloned() {
+                self.index += 1;
+                match token {
+                    Token::Punct(';') => break,
+                    Token::Punct('*') => pointer = true,
+                    Token::Punct(',') => pointer = false,
+                    Token::Ident(name) if !pointer && !name.starts_with("__") => names.push(name),
+                    _ => (),
+                }
+            }
+        }
+        packed |= self.pragma_packed;
+        let name = match names.last() {
+            Some(name) => decl_name(name),
+            None => return false,
+        };
+        if !packed {
+            self.issue(line, format!("{}: struct is not packed, padding is not imported", name));
+        }
+        for c_name in names {
+            self.structs.insert(c_name, name.clone());
+        }
+
+        self.out.push_str(&format!("\nstruct {} {{\n", name));
+        for field in fields {
+            self.out.push_str(&format!("    {}\n", field));
+        }
+        self.out.push_str("}\n");
+        true
+    }
+
+    fn pragma(&mut self, directive: &str) {
+        let directive: String = directive.chars().filter(|c| !c.is_whitespace()).collect();
+        if let Some(pack) = directive.strip_prefix("#pragmapack(") {
+            self.pragma_packed = pack.starts_with("push,1") || pack.starts_with("1)");
+        }
+    }
+
+    fn run(&mut self) {
+        while let Some((_, token)) = self.tokens.get(self.index).cloned() {
+            match token {
+                Err(directive) => {
+                    self.pragma(&directive);
+                    self.index += 1;
+                }
+                Ok(Token::Ident(ident)) if ident == "typedef" => {
+                    self.index += 1;
+                    if !self.is_ident("struct") || !self.struct_definition(true) {
+                        while self.peek().is_some() && !self.is_punct(';') {
+                            self.index += 1;
+                        }
+                    }
+                }
+                Ok(Token::Ident(ident)) if ident == "struct" => {
+                    self.struct_definition(false);
+                }
+                Ok(Token::Punct('{')) => {
+                    // Function bodies and other definitions.
+                    self.skip_group();
+                }
+                _ => self.index += 1,
+            }
+        }
+    }
+}
+
+/// Convert the packed structs of a C header to draft PDL declarations.
+pub fn import(header: &str, file_name: &str) -> Import {
+    let mut importer = Importer {
+        tokens: tokenize(header),
+        index: 0,
+        structs: HashMap::new(),
+        pragma_packed: false,
+        out: String::new(),
+        issues: vec![],
+    };
+    importer.run();
+    let mut source = format!(
+        "// Draft declarations imported from {} by `pdl import-c`.\n\
+         // Review the fields marked TODO, and the endianness.\n\n\
+         little_endian_packets\n",
+        file_name
+    );
+    source.push_str(&importer.out);
+    Import { source, issues: importer.issues }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import() {
+        let import = import(
+            r#"
+            #include <stdint.h>
+            /* Header of the vendor commands. */
+            typedef struct {
+                uint8_t opcode;
+                uint16_t handle : 12, flags : 4;
+            } __attribute__((packed)) vendor_hdr_t;
+
+            #pragma pack(push, 1)
+            struct vendor_cmd {
+                vendor_hdr_t hdr;
+                unsigned char data[4];
+                void *context;
+                union { uint8_t a; uint16_t b; } u;
+                uint8_t payload[];
+            };
+            #pragma pack(pop)
+
+            struct unpacked { uint32_t value; };
+            "#,
+            "vendor.h",
+        );
+        assert_eq!(
+            import.source,
+            r#"// Draft declarations imported from vendor.h by `pdl import-c`.
+// Review the fields marked TODO, and the endianness.
+
+little_endian_packets
+
+struct VendorHdr {
+    opcode: 8,
+    handle: 12,
+    flags: 4,
+}
+
+struct VendorCmd {
+    hdr: VendorHdr,
+    data: 8[4],
+    // TODO: void * context; pointer
+    // TODO: union u
+    payload: 8[],
+}
+
+struct Unpacked {
+    value: 32,
+}
+"#
+        );
+        assert_eq!(
+            import.issues,
+            vec![
+                Issue { line: 13, message: "VendorCmd: void * context: pointer".to_owned() },
+                Issue { line: 14, message: "VendorCmd.u: unsupported union".to_owned() },
+                Issue {
+                    line: 19,
+                    message: "Unpacked: struct is not packed, padding is not imported".to_owned()
+                },
+            ]
+        );
+    }
+}
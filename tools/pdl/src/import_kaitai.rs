@@ -0,0 +1,614 @@
+//! Import of Kaitai Struct definitions.
+//!
+//! Converts a Kaitai Struct specification (`.ksy`) to draft PDL
+//! declarations: the top-level `seq` to a packet named after `meta/id`,
+//! the `types` to structs, and the `enums` to enums, e.g.
+//!
+//! ```text
+//! meta:                           little_endian_packets
+//!   id: vendor_cmd
+//!   endian: le                    enum Opcode : 8 {
+//! seq:                                READ = 1,
+//!   - id: opcode                  }
+//!     type: u1
+//!     enum: opcode                packet VendorCmd {
+//!   - id: len                         opcode: Opcode,
+//!     type: u1                        _size_(data): 8,
+//!   - id: data                        data: 8[],
+//!     size: len                   }
+//! enums:
+//!   opcode:
+//!     1: read
+//! ```
+//!
+//! Integer and bit-sized integer types are converted to scalar fields,
+//! user types to typedef fields, `contents` to fixed fields, and byte
+//! arrays and repetitions to array fields; a `size` or `repeat-expr`
+//! referencing an earlier field of the sequence converts that field to
+//! a size or count field. Attributes with no PDL equivalent, e.g.
+//! strings, floating point types, conditional fields, switches and
+//! computed sizes, are left as `TODO` comments in the declarations and
+//! reported as issues. `instances` are not imported.
+//!
+//! Specifications are read with a reader for the subset of YAML they
+//! use: block mappings and sequences, flow sequences, plain and quoted
+//! scalars, and literal block scalars.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Draft PDL declarations converted from a specification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub source: String,
+    /// Attributes which could not be converted.
+    pub issues: Vec<String>,
+}
+
+/// Return the index of the comment starting the line, ignoring `#`
+/// in quoted strings.
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if previous.is_whitespace() => return Some(index),
+            _ => (),
+        }
+        previous = c;
+    }
+    None
+}
+
+/// Parse a YAML flow or plain scalar.
+fn parse_scalar(text: &str) -> Value {
+    let text = text.trim();
+    if let Some(items) = text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+        return Value::Array(
+            items.split(',').filter(|item| !item.trim().is_empty()).map(parse_scalar).collect(),
+        );
+    }
+    for quote in ['\'', '"'] {
+        if let Some(text) = text.strip_prefix(quote).and_then(|text| text.strip_suffix(quote)) {
+            return Value::String(text.to_owned());
+        }
+    }
+    match text {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        "" | "~" | "null" => return Value::Null,
+        _ => (),
+    }
+    match parse_integer(text) {
+        Some(integer) => Value::from(integer),
+        None => Value::String(text.to_owned()),
+    }
+}
+
+/// Parse a decimal, hexadecimal or binary integer.
+fn parse_integer(text: &str) -> Option<u64> {
+    let text = text.replace('_', "");
+    if let Some(hex) = text.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        u64::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+/// Reader of the YAML subset used by specifications.
+struct Reader {
+    /// Indentation and content of the lines.
+    lines: Vec<(usize, String)>,
+    index: usize,
+}
+
+impl Reader {
+    fn new(text: &str) -> Reader {
+        let lines = text
+            .lines()
+            .map(|line| {
+                let indent = line.len() - line.trim_start().len();
+                (indent, line.trim().to_owned())
+            })
+            .collect();
+        Reader { lines, index: 0 }
+    }
+
+    /// Skip the blank and comment lines, and return the next line.
+    fn peek(&mut self) -> Option<(usize, String)> {
+        while let Some((_, line)) = self.lines.get(self.index) {
+            if !line.is_empty() && !line.starts_with('#') && line != "---" {
+                break;
+            }
+            self.index += 1;
+        }
+        self.lines.get(self.index).cloned()
+    }
+
+    /// Split a `key: value` line, or return `None` for a scalar.
+    fn split_key(line: &str) -> Option<(String, String)> {
+        let line = match comment_start(line) {
+            Some(start) => line[..start].trim_end(),
+            None => line,
+        };
+        let (key, value) = match line.find(": ") {
+            Some(index) => (&line[..index], &line[index + 2..]),
+            None => (line.strip_suffix(':')?, ""),
+        };
+        if key.starts_with(['[', '\'', '"']) {
+            return None;
+        }
+        Some((key.trim().to_owned(), value.trim().to_owned()))
+    }
+
+    /// Read the lines more indented than `indent` as a block scalar.
+    fn block_scalar(&mut self, indent: usize) -> Value {
+        let mut text = vec![];
+        while let Some((line_indent, line)) = self.lines.get(self.index) {
+            if !line.is_empty() && *line_indent <= indent {
+                break;
+            }
+            text.push(line.clone());
+            self.index += 1;
+        }
+        Value::String(text.join("\n"))
+    }
+
+    /// Read the node starting on the next line, at `indent`.
+    fn node(&mut self, indent: usize) -> Value {
+        match self.peek() {
+            Some((line_indent, line)) if line_indent == indent => {
+                if line == "-" || line.starts_with("- ") {
+                    self.sequence(indent)
+                } else if Reader::split_key(&line).is_some() {
+                    self.mapping(indent)
+                } else {
+                    self.index += 1;
+                    let end = comment_start(&line).unwrap_or(line.len());
+                    parse_scalar(&line[..end])
+                }
+            }
+            _ => Value::Null,
+        }
+    }
+
+    fn sequence(&mut self, indent: usize) -> Value {
+        let mut items = vec![];
+        while let Some((line_indent, line)) = self.peek() {
+            if line_indent != indent || !(line == "-" || line.starts_with("- ")) {
+                break;
+            }
+            let rest = line[1..].trim_start();
+            if rest.is_empty() {
+                self.index += 1;
+                let item_indent = self.peek().map_or(indent + 1, |(indent, _)| indent);
+                items.push(if item_indent > indent { self.node(item_indent) } else { Value::Null });
+            } else {
+                // The item starts on the line of the dash: read it as
+                // if it started on its own line.
+                let item_indent = indent + line.len() - rest.len();
+                self.lines[self.index] = (item_indent, rest.to_owned());
+                items.push(self.node(item_indent));
+            }
+        }
+        Value::Array(items)
+    }
+
+    fn mapping(&mut self, indent: usize) -> Value {
+        let mut map = Map::new();
+        while let Some((line_indent, line)) = self.peek() {
+            if line_indent != indent || line.starts_with("- ") {
+                break;
+            }
+            let (key, value) = match Reader::split_key(&line) {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.index += 1;
+            let value = if value.starts_with(['|', '>']) {
+                self.block_scalar(indent)
+            } else if !value.is_empty() {
+                parse_scalar(&value)
+            } else {
+                match self.peek() {
+                    Some((next_indent, _)) if next_indent > indent => self.node(next_indent),
+                    // Sequences may be indented as their key.
+                    Some((next_indent, next)) if next_indent == indent && next.starts_with('-') => {
+                        self.sequence(indent)
+                    }
+                    _ => Value::Null,
+                }
+            };
+            map.insert(key, value);
+        }
+        Value::Object(map)
+    }
+}
+
+/// Convert a Kaitai identifier to a PDL declaration name, e.g.
+/// `vendor_cmd` to `VendorCmd`.
+fn decl_name(id: &str) -> String {
+    id.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+                .collect::<String>()
+        })
+        .collect()
+}
+
+/// Element type of a field.
+enum Element {
+    Scalar(usize),
+    Typedef(String),
+    Bytes,
+}
+
+/// Converted field of a sequence.
+struct Entry {
+    id: String,
+    /// Width of the scalar fields, which may become size or count
+    /// fields.
+    width: Option<usize>,
+    text: String,
+}
+
+/// Attributes of the sequence fields which are converted.
+const FIELD_KEYS: [&str; 10] = [
+    "id",
+    "type",
+    "enum",
+    "size",
+    "size-eos",
+    "repeat",
+    "repeat-expr",
+    "contents",
+    "doc",
+    "doc-ref",
+];
+
+struct Importer {
+    big_endian: bool,
+    little_bit_endian: bool,
+    /// PDL names of the user types, by Kaitai name.
+    types: HashMap<String, String>,
+    /// Width of the enums, from the fields which use them.
+    enum_widths: HashMap<String, usize>,
+    issues: Vec<String>,
+}
+
+impl Importer {
+    /// Return the element type of a field.
+    fn element(&mut self, field: &Map<String, Value>) -> Result<Element, String> {
+        let type_name = match field.get("type") {
+            None => return Ok(Element::Bytes),
+            Some(Value::String(type_name)) => type_name.as_str(),
+            Some(_) => return Err("switch on the type".to_owned()),
+        };
+        let integer = type_name.strip_prefix('u').or_else(|| type_name.strip_prefix('s'));
+        let width = if let Some((size, endian)) = integer.and_then(|integer| {
+            let digits = integer.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+            Some((digits.parse::<usize>().ok()?, &integer[digits.len()..]))
+        }) {
+            match endian {
+                "le" if self.big_endian => return Err("little endian field".to_owned()),
+                "be" if !self.big_endian && size > 1 => return Err("big endian field".to_owned()),
+                "" | "le" | "be" if matches!(size, 1 | 2 | 4 | 8) => size * 8,
+                _ => return Err(format!("unknown type `{}`", type_name)),
+            }
+        } else if let Some(width) = type_name.strip_prefix('b').and_then(|w| w.parse().ok()) {
+            if !self.little_bit_endian {
+                return Err("big endian bit field, set `bit-endian: le`".to_owned());
+            }
+            width
+        } else if let Some(name) = self.types.get(type_name) {
+            return Ok(Element::Typedef(name.clone()));
+        } else {
+            return Err(match type_name {
+                "f4" | "f8" | "f4le" | "f4be" | "f8le" | "f8be" => "floating point type".to_owned(),
+                "str" | "strz" => "string".to_owned(),
+                _ => format!("unknown type `{}`", type_name),
+            });
+        };
+        match field.get("enum") {
+            None => Ok(Element::Scalar(width)),
+            Some(Value::String(enum_id)) if !enum_id.contains("::") => {
+                self.enum_widths.insert(enum_id.clone(), width);
+                Ok(Element::Typedef(decl_name(enum_id)))
+            }
+            Some(_) => Err("enum of another type".to_owned()),
+        }
+    }
+
+    /// Convert a field of a sequence, updating the entry of the size
+    /// or count field it references.
+    fn field(
+        &mut self,
+        field: &Map<String, Value>,
+        entries: &mut [Entry],
+    ) -> Result<Entry, String> {
+        let id = match field.get("id") {
+            Some(Value::String(id)) => id.clone(),
+            _ => return Err("missing id".to_owned()),
+        };
+        if let Some(key) = field.keys().find(|key| !FIELD_KEYS.contains(&key.as_str())) {
+            return Err(format!("unsupported attribute `{}`", key));
+        }
+
+        if let Some(contents) = field.get("contents") {
+            let bytes: Vec<u64> = match contents {
+                Value::String(text) => text.bytes().map(u64::from).collect(),
+                Value::Array(items) => items.iter().filter_map(Value::as_u64).collect(),
+                Value::Number(byte) => byte.as_u64().into_iter().collect(),
+                _ => vec![],
+            };
+            return Ok(Entry {
+                id,
+                width: None,
+                text: bytes
+                    .iter()
+                    .map(|byte| format!("_fixed_ = 0x{:02x} : 8,", byte))
+                    .collect::<Vec<_>>()
+                    .join("\n    "),
+            });
+        }
+
+        let element = self.element(field)?;
+        let element_text = match &element {
+            Element::Scalar(width) => width.to_string(),
+            Element::Typedef(name) => name.clone(),
+            Element::Bytes => "8".to_owned(),
+        };
+        let mut reference = |value: &Value, kind: &str| match value {
+            Value::String(field_id) => {
+                let entry = entries
+                    .iter_mut()
+                    .find(|entry| entry.id == *field_id && entry.width.is_some())
+                    .ok_or_else(|| format!("{} `{}` is not a preceding integer", kind, field_id))?;
+                let width = entry.width.take().unwrap();
+                entry.text = format!("_{}_({}): {},", kind, id, width);
+                Ok(())
+            }
+            _ => Err(format!("computed {}", kind)),
+        };
+
+        let repeat = field.get("repeat").and_then(Value::as_str);
+        let size = field.get("size");
+        let text = match (&element, repeat, size) {
+            (Element::Bytes, None, Some(Value::Number(count))) => {
+                format!("{}: 8[{}],", id, count)
+            }
+            (Element::Bytes, None, Some(size)) => {
+                reference(size, "size")?;
+                format!("{}: 8[],", id)
+            }
+            (Element::Bytes, None, None) if field.get("size-eos") == Some(&Value::Bool(true)) => {
+                format!("{}: 8[],", id)
+            }
+            (Element::Bytes, ..) => return Err("byte array of unknown size".to_owned()),
+            (_, _, Some(_)) => return Err("sized field".to_owned()),
+            (_, None, None) => format!("{}: {},", id, element_text),
+            (_, Some("eos"), None) => format!("{}: {}[],", id, element_text),
+            (_, Some("expr"), None) => match field.get("repeat-expr") {
+                Some(Value::Number(count)) => format!("{}: {}[{}],", id, element_text, count),
+                Some(count) => {
+                    reference(count, "count")?;
+                    format!("{}: {}[],", id, element_text)
+                }
+                None => return Err("missing repeat-expr".to_owned()),
+            },
+            (_, Some(repeat), None) => return Err(format!("repeat {}", repeat)),
+        };
+        let width = match element {
+            Element::Scalar(width) if repeat.is_none() => Some(width),
+            _ => None,
+        };
+        Ok(Entry { id, width, text })
+    }
+
+    /// Convert a sequence to the body of a declaration.
+    fn seq(&mut self, decl: &str, seq: Option<&Value>) -> String {
+        let mut entries: Vec<Entry> = vec![];
+        for (index, field) in seq.and_then(Value::as_array).into_iter().flatten().enumerate() {
+            let field = field.as_object().cloned().unwrap_or_default();
+            match self.field(&field, &mut entries) {
+                Ok(entry) => entries.push(entry),
+                Err(reason) => {
+                    let id = field.get("id").and_then(Value::as_str).unwrap_or("?").to_owned();
+                    self.issues.push(format!("{}.{}: {}", decl, id, reason));
+                    entries.push(Entry {
+                        id: format!("#{}", index),
+                        width: None,
+                        text: format!("// TODO: {}: {}", id, reason),
+                    });
+                }
+            }
+        }
+        entries.iter().map(|entry| format!("    {}\n", entry.text)).collect()
+    }
+
+    /// Register the user types, including the nested types, by name.
+    fn register_types(&mut self, types: Option<&Value>, list: &mut Vec<(String, Value)>) {
+        for (id, spec) in types.and_then(Value::as_object).into_iter().flatten() {
+            self.types.insert(id.clone(), decl_name(id));
+            list.push((id.clone(), spec.clone()));
+            self.register_types(spec.get("types"), list);
+        }
+    }
+}
+
+/// Convert a Kaitai Struct specification to draft PDL declarations.
+pub fn import(spec: &str, file_name: &str) -> Import {
+    let spec = Reader::new(spec).node(0);
+    let meta = spec.get("meta");
+    let meta_value = |key: &str| meta.and_then(|meta| meta.get(key)).and_then(Value::as_str);
+    let mut importer = Importer {
+        big_endian: meta_value("endian") == Some("be"),
+        little_bit_endian: meta_value("bit-endian") == Some("le"),
+        types: HashMap::new(),
+        enum_widths: HashMap::new(),
+        issues: vec![],
+    };
+    match meta_value("endian") {
+        Some("le" | "be") => (),
+        Some(_) => importer.issues.push("meta: switched endianness".to_owned()),
+        None => importer.issues.push("meta: missing endianness, assuming little endian".to_owned()),
+    }
+
+    let mut types = vec![];
+    importer.register_types(spec.get("types"), &mut types);
+    let mut decls = vec![];
+    for (id, spec) in &types {
+        let name = decl_name(id);
+        let body = importer.seq(&name, spec.get("seq"));
+        decls.push(format!("struct {} {{\n{}}}\n", name, body));
+    }
+    if let Some(seq) = spec.get("seq") {
+        let name = decl_name(meta_value("id").unwrap_or("root"));
+        let body = importer.seq(&name, Some(seq));
+        decls.push(format!("packet {} {{\n{}}}\n", name, body));
+    }
+    if spec.get("instances").is_some() {
+        importer.issues.push("instances are not imported".to_owned());
+    }
+
+    let mut enums = vec![];
+    for (id, values) in spec.get("enums").and_then(Value::as_object).into_iter().flatten() {
+        let name = decl_name(id);
+        let width = match importer.enum_widths.get(id) {
+            Some(width) => *width,
+            None => {
+                importer.issues.push(format!("{}: unused enum of unknown width", name));
+                continue;
+            }
+        };
+        let mut tags = vec![];
+        for (value, tag) in values.as_object().into_iter().flatten() {
+            let tag = match tag {
+                Value::Object(tag) => tag.get("id").and_then(Value::as_str),
+                tag => tag.as_str(),
+            };
+            match (parse_integer(value), tag) {
+                (Some(value), Some(tag)) => tags.push((value, tag.to_uppercase())),
+                _ => importer.issues.push(format!("{}: invalid value `{}`", name, value)),
+            }
+        }
+        tags.sort();
+        let tags: String =
+            tags.iter().map(|(value, tag)| format!("    {} = {},\n", tag, value)).collect();
+        enums.push(format!("enum {} : {} {{\n{}}}\n", name, width, tags));
+    }
+
+    let mut source = format!(
+        "// Draft declarations imported from {} by `pdl import-kaitai`.\n\
+         // Review the fields marked TODO.\n\n{}\n",
+        file_name,
+        if importer.big_endian { "big_endian_packets" } else { "little_endian_packets" }
+    );
+    for decl in enums.iter().chain(decls.iter()) {
+        source.push('\n');
+        source.push_str(decl);
+    }
+    Import { source, issues: importer.issues }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import() {
+        let import = import(
+            r#"
+meta:
+  id: vendor_cmd
+  endian: le
+  bit-endian: le
+doc: |
+  Vendor command.
+  # Not a comment.
+seq:
+  - id: magic
+    contents: [0xca, 0xfe]
+  - id: opcode
+    type: u1
+    enum: opcode
+  - id: len   # Size of data.
+    type: u2
+  - id: num_ranges
+    type: u1
+  - id: data
+    size: len
+  - id: ranges
+    type: range
+    repeat: expr
+    repeat-expr: num_ranges
+  - id: flags
+    type: b4
+  - id: name
+    type: strz
+    encoding: ASCII
+types:
+  range:
+    seq:
+      - id: bounds
+        type: u4
+        repeat: expr
+        repeat-expr: 2
+enums:
+  opcode:
+    0x01: read
+    2:
+      id: write
+  unused:
+    1: one
+"#,
+            "vendor.ksy",
+        );
+        assert_eq!(
+            import.source,
+            r#"// Draft declarations imported from vendor.ksy by `pdl import-kaitai`.
+// Review the fields marked TODO.
+
+little_endian_packets
+
+enum Opcode : 8 {
+    READ = 1,
+    WRITE = 2,
+}
+
+struct Range {
+    bounds: 32[2],
+}
+
+packet VendorCmd {
+    _fixed_ = 0xca : 8,
+    _fixed_ = 0xfe : 8,
+    opcode: Opcode,
+    _size_(data): 16,
+    _count_(ranges): 8,
+    data: 8[],
+    ranges: Range[],
+    flags: 4,
+    // TODO: name: unsupported attribute `encoding`
+}
+"#
+        );
+        assert_eq!(
+            import.issues,
+            vec![
+                "VendorCmd.name: unsupported attribute `encoding`".to_owned(),
+                "Unused: unused enum of unknown width".to_owned(),
+            ]
+        );
+    }
+}
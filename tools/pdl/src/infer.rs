@@ -0,0 +1,218 @@
+//! Inference of packet declarations from samples.
+//!
+//! Proposes a draft declaration for many samples of the same unknown
+//! packet, e.g. the records of a vendor event collected from btsnoop
+//! logs. The bytes present in every sample are classified, in order:
+//!  - bytes with the same value in every sample become fixed fields,
+//!  - 8 or 16-bit little endian integers equal to the number of bytes
+//!    following the common prefix, plus a constant, become the size of
+//!    the payload,
+//!  - 8 or 16-bit little endian integers which increment by one from a
+//!    sample to the next, in most samples, are reported as counters,
+//!  - the other bytes become 8-bit scalar fields, annotated with the
+//!    values seen when there are few of them.
+//!
+//! The bytes beyond the shortest sample become the payload. The
+//! inference is a heuristic, and the draft must be reviewed: a field
+//! may match by chance when there are few samples.
+
+/// Fraction of the consecutive samples in which a counter must
+/// increment, in percent.
+const COUNTER_PERCENT: usize = 90;
+
+/// Maximum number of distinct values listed for a field.
+const MAX_VALUES: usize = 4;
+
+/// Draft declaration of a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Field {
+    Fixed(u8),
+    /// Size of the payload, with the constant added to it.
+    Size {
+        width: usize,
+        modifier: u64,
+    },
+    Counter {
+        width: usize,
+    },
+    Scalar {
+        values: Vec<u8>,
+    },
+}
+
+/// Read the little endian integer of `width` bits at `offset`.
+fn read_le(sample: &[u8], offset: usize, width: usize) -> u64 {
+    sample[offset..offset + width / 8].iter().rev().fold(0, |value, byte| value << 8 | *byte as u64)
+}
+
+/// Return the constant added to the payload size by the integer at
+/// `offset`, if the integer is the payload size in every sample.
+fn size_modifier(samples: &[&[u8]], prefix: usize, offset: usize, width: usize) -> Option<u64> {
+    let lengths: Vec<usize> = samples.iter().map(|sample| sample.len()).collect();
+    if lengths.iter().all(|len| *len == lengths[0]) {
+        // The size cannot be told from a constant.
+        return None;
+    }
+    let modifier = read_le(samples[0], offset, width).checked_sub((lengths[0] - prefix) as u64)?;
+    if samples
+        .iter()
+        .all(|sample| read_le(sample, offset, width) == (sample.len() - prefix) as u64 + modifier)
+    {
+        Some(modifier)
+    } else {
+        None
+    }
+}
+
+/// Return true if the integer at `offset` increments by one between
+/// most consecutive samples.
+fn is_counter(samples: &[&[u8]], offset: usize, width: usize) -> bool {
+    let mask = if width == 16 { 0xffff } else { 0xff };
+    let increments = samples
+        .windows(2)
+        .filter(|pair| {
+            read_le(pair[1], offset, width) == (read_le(pair[0], offset, width) + 1) & mask
+        })
+        .count();
+    samples.len() > 2 && increments * 100 >= (samples.len() - 1) * COUNTER_PERCENT
+}
+
+/// Classify the bytes of the common prefix of the samples.
+fn fields(samples: &[&[u8]]) -> Vec<Field> {
+    let prefix = samples.iter().map(|sample| sample.len()).min().unwrap_or(0);
+    let mut fields = vec![];
+    let mut offset = 0;
+    while offset < prefix {
+        let value = samples[0][offset];
+        if samples.iter().all(|sample| sample[offset] == value) {
+            fields.push(Field::Fixed(value));
+            offset += 1;
+            continue;
+        }
+        // Sizes are tried narrowest first, and counters widest first,
+        // as the low byte of a 16-bit counter is an 8-bit counter. A
+        // 16-bit counter must not have a constant high byte.
+        let fits = |width: &usize| offset + width / 8 <= prefix;
+        let high_byte_varies = |width: &usize| {
+            *width == 8 || samples.iter().any(|sample| sample[offset + 1] != samples[0][offset + 1])
+        };
+        let size = [8, 16].into_iter().filter(fits).find_map(|width| {
+            let modifier = size_modifier(samples, prefix, offset, width)?;
+            Some((Field::Size { width, modifier }, offset + width / 8))
+        });
+        let counter = || {
+            [16, 8]
+                .into_iter()
+                .filter(fits)
+                .filter(high_byte_varies)
+                .find(|width| is_counter(samples, offset, *width))
+                .map(|width| (Field::Counter { width }, offset + width / 8))
+        };
+        let (field, end) = size.or_else(counter).unwrap_or_else(|| {
+            let mut values: Vec<u8> = samples.iter().map(|sample| sample[offset]).collect();
+            values.sort_unstable();
+            values.dedup();
+            (Field::Scalar { values }, offset + 1)
+        });
+        fields.push(field);
+        offset = end;
+    }
+    fields
+}
+
+/// Propose a draft declaration for the packet `name` from samples of
+/// the packet. Returns an error if there are fewer than two samples.
+pub fn infer(name: &str, samples: &[Vec<u8>]) -> Result<String, String> {
+    if samples.len() < 2 {
+        return Err("at least two samples are required".to_owned());
+    }
+    let samples: Vec<&[u8]> = samples.iter().map(|sample| sample.as_slice()).collect();
+    let mut out = format!(
+        "// Draft declaration inferred from {} samples by `pdl infer`.\n\
+         // Review the fields, and name them.\n\n\
+         little_endian_packets\n\n\
+         packet {} {{\n",
+        samples.len(),
+        name
+    );
+    let mut offset = 0;
+    let mut payload_modifier = None;
+    for field in fields(&samples) {
+        match field {
+            Field::Fixed(value) => {
+                out.push_str(&format!("    _fixed_ = 0x{:02x} : 8,\n", value));
+                offset += 1;
+            }
+            Field::Size { width, modifier } => {
+                out.push_str(&format!("    _size_(_payload_): {},\n", width));
+                payload_modifier = Some(modifier);
+                offset += width / 8;
+            }
+            Field::Counter { width } => {
+                out.push_str("    // Increments by one from a sample to the next.\n");
+                out.push_str(&format!("    counter_{}: {},\n", offset, width));
+                offset += width / 8;
+            }
+            Field::Scalar { values } => {
+                if values.len() <= MAX_VALUES {
+                    let values: Vec<String> =
+                        values.iter().map(|value| format!("0x{:02x}", value)).collect();
+                    out.push_str(&format!("    // Values seen: {}.\n", values.join(", ")));
+                }
+                out.push_str(&format!("    field_{}: 8,\n", offset));
+                offset += 1;
+            }
+        }
+    }
+    match payload_modifier {
+        Some(0) => out.push_str("    _payload_,\n"),
+        Some(modifier) => out.push_str(&format!("    _payload_ : [+{}],\n", modifier)),
+        None if samples.iter().any(|sample| sample.len() > offset) => {
+            out.push_str("    _payload_,\n")
+        }
+        None => (),
+    }
+    out.push_str("}\n");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer() {
+        // Vendor event 0xff with a sub-event code, a 16-bit sequence
+        // number, a status and a variable length payload. The
+        // parameter length counts the bytes following it.
+        let samples: Vec<Vec<u8>> = (0..8u16)
+            .map(|index| {
+                let sequence = (0xfe + index).to_le_bytes();
+                let mut sample = vec![0xff, 0, 0x54, sequence[0], sequence[1], index as u8 % 2];
+                sample.extend(vec![0xaa; index as usize % 3]);
+                sample[1] = sample.len() as u8 - 2;
+                sample
+            })
+            .collect();
+        assert_eq!(
+            infer("VendorEvent", &samples).unwrap(),
+            r#"// Draft declaration inferred from 8 samples by `pdl infer`.
+// Review the fields, and name them.
+
+little_endian_packets
+
+packet VendorEvent {
+    _fixed_ = 0xff : 8,
+    _size_(_payload_): 8,
+    _fixed_ = 0x54 : 8,
+    // Increments by one from a sample to the next.
+    counter_3: 16,
+    // Values seen: 0x00, 0x01.
+    field_5: 8,
+    _payload_ : [+4],
+}
+"#
+        );
+        assert!(infer("VendorEvent", &samples[..1]).is_err());
+    }
+}
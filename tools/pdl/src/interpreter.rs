@@ -0,0 +1,109 @@
+//! Runtime packet interpreter.
+//!
+//! Loads a grammar at runtime, from PDL source or from the JSON
+//! representation produced by `pdl compile --output-format json`, and
+//! decodes and encodes packets against it without generating code.
+//! Packets are decoded to the value tree of [`decoder::Packet`], and
+//! encoded from JSON objects as described in [`encoder`].
+
+use codespan_reporting::diagnostic::Diagnostic;
+use std::ffi::OsStr;
+use std::path::Path;
+
+use crate::ast;
+use crate::backends::json;
+use crate::decoder;
+use crate::encoder;
+use crate::parser;
+
+/// Load a grammar file: the JSON representation of a grammar if the
+/// file has the `.json` extension, PDL source otherwise.
+pub fn load_file(
+    sources: &mut ast::SourceDatabase,
+    path: String,
+) -> Result<ast::Grammar, Diagnostic<ast::FileId>> {
+    if Path::new(&path).extension() != Some(OsStr::new("json")) {
+        return parser::parse_file(sources, path);
+    }
+    std::fs::read_to_string(&path)
+        .map_err(|err| err.to_string())
+        .and_then(|text| serde_json::from_str(&text).map_err(|err| err.to_string()))
+        .and_then(|value| json::from_value(sources, &value))
+        .map_err(|err| {
+            Diagnostic::error().with_message(format!("failed to load '{}': {}", path, err))
+        })
+}
+
+/// Interpreter of the packets of a grammar.
+pub struct Interpreter {
+    grammar: ast::Grammar,
+}
+
+impl Interpreter {
+    pub fn new(grammar: ast::Grammar) -> Self {
+        Interpreter { grammar }
+    }
+
+    /// Load the grammar of the interpreter from a file, see
+    /// [`load_file`].
+    pub fn from_file(
+        sources: &mut ast::SourceDatabase,
+        path: String,
+    ) -> Result<Self, Diagnostic<ast::FileId>> {
+        load_file(sources, path).map(Interpreter::new)
+    }
+
+    pub fn grammar(&self) -> &ast::Grammar {
+        &self.grammar
+    }
+
+    /// Decode `data` as the packet or struct `id`, see
+    /// [`decoder::decode`].
+    pub fn decode(&self, id: &str, data: &[u8]) -> Result<decoder::Packet, String> {
+        decoder::decode(&self.grammar, id, data)
+    }
+
+    /// Decode a packet given as a hexadecimal string.
+    pub fn decode_hex(&self, id: &str, hex: &str) -> Result<decoder::Packet, String> {
+        self.decode(id, &decoder::parse_hex(hex)?)
+    }
+
+    /// Encode the packet or struct `id` from the values of its fields,
+    /// see [`encoder::encode`].
+    pub fn encode(&self, id: &str, fields: &serde_json::Value) -> Result<Vec<u8>, String> {
+        encoder::encode(&self.grammar, id, fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+    use serde_json::json;
+
+    #[test]
+    fn test_interpreter() {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+            packet Write : Command (op = WRITE) { addr: 16, value: 8 }
+            "#,
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.json");
+        std::fs::write(&path, json::generate(&db, &grammar)).unwrap();
+
+        let mut sources = ast::SourceDatabase::new();
+        let path = path.display().to_string();
+        let interpreter = Interpreter::from_file(&mut sources, path).expect("loading failure");
+        let bytes = interpreter.encode("Write", &json!({ "addr": 0x1234, "value": 7 })).unwrap();
+        assert_eq!(bytes, vec![0x02, 0x03, 0x34, 0x12, 0x07]);
+        let packet = interpreter.decode("Write", &bytes).unwrap();
+        assert_eq!(packet.child.as_ref().map(|child| child.id.as_str()), Some("Write"));
+        assert!(interpreter.decode_hex("Write", "0103341207").is_err());
+    }
+}
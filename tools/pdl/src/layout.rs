@@ -0,0 +1,546 @@
+//! Field layout queries.
+//!
+//! Computes the flattened field list of packet and struct
+//! declarations, with group fields inlined, and the bit offset and
+//! width of every field. Offsets are counted from the start of the
+//! outermost parent declaration, so that the fields of a child packet
+//! are located inside the payload of its parent. Fields following a
+//! variable size field have a dynamic offset, relative to the end of
+//! the last variable size field.
+//!
+//! This is the layout used by the documentation generators, see
+//! [`crate::backends::csv`] and [`crate::backends::diagram`]. It also
+//! defines the wire format hashes of the declarations, see
+//! [`Layout::wire_hash`], and the group inlining and type widths used
+//! by every pass walking the fields of a declaration, see [`flatten`]
+//! and [`scalar_type_width`].
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::ast;
+use crate::stamp;
+
+/// Bit offset of a field.
+#[derive(Debug, Clone, Copy)]
+pub enum Offset<'d> {
+    /// Offset from the start of the outermost parent declaration.
+    Static(usize),
+    /// Offset from the end of the variable size field `after`.
+    Dynamic { after: &'d ast::Field, bits: usize },
+}
+
+/// Field of a declaration, with its layout.
+#[derive(Debug, Clone)]
+pub struct FieldLayout<'d> {
+    pub field: &'d ast::Field,
+    /// Value set by a group constraint, if any.
+    pub value: Option<String>,
+    /// Bit offset of the field, `None` if a parent declaration is
+    /// undeclared or recursive. Such grammars are rejected by the
+    /// linter.
+    pub offset: Option<Offset<'d>>,
+    /// Bit width of the field, `None` if the field has a variable size.
+    pub width: Option<usize>,
+}
+
+/// Field of a declaration, with the group fields inlined, see
+/// [`flatten`].
+#[derive(Debug, Clone, Copy)]
+pub struct FlatField<'d> {
+    pub field: &'d ast::Field,
+    /// Value set by the constraints of the enclosing group fields, or
+    /// by the default constraints of the groups, if any.
+    pub value: Option<&'d ast::Expr>,
+}
+
+/// Layout of the declarations of a grammar.
+pub struct Layout<'d> {
+    typedefs: Cow<'d, HashMap<&'d str, &'d ast::Decl>>,
+}
+
+impl<'d> Offset<'d> {
+    /// Return the offset of a field if it is static.
+    pub fn static_offset(&self) -> Option<usize> {
+        match self {
+            Offset::Static(offset) => Some(*offset),
+            Offset::Dynamic { .. } => None,
+        }
+    }
+
+    /// Return the offset following a field of the given width.
+    fn after(self, field: &'d ast::Field, width: Option<usize>) -> Offset<'d> {
+        match (self, width) {
+            (Offset::Static(offset), Some(width)) => Offset::Static(offset + width),
+            (Offset::Dynamic { after, bits }, Some(width)) => {
+                Offset::Dynamic { after, bits: bits + width }
+            }
+            (_, None) => Offset::Dynamic { after: field, bits: 0 },
+        }
+    }
+}
+
+/// Return the text of a constraint value: an enum tag identifier or an
+/// integer.
+pub fn constraint_value(value: &ast::Expr) -> String {
+    match value {
+        ast::Expr::Identifier { name, .. } => name.to_string(),
+        ast::Expr::Integer { value, .. } => value.to_string(),
+        _ => "?".to_owned(),
+    }
+}
+
+/// Flatten the fields of a declaration, inlining the group fields
+/// recursively. The constraints of a group field take precedence over
+/// the default constraints of the group declaration. Group fields
+/// referencing an undeclared or recursive group are left in place:
+/// such grammars are rejected by the linter.
+pub fn flatten<'d>(
+    typedefs: &HashMap<&'d str, &'d ast::Decl>,
+    fields: &'d [ast::Field],
+) -> Vec<FlatField<'d>> {
+    let mut flattened = vec![];
+    flatten_in(typedefs, fields, &HashMap::new(), &mut vec![], &mut flattened);
+    flattened
+}
+
+fn flatten_in<'d>(
+    typedefs: &HashMap<&'d str, &'d ast::Decl>,
+    fields: &'d [ast::Field],
+    constraints: &HashMap<&'d str, &'d ast::Expr>,
+    stack: &mut Vec<&'d str>,
+    flattened: &mut Vec<FlatField<'d>>,
+) {
+    for field in fields {
+        if let ast::Field::Group { group_id, constraints: group_constraints, .. } = field {
+            if let Some(ast::Decl::Group { id, fields, constraints: defaults, .. }) =
+                typedefs.get(group_id.as_str())
+            {
+                if !stack.contains(&id.as_str()) {
+                    let mut constraints = constraints.clone();
+                    for constraint in group_constraints {
+                        constraints.insert(constraint.id.as_str(), &constraint.value);
+                    }
+                    for constraint in defaults {
+                        constraints.entry(constraint.id.as_str()).or_insert(&constraint.value);
+                    }
+                    stack.push(id);
+                    flatten_in(typedefs, fields, &constraints, stack, flattened);
+                    stack.pop();
+                    continue;
+                }
+            }
+        }
+        let value = field.id().and_then(|id| constraints.get(id.as_str())).copied();
+        flattened.push(FlatField { field, value });
+    }
+}
+
+/// Return the bit width of an enum, checksum or custom field type,
+/// whose values are read as integers, or `None` for other types. See
+/// [`Layout::type_width`] for the width of structs.
+pub fn scalar_type_width(typedefs: &HashMap<&str, &ast::Decl>, type_id: &str) -> Option<usize> {
+    match typedefs.get(type_id) {
+        Some(ast::Decl::Enum { width, .. }) | Some(ast::Decl::Checksum { width, .. }) => {
+            Some(*width)
+        }
+        Some(ast::Decl::CustomField { width, .. }) => *width,
+        _ => None,
+    }
+}
+
+impl<'d> Layout<'d> {
+    pub fn new(grammar: &'d ast::Grammar) -> Self {
+        Layout {
+            typedefs: Cow::Owned(
+                grammar
+                    .declarations
+                    .iter()
+                    .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Create the layout of the declarations indexed by identifier.
+    pub fn from_typedefs(typedefs: &'d HashMap<&'d str, &'d ast::Decl>) -> Self {
+        Layout { typedefs: Cow::Borrowed(typedefs) }
+    }
+
+    /// Return the static bit width of a type, or `None` if the type
+    /// has a variable size or is undeclared.
+    pub fn type_width(&self, type_id: &str) -> Option<usize> {
+        self.type_width_in(type_id, &mut vec![])
+    }
+
+    fn type_width_in(&self, type_id: &str, stack: &mut Vec<&'d str>) -> Option<usize> {
+        match self.typedefs.get(type_id) {
+            Some(ast::Decl::Struct { id, fields, parent_id: None, .. }) => {
+                if stack.contains(&id.as_str()) {
+                    return None;
+                }
+                stack.push(id);
+                let width = self.flatten_with_widths(fields, stack).iter().map(|f| f.2).sum();
+                stack.pop();
+                width
+            }
+            _ => scalar_type_width(&self.typedefs, type_id),
+        }
+    }
+
+    /// Flatten the fields of a declaration, see [`flatten`]. Returns
+    /// the fields with the constrained value, if any, and their static
+    /// bit width.
+    fn flatten_with_widths(
+        &self,
+        fields: &'d [ast::Field],
+        stack: &mut Vec<&'d str>,
+    ) -> Vec<(&'d ast::Field, Option<String>, Option<usize>)> {
+        flatten(&self.typedefs, fields)
+            .into_iter()
+            .map(|FlatField { field, value }| {
+                let width = match field {
+                    ast::Field::Checksum { .. } => Some(0),
+                    ast::Field::Size { width, .. }
+                    | ast::Field::Count { width, .. }
+                    | ast::Field::Reserved { width, .. }
+                    | ast::Field::Scalar { width, .. }
+                    | ast::Field::Fixed { width: Some(width), .. } => Some(*width),
+                    ast::Field::Fixed { enum_id: Some(type_id), .. }
+                    | ast::Field::Typedef { type_id, .. } => self.type_width_in(type_id, stack),
+                    ast::Field::Array { width, type_id, size: Some(size), .. } => {
+                        match (width, type_id) {
+                            (Some(width), _) => Some(width * size),
+                            (_, Some(type_id)) => {
+                                self.type_width_in(type_id, stack).map(|w| w * size)
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                (field, value.map(constraint_value), width)
+            })
+            .collect()
+    }
+
+    /// Return the bit offset of the payload or body of a packet or
+    /// struct declaration, or of its end if it has none.
+    pub fn payload_offset(&self, decl_id: &str) -> Option<Offset<'d>> {
+        self.payload_offset_in(decl_id, &mut vec![])
+    }
+
+    fn payload_offset_in(&self, decl_id: &str, stack: &mut Vec<&'d str>) -> Option<Offset<'d>> {
+        let (id, fields, parent_id) = match self.typedefs.get(decl_id) {
+            Some(ast::Decl::Packet { id, fields, parent_id, .. })
+            | Some(ast::Decl::Struct { id, fields, parent_id, .. }) => (id, fields, parent_id),
+            _ => return None,
+        };
+        if stack.contains(&id.as_str()) {
+            return None;
+        }
+        stack.push(id);
+        let mut offset = self.start_offset(parent_id, stack);
+        for (field, _, width) in self.flatten_with_widths(fields, stack) {
+            if matches!(field, ast::Field::Payload { .. } | ast::Field::Body { .. }) {
+                break;
+            }
+            offset = offset.map(|offset| offset.after(field, width));
+        }
+        stack.pop();
+        offset
+    }
+
+    fn start_offset(
+        &self,
+        parent_id: &Option<ast::Symbol>,
+        stack: &mut Vec<&'d str>,
+    ) -> Option<Offset<'d>> {
+        match parent_id {
+            Some(parent_id) => self.payload_offset_in(parent_id, stack),
+            None => Some(Offset::Static(0)),
+        }
+    }
+
+    /// Return the fields of a packet or struct declaration, with
+    /// groups inlined, in declaration order.
+    pub fn fields(&self, decl: &'d ast::Decl) -> Vec<FieldLayout<'d>> {
+        let (id, fields, parent_id) = match decl {
+            ast::Decl::Packet { id, fields, parent_id, .. }
+            | ast::Decl::Struct { id, fields, parent_id, .. } => (id, fields, parent_id),
+            _ => return vec![],
+        };
+        let mut stack = vec![id.as_str()];
+        let mut offset = self.start_offset(parent_id, &mut stack);
+        let mut layout = vec![];
+        for (field, value, width) in self.flatten_with_widths(fields, &mut stack) {
+            layout.push(FieldLayout { field, value, offset, width });
+            offset = offset.map(|offset| offset.after(field, width));
+        }
+        layout
+    }
+
+    /// Return the canonical description of the wire format of a packet
+    /// or struct declaration, one field per line: the flattened fields
+    /// of its parents, with the fields of the declaration in place of
+    /// their payload, and the constrained values. Names are left out,
+    /// except the names of the fields referenced by size, count, and
+    /// checksum fields, so that renaming a field or a type does not
+    /// change the description. Returns `None` if a parent declaration
+    /// is undeclared or recursive.
+    pub fn wire_format(&self, decl: &'d ast::Decl) -> Option<String> {
+        self.wire_format_in(decl, &mut vec![])
+    }
+
+    /// Return the wire format hash of a packet or struct declaration,
+    /// the 64-bit FNV-1a hash of its [`Layout::wire_format`]. The hash
+    /// changes whenever the wire format of the declaration changes.
+    pub fn wire_hash(&self, decl: &'d ast::Decl) -> Option<u64> {
+        self.wire_format(decl).map(|format| stamp::hash(&[format.as_bytes()]))
+    }
+
+    fn wire_format_in(&self, decl: &'d ast::Decl, stack: &mut Vec<&'d str>) -> Option<String> {
+        let mut lineage = vec![decl];
+        while let Some(ast::Decl::Packet { parent_id: Some(parent_id), .. })
+        | Some(ast::Decl::Struct { parent_id: Some(parent_id), .. }) = lineage.last()
+        {
+            match self.typedefs.get(parent_id.as_str()) {
+                Some(parent) if !lineage.iter().any(|decl| std::ptr::eq(*decl, *parent)) => {
+                    lineage.push(parent)
+                }
+                _ => return None,
+            }
+        }
+        // The constraints of the closest declaration take precedence.
+        let mut constraints = HashMap::new();
+        for decl in &lineage {
+            if let ast::Decl::Packet { constraints: decl_constraints, .. }
+            | ast::Decl::Struct { constraints: decl_constraints, .. } = decl
+            {
+                for constraint in decl_constraints {
+                    constraints
+                        .entry(constraint.id.as_str())
+                        .or_insert_with(|| constraint_value(&constraint.value));
+                }
+            }
+        }
+        lineage.reverse();
+        let mut lines = vec![];
+        self.describe(&lineage, &constraints, stack, &mut lines);
+        Some(lines.join("\n"))
+    }
+
+    /// Describe the fields of the first declaration of the lineage,
+    /// with the fields of the next declarations in place of its payload.
+    fn describe(
+        &self,
+        lineage: &[&'d ast::Decl],
+        constraints: &HashMap<&'d str, String>,
+        stack: &mut Vec<&'d str>,
+        lines: &mut Vec<String>,
+    ) {
+        let (id, fields) = match lineage[0] {
+            ast::Decl::Packet { id, fields, .. } | ast::Decl::Struct { id, fields, .. } => {
+                (id, fields)
+            }
+            _ => return,
+        };
+        if stack.contains(&id.as_str()) {
+            return;
+        }
+        stack.push(id);
+        for (field, value, width) in self.flatten_with_widths(fields, stack) {
+            if matches!(field, ast::Field::Payload { .. } | ast::Field::Body { .. })
+                && lineage.len() > 1
+            {
+                self.describe(&lineage[1..], constraints, stack, lines);
+                continue;
+            }
+            let mut line = self.field_kind(field, stack);
+            match width {
+                Some(width) => line.push_str(&format!(" : {}", width)),
+                None => line.push_str(" : ?"),
+            }
+            let value =
+                value.or_else(|| field.id().and_then(|id| constraints.get(id.as_str())).cloned());
+            if let Some(value) = value {
+                line.push_str(&format!(" = {}", value));
+            }
+            lines.push(line);
+        }
+        stack.pop();
+    }
+
+    fn field_kind(&self, field: &'d ast::Field, stack: &mut Vec<&'d str>) -> String {
+        match field {
+            ast::Field::Checksum { field_id, .. } => format!("checksum({})", field_id),
+            ast::Field::Padding { width, .. } => format!("padding({})", width),
+            ast::Field::Size { field_id, .. } => format!("size({})", field_id),
+            ast::Field::Count { field_id, .. } => format!("count({})", field_id),
+            ast::Field::Body { .. } => "body".to_owned(),
+            ast::Field::Payload { size_modifier, .. } => {
+                format!("payload{}", size_modifier.as_deref().unwrap_or(""))
+            }
+            ast::Field::Fixed { value: Some(value), .. } => format!("fixed {:#x}", value),
+            ast::Field::Fixed { enum_id: Some(enum_id), tag_id: Some(tag_id), .. } => {
+                match self.typedefs.get(enum_id.as_str()) {
+                    Some(ast::Decl::Enum { tags, .. }) => {
+                        match tags.iter().find(|tag| &tag.id == tag_id) {
+                            Some(tag) => format!("fixed {:#x}", tag.value),
+                            None => "fixed ?".to_owned(),
+                        }
+                    }
+                    _ => "fixed ?".to_owned(),
+                }
+            }
+            ast::Field::Fixed { .. } => "fixed ?".to_owned(),
+            ast::Field::Reserved { .. } => "reserved".to_owned(),
+            ast::Field::Array { width, type_id, size_modifier, size, .. } => {
+                let element = match (width, type_id) {
+                    (Some(width), _) => format!("{}", width),
+                    (_, Some(type_id)) => self.type_kind(type_id, stack),
+                    _ => "?".to_owned(),
+                };
+                format!(
+                    "array[{}{}] of {}",
+                    size.map(|size| size.to_string()).unwrap_or_default(),
+                    size_modifier.as_deref().unwrap_or(""),
+                    element
+                )
+            }
+            ast::Field::Scalar { .. } => "scalar".to_owned(),
+            ast::Field::Typedef { type_id, .. } => self.type_kind(type_id, stack),
+            ast::Field::Group { .. } => "group".to_owned(),
+        }
+    }
+
+    /// Describe a type, with the wire format of structs in place.
+    fn type_kind(&self, type_id: &str, stack: &mut Vec<&'d str>) -> String {
+        match self.typedefs.get(type_id) {
+            Some(ast::Decl::Enum { .. }) => "enum".to_owned(),
+            Some(ast::Decl::Checksum { .. }) => "checksum".to_owned(),
+            Some(ast::Decl::CustomField { .. }) => "custom".to_owned(),
+            Some(decl @ ast::Decl::Struct { .. }) => match self.wire_format_in(decl, stack) {
+                Some(format) => format!("struct {{ {} }}", format.replace('\n', ", ")),
+                None => "struct ?".to_owned(),
+            },
+            _ => "?".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_fields() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            group Header { op: 8, len: 8 }
+            packet Command { Header { op = 1 }, _payload_ }
+            packet Read : Command { data: 8[], crc: 16, status: 8 }
+            "#,
+        );
+        let layout = Layout::new(&grammar);
+        let fields = layout.fields(grammar.declarations.get(2).unwrap());
+        let summary: Vec<_> = fields
+            .iter()
+            .map(|f| match f.offset {
+                Some(Offset::Static(offset)) => format!("{}", offset),
+                Some(Offset::Dynamic { after, bits }) => {
+                    format!("{}+{}", after.id().unwrap(), bits)
+                }
+                None => "?".to_owned(),
+            })
+            .collect();
+        assert_eq!(summary, vec!["16", "data+0", "data+16"]);
+        assert_eq!(fields[0].width, None);
+        assert_eq!(fields[1].width, Some(16));
+
+        let fields = layout.fields(grammar.declarations.get(1).unwrap());
+        assert_eq!(fields[0].value.as_deref(), Some("1"));
+        assert_eq!(layout.payload_offset("Command").and_then(|o| o.static_offset()), Some(16));
+        assert_eq!(layout.type_width("Header"), None);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            group Inner { a: 8, b: 8 }
+            group Outer (b = 2) { Inner { a = 1 }, c: 8 }
+            group Loop { Loop, d: 8 }
+            packet P { Outer, Loop }
+            "#,
+        );
+        let typedefs: HashMap<_, _> = grammar
+            .declarations
+            .iter()
+            .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+            .collect();
+        let fields = match typedefs["P"] {
+            ast::Decl::Packet { fields, .. } => fields,
+            _ => unreachable!(),
+        };
+        let flattened: Vec<_> = flatten(&typedefs, fields)
+            .iter()
+            .map(|f| (f.field.id().map(|id| id.to_string()), f.value.map(constraint_value)))
+            .collect();
+        assert_eq!(
+            flattened,
+            vec![
+                (Some("a".to_owned()), Some("1".to_owned())),
+                (Some("b".to_owned()), Some("2".to_owned())),
+                (Some("c".to_owned()), None),
+                (None, None),
+                (Some("d".to_owned()), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wire_hash() {
+        let wire_hashes = |text: &str| {
+            let grammar = grammar!(text);
+            let layout = Layout::new(&grammar);
+            let last = grammar.declarations.last().unwrap();
+            (layout.wire_format(last).unwrap(), layout.wire_hash(last).unwrap())
+        };
+        let (format, hash) = wire_hashes(
+            r#"
+            little_endian_packets
+            struct Point { x: 8, y: 8 }
+            packet Parent { op: 8, _size_(_payload_): 8, _payload_, crc: 16 }
+            packet Child : Parent (op = 2) { point: Point, data: 8[] }
+            "#,
+        );
+        assert_eq!(
+            format,
+            "scalar : 8 = 2\nsize(_payload_) : 8\nstruct { scalar : 8, scalar : 8 } : 16\n\
+             array[] of 8 : ?\nscalar : 16"
+        );
+        // Renaming fields and types does not change the hash.
+        let (_, renamed) = wire_hashes(
+            r#"
+            little_endian_packets
+            struct Coordinates { a: 8, b: 8 }
+            packet Parent { opcode: 8, _size_(_payload_): 8, _payload_, fcs: 16 }
+            packet Child : Parent (opcode = 2) { position: Coordinates, bytes: 8[] }
+            "#,
+        );
+        assert_eq!(renamed, hash);
+        let (_, changed) = wire_hashes(
+            r#"
+            little_endian_packets
+            struct Point { x: 8, y: 16 }
+            packet Parent { op: 8, _size_(_payload_): 8, _payload_, crc: 16 }
+            packet Child : Parent (op = 2) { point: Point, data: 8[] }
+            "#,
+        );
+        assert_ne!(changed, hash);
+    }
+}
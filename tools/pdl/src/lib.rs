@@ -0,0 +1,33 @@
+//! PDL library for build scripts.
+//!
+//! Wraps the parser, the linter and the generators of the `pdl` tool,
+//! see [`Config`]. The [`playground`] module exposes the toolchain
+//! without filesystem access, for WebAssembly builds.
+
+pub mod ast;
+pub mod backends;
+mod build;
+mod clock;
+pub mod corpus;
+pub mod decoder;
+pub mod diagnostics;
+pub mod encoder;
+pub mod fuzz;
+pub mod golden;
+pub mod interpreter;
+pub mod layout;
+pub mod lint;
+pub mod parser;
+pub mod playground;
+pub mod printer;
+pub mod registry;
+pub mod stamp;
+pub mod stdlib;
+#[cfg(test)]
+#[allow(dead_code)]
+mod test_utils;
+pub mod vectors;
+pub mod visit;
+
+pub use backends::Backend;
+pub use build::{Config, Error};
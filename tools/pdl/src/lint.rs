@@ -1,16 +1,34 @@
-use codespan_reporting::diagnostic::Diagnostic;
-use codespan_reporting::files;
-use codespan_reporting::term;
-use codespan_reporting::term::termcolor;
-use std::collections::HashMap;
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle, Severity};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::{fmt, ops};
 
 use crate::ast::*;
+use crate::clock::Instant;
+use crate::diagnostics;
+use crate::layout::{Layout, Offset};
 
 /// Aggregate linter diagnostics.
 pub struct LintDiagnostics {
     pub diagnostics: Vec<Diagnostic<FileId>>,
 }
 
+/// Time spent in each phase of the analysis of a grammar, see
+/// [`Grammar::lint_with_timings`].
+#[derive(Debug, Default, Clone)]
+pub struct LintTimings {
+    /// Construction of the declaration scopes.
+    pub scope: Duration,
+    /// Inheritance and group insertion, in `Scope::finalize`.
+    pub finalize: Duration,
+    /// Checks of the declarations.
+    pub checks: Duration,
+    /// Time spent in the checks of each named declaration.
+    pub declarations: Vec<(String, Duration)>,
+}
+
 /// Implement lint checks for an AST element.
 pub trait Lintable {
     /// Generate lint warnings and errors for the
@@ -26,30 +44,42 @@ pub trait Lintable {
 struct FieldPath<'d>(Vec<&'d Field>);
 
 /// Gather information about the full grammar declaration.
-struct Scope<'d> {
+pub struct Scope<'d> {
+    // Declarations of the grammar.
+    declarations: &'d Arena<Decl>,
+
     // Collection of Group, Packet, Enum, Struct, Checksum, and CustomField declarations.
-    typedef: HashMap<String, &'d Decl>,
+    typedef: HashMap<&'d str, &'d Decl>,
+
+    // Keys of the declarations of typedef.
+    keys: HashMap<&'d str, DeclId>,
 
     // Collection of Packet, Struct, and Group scope declarations.
-    scopes: HashMap<&'d Decl, PacketScope<'d>>,
+    scopes: HashMap<DeclId, PacketScope<'d>>,
+
+    // Packet, Struct, and Group declarations in reverse topological order.
+    order: Vec<&'d Decl>,
+
+    // Packet and Struct declarations, indexed by parent identifier.
+    children: HashMap<&'d str, Vec<&'d Decl>>,
 }
 
 /// Gather information about a Packet, Struct, or Group declaration.
 struct PacketScope<'d> {
     // Checksum starts, indexed by the checksum field id.
-    checksums: HashMap<String, FieldPath<'d>>,
+    checksums: HashMap<&'d str, FieldPath<'d>>,
 
     // Size or count fields, indexed by the field id.
-    sizes: HashMap<String, FieldPath<'d>>,
+    sizes: HashMap<&'d str, FieldPath<'d>>,
 
     // Payload or body field.
     payload: Option<FieldPath<'d>>,
 
     // Typedef, scalar, array fields.
-    named: HashMap<String, FieldPath<'d>>,
+    named: HashMap<&'d str, FieldPath<'d>>,
 
     // Group fields.
-    groups: HashMap<String, &'d Field>,
+    groups: HashMap<&'d str, &'d Field>,
 
     // Flattened field declarations.
     // Contains field declarations from the original Packet, Struct, or Group,
@@ -59,27 +89,140 @@ struct PacketScope<'d> {
     fields: Vec<FieldPath<'d>>,
 
     // Constraint declarations gathered from Group inlining.
-    constraints: HashMap<String, &'d Constraint>,
+    constraints: HashMap<&'d str, &'d Constraint>,
+
+    // Default values of Group fields, gathered from Group declarations
+    // and inlining. Defaults are overridden by the constraints of the
+    // Group insertion, and resolved into constraints in Packet and
+    // Struct declarations.
+    defaults: HashMap<&'d str, &'d Constraint>,
 
     // Local and inherited field declarations. Only named fields are preserved.
     // Saved here for reference for parent constraint resolving.
-    all_fields: HashMap<String, &'d Field>,
+    all_fields: Arc<Inherited<'d, &'d Field>>,
 
     // Local and inherited constraint declarations.
     // Saved here for constraint conflict checks.
-    all_constraints: HashMap<String, &'d Constraint>,
+    all_constraints: Arc<Inherited<'d, &'d Constraint>>,
+}
+
+/// Map of local declarations, layered over the map of the parent
+/// declaration. The parent map is shared by all the children instead of
+/// being copied into each of them.
+#[derive(Debug, Clone)]
+struct Inherited<'d, T> {
+    local: HashMap<&'d str, T>,
+    parent: Option<Arc<Inherited<'d, T>>>,
+}
+
+impl<'d, T: Copy> Inherited<'d, T> {
+    fn new(parent: Option<Arc<Inherited<'d, T>>>) -> Arc<Self> {
+        Arc::new(Inherited { local: HashMap::new(), parent })
+    }
+
+    /// Return the closest declaration of `id`.
+    fn get(&self, id: &str) -> Option<T> {
+        let mut map = Some(self);
+        while let Some(current) = map {
+            if let Some(value) = current.local.get(id) {
+                return Some(*value);
+            }
+            map = current.parent.as_deref();
+        }
+        None
+    }
+
+    /// Insert a local declaration, returning the previous local or
+    /// inherited declaration of `id`.
+    fn insert(&mut self, id: &'d str, value: T) -> Option<T> {
+        let prev = self.get(id);
+        self.local.insert(id, value);
+        prev
+    }
+
+    /// Return the closest declarations of all identifiers.
+    fn values(&self) -> Vec<T> {
+        let mut values = HashMap::new();
+        let mut map = Some(self);
+        while let Some(current) = map {
+            for (id, value) in &current.local {
+                values.entry(*id).or_insert(*value);
+            }
+            map = current.parent.as_deref();
+        }
+        values.into_values().collect()
+    }
+}
+
+/// Bounds of the encoded size of a declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeBound {
+    pub min_bits: usize,
+    /// `None` if the size is unbounded.
+    pub max_bits: Option<usize>,
+    /// True if the minimum and maximum sizes are equal.
+    pub fixed: bool,
 }
 
-impl std::cmp::Eq for &Decl {}
-impl<'d> std::cmp::PartialEq for &'d Decl {
-    fn eq(&self, other: &Self) -> bool {
-        std::ptr::eq(*self, *other)
+impl SizeBound {
+    fn range(min_bits: usize, max_bits: Option<usize>) -> SizeBound {
+        SizeBound { min_bits, max_bits, fixed: max_bits == Some(min_bits) }
+    }
+
+    fn exact(bits: usize) -> SizeBound {
+        SizeBound::range(bits, Some(bits))
+    }
+
+    fn unbounded() -> SizeBound {
+        SizeBound::range(0, None)
+    }
+
+    /// Return the bounds of `min_count` to `max_count` repetitions.
+    fn repeat(self, min_count: usize, max_count: Option<usize>) -> SizeBound {
+        let max_bits = match (self.max_bits, max_count) {
+            (Some(0), _) => Some(0),
+            (Some(max_bits), Some(max_count)) => max_bits.checked_mul(max_count),
+            _ => None,
+        };
+        SizeBound::range(self.min_bits.saturating_mul(min_count), max_bits)
+    }
+}
+
+impl ops::Add for SizeBound {
+    type Output = SizeBound;
+
+    fn add(self, other: SizeBound) -> SizeBound {
+        let max_bits = match (self.max_bits, other.max_bits) {
+            (Some(lhs), Some(rhs)) => lhs.checked_add(rhs),
+            _ => None,
+        };
+        SizeBound::range(self.min_bits.saturating_add(other.min_bits), max_bits)
+    }
+}
+
+impl fmt::Display for SizeBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max_bits {
+            _ if self.fixed => write!(f, "{} bits", self.min_bits),
+            Some(max_bits) => write!(f, "{} to {} bits", self.min_bits, max_bits),
+            None => write!(f, "at least {} bits", self.min_bits),
+        }
     }
 }
 
-impl<'d> std::hash::Hash for &'d Decl {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        std::ptr::hash(*self, state);
+/// Value of a constrained field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintValue<'d> {
+    Scalar(usize),
+    Tag { enum_id: &'d str, tag_id: &'d str, value: usize },
+}
+
+impl ConstraintValue<'_> {
+    /// Return the integer value of the field.
+    pub fn value(&self) -> usize {
+        match self {
+            ConstraintValue::Scalar(value) | ConstraintValue::Tag { value, .. } => *value,
+        }
     }
 }
 
@@ -94,23 +237,28 @@ impl LintDiagnostics {
         LintDiagnostics { diagnostics: vec![] }
     }
 
-    pub fn print(
-        &self,
-        sources: &SourceDatabase,
-        color: termcolor::ColorChoice,
-    ) -> Result<(), files::Error> {
-        let writer = termcolor::StandardStream::stderr(color);
-        let config = term::Config::default();
-        for d in self.diagnostics.iter() {
-            term::emit(&mut writer.lock(), &config, sources, d)?;
-        }
-        Ok(())
+    /// Return the diagnostics in the structured representation of
+    /// [`crate::diagnostics`].
+    pub fn structured(&self) -> Vec<diagnostics::Diagnostic> {
+        self.diagnostics.iter().map(diagnostics::Diagnostic::from).collect()
     }
 
     fn push(&mut self, diagnostic: Diagnostic<FileId>) {
         self.diagnostics.push(diagnostic)
     }
 
+    /// Sort the diagnostics by the location of their primary label.
+    /// The sort is stable: diagnostics reported at the same location
+    /// keep their relative order.
+    fn sort(&mut self) {
+        self.diagnostics.sort_by_key(|d| {
+            d.labels
+                .iter()
+                .find(|label| matches!(label.style, LabelStyle::Primary))
+                .map(|label| (label.file_id, label.range.start, label.range.end))
+        })
+    }
+
     fn err_undeclared(&mut self, id: &str, loc: &SourceRange) {
         self.diagnostics.push(
             Diagnostic::error()
@@ -135,12 +283,22 @@ fn bit_width(val: usize) -> usize {
     usize::BITS as usize - val.leading_zeros() as usize
 }
 
+/// Return the maximum value of an unsigned integer field, or `None` if
+/// it does not fit `usize`.
+fn max_value(width: usize) -> Option<usize> {
+    match width {
+        _ if width < usize::BITS as usize => Some((1 << width) - 1),
+        _ if width == usize::BITS as usize => Some(usize::MAX),
+        _ => None,
+    }
+}
+
 impl<'d> PacketScope<'d> {
     /// Insert a field declaration into a packet scope.
     fn insert(&mut self, field: &'d Field, result: &mut LintDiagnostics) {
         match field {
             Field::Checksum { loc, field_id, .. } => {
-                self.checksums.insert(field_id.clone(), FieldPath(vec![field])).map(|prev| {
+                self.checksums.insert(field_id.as_str(), FieldPath(vec![field])).map(|prev| {
                     result.push(
                         Diagnostic::error()
                             .with_message(format!(
@@ -160,7 +318,7 @@ impl<'d> PacketScope<'d> {
             Field::Padding { .. } | Field::Reserved { .. } | Field::Fixed { .. } => None,
 
             Field::Size { loc, field_id, .. } | Field::Count { loc, field_id, .. } => {
-                self.sizes.insert(field_id.clone(), FieldPath(vec![field])).map(|prev| {
+                self.sizes.insert(field_id.as_str(), FieldPath(vec![field])).map(|prev| {
                     result.push(
                         Diagnostic::error()
                             .with_message(format!(
@@ -196,11 +354,11 @@ impl<'d> PacketScope<'d> {
             | Field::Scalar { loc, id, .. }
             | Field::Typedef { loc, id, .. } => self
                 .named
-                .insert(id.clone(), FieldPath(vec![field]))
+                .insert(id.as_str(), FieldPath(vec![field]))
                 .map(|prev| result.err_redeclared(id, "field", loc, prev.loc())),
 
             Field::Group { loc, group_id, .. } => {
-                self.groups.insert(group_id.clone(), field).map(|prev| {
+                self.groups.insert(group_id.as_str(), field).map(|prev| {
                     result.push(
                         Diagnostic::error()
                             .with_message(format!("duplicate group `{}` insertion", group_id))
@@ -226,12 +384,12 @@ impl<'d> PacketScope<'d> {
         result: &mut LintDiagnostics,
     ) {
         // Check constraints.
-        assert!(self.all_constraints.is_empty());
-        self.all_constraints = parent.all_constraints.clone();
+        assert!(self.all_constraints.local.is_empty());
+        let mut all_constraints = Inherited::new(Some(parent.all_constraints.clone()));
+        let all_constraints_mut = Arc::get_mut(&mut all_constraints).unwrap();
         for constraint in constraints {
             lint_constraint(scope, parent, constraint, result);
-            let id = constraint.id.clone();
-            if let Some(prev) = self.all_constraints.insert(id, constraint) {
+            if let Some(prev) = all_constraints_mut.insert(constraint.id.as_str(), constraint) {
                 result.push(
                     Diagnostic::error()
                         .with_message(format!("duplicate constraint on field `{}`", constraint.id))
@@ -247,11 +405,12 @@ impl<'d> PacketScope<'d> {
         // but generate no duplication warnings, the constraints
         // do no apply to the same field set.
         for (id, constraint) in self.constraints.iter() {
-            self.all_constraints.insert(id.clone(), constraint);
+            all_constraints_mut.insert(id, constraint);
         }
+        self.all_constraints = all_constraints;
 
-        // Save parent fields.
-        self.all_fields = parent.all_fields.clone();
+        // Link parent fields.
+        self.all_fields = Inherited::new(Some(parent.all_fields.clone()));
     }
 
     /// Insert group field declarations into a packet scope.
@@ -276,7 +435,7 @@ impl<'d> PacketScope<'d> {
         }
 
         for (id, field) in packet_scope.checksums.iter() {
-            if let Some(prev) = self.checksums.insert(id.clone(), field.clone()) {
+            if let Some(prev) = self.checksums.insert(id, field.clone()) {
                 err_redeclared_by_group(
                     result,
                     format!("inserted group redeclares checksum start for `{}`", id),
@@ -286,7 +445,7 @@ impl<'d> PacketScope<'d> {
             }
         }
         for (id, field) in packet_scope.sizes.iter() {
-            if let Some(prev) = self.sizes.insert(id.clone(), field.clone()) {
+            if let Some(prev) = self.sizes.insert(id, field.clone()) {
                 err_redeclared_by_group(
                     result,
                     format!("inserted group redeclares size or count for `{}`", id),
@@ -308,7 +467,7 @@ impl<'d> PacketScope<'d> {
         for (id, field) in packet_scope.named.iter() {
             let mut path = vec![group];
             path.extend(field.0.clone());
-            if let Some(prev) = self.named.insert(id.clone(), FieldPath(path)) {
+            if let Some(prev) = self.named.insert(id, FieldPath(path)) {
                 err_redeclared_by_group(
                     result,
                     format!("inserted group redeclares field `{}`", id),
@@ -327,14 +486,13 @@ impl<'d> PacketScope<'d> {
 
         // Append group constraints to the caller packet_scope.
         for (id, constraint) in packet_scope.constraints.iter() {
-            self.constraints.insert(id.clone(), constraint);
+            self.constraints.insert(id, constraint);
         }
 
         // Add constraints to the packet_scope, checking for duplicate constraints.
         for constraint in constraints {
             lint_constraint(scope, packet_scope, constraint, result);
-            let id = constraint.id.clone();
-            if let Some(prev) = self.constraints.insert(id, constraint) {
+            if let Some(prev) = self.constraints.insert(constraint.id.as_str(), constraint) {
                 result.push(
                     Diagnostic::error()
                         .with_message(format!("duplicate constraint on field `{}`", constraint.id))
@@ -345,14 +503,68 @@ impl<'d> PacketScope<'d> {
                 )
             }
         }
+
+        // Append the group defaults which are not overridden.
+        for (id, constraint) in packet_scope.defaults.iter() {
+            if !self.constraints.contains_key(id) {
+                self.defaults.insert(id, constraint);
+            }
+        }
+    }
+
+    /// Set the default values declared by a group. They override the
+    /// defaults of the groups it inserts, but not their constraints.
+    fn set_defaults(
+        &mut self,
+        scope: &Scope,
+        defaults: impl Iterator<Item = &'d Constraint>,
+        result: &mut LintDiagnostics,
+    ) {
+        let mut declared: HashMap<&str, &Constraint> = HashMap::new();
+        for constraint in defaults {
+            lint_constraint(scope, self, constraint, result);
+            if let Some(prev) = declared.insert(constraint.id.as_str(), constraint) {
+                result.push(
+                    Diagnostic::error()
+                        .with_message(format!("duplicate default value for `{}`", constraint.id))
+                        .with_labels(vec![
+                            constraint.loc.primary(),
+                            prev.loc.secondary().with_message("the default is first set here"),
+                        ]),
+                )
+            } else if let Some(prev) = self.constraints.get(constraint.id.as_str()) {
+                result.push(
+                    Diagnostic::error()
+                        .with_message(format!("conflicting default value for `{}`", constraint.id))
+                        .with_labels(vec![
+                            constraint.loc.primary(),
+                            prev.loc
+                                .secondary()
+                                .with_message("the field is constrained by the group insertion"),
+                        ]),
+                )
+            } else {
+                self.defaults.insert(constraint.id.as_str(), constraint);
+            }
+        }
+    }
+
+    /// Resolve the defaults left after group inlining into constraints.
+    fn resolve_defaults(&mut self) {
+        for (id, constraint) in self.defaults.drain() {
+            self.constraints.insert(id, constraint);
+        }
     }
 
     /// Cleanup scope after processing all fields.
     fn finalize(&mut self, result: &mut LintDiagnostics) {
         // Check field shadowing.
+        // The fields are inserted before the children link to the map,
+        // so the map is not shared yet.
+        let all_fields = Arc::make_mut(&mut self.all_fields);
         for f in self.fields.iter().map(|f| f.0.last().unwrap()) {
             if let Some(id) = f.id() {
-                if let Some(prev) = self.all_fields.insert(id.clone(), f) {
+                if let Some(prev) = all_fields.insert(id.as_str(), f) {
                     result.push(
                         Diagnostic::warning()
                             .with_message(format!("declaration of `{}` shadows parent field", id))
@@ -377,7 +589,7 @@ fn lint_constraint(
     result: &mut LintDiagnostics,
 ) {
     // Validate constraint value types.
-    match (packet_scope.all_fields.get(&constraint.id), &constraint.value) {
+    match (packet_scope.all_fields.get(constraint.id.as_str()), &constraint.value) {
         (
             Some(Field::Scalar { loc: field_loc, width, .. }),
             Expr::Integer { value, loc: value_loc, .. },
@@ -396,7 +608,7 @@ fn lint_constraint(
         }
 
         (Some(Field::Typedef { type_id, loc: field_loc, .. }), _) => {
-            match (scope.typedef.get(type_id), &constraint.value) {
+            match (scope.typedef.get(type_id.as_str()), &constraint.value) {
                 (Some(Decl::Enum { tags, .. }), Expr::Identifier { name, loc: name_loc, .. }) => {
                     if !tags.iter().any(|t| &t.id == name) {
                         result.push(
@@ -439,7 +651,15 @@ fn lint_constraint(
                 field_loc.secondary().with_message("the value is used here"),
             ]))
         }
-        (Some(_), _) => unreachable!(),
+        (Some(field), _) => {
+            result.push(Diagnostic::error().with_message("invalid constraint").with_labels(vec![
+                constraint.loc.primary(),
+                field.loc().secondary().with_message(format!(
+                    "`{}` is not a scalar or enum field",
+                    constraint.id
+                )),
+            ]))
+        }
         (None, _) => result.push(
             Diagnostic::error()
                 .with_message(format!("undeclared identifier `{}`", constraint.id))
@@ -449,15 +669,213 @@ fn lint_constraint(
 }
 
 impl<'d> Scope<'d> {
-    // Sort Packet, Struct, and Group declarations by reverse topological
-    // orde, and inline Group fields.
+    /// Gather the declarations of the grammar.
+    /// Returns the lint diagnostics if the declarations are invalid.
+    pub fn new(grammar: &'d Grammar) -> Result<Scope<'d>, LintDiagnostics> {
+        let mut result = LintDiagnostics::new();
+        let scope = grammar.scope(&mut result);
+        if result.diagnostics.iter().any(|d| matches!(d.severity, Severity::Error | Severity::Bug))
+        {
+            Err(result)
+        } else {
+            Ok(scope)
+        }
+    }
+
+    /// Return the bounds of the encoded size of the declaration `id`,
+    /// including the fields inherited from its parents, or `None` if
+    /// `id` is not declared. Payloads are bounded by their size field,
+    /// arrays by their static count, or their count or size field.
+    pub fn decl_size(&self, id: &str) -> Option<SizeBound> {
+        self.typedef.get(id).map(|_| self.type_size(id, 0))
+    }
+
+    /// Return the values of the fields constrained by the declaration
+    /// `id`, its parents, and the groups they insert, indexed by field
+    /// identifier. Returns `None` if `id` is not a packet or struct
+    /// declaration.
+    pub fn constraint_values(&self, id: &str) -> Option<BTreeMap<&'d str, ConstraintValue<'d>>> {
+        let decl = self.typedef.get(id)?;
+        if !matches!(decl, Decl::Packet { .. } | Decl::Struct { .. }) {
+            return None;
+        }
+        // The constraints of the groups inserted in the root declaration
+        // are not inherited.
+        let mut root = *decl;
+        for _ in 0..self.typedef.len() {
+            match root {
+                Decl::Packet { parent_id: Some(parent_id), .. }
+                | Decl::Struct { parent_id: Some(parent_id), .. } => {
+                    match self.typedef.get(parent_id.as_str()) {
+                        Some(parent) => root = parent,
+                        None => break,
+                    }
+                }
+                _ => break,
+            }
+        }
+        let root_constraints = self.packet_scope(root)?.constraints.values();
+        let packet_scope = self.packet_scope(decl)?;
+        let mut values = BTreeMap::new();
+        for constraint in root_constraints.copied().chain(packet_scope.all_constraints.values()) {
+            let field = packet_scope.all_fields.get(constraint.id.as_str());
+            let value = match (field, &constraint.value) {
+                (Some(Field::Scalar { .. }), Expr::Integer { value, .. }) => {
+                    ConstraintValue::Scalar(*value)
+                }
+                (Some(Field::Typedef { type_id, .. }), Expr::Identifier { name, .. }) => {
+                    match self.typedef.get(type_id.as_str()) {
+                        Some(Decl::Enum { id, tags, .. }) => {
+                            match tags.iter().find(|t| &t.id == name) {
+                                Some(tag) => ConstraintValue::Tag {
+                                    enum_id: id,
+                                    tag_id: &tag.id,
+                                    value: tag.value,
+                                },
+                                None => continue,
+                            }
+                        }
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+            values.insert(constraint.id.as_str(), value);
+        }
+        Some(values)
+    }
+
+    /// Return the key of the declaration `id`.
+    pub fn key(&self, id: &str) -> Option<DeclId> {
+        self.keys.get(id).copied()
+    }
+
+    /// Return the declaration of a key.
+    pub fn decl(&self, key: DeclId) -> &'d Decl {
+        &self.declarations[key]
+    }
+
+    /// Return the scope of a packet, struct, or group declaration.
+    fn packet_scope(&self, decl: &Decl) -> Option<&PacketScope<'d>> {
+        self.scopes.get(&self.key(decl.id()?)?)
+    }
+
+    /// Iterate over the packet, struct, and group declarations, each
+    /// after its parent and the structs and groups it uses.
+    /// Independent declarations are listed in source order.
+    pub fn ordered_declarations(&self) -> impl Iterator<Item = &'d Decl> + '_ {
+        self.order.iter().copied()
+    }
+
+    /// Iterate over the packet or struct declarations whose parent is
+    /// `id`, in source order.
+    pub fn iter_children(&self, id: &str) -> impl Iterator<Item = &'d Decl> + '_ {
+        self.children.get(id).into_iter().flatten().copied()
+    }
+
+    fn type_size(&self, type_id: &str, depth: usize) -> SizeBound {
+        // Recursive declarations have an unbounded size.
+        if depth > self.typedef.len() {
+            return SizeBound::unbounded();
+        }
+        match self.typedef.get(type_id) {
+            Some(Decl::Enum { width, .. }) | Some(Decl::Checksum { width, .. }) => {
+                SizeBound::exact(*width)
+            }
+            Some(Decl::CustomField { width: Some(width), .. }) => SizeBound::exact(*width),
+            Some(decl) => self.inherited_size(decl, None, depth),
+            None => SizeBound::unbounded(),
+        }
+    }
+
+    /// Return the size of a declaration and its parents, with the
+    /// payload size if known.
+    fn inherited_size(&self, decl: &Decl, payload: Option<SizeBound>, depth: usize) -> SizeBound {
+        let size = self.fields_size(decl, payload, depth);
+        let parent = match decl {
+            Decl::Packet { parent_id: Some(parent_id), .. }
+            | Decl::Struct { parent_id: Some(parent_id), .. } => {
+                self.typedef.get(parent_id.as_str())
+            }
+            _ => None,
+        };
+        match parent {
+            Some(parent) if depth < self.typedef.len() => {
+                self.inherited_size(parent, Some(size), depth + 1)
+            }
+            _ => size,
+        }
+    }
+
+    /// Return the size of the local fields of a declaration, with the
+    /// payload size if known.
+    fn fields_size(&self, decl: &Decl, payload: Option<SizeBound>, depth: usize) -> SizeBound {
+        let packet_scope = match self.packet_scope(decl) {
+            Some(packet_scope) => packet_scope,
+            None => return SizeBound::unbounded(),
+        };
+        let size_field_bound = |id: &str| match packet_scope.sizes.get(id).map(|f| f.0.last()) {
+            Some(Some(Field::Size { width, .. })) => {
+                SizeBound::range(0, max_value(*width).and_then(|bytes| bytes.checked_mul(8)))
+            }
+            _ => SizeBound::unbounded(),
+        };
+        let payload = payload.unwrap_or_else(|| match &packet_scope.payload {
+            Some(FieldPath(path)) => match path.last() {
+                Some(Field::Body { .. }) => size_field_bound("_body_"),
+                _ => size_field_bound("_payload_"),
+            },
+            None => SizeBound::exact(0),
+        });
+        let mut sizes = vec![];
+        for field in packet_scope.fields.iter().map(|f| *f.0.last().unwrap()) {
+            let size = match field {
+                Field::Checksum { .. } => SizeBound::exact(0),
+                Field::Padding { width, .. } => {
+                    // The padding extends the preceding array.
+                    sizes.pop();
+                    SizeBound::exact(width * 8)
+                }
+                Field::Size { width, .. }
+                | Field::Count { width, .. }
+                | Field::Reserved { width, .. }
+                | Field::Scalar { width, .. }
+                | Field::Fixed { width: Some(width), .. } => SizeBound::exact(*width),
+                Field::Fixed { enum_id: Some(type_id), .. } | Field::Typedef { type_id, .. } => {
+                    self.type_size(type_id, depth + 1)
+                }
+                Field::Body { .. } | Field::Payload { .. } => payload,
+                Field::Array { id, width, type_id, size, .. } => {
+                    let element = match (width, type_id) {
+                        (Some(width), _) => SizeBound::exact(*width),
+                        (_, Some(type_id)) => self.type_size(type_id, depth + 1),
+                        _ => SizeBound::unbounded(),
+                    };
+                    match (size, packet_scope.sizes.get(id.as_str()).map(|f| f.0.last())) {
+                        (Some(size), _) => element.repeat(*size, Some(*size)),
+                        (None, Some(Some(Field::Count { width, .. }))) => {
+                            element.repeat(0, max_value(*width))
+                        }
+                        (None, Some(Some(Field::Size { .. }))) => size_field_bound(id),
+                        _ => element.repeat(0, None),
+                    }
+                }
+                Field::Fixed { .. } | Field::Group { .. } => SizeBound::unbounded(),
+            };
+            sizes.push(size)
+        }
+        sizes.into_iter().fold(SizeBound::exact(0), |total, size| total + size)
+    }
+
+    // Sort the Packet, Struct, and Group declarations reachable from
+    // `roots` by reverse topological order, and inline Group fields.
     // Raises errors and warnings for:
     //      - undeclared included Groups,
     //      - undeclared Typedef fields,
     //      - undeclared Packet or Struct parents,
     //      - recursive Group insertion,
     //      - recursive Packet or Struct inheritance.
-    fn finalize(&mut self, result: &mut LintDiagnostics) -> Vec<&'d Decl> {
+    fn finalize(&mut self, roots: &[DeclId], result: &mut LintDiagnostics) -> Vec<&'d Decl> {
         // Auxiliary function implementing BFS on Packet tree.
         enum Mark {
             Temporary,
@@ -465,18 +883,19 @@ impl<'d> Scope<'d> {
         }
         struct Context<'d> {
             list: Vec<&'d Decl>,
-            visited: HashMap<&'d Decl, Mark>,
-            scopes: HashMap<&'d Decl, PacketScope<'d>>,
+            visited: HashMap<DeclId, Mark>,
+            scopes: HashMap<DeclId, PacketScope<'d>>,
         }
 
         fn bfs<'s, 'd>(
-            decl: &'d Decl,
+            key: DeclId,
             context: &'s mut Context<'d>,
             scope: &Scope<'d>,
             result: &mut LintDiagnostics,
         ) -> Option<&'s PacketScope<'d>> {
-            match context.visited.get(&decl) {
-                Some(Mark::Permanent) => return context.scopes.get(&decl),
+            let decl = scope.decl(key);
+            match context.visited.get(&key) {
+                Some(Mark::Permanent) => return context.scopes.get(&key),
                 Some(Mark::Temporary) => {
                     result.push(
                         Diagnostic::error()
@@ -500,14 +919,14 @@ impl<'d> Scope<'d> {
                 _ => return None,
             };
 
-            context.visited.insert(decl, Mark::Temporary);
+            context.visited.insert(key, Mark::Temporary);
             let mut lscope = decl.scope(result).unwrap();
 
             // Iterate over Struct and Group fields.
             for f in fields {
                 match f {
                     Field::Group { group_id, constraints, .. } => {
-                        match scope.typedef.get(group_id) {
+                        match scope.typedef.get(group_id.as_str()) {
                             None => result.push(
                                 Diagnostic::error()
                                     .with_message(format!(
@@ -516,9 +935,11 @@ impl<'d> Scope<'d> {
                                     ))
                                     .with_labels(vec![f.loc().primary()]),
                             ),
-                            Some(group_decl @ Decl::Group { .. }) => {
+                            Some(Decl::Group { .. }) => {
                                 // Recurse to flatten the inserted group.
-                                if let Some(rscope) = bfs(group_decl, context, scope, result) {
+                                if let Some(rscope) =
+                                    bfs(scope.keys[group_id.as_str()], context, scope, result)
+                                {
                                     // Inline the group fields and constraints into
                                     // the current scope.
                                     lscope.inline(scope, rscope, f, constraints.iter(), result)
@@ -537,7 +958,7 @@ impl<'d> Scope<'d> {
                     }
                     Field::Typedef { type_id, .. } => {
                         lscope.fields.push(FieldPath(vec![f]));
-                        match scope.typedef.get(type_id) {
+                        match scope.typedef.get(type_id.as_str()) {
                             None => result.push(
                                 Diagnostic::error()
                                     .with_message(format!(
@@ -546,18 +967,32 @@ impl<'d> Scope<'d> {
                                     ))
                                     .with_labels(vec![f.loc().primary()]),
                             ),
-                            Some(struct_decl @ Decl::Struct { .. }) => {
-                                bfs(struct_decl, context, scope, result);
+                            Some(Decl::Struct { .. }) => {
+                                bfs(scope.keys[type_id.as_str()], context, scope, result);
                             }
                             Some(_) => (),
                         }
                     }
+                    Field::Array { type_id: Some(type_id), .. } => {
+                        lscope.fields.push(FieldPath(vec![f]));
+                        // Arrays of the enclosing struct are not recursive.
+                        if let Some(Decl::Struct { .. }) = scope.typedef.get(type_id.as_str()) {
+                            let key = scope.keys[type_id.as_str()];
+                            if !context.visited.contains_key(&key) {
+                                bfs(key, context, scope, result);
+                            }
+                        }
+                    }
                     _ => lscope.fields.push(FieldPath(vec![f])),
                 }
             }
 
+            if !matches!(decl, Decl::Group { .. }) {
+                lscope.resolve_defaults();
+            }
+
             // Iterate over parent declaration.
-            let parent = parent_id.and_then(|id| scope.typedef.get(id));
+            let parent = parent_id.and_then(|id| scope.typedef.get(id.as_str()));
             match (decl, parent) {
                 (Decl::Packet { parent_id: Some(_), .. }, None)
                 | (Decl::Struct { parent_id: Some(_), .. }, None) => result.push(
@@ -576,8 +1011,9 @@ impl<'d> Scope<'d> {
                         .with_labels(vec![decl.loc().primary()])
                         .with_notes(vec![format!("hint: expected {} parent", decl.kind())]),
                 ),
-                (_, Some(parent_decl)) => {
-                    if let Some(rscope) = bfs(parent_decl, context, scope, result) {
+                (_, Some(_)) => {
+                    let parent_key = scope.keys[parent_id.unwrap().as_str()];
+                    if let Some(rscope) = bfs(parent_key, context, scope, result) {
                         // Import the parent fields and constraints into the current scope.
                         lscope.inherit(scope, rscope, decl.constraints(), result)
                     }
@@ -586,17 +1022,20 @@ impl<'d> Scope<'d> {
             }
 
             lscope.finalize(result);
+            if let Decl::Group { constraints, .. } = decl {
+                lscope.set_defaults(scope, constraints.iter(), result);
+            }
             context.list.push(decl);
-            context.visited.insert(decl, Mark::Permanent);
-            context.scopes.insert(decl, lscope);
-            context.scopes.get(&decl)
+            context.visited.insert(key, Mark::Permanent);
+            context.scopes.insert(key, lscope);
+            context.scopes.get(&key)
         }
 
         let mut context =
             Context::<'d> { list: vec![], visited: HashMap::new(), scopes: HashMap::new() };
 
-        for decl in self.typedef.values() {
-            bfs(decl, &mut context, self, result);
+        for key in roots {
+            bfs(*key, &mut context, self, result);
         }
 
         self.scopes = context.scopes;
@@ -629,7 +1068,7 @@ fn lint_enum(tags: &[Tag], width: usize, result: &mut LintDiagnostics) {
     for tag in tags {
         // Tags must be unique within the scope of the
         // enum declaration.
-        if let Some(prev) = local_scope.insert(tag.id.clone(), tag) {
+        if let Some(prev) = local_scope.insert(tag.id.as_str(), tag) {
             result.push(
                 Diagnostic::error()
                     .with_message(format!("redeclaration of tag identifier `{}`", &tag.id))
@@ -669,7 +1108,7 @@ fn lint_checksum(
     match field_decl.and_then(|f| f.0.last()) {
         Some(Field::Typedef { loc: field_loc, type_id, .. }) => {
             // Check declaration type of checksum field.
-            match scope.typedef.get(type_id) {
+            match scope.typedef.get(type_id.as_str()) {
                 Some(Decl::Checksum { .. }) => (),
                 Some(decl) => result.push(
                     Diagnostic::error()
@@ -906,8 +1345,8 @@ fn lint_fixed(
     path: &FieldPath,
     width: &Option<usize>,
     value: &Option<usize>,
-    enum_id: &Option<String>,
-    tag_id: &Option<String>,
+    enum_id: &Option<Symbol>,
+    tag_id: &Option<Symbol>,
     result: &mut LintDiagnostics,
 ) {
     // By parsing constraint, we already have that either
@@ -928,7 +1367,7 @@ fn lint_fixed(
     } else {
         // The fixed field should reference a valid enum id and tag id
         // association.
-        match scope.typedef.get(enum_id.as_ref().unwrap()) {
+        match scope.typedef.get(enum_id.as_deref().unwrap()) {
             Some(Decl::Enum { tags, .. }) => {
                 match tags.iter().find(|t| &t.id == tag_id.as_ref().unwrap()) {
                     Some(_) => (),
@@ -970,7 +1409,7 @@ fn lint_array(
     _packet_scope: &PacketScope,
     path: &FieldPath,
     _width: &Option<usize>,
-    type_id: &Option<String>,
+    type_id: &Option<Symbol>,
     _size_modifier: &Option<String>,
     _size: &Option<usize>,
     result: &mut LintDiagnostics,
@@ -984,7 +1423,7 @@ fn lint_array(
     let array_loc = path.loc();
 
     if type_id.is_some() {
-        match scope.typedef.get(type_id.as_ref().unwrap()) {
+        match scope.typedef.get(type_id.as_deref().unwrap()) {
             Some(Decl::Enum { .. })
             | Some(Decl::Struct { .. })
             | Some(Decl::CustomField { .. }) => (),
@@ -1083,14 +1522,96 @@ fn lint_field(
     }
 }
 
+// Helper for linting the alignment of the fields of a struct declaration.
+// A field spanning more bytes than its width requires is decoded with
+// multi-byte shifts and masks. The layout of packets is set by the
+// specification, so only structs are checked. The fields of a child
+// struct start at the payload offset of its parent.
+fn lint_straddling(
+    scope: &Scope,
+    packet_scope: &PacketScope,
+    parent_id: &Option<Symbol>,
+    result: &mut LintDiagnostics,
+) {
+    // Bit offset from the start of the outermost parent struct, or
+    // from the end of the last variable size field.
+    let mut offset = 0;
+    let mut after: Option<&Field> = None;
+    if let Some(parent_id) = parent_id {
+        match Layout::from_typedefs(&scope.typedef).payload_offset(parent_id) {
+            Some(Offset::Static(bits)) => offset = bits,
+            Some(Offset::Dynamic { after: field, bits }) => {
+                offset = bits;
+                after = Some(field);
+            }
+            // The parent declaration is invalid, and reported by
+            // Scope::finalize.
+            None => return,
+        }
+    }
+    for path in packet_scope.fields.iter() {
+        let field = *path.0.last().unwrap();
+        let width = match field {
+            Field::Checksum { .. } => Some(0),
+            Field::Size { width, .. }
+            | Field::Count { width, .. }
+            | Field::Reserved { width, .. }
+            | Field::Scalar { width, .. }
+            | Field::Fixed { width: Some(width), .. } => Some(*width),
+            Field::Fixed { enum_id: Some(type_id), .. } | Field::Typedef { type_id, .. } => {
+                let size = scope.type_size(type_id, 0);
+                if size.fixed {
+                    Some(size.min_bits)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        let width = match width {
+            Some(width) => width,
+            None => {
+                offset = 0;
+                after = Some(field);
+                continue;
+            }
+        };
+        let start = offset % 8;
+        if width > 0
+            && !matches!(field, Field::Reserved { .. })
+            && (start + width - 1) / 8 > (width - 1) / 8
+        {
+            let name = match field.id() {
+                Some(id) => format!("field `{}`", id),
+                None => format!("{} field", field.kind()),
+            };
+            let position = match after.and_then(Field::id) {
+                Some(id) => format!("bits {}..{} after `{}`", offset, offset + width, id),
+                None => format!("bits {}..{} of the struct", offset, offset + width),
+            };
+            result.push(
+                Diagnostic::warning()
+                    .with_message(format!("{} straddles a byte boundary", name))
+                    .with_labels(vec![path.loc().primary().with_message(position)])
+                    .with_notes(vec![format!(
+                        "hint: reorder the fields, or insert `_reserved_ : {}` before the field \
+                         to align it to a byte boundary",
+                        8 - start
+                    )]),
+            )
+        }
+        offset += width;
+    }
+}
+
 // Helper for linting a packet declaration.
 fn lint_packet(
     scope: &Scope,
-    decl: &Decl,
+    key: DeclId,
     id: &str,
     loc: &SourceRange,
     constraints: &[Constraint],
-    parent_id: &Option<String>,
+    parent_id: &Option<Symbol>,
     result: &mut LintDiagnostics,
 ) {
     // The parent declaration is checked by Scope::finalize.
@@ -1114,7 +1635,7 @@ fn lint_packet(
 
     // Retrieve pre-computed packet scope.
     // Scope validation was done before, so it must exist.
-    let packet_scope = &scope.scopes.get(&decl).unwrap();
+    let packet_scope = &scope.scopes.get(&key).unwrap();
 
     for field in packet_scope.fields.iter() {
         lint_field(scope, packet_scope, field, result)
@@ -1124,11 +1645,11 @@ fn lint_packet(
 // Helper for linting a struct declaration.
 fn lint_struct(
     scope: &Scope,
-    decl: &Decl,
+    key: DeclId,
     id: &str,
     loc: &SourceRange,
     constraints: &[Constraint],
-    parent_id: &Option<String>,
+    parent_id: &Option<Symbol>,
     result: &mut LintDiagnostics,
 ) {
     // The parent declaration is checked by Scope::finalize.
@@ -1152,11 +1673,12 @@ fn lint_struct(
 
     // Retrieve pre-computed packet scope.
     // Scope validation was done before, so it must exist.
-    let packet_scope = &scope.scopes.get(&decl).unwrap();
+    let packet_scope = &scope.scopes.get(&key).unwrap();
 
     for field in packet_scope.fields.iter() {
         lint_field(scope, packet_scope, field, result)
     }
+    lint_straddling(scope, packet_scope, parent_id, result);
 }
 
 impl Decl {
@@ -1185,8 +1707,9 @@ impl Decl {
 
                     fields: Vec::new(),
                     constraints: HashMap::new(),
-                    all_fields: HashMap::new(),
-                    all_constraints: HashMap::new(),
+                    defaults: HashMap::new(),
+                    all_fields: Inherited::new(None),
+                    all_constraints: Inherited::new(None),
                 };
                 for field in fields {
                     scope.insert(field, result)
@@ -1197,15 +1720,15 @@ impl Decl {
         }
     }
 
-    fn lint<'d>(&'d self, scope: &Scope<'d>, result: &mut LintDiagnostics) {
+    fn lint<'d>(&'d self, scope: &Scope<'d>, key: DeclId, result: &mut LintDiagnostics) {
         match self {
             Decl::Checksum { .. } | Decl::CustomField { .. } => (),
             Decl::Enum { tags, width, .. } => lint_enum(tags, *width, result),
             Decl::Packet { id, loc, constraints, parent_id, .. } => {
-                lint_packet(scope, self, id, loc, constraints, parent_id, result)
+                lint_packet(scope, key, id, loc, constraints, parent_id, result)
             }
             Decl::Struct { id, loc, constraints, parent_id, .. } => {
-                lint_struct(scope, self, id, loc, constraints, parent_id, result)
+                lint_struct(scope, key, id, loc, constraints, parent_id, result)
             }
             // Groups are finalizeed before linting, to make sure
             // potential errors are raised only once.
@@ -1213,68 +1736,450 @@ impl Decl {
             Decl::Test { .. } => (),
         }
     }
-
-    fn kind(&self) -> &str {
-        match self {
-            Decl::Checksum { .. } => "checksum",
-            Decl::CustomField { .. } => "custom field",
-            Decl::Enum { .. } => "enum",
-            Decl::Packet { .. } => "packet",
-            Decl::Struct { .. } => "struct",
-            Decl::Group { .. } => "group",
-            Decl::Test { .. } => "test",
-        }
-    }
 }
 
 impl Grammar {
     fn scope<'d>(&'d self, result: &mut LintDiagnostics) -> Scope<'d> {
-        let mut scope = Scope { typedef: HashMap::new(), scopes: HashMap::new() };
+        self.scope_with_timings(result, &mut LintTimings::default())
+    }
+
+    fn scope_with_timings<'d>(
+        &'d self,
+        result: &mut LintDiagnostics,
+        timings: &mut LintTimings,
+    ) -> Scope<'d> {
+        let roots: Vec<_> = self.declarations.iter_ids().map(|(key, _)| key).collect();
+        self.partial_scope(&roots, result, timings)
+    }
+
+    /// Build the scope of the declarations `roots`, and of the
+    /// declarations they depend on, transitively. The scopes of the other
+    /// packet, struct, and group declarations are not computed.
+    fn partial_scope<'d>(
+        &'d self,
+        roots: &[DeclId],
+        result: &mut LintDiagnostics,
+        timings: &mut LintTimings,
+    ) -> Scope<'d> {
+        let start = Instant::now();
+        let mut scope = Scope {
+            declarations: &self.declarations,
+            typedef: HashMap::new(),
+            keys: HashMap::new(),
+            scopes: HashMap::new(),
+            order: Vec::new(),
+            children: HashMap::new(),
+        };
 
         // Gather top-level declarations.
         // Validate the top-level scopes (Group, Packet, Typedef).
         //
         // TODO: switch to try_insert when stable
-        for decl in &self.declarations {
+        for (key, decl) in self.declarations.iter_ids() {
             if let Some(id) = decl.id() {
-                if let Some(prev) = scope.typedef.insert(id.clone(), decl) {
+                if let Some(prev) = scope.typedef.insert(id.as_str(), decl) {
                     result.err_redeclared(id, decl.kind(), decl.loc(), prev.loc())
                 }
+                scope.keys.insert(id.as_str(), key);
             }
-            if let Some(lscope) = decl.scope(result) {
-                scope.scopes.insert(decl, lscope);
+            match decl {
+                Decl::Packet { parent_id: Some(parent_id), .. }
+                | Decl::Struct { parent_id: Some(parent_id), .. } => {
+                    scope.children.entry(parent_id.as_str()).or_default().push(decl)
+                }
+                _ => (),
+            }
+        }
+        for key in roots {
+            if let Some(lscope) = self.declarations[*key].scope(result) {
+                scope.scopes.insert(*key, lscope);
             }
         }
 
-        scope.finalize(result);
+        timings.scope += start.elapsed();
+
+        let start = Instant::now();
+        scope.order = scope.finalize(roots, result);
+        timings.finalize += start.elapsed();
         scope
     }
+
+    /// Lint the grammar, as [`Lintable::lint`], and add the time spent
+    /// in each phase of the analysis to `timings`.
+    pub fn lint_with_timings(&self, timings: &mut LintTimings) -> LintDiagnostics {
+        let mut result = LintDiagnostics::new();
+        let scope = self.scope_with_timings(&mut result, timings);
+        if !result.diagnostics.is_empty() {
+            return result;
+        }
+        let start = Instant::now();
+        let declarations: Vec<_> = self.declarations.iter_ids().collect();
+        for ((_, decl), (diagnostics, elapsed)) in
+            declarations.iter().zip(lint_declarations(&scope, &declarations))
+        {
+            if let Some(id) = decl.id() {
+                timings.declarations.push((id.to_string(), elapsed));
+            }
+            result.diagnostics.extend(diagnostics.diagnostics)
+        }
+        timings.checks += start.elapsed();
+        result.sort();
+        result
+    }
 }
 
-impl Lintable for Grammar {
-    fn lint(&self) -> LintDiagnostics {
+/// Number of declarations from which the declaration checks run in
+/// parallel. Spawning the threads costs more than checking a few
+/// declarations, e.g. on every change of a file open in the language
+/// server.
+const PARALLEL_LINT_THRESHOLD: usize = 256;
+
+/// Run the declaration checks, and return the diagnostics of each
+/// declaration, with the time spent checking it. The checks only read
+/// the scope: they run on chunks of declarations in parallel, and the
+/// results are returned in the order of the declarations. The checks
+/// of small grammars run inline, as do all checks on
+/// `wasm32-unknown-unknown`, which has no threads.
+fn lint_declarations<'d>(
+    scope: &Scope<'d>,
+    declarations: &[(DeclId, &'d Decl)],
+) -> Vec<(LintDiagnostics, Duration)> {
+    let lint_chunk = |chunk: &[(DeclId, &'d Decl)]| {
+        chunk
+            .iter()
+            .map(|(key, decl)| {
+                let start = Instant::now();
+                let mut result = LintDiagnostics::new();
+                decl.lint(scope, *key, &mut result);
+                (result, start.elapsed())
+            })
+            .collect::<Vec<_>>()
+    };
+    if cfg!(target_arch = "wasm32") || declarations.len() < PARALLEL_LINT_THRESHOLD {
+        return lint_chunk(declarations);
+    }
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let chunk_size = (declarations.len() + threads - 1) / threads;
+    thread::scope(|s| {
+        let handles: Vec<_> = declarations
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || lint_chunk(chunk)))
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    })
+}
+
+/// Return the identifiers of the declarations referenced by a
+/// declaration: its parent, and the types of its fields.
+fn referenced_declarations(decl: &Decl) -> Vec<&str> {
+    let (fields, parent_id) = match decl {
+        Decl::Packet { fields, parent_id, .. } | Decl::Struct { fields, parent_id, .. } => {
+            (&fields[..], parent_id.as_deref())
+        }
+        Decl::Group { fields, .. } => (&fields[..], None),
+        Decl::Test { type_id, .. } => return vec![type_id.as_str()],
+        _ => return vec![],
+    };
+    let mut ids: Vec<&str> = parent_id.into_iter().collect();
+    for field in fields {
+        match field {
+            Field::Typedef { type_id, .. } | Field::Array { type_id: Some(type_id), .. } => {
+                ids.push(type_id)
+            }
+            Field::Fixed { enum_id: Some(enum_id), .. } => ids.push(enum_id),
+            Field::Group { group_id, .. } => ids.push(group_id),
+            _ => (),
+        }
+    }
+    ids
+}
+
+fn span(decl: &Decl) -> ops::Range<usize> {
+    decl.loc().start.offset..decl.loc().end.offset
+}
+
+/// Return the source text of a declaration.
+fn source<'a>(sources: &'a SourceDatabase, decl: &Decl) -> &'a str {
+    sources.get(decl.loc().file).map_or("", |file| &file.source()[span(decl)])
+}
+
+/// Cached diagnostics of a declaration.
+struct CacheEntry {
+    span: ops::Range<usize>,
+    source: String,
+    diagnostics: Vec<Diagnostic<FileId>>,
+}
+
+/// Lint cache, for the incremental analysis of successive versions of
+/// a grammar.
+///
+/// The declarations added, removed, or whose source text changed since
+/// the previous version are found by comparing with the cached source
+/// text, and are marked dirty together with their dependents,
+/// transitively. Only the dirty declarations are scoped and checked
+/// again, with the declarations they depend on; the diagnostics of the
+/// other declarations are reused, unless the declaration moved in the
+/// source, as the diagnostics would point to the previous location.
+#[derive(Default)]
+pub struct Cache {
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Lint a new version of the grammar.
+    pub fn lint(&mut self, sources: &SourceDatabase, grammar: &Grammar) -> LintDiagnostics {
+        let ids: HashSet<&str> =
+            grammar.declarations.iter().filter_map(Decl::id).map(Symbol::as_str).collect();
+
+        // Mark the changed declarations, and their transitive dependents.
+        let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+        for decl in &grammar.declarations {
+            if let Some(id) = decl.id() {
+                for reference in referenced_declarations(decl) {
+                    dependents.entry(reference).or_default().push(id);
+                }
+            }
+        }
+        let mut queue: Vec<&str> = grammar
+            .declarations
+            .iter()
+            .filter(|decl| match decl.id().and_then(|id| self.entries.get(id.as_str())) {
+                Some(entry) => entry.source != source(sources, decl),
+                None => true,
+            })
+            .filter_map(|decl| decl.id().map(Symbol::as_str))
+            .chain(self.entries.keys().map(String::as_str).filter(|id| !ids.contains(id)))
+            .collect();
+        let mut dirty: HashSet<&str> = queue.iter().copied().collect();
+        while let Some(id) = queue.pop() {
+            for dependent in dependents.get(id).into_iter().flatten() {
+                if dirty.insert(dependent) {
+                    queue.push(dependent);
+                }
+            }
+        }
+
+        // Check the dirty declarations, and the declarations that moved.
+        let declarations: Vec<_> = grammar
+            .declarations
+            .iter_ids()
+            .filter(|(_, decl)| match decl.id().and_then(|id| self.entries.get(id.as_str())) {
+                Some(entry) => {
+                    entry.span != span(decl) || dirty.contains(decl.id().unwrap().as_str())
+                }
+                None => true,
+            })
+            .collect();
+        let keys: Vec<_> = declarations.iter().map(|(key, _)| *key).collect();
         let mut result = LintDiagnostics::new();
-        let scope = self.scope(&mut result);
+        let scope = grammar.partial_scope(&keys, &mut result, &mut LintTimings::default());
         if !result.diagnostics.is_empty() {
+            self.entries.clear();
             return result;
         }
-        for decl in &self.declarations {
-            decl.lint(&scope, &mut result)
+        for ((_, decl), (diagnostics, _)) in
+            declarations.iter().zip(lint_declarations(&scope, &declarations))
+        {
+            if let Some(id) = decl.id() {
+                let entry = CacheEntry {
+                    span: span(decl),
+                    source: source(sources, decl).to_owned(),
+                    diagnostics: diagnostics.diagnostics,
+                };
+                self.entries.insert(id.to_string(), entry);
+            } else {
+                result.diagnostics.extend(diagnostics.diagnostics)
+            }
+        }
+
+        self.entries.retain(|id, _| ids.contains(id.as_str()));
+        for entry in self.entries.values() {
+            result.diagnostics.extend(entry.diagnostics.iter().cloned())
         }
+        result.sort();
         result
     }
 }
 
+impl Lintable for Grammar {
+    fn lint(&self) -> LintDiagnostics {
+        self.lint_with_timings(&mut LintTimings::default())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::ast::*;
-    use crate::lint::Lintable;
-    use crate::parser::parse_inline;
+    use crate::lint::{
+        Cache, ConstraintValue, Inherited, Lintable, Scope, PARALLEL_LINT_THRESHOLD,
+    };
+    use crate::test_utils::grammar;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_decl_size() {
+        let mut db = SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+        little_endian_packets
+        enum Op : 8 { READ = 1, WRITE = 2 }
+        struct Handle { value: 12, _reserved_: 4 }
+        packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+        packet Read : Command (op = READ) { handles: Handle[2] }
+        packet Write : Command (op = WRITE) { _count_(handles): 2, _reserved_: 6, handles: Handle[] }
+        packet Raw { data: 8[] }
+        "#
+        );
+        let scope = Scope::new(&grammar).ok().unwrap();
+        let size = |id| scope.decl_size(id).unwrap().to_string();
+        assert_eq!(size("Op"), "8 bits");
+        assert_eq!(size("Handle"), "16 bits");
+        assert_eq!(size("Command"), "16 to 2056 bits");
+        assert_eq!(size("Read"), "48 bits");
+        assert_eq!(size("Write"), "24 to 72 bits");
+        assert_eq!(size("Raw"), "at least 0 bits");
+        assert!(scope.decl_size("Other").is_none());
+    }
+
+    #[test]
+    fn test_constraint_values() {
+        let mut db = SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+        little_endian_packets
+        enum Op : 8 { READ = 1, WRITE = 2 }
+        group Header { version: 4, kind: 4 }
+        packet Command { Header { version = 1 }, op: Op, _payload_ }
+        packet Read : Command (op = READ, kind = 3) { _payload_ }
+        packet ReadBlock : Read { block: 8 }
+        "#
+        );
+        let scope = Scope::new(&grammar).ok().unwrap();
+        let values = scope.constraint_values("ReadBlock").unwrap();
+        assert_eq!(
+            values.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("kind", ConstraintValue::Scalar(3)),
+                ("op", ConstraintValue::Tag { enum_id: "Op", tag_id: "READ", value: 1 }),
+                ("version", ConstraintValue::Scalar(1)),
+            ]
+        );
+        assert_eq!(scope.constraint_values("Command").unwrap().len(), 1);
+        assert!(scope.constraint_values("Op").is_none());
+    }
 
-    macro_rules! grammar {
-        ($db:expr, $text:literal) => {
-            parse_inline($db, "stdin".to_owned(), $text.to_owned()).expect("parsing failure")
+    #[test]
+    fn test_group_defaults() {
+        let mut db = SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+        little_endian_packets
+        group Inner (v = 1) { v: 8 }
+        group Header (op = 1, flags = 0) { op: 8, flags: 8, Inner }
+        group Outer (v = 2) { Inner }
+        packet Read { Header { op = 2 }, _payload_ }
+        packet Write { Header, _payload_ }
+        packet Other { Outer }
+        "#
+        );
+        let scope = Scope::new(&grammar).ok().unwrap();
+        let values = |id| -> Vec<(&str, usize)> {
+            let values = scope.constraint_values(id).unwrap();
+            values.into_iter().map(|(id, value)| (id, value.value())).collect()
         };
+        assert_eq!(values("Read"), vec![("flags", 0), ("op", 2), ("v", 1)]);
+        assert_eq!(values("Write"), vec![("flags", 0), ("op", 1), ("v", 1)]);
+        assert_eq!(values("Other"), vec![("v", 2)]);
+
+        let grammar = grammar!(
+            &mut db,
+            r#"
+        little_endian_packets
+        group Inner (v = 1) { v: 8 }
+        group Outer (v = 2) { Inner { v = 3 } }
+        "#
+        );
+        let result = grammar.lint();
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].message, "conflicting default value for `v`");
+    }
+
+    #[test]
+    fn test_straddling_child_struct() {
+        let mut db = SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+        little_endian_packets
+        struct A { a: 4, _payload_ }
+        struct B : A { b: 8 }
+        struct C : A { c: 4, d: 8 }
+        "#
+        );
+        let result = grammar.lint();
+        let messages: Vec<_> = result.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec!["field `b` straddles a byte boundary"]);
+        assert_eq!(result.diagnostics[0].labels[0].message, "bits 4..12 of the struct");
+    }
+
+    #[test]
+    fn test_straddling() {
+        let mut db = SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+        little_endian_packets
+        struct S { a: 4, b: 6, c: 6, _reserved_: 4, d: 12 }
+        packet P { a: 4, b: 6, c: 6 }
+        "#
+        );
+        let result = grammar.lint();
+        let messages: Vec<_> = result.diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec!["field `b` straddles a byte boundary"]);
+        assert_eq!(result.diagnostics[0].labels[0].message, "bits 4..10 of the struct");
+        assert_eq!(
+            result.diagnostics[0].notes,
+            vec![
+                "hint: reorder the fields, or insert `_reserved_ : 4` before the field \
+                  to align it to a byte boundary"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ordered_declarations() {
+        let mut db = SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+        little_endian_packets
+        packet Read : Command { handles: Handle[] }
+        packet Command { Header, _payload_ }
+        group Header { op: 8 }
+        struct Handle { value: 8 }
+        packet Other { }
+        "#
+        );
+        let scope = Scope::new(&grammar).ok().unwrap();
+        let order: Vec<_> = scope.ordered_declarations().map(|decl| decl.id().unwrap()).collect();
+        assert_eq!(order, vec!["Handle", "Header", "Command", "Read", "Other"]);
+        let children: Vec<_> =
+            scope.iter_children("Command").map(|decl| decl.id().unwrap().as_str()).collect();
+        assert_eq!(children, vec!["Read"]);
+        assert_eq!(scope.iter_children("Read").count(), 0);
+
+        let key = scope.key("Read").unwrap();
+        assert_eq!(key.index(), 0);
+        assert!(std::ptr::eq(scope.decl(key), &grammar.declarations[key]));
+        let copy = grammar.clone();
+        assert_eq!(Scope::new(&copy).ok().unwrap().key("Read"), Some(key));
     }
 
     #[test]
@@ -1291,4 +2196,78 @@ mod test {
         let result = grammar.lint();
         assert!(!result.diagnostics.is_empty());
     }
+
+    #[test]
+    fn test_inherited() {
+        let mut parent = Inherited::new(None);
+        Arc::get_mut(&mut parent).unwrap().insert("a", 1);
+        let mut child = Inherited::new(Some(parent.clone()));
+        let child_mut = Arc::get_mut(&mut child).unwrap();
+        assert_eq!(child_mut.insert("a", 2), Some(1));
+        assert_eq!(child_mut.insert("b", 3), None);
+        assert_eq!(parent.get("a"), Some(1));
+        assert_eq!(child.get("a"), Some(2));
+        let mut values = child.values();
+        values.sort();
+        assert_eq!(values, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_diagnostics_order() {
+        let mut db = SourceDatabase::new();
+        let grammar = grammar!(
+            &mut db,
+            r#"
+        little_endian_packets
+        packet A (a = 1) { a: 8 }
+        packet B (b = 1) { b: 8 }
+        packet C (c = 1) { c: 8 }
+        packet D (d = 1) { d: 8 }
+        packet E (e = 1) { e: 8 }
+        "#
+        );
+        let result = grammar.lint();
+        let starts: Vec<_> = result.diagnostics.iter().map(|d| d.labels[0].range.start).collect();
+        assert_eq!(starts.len(), 5);
+        assert!(starts.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_diagnostics_order_parallel() {
+        let mut db = SourceDatabase::new();
+        let mut text = "little_endian_packets\n".to_owned();
+        for index in 0..2 * PARALLEL_LINT_THRESHOLD {
+            text.push_str(&format!("packet P{index} (a = 1) {{ a: 8 }}\n"));
+        }
+        let grammar = grammar!(&mut db, text);
+        let result = grammar.lint();
+        let starts: Vec<_> = result.diagnostics.iter().map(|d| d.labels[0].range.start).collect();
+        assert_eq!(starts.len(), 2 * PARALLEL_LINT_THRESHOLD);
+        assert!(starts.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut db = SourceDatabase::new();
+        let mut cache = Cache::new();
+        let before = grammar!(&mut db, "little_endian_packets packet A (a = 1) { a: 8 }");
+        let after = grammar!(&mut db, "little_endian_packets packet A         { a: 8 }");
+        assert_eq!(cache.lint(&db, &before).diagnostics.len(), 1);
+        // The modified declaration is detected, and checked again.
+        assert_eq!(cache.lint(&db, &after).diagnostics.len(), 0);
+        assert_eq!(cache.lint(&db, &after).diagnostics.len(), 0);
+    }
+
+    #[test]
+    fn test_cache_dependents() {
+        let mut db = SourceDatabase::new();
+        let mut cache = Cache::new();
+        let before =
+            grammar!(&mut db, "little_endian_packets enum E : 8 { X = 1 } packet A { e: E }");
+        let after =
+            grammar!(&mut db, "little_endian_packets enum F : 8 { X = 1 } packet A { e: E }");
+        assert_eq!(cache.lint(&db, &before).diagnostics.len(), 0);
+        // The dependent of the removed declaration is checked again.
+        assert_eq!(cache.lint(&db, &after).diagnostics.len(), 1);
+    }
 }
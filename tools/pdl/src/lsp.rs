@@ -0,0 +1,539 @@
+//! Language Server Protocol server.
+//!
+//! The server exchanges JSON-RPC messages over stdin and stdout and
+//! implements the following requests and notifications:
+//!
+//! - `textDocument/didOpen`, `didChange`, `didClose`: documents are
+//!   synchronized in full, and only the modified document is parsed and
+//!   linted again, incrementally: only the modified declarations and
+//!   their dependents are checked, see [`lint::Cache`]. The resulting
+//!   diagnostics are published with `textDocument/publishDiagnostics`,
+//! - `textDocument/definition`: go to the declaration of a typedef,
+//!   parent, or group reference,
+//! - `textDocument/references`: list the references to a declaration,
+//!   see [`crate::references`],
+//! - `textDocument/hover`: show the layout of the referenced packet or
+//!   struct as a bit diagram, or the tags of the referenced enum,
+//! - `textDocument/documentSymbol`: list the declarations and their
+//!   fields.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+use crate::ast;
+use crate::backends::diagram;
+use crate::diagnostics::{lsp_range as range, Diagnostic};
+use crate::lint;
+use crate::parser::{self, is_identifier_char};
+use crate::references;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Open document, with the result of its analysis.
+struct Document {
+    text: String,
+    grammar: Option<ast::Grammar>,
+    diagnostics: Vec<Value>,
+    cache: lint::Cache,
+}
+
+/// Convert an LSP position to a byte offset.
+fn offset(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let line_start = if line == 0 {
+        0
+    } else {
+        text.match_indices('\n').nth(line - 1).map(|(index, _)| index + 1)?
+    };
+    let mut units = 0;
+    for (index, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(line_start + index);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+fn loc_range(text: &str, loc: &ast::SourceRange) -> Value {
+    range(text, loc.start.offset..loc.end.offset)
+}
+
+/// Return the identifier found at the byte offset.
+fn identifier_at(text: &str, offset: usize) -> Option<&str> {
+    let start = text[..offset].rfind(|c| !is_identifier_char(c)).map_or(0, |index| index + 1);
+    let end = text[offset..].find(|c| !is_identifier_char(c)).map_or(text.len(), |i| offset + i);
+    Some(&text[start..end]).filter(|word| !word.is_empty())
+}
+
+/// Return the byte range of the identifier of a declaration, which
+/// is the first occurrence of the identifier after the keyword.
+fn id_range(text: &str, loc: &ast::SourceRange, id: &str) -> Range<usize> {
+    let source = &text[loc.start.offset..loc.end.offset];
+    source
+        .match_indices(id)
+        .find(|(index, _)| {
+            let before = source[..*index].chars().next_back();
+            let after = source[index + id.len()..].chars().next();
+            !matches!(before, Some(c) if is_identifier_char(c))
+                && !matches!(after, Some(c) if is_identifier_char(c))
+        })
+        .map_or(loc.start.offset..loc.start.offset, |(index, _)| {
+            loc.start.offset + index..loc.start.offset + index + id.len()
+        })
+}
+
+impl Document {
+    /// Analyze a new version of a document. The lint results of the
+    /// declarations unchanged since the `previous` version are reused.
+    fn new(uri: &str, text: String, previous: Option<Document>) -> Document {
+        let mut sources = ast::SourceDatabase::new();
+        match parser::parse_inline(&mut sources, uri.to_owned(), text.clone()) {
+            Ok(grammar) => {
+                let mut cache = match previous {
+                    Some(Document { cache, .. }) => cache,
+                    None => lint::Cache::new(),
+                };
+                let diagnostics = cache
+                    .lint(&sources, &grammar)
+                    .structured()
+                    .iter()
+                    .map(|diagnostic| diagnostic.to_lsp(uri, &text))
+                    .collect();
+                Document { text, grammar: Some(grammar), diagnostics, cache }
+            }
+            Err(diagnostic) => {
+                let diagnostics = vec![Diagnostic::from(&diagnostic).to_lsp(uri, &text)];
+                Document { text, grammar: None, diagnostics, cache: lint::Cache::new() }
+            }
+        }
+    }
+
+    /// Return the declaration referenced at the position.
+    fn referenced_decl(&self, position: &Value) -> Option<&ast::Decl> {
+        let word = identifier_at(&self.text, offset(&self.text, position)?)?;
+        self.grammar
+            .as_ref()?
+            .declarations
+            .iter()
+            .find(|decl| decl.id().map(ast::Symbol::as_str) == Some(word))
+    }
+
+    fn definition(&self, uri: &str, position: &Value) -> Value {
+        match self.referenced_decl(position) {
+            Some(decl) => {
+                let id_range = id_range(&self.text, decl.loc(), decl.id().unwrap());
+                json!({ "uri": uri, "range": range(&self.text, id_range) })
+            }
+            None => Value::Null,
+        }
+    }
+
+    fn references(&self, uri: &str, params: &Value) -> Value {
+        let (grammar, decl) = match (&self.grammar, self.referenced_decl(&params["position"])) {
+            (Some(grammar), Some(decl)) => (grammar, decl),
+            _ => return Value::Null,
+        };
+        let mut ranges = vec![];
+        if params["context"]["includeDeclaration"].as_bool().unwrap_or(false) {
+            ranges.extend(references::declaration_range(&self.text, decl));
+        }
+        let index = references::Index::new(&self.text, grammar);
+        ranges.extend(
+            index.references(decl.id().unwrap()).iter().map(|reference| reference.range.clone()),
+        );
+        let locations = ranges
+            .into_iter()
+            .map(|id_range| json!({ "uri": uri, "range": range(&self.text, id_range) }))
+            .collect();
+        Value::Array(locations)
+    }
+
+    fn hover(&self, position: &Value) -> Value {
+        let (grammar, decl) = match (&self.grammar, self.referenced_decl(position)) {
+            (Some(grammar), Some(decl)) => (grammar, decl),
+            _ => return Value::Null,
+        };
+        let header = format!("{} {}", decl.kind(), decl.id().unwrap());
+        let value = match decl {
+            ast::Decl::Packet { id, .. } | ast::Decl::Struct { id, .. } => {
+                let size = lint::Scope::new(grammar).ok().and_then(|scope| scope.decl_size(id));
+                let header = match size {
+                    Some(size) => format!("{} ({})", header, size),
+                    None => header,
+                };
+                let lines = diagram::decl_diagram(grammar, decl).unwrap();
+                format!("```\n{}\n\n{}\n```", header, lines.join("\n"))
+            }
+            ast::Decl::Enum { tags, width, .. } => {
+                let tags: Vec<_> =
+                    tags.iter().map(|tag| format!("  {} = {:#x}", tag.id, tag.value)).collect();
+                format!("```\n{} : {} {{\n{}\n}}\n```", header, width, tags.join(",\n"))
+            }
+            _ => format!("```\n{}\n```", header),
+        };
+        json!({ "contents": { "kind": "markdown", "value": value } })
+    }
+
+    fn symbols(&self) -> Value {
+        let grammar = match &self.grammar {
+            Some(grammar) => grammar,
+            None => return json!([]),
+        };
+        let text = &self.text;
+        let symbol = |name: &str, kind: u64, loc: &ast::SourceRange, children: Vec<Value>| {
+            json!({
+                "name": name,
+                "kind": kind,
+                "range": loc_range(text, loc),
+                "selectionRange": range(text, id_range(text, loc, name)),
+                "children": children,
+            })
+        };
+        let fields = |fields: &[ast::Field]| -> Vec<Value> {
+            fields
+                .iter()
+                .filter_map(|field| field.id().map(|id| symbol(id, 8, field.loc(), vec![])))
+                .collect()
+        };
+        let symbols: Vec<Value> = grammar
+            .declarations
+            .iter()
+            .filter_map(|decl| {
+                let id = decl.id()?;
+                Some(match decl {
+                    ast::Decl::Packet { fields: f, .. } => symbol(id, 5, decl.loc(), fields(f)),
+                    ast::Decl::Struct { fields: f, .. } => symbol(id, 23, decl.loc(), fields(f)),
+                    ast::Decl::Group { fields: f, .. } => symbol(id, 11, decl.loc(), fields(f)),
+                    ast::Decl::Enum { tags, .. } => {
+                        let tags = tags.iter().map(|tag| symbol(&tag.id, 22, &tag.loc, vec![]));
+                        symbol(id, 10, decl.loc(), tags.collect())
+                    }
+                    _ => symbol(id, 26, decl.loc(), vec![]),
+                })
+            })
+            .collect();
+        Value::Array(symbols)
+    }
+}
+
+/// Language server state.
+struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn new() -> Server {
+        Server { documents: HashMap::new(), shutdown: false }
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let diagnostics = self.documents.get(uri).map_or(vec![], |d| d.diagnostics.clone());
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let document =
+            params["textDocument"]["uri"].as_str().and_then(|uri| self.documents.get(uri));
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "pdl" },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => {
+                Ok(document.map_or(Value::Null, |d| d.definition(uri, &params["position"])))
+            }
+            "textDocument/references" => {
+                Ok(document.map_or(Value::Null, |d| d.references(uri, params)))
+            }
+            "textDocument/hover" => {
+                Ok(document.map_or(Value::Null, |d| d.hover(&params["position"])))
+            }
+            "textDocument/documentSymbol" => Ok(document.map_or(Value::Null, Document::symbols)),
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method {}", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri.to_owned(),
+            None => return vec![],
+        };
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), Document::new(&uri, text.to_owned(), None));
+            }
+            "textDocument/didChange" => {
+                // Full synchronization: the last change holds the
+                // complete text of the document.
+                let changes = params["contentChanges"].as_array();
+                match changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    Some(text) => {
+                        let previous = self.documents.remove(&uri);
+                        let document = Document::new(&uri, text.to_owned(), previous);
+                        self.documents.insert(uri.clone(), document);
+                    }
+                    None => return vec![],
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+            }
+            _ => return vec![],
+        }
+        vec![self.publish_diagnostics(&uri)]
+    }
+
+    /// Handle an incoming message, and return the messages to send
+    /// back to the client.
+    fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let response = match self.request(method, params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, error)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": code, "message": error },
+                    }),
+                };
+                vec![response]
+            }
+            None => self.notification(method, params),
+        }
+    }
+}
+
+/// Read a message from the client. Returns `None` at the end of the
+/// input stream.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<Value, String>>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let content_length = match content_length {
+        Some(content_length) => content_length,
+        None => return Ok(Some(Err("missing Content-Length header".to_owned()))),
+    };
+    let mut content = vec![0; content_length];
+    input.read_exact(&mut content)?;
+    Ok(Some(serde_json::from_slice(&content).map_err(|err| err.to_string())))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = serde_json::to_string(message).unwrap();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+    output.flush()
+}
+
+/// Run the server until the client sends the `exit` notification or
+/// closes the input stream. Returns `true` if the server was shut down
+/// properly.
+pub fn run(input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(input)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(err) => {
+                let error = json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": PARSE_ERROR, "message": err },
+                });
+                write_message(output, &error)?;
+                continue;
+            }
+        };
+        if message["method"] == "exit" {
+            return Ok(server.shutdown);
+        }
+        for response in server.handle(&message) {
+            write_message(output, &response)?;
+        }
+    }
+    Ok(server.shutdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::lsp_position as position;
+
+    const TEXT: &str = r#"little_endian_packets
+enum Op : 8 { READ = 1, WRITE = 2 }
+packet Command { op: Op, _payload_ }
+packet Read : Command (op = READ) { handle: 16 }
+"#;
+
+    fn open(server: &mut Server) -> Vec<Value> {
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///a.pdl", "text": TEXT } },
+        }))
+    }
+
+    #[test]
+    fn test_position() {
+        let text = "ab\ncé€d\n";
+        assert_eq!(position(text, 0), json!({ "line": 0, "character": 0 }));
+        assert_eq!(position(text, 4), json!({ "line": 1, "character": 1 }));
+        assert_eq!(position(text, 9), json!({ "line": 1, "character": 3 }));
+        assert_eq!(offset(text, &json!({ "line": 1, "character": 3 })), Some(9));
+        assert_eq!(offset(text, &json!({ "line": 1, "character": 10 })), Some(10));
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut server = Server::new();
+        let messages = open(&mut server);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(messages[0]["params"]["diagnostics"], json!([]));
+
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": "file:///a.pdl", "version": 2 },
+                "contentChanges": [{ "text": "packet A : B {}" }],
+            },
+        }));
+        let diagnostics = messages[0]["params"]["diagnostics"].as_array().unwrap();
+        assert!(!diagnostics.is_empty());
+        assert_eq!(diagnostics[0]["severity"], 1);
+    }
+
+    #[test]
+    fn test_definition() {
+        let mut server = Server::new();
+        open(&mut server);
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "textDocument/definition",
+            "params": {
+                "textDocument": { "uri": "file:///a.pdl" },
+                "position": { "line": 3, "character": 16 },
+            },
+        }));
+        assert_eq!(
+            messages[0]["result"],
+            json!({
+                "uri": "file:///a.pdl",
+                "range": {
+                    "start": { "line": 2, "character": 7 },
+                    "end": { "line": 2, "character": 14 },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_references() {
+        let mut server = Server::new();
+        open(&mut server);
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "textDocument/references",
+            "params": {
+                "textDocument": { "uri": "file:///a.pdl" },
+                "position": { "line": 2, "character": 8 },
+                "context": { "includeDeclaration": true },
+            },
+        }));
+        let lines: Vec<_> = messages[0]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| location["range"]["start"]["line"].as_u64().unwrap())
+            .collect();
+        assert_eq!(lines, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_hover() {
+        let mut server = Server::new();
+        open(&mut server);
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "textDocument/hover",
+            "params": {
+                "textDocument": { "uri": "file:///a.pdl" },
+                "position": { "line": 3, "character": 9 },
+            },
+        }));
+        let value = messages[0]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(value.starts_with("```\npacket Read (24 bits)\n"));
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut server = Server::new();
+        open(&mut server);
+        let messages = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "textDocument/documentSymbol",
+            "params": { "textDocument": { "uri": "file:///a.pdl" } },
+        }));
+        let symbols = messages[0]["result"].as_array().unwrap();
+        let names: Vec<_> = symbols.iter().map(|s| s["name"].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["Op", "Command", "Read"]);
+        assert_eq!(symbols[1]["children"][0]["name"], "op");
+    }
+
+    #[test]
+    fn test_run() {
+        let message =
+            |content: &str| format!("Content-Length: {}\r\n\r\n{}", content.len(), content);
+        let input = [
+            message(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#),
+            message(r#"{"jsonrpc":"2.0","id":2,"method":"shutdown"}"#),
+            message(r#"{"jsonrpc":"2.0","method":"exit"}"#),
+        ]
+        .concat();
+        let mut output = vec![];
+        assert!(run(&mut input.as_bytes(), &mut output).unwrap());
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("Content-Length: "));
+        assert!(output.contains(r#""hoverProvider":true"#));
+    }
+}
@@ -1,13 +1,528 @@
 //! PDL parser and linter.
 
-use codespan_reporting::term::{self, termcolor};
+use codespan_reporting::diagnostic::Severity;
+use codespan_reporting::files::Files;
 use structopt::StructOpt;
 
-mod ast;
-mod lint;
-mod parser;
+use pdl_build::{
+    ast, backends, corpus, decoder, diagnostics, encoder, golden, interpreter, layout, lint,
+    parser, printer, registry, stamp, stdlib, vectors, visit,
+};
 
-use crate::lint::Lintable;
+mod bindiff;
+mod compat;
+mod coverage;
+mod depfile;
+mod diff;
+mod emitter;
+mod graph;
+mod identify;
+mod import_c;
+mod import_kaitai;
+mod infer;
+mod lsp;
+mod manifest;
+mod pcapng;
+mod references;
+mod rename;
+mod repl;
+mod report;
+mod snoop;
+#[cfg(test)]
+#[allow(dead_code)]
+mod test_utils;
+mod watch;
+
+use crate::emitter::{Color, Emitter, ErrorFormat};
+use crate::interpreter::Interpreter;
+use crate::report::{Report, ReportFormat};
+
+#[derive(Debug, Clone, Copy)]
+enum InputFormat {
+    Pdl,
+}
+
+impl std::str::FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "pdl" => Ok(Self::Pdl),
+            _ => Err(format!("could not parse {:?}, valid options are 'pdl'.", input)),
+        }
+    }
+}
+
+// Options shared by the generating subcommands. A plain comment: the
+// doc comment of a flattened struct replaces the about text of the
+// subcommand.
+#[derive(Debug, StructOpt)]
+struct GenerateOpt {
+    /// Read the input in this format. Only "pdl" is supported; the
+    /// flag documents the format of the standard input in pipelines.
+    #[structopt(long = "--input-format", name = "INPUT_FORMAT", default_value = "pdl")]
+    input_format: InputFormat,
+
+    /// Write the output to this file instead of stdout, or to stdout
+    /// if `-`.
+    #[structopt(long = "--output", name = "OUTPUT")]
+    output_file: Option<String>,
+
+    /// Write the list of the files read to generate the output to
+    /// this file, in the Make syntax, for ninja and Soong. Requires
+    /// `--output`.
+    #[structopt(long = "--depfile", name = "DEPFILE")]
+    depfile: Option<String>,
+
+    /// Keep running, and regenerate the output whenever the input
+    /// file changes.
+    #[structopt(short, long)]
+    watch: bool,
+
+    /// Generate only the declarations reachable from these comma
+    /// separated declarations: the roots, the declarations deriving
+    /// from them, and the declarations they use.
+    #[structopt(long = "--roots", name = "ROOTS", use_delimiter = true)]
+    roots: Vec<String>,
+
+    /// Generate only the declarations matching one of these glob
+    /// patterns, e.g. `--only 'Le*'`. The selected declarations must
+    /// not use the declarations filtered out.
+    #[structopt(long = "--only", name = "ONLY_PATTERN")]
+    only: Vec<String>,
+
+    /// Do not generate the declarations matching these glob patterns,
+    /// e.g. `--exclude 'Vendor*'`. The selected declarations must not
+    /// use the declarations filtered out.
+    #[structopt(long = "--exclude", name = "EXCLUDE_PATTERN")]
+    exclude: Vec<String>,
+
+    /// Input file, or `-` to read the standard input.
+    #[structopt(name = "FILE")]
+    input_file: String,
+}
+
+// Declarations decoding the HCI records of btsnoop logs. A plain
+// comment, see `GenerateOpt`.
+#[derive(Debug, StructOpt)]
+struct SnoopOpt {
+    /// Declaration of the HCI commands.
+    #[structopt(long, default_value = "Command")]
+    command: String,
+
+    /// Declaration of the HCI events.
+    #[structopt(long, default_value = "Event")]
+    event: String,
+
+    /// Declaration of the ACL packets.
+    #[structopt(long, default_value = "Acl")]
+    acl: String,
+
+    /// Declaration of the SCO packets.
+    #[structopt(long, default_value = "Sco")]
+    sco: String,
+
+    /// Declaration of the ISO packets.
+    #[structopt(long, default_value = "Iso")]
+    iso: String,
+}
+
+impl SnoopOpt {
+    /// Return the declaration decoding the records of this type.
+    fn declaration(&self, packet_type: snoop::PacketType) -> &str {
+        match packet_type {
+            snoop::PacketType::Command => &self.command,
+            snoop::PacketType::Event => &self.event,
+            snoop::PacketType::Acl => &self.acl,
+            snoop::PacketType::Sco => &self.sco,
+            snoop::PacketType::Iso => &self.iso,
+        }
+    }
+}
+
+/// Number of declarations listed in each section of `--timings`.
+const SLOWEST_DECLARATIONS: usize = 10;
+
+/// Output formats of the `doc` subcommand.
+const DOC_FORMATS: [&str; 3] = ["diagram", "mermaid", "csv"];
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Generate code from a grammar.
+    Compile {
+        /// Generate output in this format ("json", "scapy", or "hashes"
+        /// for the wire format hash manifest; the documentation formats
+        /// of `pdl doc` are also accepted). The JSON output follows a
+        /// versioned schema, see `src/backends/json.rs`. The flag can be repeated to generate
+        /// several formats from a single analysis of the input; the
+        /// outputs are then written to `--output` completed with the
+        /// extension of each format.
+        #[structopt(
+            short,
+            long = "--output-format",
+            name = "FORMAT",
+            default_value = "json",
+            number_of_values = 1
+        )]
+        output_format: Vec<String>,
+
+        /// Render this template instead of generating the output format,
+        /// see `src/backends/template.rs` for the syntax and the context.
+        #[structopt(long = "--template", name = "TEMPLATE")]
+        template: Option<String>,
+
+        /// Record the wire format hash of every packet and struct in
+        /// the generated Scapy classes, as the `_wire_hash` attribute.
+        #[structopt(long = "--wire-hashes")]
+        wire_hashes: bool,
+
+        #[structopt(flatten)]
+        generate: GenerateOpt,
+    },
+
+    /// Run the compilations listed in a manifest, see
+    /// `src/manifest.rs` for the format. Input files shared by several
+    /// compilations are parsed once.
+    Batch {
+        /// Manifest file, e.g. `pdl.toml`.
+        #[structopt(name = "MANIFEST")]
+        manifest_file: String,
+    },
+
+    /// Parse and lint the input files, and report the diagnostics.
+    /// Exits with an error if any file has errors.
+    Lint {
+        /// Input files.
+        #[structopt(name = "FILE", required = true)]
+        input_files: Vec<String>,
+    },
+
+    /// Rewrite the input files in canonical format.
+    Fmt {
+        /// Do not write the files; report the files that are not
+        /// formatted and exit with an error if there are any.
+        #[structopt(long)]
+        check: bool,
+
+        /// Input files, or `-` to print the formatted standard input.
+        #[structopt(name = "FILE", required = true)]
+        input_files: Vec<String>,
+    },
+
+    /// Print the dependency graph of the declarations in JSON: the
+    /// parents of packets and structs, and the types used by their
+    /// fields. See `src/graph.rs` for the format.
+    Deps {
+        /// Input file.
+        #[structopt(name = "FILE")]
+        input_file: String,
+    },
+
+    /// Print the packets and structs of a grammar with the schema of
+    /// their fields, in JSON. See `src/registry.rs` for the format.
+    Packets {
+        /// Input file, PDL source or JSON representation (`.json`).
+        #[structopt(name = "FILE")]
+        input_file: String,
+    },
+
+    /// Check the test vectors of a file against the dynamic encoder and
+    /// decoder, e.g. `pdl test vectors.json`. See `src/vectors.rs` for
+    /// the format. With `--output-format`, generate the conformance
+    /// tests of the vectors for a backend instead.
+    ///
+    /// Given a directory, compile its `.pdl` fixtures and compare the
+    /// diagnostics and the output of `--output-format` ("json" by
+    /// default) with the golden files of the fixtures. See
+    /// `src/golden.rs` for the layout.
+    Test {
+        /// Grammar file, replacing the grammar named by the vectors.
+        #[structopt(long = "--grammar", name = "GRAMMAR")]
+        grammar_file: Option<String>,
+
+        /// Generate the conformance tests for this backend ("scapy").
+        #[structopt(long = "--output-format", name = "OUTPUT_FORMAT")]
+        output_format: Option<String>,
+
+        /// Write the conformance tests to this file instead of the
+        /// standard output.
+        #[structopt(long = "--output", name = "OUTPUT")]
+        output: Option<String>,
+
+        /// Generate the Scapy conformance tests as a pytest module
+        /// importing the layers from the Python module MODULE, with a
+        /// round-trip test for each packet and struct.
+        #[structopt(long = "--pytest", name = "MODULE")]
+        pytest: Option<String>,
+
+        /// Update the golden files of the fixtures instead of comparing
+        /// them.
+        #[structopt(long)]
+        bless: bool,
+
+        /// Test vector file, or directory of golden fixtures.
+        #[structopt(name = "VECTORS")]
+        vectors_file: String,
+    },
+
+    /// Generate test vectors with random field values for the packets
+    /// of a grammar, e.g. `pdl corpus --count 16 hci.pdl`. Converting
+    /// the same corpus to the conformance tests of several backends
+    /// with `pdl test --output-format` checks that the backends agree.
+    /// See `src/corpus.rs`.
+    Corpus {
+        /// Number of vectors generated for each packet.
+        #[structopt(long, default_value = "8")]
+        count: usize,
+
+        /// Seed of the random field values.
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+
+        /// Write the corpus to this file instead of the standard
+        /// output.
+        #[structopt(long = "--output", name = "OUTPUT")]
+        output: Option<String>,
+
+        /// Input file, PDL source or JSON representation (`.json`). The
+        /// file is named by the corpus as given.
+        #[structopt(name = "FILE")]
+        input_file: String,
+    },
+
+    /// Experimental: propose a draft declaration from many samples of
+    /// the same unknown packet, with the constant bytes as fixed
+    /// fields, and the counters and payload size fields detected. See
+    /// `src/infer.rs`.
+    Infer {
+        /// Name of the proposed packet.
+        #[structopt(long, default_value = "Inferred")]
+        name: String,
+
+        /// Only use the samples starting with these bytes, in
+        /// hexadecimal, e.g. `ff54` for a vendor event.
+        #[structopt(long)]
+        prefix: Option<String>,
+
+        /// Samples: a btsnoop log, whose records are the samples, or a
+        /// text file with one sample per line, in hexadecimal. Blank
+        /// lines and lines starting with `#` are ignored.
+        #[structopt(name = "SAMPLES")]
+        samples_file: String,
+    },
+
+    /// Convert the packed struct definitions of a C header, e.g. from a
+    /// vendor HAL, to draft PDL declarations. Fields which cannot be
+    /// inferred, e.g. unions and pointers, are left as TODO comments
+    /// and reported. See `src/import_c.rs`.
+    ImportC {
+        /// Write the declarations to this file instead of the standard
+        /// output.
+        #[structopt(long = "--output", name = "OUTPUT")]
+        output: Option<String>,
+
+        /// C header.
+        #[structopt(name = "HEADER")]
+        header_file: String,
+    },
+
+    /// Convert a Kaitai Struct specification (`.ksy`) to draft PDL
+    /// declarations. Attributes with no PDL equivalent are left as TODO
+    /// comments and reported. See `src/import_kaitai.rs`.
+    ImportKaitai {
+        /// Write the declarations to this file instead of the standard
+        /// output.
+        #[structopt(long = "--output", name = "OUTPUT")]
+        output: Option<String>,
+
+        /// Kaitai Struct specification.
+        #[structopt(name = "KSY")]
+        spec_file: String,
+    },
+
+    /// List the bundled Bluetooth definitions, or print the source of
+    /// the definitions NAME. The definitions are used as input files
+    /// with the name `stdlib:NAME`.
+    Stdlib {
+        #[structopt(name = "NAME")]
+        name: Option<String>,
+    },
+
+    /// Rename a declaration and update its references in the input
+    /// files.
+    Rename {
+        /// Current name of the declaration.
+        #[structopt(name = "OLD")]
+        old: String,
+
+        /// New name of the declaration.
+        #[structopt(name = "NEW")]
+        new: String,
+
+        /// Input files.
+        #[structopt(name = "FILE", required = true)]
+        input_files: Vec<String>,
+    },
+
+    /// Generate documentation from a grammar.
+    Doc {
+        /// Generate documentation in this format ("diagram", "mermaid",
+        /// or "csv").
+        #[structopt(short, long = "--format", name = "FORMAT", default_value = "diagram")]
+        format: String,
+
+        #[structopt(flatten)]
+        generate: GenerateOpt,
+    },
+
+    /// Run a Language Server Protocol server on stdin and stdout.
+    Lsp,
+
+    /// Decode a packet given as a hexadecimal string, and print its
+    /// fields, e.g. `pdl decode --packet LeMetaEvent hci.pdl 043e...`.
+    Decode {
+        /// Packet or struct declaration to decode. Child declarations
+        /// are selected from the constraints on the decoded values.
+        #[structopt(long)]
+        packet: String,
+
+        /// Input file, PDL source or JSON representation (`.json`).
+        #[structopt(name = "FILE")]
+        input_file: String,
+
+        /// Packet bytes, in hexadecimal.
+        #[structopt(name = "HEX")]
+        hex: String,
+    },
+
+    /// Decode two packets given as hexadecimal strings, and print the
+    /// fields whose values differ, e.g.
+    /// `pdl bindiff --packet LeMetaEvent hci.pdl 043e... 043e...`.
+    Bindiff {
+        /// Packet or struct declaration to decode.
+        #[structopt(long)]
+        packet: String,
+
+        /// Input file, PDL source or JSON representation (`.json`).
+        #[structopt(name = "FILE")]
+        input_file: String,
+
+        /// Bytes of the first packet, in hexadecimal.
+        #[structopt(name = "OLD_HEX")]
+        old_hex: String,
+
+        /// Bytes of the second packet, in hexadecimal.
+        #[structopt(name = "NEW_HEX")]
+        new_hex: String,
+    },
+
+    /// Decode the HCI records of a btsnoop log, and print their fields.
+    Snoop {
+        #[structopt(flatten)]
+        declarations: SnoopOpt,
+
+        /// Input file, e.g. `hci_packets.pdl`, or its JSON representation.
+        #[structopt(name = "FILE")]
+        input_file: String,
+
+        /// btsnoop log file.
+        #[structopt(name = "LOG")]
+        log_file: String,
+    },
+
+    /// Report the packets, structs and enum tags exercised by test
+    /// vectors or by the records of a btsnoop log, and those never
+    /// exercised. See `src/coverage.rs` for the JSON format.
+    Coverage {
+        /// Report format ("text" or "json").
+        #[structopt(long, default_value = "text")]
+        format: String,
+
+        /// Test vector file, see `src/vectors.rs`. The flag can be
+        /// repeated.
+        #[structopt(long = "--vectors", name = "VECTORS", number_of_values = 1)]
+        vectors_files: Vec<String>,
+
+        /// btsnoop log file.
+        #[structopt(long = "--snoop", name = "LOG")]
+        snoop_file: Option<String>,
+
+        #[structopt(flatten)]
+        declarations: SnoopOpt,
+
+        /// Input file, PDL source or JSON representation (`.json`).
+        #[structopt(name = "FILE")]
+        input_file: String,
+    },
+
+    /// Decode bytes of unknown type as every root packet, and print the
+    /// candidates that decode, best first, followed by the fields of
+    /// the best candidate.
+    Identify {
+        /// Input file, PDL source or JSON representation (`.json`).
+        #[structopt(name = "FILE")]
+        input_file: String,
+
+        /// Packet bytes, in hexadecimal.
+        #[structopt(name = "HEX")]
+        hex: String,
+    },
+
+    /// Compare two revisions of a grammar and report the changes of
+    /// the wire format. Exits with an error if any change is breaking.
+    Compat {
+        /// Previous revision.
+        #[structopt(name = "OLD")]
+        old_file: String,
+
+        /// New revision.
+        #[structopt(name = "NEW")]
+        new_file: String,
+    },
+
+    /// Print the declarations added, removed or changed between two
+    /// grammars, with the changes of their fields.
+    Diff {
+        /// Previous revision.
+        #[structopt(name = "OLD")]
+        old_file: String,
+
+        /// New revision.
+        #[structopt(name = "NEW")]
+        new_file: String,
+    },
+
+    /// Start an interactive session to decode, edit and encode
+    /// packets. Type 'help' for the list of commands.
+    Repl,
+
+    /// Encode a packet from the values of its fields, and print its
+    /// bytes in hexadecimal, e.g. `pdl encode --packet X hci.pdl fields.json`.
+    /// See `src/encoder.rs` for the format of the field values.
+    Encode {
+        /// Packet or struct declaration to encode.
+        #[structopt(long)]
+        packet: String,
+
+        /// Input file, PDL source or JSON representation (`.json`).
+        #[structopt(name = "FILE")]
+        input_file: String,
+
+        /// JSON file with the field values.
+        #[structopt(name = "FIELDS")]
+        fields_file: String,
+
+        /// Also write the packet to this pcapng capture, for Wireshark.
+        #[structopt(long = "--pcapng", name = "PCAPNG")]
+        pcapng_file: Option<String>,
+
+        /// HCI packet type recorded in the capture ("command", "acl",
+        /// "sco", "event", or "iso"). Selected from the name of the root
+        /// declaration by default.
+        #[structopt(long = "--hci-type", name = "HCI_TYPE")]
+        hci_type: Option<snoop::PacketType>,
+    },
+}
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "pdl-parser", about = "Packet Description Language parser tool.")]
@@ -16,29 +531,1353 @@ struct Opt {
     #[structopt(short, long = "--version")]
     version: bool,
 
-    /// Input file.
-    #[structopt(name = "FILE")]
+    /// Render the diagnostics in this format: "full" with the source
+    /// snippets, "short" with the source lines only, "oneline" in
+    /// the GCC format `file:line:column: severity: message`, "json"
+    /// with one object per line, or "sarif" for code scanning tools.
+    #[structopt(long = "--error-format", name = "ERROR_FORMAT", default_value = "full")]
+    error_format: ErrorFormat,
+
+    /// Color the diagnostics: "always", "never", or "auto", to color
+    /// them only when stderr is a terminal.
+    #[structopt(long = "--color", name = "COLOR", default_value = "auto")]
+    color: Color,
+
+    /// Stop rendering errors after this many, and fold diagnostics
+    /// similar to those already rendered, except in the oneline, json
+    /// and sarif formats. The totals are still reported.
+    #[structopt(long = "--max-errors", name = "MAX_ERRORS")]
+    max_errors: Option<usize>,
+
+    /// Write a summary of the run in this format ("json"): files
+    /// processed, declarations generated, diagnostics by code, and
+    /// time spent in each phase. See `src/report.rs`.
+    #[structopt(long = "--report", name = "REPORT_FORMAT")]
+    report: Option<ReportFormat>,
+
+    /// Write the summary to this file instead of stderr.
+    #[structopt(long = "--report-file", name = "REPORT_FILE")]
+    report_file: Option<String>,
+
+    /// Print the time spent in each phase on stderr: parsing, scope
+    /// construction, declaration checks, and each backend, followed
+    /// by the declarations whose lint checks and generation were the
+    /// slowest.
+    #[structopt(long = "--timings")]
+    timings: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+/// Parse and lint the source. Returns `None` if the source cannot be
+/// parsed, or has lint errors.
+fn analyze(
+    emitter: &Emitter,
+    sources: &mut ast::SourceDatabase,
+    name: &str,
+    source: &str,
+    report: &mut Report,
+) -> Option<ast::Grammar> {
+    let mut timings = parser::ParseTimings::default();
+    let grammar = report.time("parse", || {
+        parser::parse_inline_with_timings(sources, name.to_owned(), source.to_owned(), &mut timings)
+    });
+    report.add_parse_timings(&timings);
+    let grammar = match grammar {
+        Ok(grammar) => grammar,
+        Err(err) => {
+            emitter.emit(sources, std::slice::from_ref(&err));
+            report.add_file(name, None, &[err]);
+            return None;
+        }
+    };
+    let mut timings = lint::LintTimings::default();
+    let lint = report.time("lint", || grammar.lint_with_timings(&mut timings));
+    report.add_lint_timings(&timings);
+    emitter.emit(sources, &lint.diagnostics);
+    report.add_file(name, Some(grammar.declarations.len()), &lint.diagnostics);
+    if lint.diagnostics.iter().any(|d| matches!(d.severity, Severity::Error | Severity::Bug)) {
+        return None;
+    }
+    Some(grammar)
+}
+
+/// Generate the output of a linted grammar with the selected backend,
+/// restricted to the selected declarations. Returns `None` if the
+/// selection is invalid.
+fn generate_output(
+    sources: &ast::SourceDatabase,
+    mut grammar: ast::Grammar,
+    selection: &graph::Selection,
+    backend: &dyn backends::Backend,
+    report: &mut Report,
+) -> Option<String> {
+    if !select(&mut grammar, selection) {
+        return None;
+    }
+    let scope = lint::Scope::new(&grammar).ok();
+    let mut output = vec![];
+    let mut timings = backends::GenerateTimings::default();
+    let generated = report.time_backend(backend.name(), || {
+        backend.generate(sources, &grammar, scope.as_ref(), &mut output, &mut timings)
+    });
+    report.add_generate_timings(backend.name(), &timings);
+    if let Err(err) = generated {
+        eprintln!("failed to generate the {} output: {}", backend.name(), err);
+        return None;
+    }
+    report.add_generated(grammar.declarations.len());
+    Some(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Restrict the grammar to the selected declarations. Returns false if
+/// the selection is invalid.
+fn select(grammar: &mut ast::Grammar, selection: &graph::Selection) -> bool {
+    if !selection.is_empty() {
+        if let Err(errors) = selection.apply(grammar) {
+            for err in errors {
+                eprintln!("{}", err);
+            }
+            return false;
+        }
+    }
+    true
+}
+
+/// Parse and lint the source, and generate the output with the
+/// selected backend, restricted to the selected declarations. Returns
+/// `None` if the source cannot be parsed, has lint errors, or the
+/// selection is invalid.
+fn compile(
+    emitter: &Emitter,
+    name: &str,
+    source: &str,
+    selection: &graph::Selection,
+    backend: &dyn backends::Backend,
+    report: &mut Report,
+) -> Option<String> {
+    let mut sources = ast::SourceDatabase::new();
+    let grammar = analyze(emitter, &mut sources, name, source, report)?;
+    generate_output(&sources, grammar, selection, backend, report)
+}
+
+/// Decode a packet and print its fields.
+/// Returns false if the input file could not be parsed, or the packet
+/// could not be decoded.
+fn decode_packet(emitter: &Emitter, input_file: String, packet: &str, hex: &str) -> bool {
+    let mut sources = ast::SourceDatabase::new();
+    let interpreter = match Interpreter::from_file(&mut sources, input_file) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            return false;
+        }
+    };
+    let registry = registry::Registry::new(interpreter);
+    match decoder::parse_hex(hex).and_then(|bytes| registry.decode(packet, &bytes)) {
+        Ok(decoded) => {
+            println!("{}", decoded);
+            true
+        }
+        Err(err) => {
+            eprintln!("failed to decode {}: {}", packet, err);
+            false
+        }
+    }
+}
+
+/// Decode two packets and print the fields that differ.
+/// Returns false if the input file could not be parsed, or either
+/// packet could not be decoded.
+fn diff_packets(emitter: &Emitter, input_file: String, packet: &str, hex: [&str; 2]) -> bool {
+    let mut sources = ast::SourceDatabase::new();
+    let interpreter = match Interpreter::from_file(&mut sources, input_file) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            return false;
+        }
+    };
+    let decode = |hex| interpreter.decode_hex(packet, hex);
+    match (decode(hex[0]), decode(hex[1])) {
+        (Ok(old), Ok(new)) => {
+            for difference in bindiff::diff(&old, &new) {
+                println!("{}", difference);
+            }
+            true
+        }
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("failed to decode {}: {}", packet, err);
+            false
+        }
+    }
+}
+
+/// Decode the records of a btsnoop log, and print their fields.
+/// `declarations` selects the declaration of each packet type.
+/// Returns false if the input file or the log could not be parsed;
+/// records which cannot be decoded are reported and skipped.
+fn decode_snoop(
+    emitter: &Emitter,
     input_file: String,
+    log_file: &str,
+    declarations: &SnoopOpt,
+) -> bool {
+    let mut sources = ast::SourceDatabase::new();
+    let interpreter = match Interpreter::from_file(&mut sources, input_file) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            return false;
+        }
+    };
+    let records = std::fs::read(log_file)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| snoop::parse(&bytes));
+    let records = match records {
+        Ok(records) => records,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", log_file, err);
+            return false;
+        }
+    };
+    let start = records.first().map_or(0, |record| record.timestamp);
+    for (index, record) in records.iter().enumerate() {
+        let elapsed = record.timestamp.saturating_sub(start);
+        println!(
+            "#{} +{}.{:06} {} {}",
+            index + 1,
+            elapsed / 1_000_000,
+            elapsed % 1_000_000,
+            if record.received { "controller > host" } else { "host > controller" },
+            record.packet_type
+        );
+        let id = declarations.declaration(record.packet_type);
+        match interpreter.decode(id, &record.data) {
+            Ok(packet) => println!("{}", packet),
+            Err(err) => println!("failed to decode {}: {}", id, err),
+        }
+    }
+    true
+}
+
+/// Decode the test vectors and the records of a btsnoop log, and
+/// report the coverage of the declarations of the input file. Returns
+/// false if the files could not be loaded.
+fn report_coverage(
+    emitter: &Emitter,
+    input_file: String,
+    vectors_files: &[String],
+    snoop_file: Option<&str>,
+    declarations: &SnoopOpt,
+    format: &str,
+) -> bool {
+    if !matches!(format, "text" | "json") {
+        eprintln!("could not parse {:?}, valid options are 'text', 'json'.", format);
+        return false;
+    }
+    if vectors_files.is_empty() && snoop_file.is_none() {
+        eprintln!("missing packets to decode, see --vectors and --snoop");
+        return false;
+    }
+    let mut sources = ast::SourceDatabase::new();
+    let interpreter = match Interpreter::from_file(&mut sources, input_file) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            return false;
+        }
+    };
+
+    let mut packets = vec![];
+    for vectors_file in vectors_files {
+        match std::fs::read_to_string(vectors_file)
+            .map_err(|err| err.to_string())
+            .and_then(|text| vectors::parse(&text))
+        {
+            Ok(vectors) => packets
+                .extend(vectors.vectors.into_iter().map(|vector| (vector.packet, vector.bytes))),
+            Err(err) => {
+                eprintln!("failed to load {}: {}", vectors_file, err);
+                return false;
+            }
+        }
+    }
+    if let Some(snoop_file) = snoop_file {
+        match std::fs::read(snoop_file)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| snoop::parse(&bytes))
+        {
+            Ok(records) => packets.extend(records.into_iter().map(|record| {
+                (declarations.declaration(record.packet_type).to_owned(), record.data)
+            })),
+            Err(err) => {
+                eprintln!("failed to read {}: {}", snoop_file, err);
+                return false;
+            }
+        }
+    }
+
+    let mut coverage = coverage::Coverage::new(interpreter.grammar());
+    for (id, bytes) in &packets {
+        match interpreter.decode(id, bytes) {
+            Ok(packet) => coverage.add(&packet),
+            Err(_) => coverage.add_failure(),
+        }
+    }
+    match format {
+        "json" => print!("{}", coverage.to_json()),
+        _ => print!("{}", coverage.to_text()),
+    }
+    true
+}
+
+/// Print the candidate packets matching the bytes. Returns false if
+/// the input file could not be parsed, or no packet matches.
+fn identify_packet(emitter: &Emitter, input_file: String, hex: &str) -> bool {
+    let mut sources = ast::SourceDatabase::new();
+    let interpreter = match Interpreter::from_file(&mut sources, input_file) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            return false;
+        }
+    };
+    let bytes = match decoder::parse_hex(hex) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("invalid packet bytes: {}", err);
+            return false;
+        }
+    };
+    let candidates = identify::identify(interpreter.grammar(), &bytes);
+    for (index, candidate) in candidates.iter().enumerate() {
+        println!("{}. {}", index + 1, candidate);
+    }
+    match candidates.first() {
+        Some(best) => {
+            println!("\n{}", best.packet);
+            true
+        }
+        None => {
+            eprintln!("no packet matches the bytes");
+            false
+        }
+    }
+}
+
+/// Encode a packet and print its bytes, and write it to a pcapng
+/// capture if requested. Returns false if the input files could not be
+/// parsed, the packet could not be encoded, or the capture could not be
+/// written.
+fn encode_packet(
+    emitter: &Emitter,
+    input_file: String,
+    packet: &str,
+    fields_file: &str,
+    pcapng_file: Option<&str>,
+    hci_type: Option<snoop::PacketType>,
+) -> bool {
+    let mut sources = ast::SourceDatabase::new();
+    let interpreter = match Interpreter::from_file(&mut sources, input_file) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            return false;
+        }
+    };
+    let fields = std::fs::read_to_string(fields_file)
+        .map_err(|err| err.to_string())
+        .and_then(|fields| serde_json::from_str(&fields).map_err(|err| err.to_string()));
+    let bytes = match fields.and_then(|fields| interpreter.encode(packet, &fields)) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("failed to encode {}: {}", packet, err);
+            return false;
+        }
+    };
+    println!("{}", bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>());
+    if let Some(pcapng_file) = pcapng_file {
+        let packet_type =
+            match hci_type.or_else(|| pcapng::packet_type(interpreter.grammar(), packet)) {
+                Some(packet_type) => packet_type,
+                None => {
+                    eprintln!("cannot select the HCI packet type of {}, see --hci-type", packet);
+                    return false;
+                }
+            };
+        let written = std::fs::File::create(pcapng_file).and_then(|file| {
+            pcapng::Writer::new(file)?.write_packet(packet_type, &bytes, pcapng::now())
+        });
+        if let Err(err) = written {
+            eprintln!("failed to write {}: {}", pcapng_file, err);
+            return false;
+        }
+    }
+    true
+}
+
+/// Check the vectors of a test vector file, or generate their
+/// conformance tests with the selected backend, or as a pytest module
+/// testing the Scapy layers of `pytest_module`. Returns false if the
+/// files could not be loaded, or if any vector fails.
+fn test_vectors(
+    emitter: &Emitter,
+    vectors_file: &str,
+    grammar_file: Option<String>,
+    backend: Option<&dyn backends::Backend>,
+    pytest_module: Option<&str>,
+    output: Option<&str>,
+) -> bool {
+    let vectors = match std::fs::read_to_string(vectors_file)
+        .map_err(|err| err.to_string())
+        .and_then(|text| vectors::parse(&text))
+    {
+        Ok(vectors) => vectors,
+        Err(err) => {
+            eprintln!("failed to load {}: {}", vectors_file, err);
+            return false;
+        }
+    };
+    // The grammar named by the vectors is relative to the vectors.
+    let grammar_file = match (grammar_file, vectors.grammar) {
+        (Some(grammar_file), _) => grammar_file,
+        (None, Some(grammar)) if grammar.starts_with(stdlib::PREFIX) => grammar,
+        (None, Some(grammar)) => {
+            let directory = std::path::Path::new(vectors_file).parent().unwrap_or(".".as_ref());
+            directory.join(grammar).display().to_string()
+        }
+        (None, None) => {
+            eprintln!("{} does not name its grammar, see --grammar", vectors_file);
+            return false;
+        }
+    };
+    let mut sources = ast::SourceDatabase::new();
+    let interpreter = match Interpreter::from_file(&mut sources, grammar_file) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            return false;
+        }
+    };
+
+    let tests = match (pytest_module, backend) {
+        (Some(module), _) => Some(
+            backends::scapy::generate_pytest(
+                &sources,
+                interpreter.grammar(),
+                module,
+                &vectors.vectors,
+            )
+            .into_bytes(),
+        ),
+        (None, Some(backend)) => {
+            let mut tests = vec![];
+            if let Err(err) = backend.generate_tests(
+                &sources,
+                interpreter.grammar(),
+                &vectors.vectors,
+                &mut tests,
+            ) {
+                eprintln!("failed to generate the conformance tests: {}", err);
+                return false;
+            }
+            Some(tests)
+        }
+        (None, None) => None,
+    };
+    if let Some(tests) = tests {
+        return match output {
+            Some(output) => match std::fs::write(output, &tests) {
+                Ok(()) => true,
+                Err(err) => {
+                    eprintln!("failed to write {}: {}", output, err);
+                    false
+                }
+            },
+            None => {
+                print!("{}", String::from_utf8_lossy(&tests));
+                true
+            }
+        };
+    }
+
+    let mut failed = 0;
+    for vector in &vectors.vectors {
+        match vectors::check(interpreter.grammar(), vector) {
+            Ok(()) => println!("test {} ... ok", vector.name),
+            Err(err) => {
+                println!("test {} ... FAILED: {}", vector.name, err);
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", vectors.vectors.len() - failed, failed);
+    failed == 0
+}
+
+/// Generate a corpus of random test vectors for the packets of the
+/// input file. Returns false if the input file could not be loaded,
+/// or the corpus could not be written.
+fn generate_corpus(
+    emitter: &Emitter,
+    input_file: String,
+    count: usize,
+    seed: u64,
+    output: Option<&str>,
+) -> bool {
+    let mut sources = ast::SourceDatabase::new();
+    let interpreter = match Interpreter::from_file(&mut sources, input_file.clone()) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            return false;
+        }
+    };
+    let corpus = vectors::TestVectors {
+        grammar: Some(input_file),
+        vectors: corpus::generate(interpreter.grammar(), count, seed),
+    };
+    match output {
+        Some(output) => match std::fs::write(output, corpus.to_json()) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("failed to write {}: {}", output, err);
+                false
+            }
+        },
+        None => {
+            print!("{}", corpus.to_json());
+            true
+        }
+    }
+}
+
+/// Read the samples of a btsnoop log or hexadecimal text file, and
+/// print the declaration inferred from those starting with `prefix`.
+/// Returns false if the samples could not be read, or are too few.
+fn infer_declaration(samples_file: &str, name: &str, prefix: Option<&str>) -> bool {
+    let bytes = match std::fs::read(samples_file) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", samples_file, err);
+            return false;
+        }
+    };
+    let samples: Result<Vec<Vec<u8>>, String> = if bytes.starts_with(b"btsnoop\0") {
+        snoop::parse(&bytes).map(|records| records.into_iter().map(|record| record.data).collect())
+    } else {
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(decoder::parse_hex)
+            .collect()
+    };
+    let prefix = match prefix.map(decoder::parse_hex).transpose() {
+        Ok(prefix) => prefix.unwrap_or_default(),
+        Err(err) => {
+            eprintln!("invalid prefix: {}", err);
+            return false;
+        }
+    };
+    let samples: Vec<Vec<u8>> = match samples {
+        Ok(samples) => samples.into_iter().filter(|sample| sample.starts_with(&prefix)).collect(),
+        Err(err) => {
+            eprintln!("failed to read the samples of {}: {}", samples_file, err);
+            return false;
+        }
+    };
+    match infer::infer(name, &samples) {
+        Ok(declaration) => {
+            print!("{}", declaration);
+            true
+        }
+        Err(err) => {
+            eprintln!("{}: {}", samples_file, err);
+            false
+        }
+    }
+}
+
+/// Convert the structs of a C header to draft PDL declarations, and
+/// report the fields which could not be converted. Returns false if
+/// the header could not be read, or the declarations could not be
+/// written.
+fn import_c_header(header_file: &str, output: Option<&str>) -> bool {
+    let header = match std::fs::read_to_string(header_file) {
+        Ok(header) => header,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", header_file, err);
+            return false;
+        }
+    };
+    let import = import_c::import(&header, header_file);
+    for issue in &import.issues {
+        eprintln!("{}:{}: warning: {}", header_file, issue.line, issue.message);
+    }
+    match output {
+        Some(output) => match std::fs::write(output, &import.source) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("failed to write {}: {}", output, err);
+                false
+            }
+        },
+        None => {
+            print!("{}", import.source);
+            true
+        }
+    }
+}
+
+/// Convert a Kaitai Struct specification to draft PDL declarations,
+/// and report the attributes which could not be converted. Returns
+/// false if the specification could not be read, or the declarations
+/// could not be written.
+fn import_kaitai_spec(spec_file: &str, output: Option<&str>) -> bool {
+    let spec = match std::fs::read_to_string(spec_file) {
+        Ok(spec) => spec,
+        Err(err) => {
+            eprintln!("failed to read {}: {}", spec_file, err);
+            return false;
+        }
+    };
+    let import = import_kaitai::import(&spec, spec_file);
+    for issue in &import.issues {
+        eprintln!("{}: warning: {}", spec_file, issue);
+    }
+    match output {
+        Some(output) => match std::fs::write(output, &import.source) {
+            Ok(()) => true,
+            Err(err) => {
+                eprintln!("failed to write {}: {}", output, err);
+                false
+            }
+        },
+        None => {
+            print!("{}", import.source);
+            true
+        }
+    }
+}
+
+/// Run the golden fixtures of a directory with the selected backend,
+/// and report the golden files that differ, or update them with
+/// `bless`. Returns false if the fixtures could not be run, or if any
+/// golden file differs.
+fn test_golden(directory: &str, backend: Option<&dyn backends::Backend>, bless: bool) -> bool {
+    let backends: Vec<_> = backend.into_iter().collect();
+    let outcomes = match golden::run(directory.as_ref(), &backends, bless) {
+        Ok(outcomes) => outcomes,
+        Err(err) => {
+            eprintln!("failed to run the fixtures of {}: {}", directory, err);
+            return false;
+        }
+    };
+    let mut failed = 0;
+    for outcome in &outcomes {
+        let fixture = outcome.fixture.display();
+        match (outcome.mismatches.is_empty(), bless) {
+            (true, _) => println!("test {} ... ok", fixture),
+            (false, true) => println!("test {} ... blessed", fixture),
+            (false, false) => {
+                println!("test {} ... FAILED", fixture);
+                failed += 1;
+            }
+        }
+        for mismatch in &outcome.mismatches {
+            println!("    {}", mismatch.describe());
+        }
+    }
+    println!("{} passed, {} failed", outcomes.len() - failed, failed);
+    failed == 0
+}
+
+/// Parse two revisions of a grammar.
+fn parse_revisions(
+    emitter: &Emitter,
+    old_file: String,
+    new_file: String,
+) -> Option<(ast::Grammar, ast::Grammar)> {
+    let mut sources = ast::SourceDatabase::new();
+    let grammars = parser::parse_file(&mut sources, old_file)
+        .and_then(|old| parser::parse_file(&mut sources, new_file).map(|new| (old, new)));
+    match grammars {
+        Ok(grammars) => Some(grammars),
+        Err(err) => {
+            emitter.emit(&sources, std::slice::from_ref(&err));
+            None
+        }
+    }
+}
+
+/// Report the wire format changes between two grammar revisions.
+/// Returns false if either file could not be parsed, or a change is
+/// breaking.
+fn check_compat(emitter: &Emitter, old_file: String, new_file: String) -> bool {
+    let (old, new) = match parse_revisions(emitter, old_file, new_file) {
+        Some(grammars) => grammars,
+        None => return false,
+    };
+    let changes = compat::check(&old, &new);
+    for change in &changes {
+        println!("{}", change);
+    }
+    !changes.iter().any(|change| change.breaking)
+}
+
+/// Format the input files, or check that they are formatted.
+/// Returns false if any file could not be parsed, or is not
+/// formatted in check mode.
+fn format_files(emitter: &Emitter, input_files: Vec<String>, check: bool) -> bool {
+    let mut success = true;
+    for input_file in input_files {
+        let mut sources = ast::SourceDatabase::new();
+        let grammar = match parser::parse_file(&mut sources, input_file.clone()) {
+            Ok(grammar) => grammar,
+            Err(err) => {
+                emitter.emit(&sources, std::slice::from_ref(&err));
+                success = false;
+                continue;
+            }
+        };
+        let formatted = printer::print(&sources, &grammar);
+        if input_file == "-" && !check {
+            print!("{}", formatted);
+            continue;
+        }
+        if *sources.source(grammar.file).unwrap() == formatted {
+            continue;
+        }
+        if check {
+            eprintln!("{} is not formatted", sources.name(grammar.file).unwrap());
+            success = false;
+        } else if let Err(err) = std::fs::write(&input_file, formatted) {
+            eprintln!("failed to write {}: {}", input_file, err);
+            success = false;
+        }
+    }
+    success
+}
+
+/// Rename a declaration in the input files, which are rewritten in
+/// place. Returns false if a file could not be parsed, the new name
+/// is invalid or already declared, or the declaration is not found.
+fn rename_files(emitter: &Emitter, old: &str, new: &str, input_files: Vec<String>) -> bool {
+    if !rename::is_identifier(new) {
+        eprintln!("invalid identifier `{}`", new);
+        return false;
+    }
+    let mut sources = ast::SourceDatabase::new();
+    let mut grammars = vec![];
+    for input_file in input_files {
+        match parser::parse_file(&mut sources, input_file.clone()) {
+            Ok(grammar) => grammars.push((input_file, grammar)),
+            Err(err) => {
+                emitter.emit(&sources, std::slice::from_ref(&err));
+                return false;
+            }
+        }
+    }
+    let declares = |grammar: &ast::Grammar, id: &str| {
+        grammar.declarations.iter().any(|decl| decl.id().map(ast::Symbol::as_str) == Some(id))
+    };
+    if let Some((input_file, _)) = grammars.iter().find(|(_, grammar)| declares(grammar, new)) {
+        eprintln!("`{}` is already declared in {}", new, input_file);
+        return false;
+    }
+    if !grammars.iter().any(|(_, grammar)| declares(grammar, old)) {
+        eprintln!("`{}` is not declared in the input files", old);
+        return false;
+    }
+    let mut success = true;
+    for (input_file, grammar) in &grammars {
+        if let Some(renamed) = rename::rename(&sources, grammar, old, new) {
+            if let Err(err) = std::fs::write(input_file, renamed) {
+                eprintln!("failed to write {}: {}", input_file, err);
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+/// Parse and lint the input files, and report the diagnostics.
+/// Returns false if any file could not be parsed or has lint errors.
+fn lint_files(emitter: &Emitter, input_files: Vec<String>, report: &mut Report) -> bool {
+    let mut success = true;
+    for input_file in input_files {
+        let mut sources = ast::SourceDatabase::new();
+        let mut timings = parser::ParseTimings::default();
+        let grammar = report.time("parse", || {
+            parser::parse_file_with_timings(&mut sources, input_file.clone(), &mut timings)
+        });
+        report.add_parse_timings(&timings);
+        let grammar = match grammar {
+            Ok(grammar) => grammar,
+            Err(err) => {
+                emitter.emit(&sources, std::slice::from_ref(&err));
+                report.add_file(&input_file, None, &[err]);
+                success = false;
+                continue;
+            }
+        };
+        let mut timings = lint::LintTimings::default();
+        let lint = report.time("lint", || grammar.lint_with_timings(&mut timings));
+        report.add_lint_timings(&timings);
+        emitter.emit(&sources, &lint.diagnostics);
+        let name = sources.name(grammar.file).unwrap();
+        report.add_file(&name, Some(grammar.declarations.len()), &lint.diagnostics);
+        if lint.diagnostics.iter().any(|d| matches!(d.severity, Severity::Error | Severity::Bug)) {
+            success = false;
+        }
+    }
+    success
+}
+
+/// Generate the output of the input file with the backend, as
+/// configured by the shared options. Returns false on failure; does
+/// not return in watch mode.
+fn generate(
+    emitter: &Emitter,
+    opt: GenerateOpt,
+    backend: &dyn backends::Backend,
+    report: &mut Report,
+) -> bool {
+    // PDL is the only input format.
+    let InputFormat::Pdl = opt.input_format;
+    let input_file = opt.input_file;
+    let output_file = opt.output_file.filter(|output_file| output_file != "-");
+    let selection = graph::Selection { roots: opt.roots, only: opt.only, exclude: opt.exclude };
+    if opt.depfile.is_some() && output_file.is_none() {
+        eprintln!("--depfile requires --output");
+        return false;
+    }
+    if opt.watch {
+        if input_file == "-" {
+            eprintln!("cannot watch the standard input");
+            return false;
+        }
+        watch::Watcher::new(input_file.clone(), output_file).run(|source| {
+            compile(emitter, &input_file, source, &selection, backend, &mut Report::new())
+        });
+    }
+
+    let (name, source) = match report.time("read", || parser::read_source(&input_file)) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read input file '{}': {}", input_file, err);
+            return false;
+        }
+    };
+    let target = Target { input_file, output_file, depfile: opt.depfile, selection };
+    build_target(&target, &source, backend, report, |report| {
+        compile(emitter, &name, &source, &target.selection, backend, report)
+    })
+}
+
+/// Generate the outputs of the input file with several backends, as
+/// configured by the shared options. The input file is parsed and
+/// analyzed once, and the outputs are written to `--output` completed
+/// with the extension of each backend. Returns false on failure.
+fn generate_all(
+    emitter: &Emitter,
+    opt: GenerateOpt,
+    backends: &[&dyn backends::Backend],
+    report: &mut Report,
+) -> bool {
+    // PDL is the only input format.
+    let InputFormat::Pdl = opt.input_format;
+    let output_file = match opt.output_file.filter(|output_file| output_file != "-") {
+        Some(output_file) => output_file,
+        None => {
+            eprintln!("several output formats require --output");
+            return false;
+        }
+    };
+    if opt.watch || opt.depfile.is_some() {
+        eprintln!("--watch and --depfile require a single output format");
+        return false;
+    }
+    let selection = graph::Selection { roots: opt.roots, only: opt.only, exclude: opt.exclude };
+    let (name, source) = match report.time("read", || parser::read_source(&opt.input_file)) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("failed to read input file '{}': {}", opt.input_file, err);
+            return false;
+        }
+    };
+
+    // Only analyze the input if an output is out of date.
+    let mut targets = vec![];
+    for backend in backends {
+        let output_file = format!("{}.{}", output_file, backend.extension());
+        let stamp = target_stamp(&output_file, &selection, &source, *backend);
+        if !stamp.is_fresh() {
+            targets.push((*backend, output_file, stamp));
+        }
+    }
+    if targets.is_empty() {
+        return true;
+    }
+
+    let mut sources = ast::SourceDatabase::new();
+    let mut grammar = match analyze(emitter, &mut sources, &name, &source, report) {
+        Some(grammar) => grammar,
+        None => return false,
+    };
+    if !select(&mut grammar, &selection) {
+        return false;
+    }
+    let scope = lint::Scope::new(&grammar).ok();
+    let mut success = true;
+    for (backend, output_file, stamp) in targets {
+        let mut output = vec![];
+        let mut timings = backends::GenerateTimings::default();
+        let generated = report.time_backend(backend.name(), || {
+            backend.generate(&sources, &grammar, scope.as_ref(), &mut output, &mut timings)
+        });
+        report.add_generate_timings(backend.name(), &timings);
+        if let Err(err) = generated {
+            eprintln!("failed to generate the {} output: {}", backend.name(), err);
+            success = false;
+            continue;
+        }
+        report.add_generated(grammar.declarations.len());
+        success &= write_output(&output_file, Some(&stamp), &output, report);
+    }
+    success
+}
+
+/// Generation of an input file.
+struct Target {
+    input_file: String,
+    /// Output file, or `None` to write to stdout.
+    output_file: Option<String>,
+    depfile: Option<String>,
+    selection: graph::Selection,
+}
+
+/// Write the depfile of the target, and generate its output unless
+/// it is up to date. `compile` returns the generated output.
+/// Returns false on failure.
+fn build_target(
+    target: &Target,
+    source: &str,
+    backend: &dyn backends::Backend,
+    report: &mut Report,
+    compile: impl FnOnce(&mut Report) -> Option<String>,
+) -> bool {
+    if let (Some(depfile), Some(output_file)) = (&target.depfile, &target.output_file) {
+        let mut inputs: Vec<String> = std::iter::once(target.input_file.clone())
+            .filter(|input_file| input_file != "-")
+            .collect();
+        inputs.extend(backend.inputs().iter().map(|input| input.display().to_string()));
+        let written =
+            report.time("write", || depfile::write(depfile.as_ref(), output_file, &inputs));
+        if let Err(err) = written {
+            eprintln!("failed to write {}: {}", depfile, err);
+            return false;
+        }
+    }
+    let stamp = target
+        .output_file
+        .as_ref()
+        .map(|output_file| target_stamp(output_file, &target.selection, source, backend));
+    if matches!(&stamp, Some(stamp) if stamp.is_fresh()) {
+        return true;
+    }
+    let output = match compile(report) {
+        Some(output) => output,
+        None => return false,
+    };
+    match &target.output_file {
+        Some(output_file) => write_output(output_file, stamp.as_ref(), output.as_bytes(), report),
+        None => {
+            print!("{}", output);
+            true
+        }
+    }
+}
+
+/// Return the stamp of an output file, recording the backend, the
+/// selection and the source used to generate it.
+fn target_stamp(
+    output_file: &str,
+    selection: &graph::Selection,
+    source: &str,
+    backend: &dyn backends::Backend,
+) -> stamp::Stamp {
+    let fingerprint = backend.fingerprint();
+    let selection = selection.fingerprint();
+    stamp::Stamp::new(
+        output_file.as_ref(),
+        &[fingerprint.as_bytes(), selection.as_bytes(), source.as_bytes()],
+    )
+}
+
+/// Write the output file and its stamp. Returns false on failure.
+fn write_output(
+    output_file: &str,
+    stamp: Option<&stamp::Stamp>,
+    output: &[u8],
+    report: &mut Report,
+) -> bool {
+    let written = report.time("write", || {
+        std::fs::write(output_file, output).and_then(|()| stamp.map_or(Ok(()), stamp::Stamp::write))
+    });
+    if let Err(err) = written {
+        eprintln!("failed to write {}: {}", output_file, err);
+        return false;
+    }
+    true
+}
+
+/// Run the compilations of a manifest. Returns false if the manifest
+/// is invalid, or any compilation fails; the remaining compilations
+/// are still run.
+fn run_manifest(
+    emitter: &Emitter,
+    manifest_file: &str,
+    registry: &backends::Registry,
+    report: &mut Report,
+) -> bool {
+    let compilations = std::fs::read_to_string(manifest_file)
+        .map_err(|err| err.to_string())
+        .and_then(|text| manifest::parse(&text));
+    let compilations = match compilations {
+        Ok(compilations) => compilations,
+        Err(err) => {
+            eprintln!("invalid manifest {}: {}", manifest_file, err);
+            return false;
+        }
+    };
+    let dir = std::path::Path::new(manifest_file).parent().unwrap_or_else(|| "".as_ref());
+    let path = |file: &str| dir.join(file).display().to_string();
+
+    let mut sources = ast::SourceDatabase::new();
+    // Source and grammar of the input files, by path.
+    let mut inputs: std::collections::HashMap<String, (String, Option<ast::Grammar>)> =
+        Default::default();
+    let mut success = true;
+    for compilation in compilations {
+        let template_backend;
+        let backend: &dyn backends::Backend = match &compilation.template {
+            Some(template) => {
+                match backends::template::TemplateBackend::from_file(path(template)) {
+                    Ok(backend) => {
+                        template_backend = backend;
+                        &template_backend
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "{}:{}: invalid template {}",
+                            manifest_file, compilation.line, err
+                        );
+                        success = false;
+                        continue;
+                    }
+                }
+            }
+            None => match registry.get(&compilation.format) {
+                Some(backend) => backend,
+                None => {
+                    eprintln!(
+                        "{}:{}: unknown format {:?}, valid options are '{}'.",
+                        manifest_file,
+                        compilation.line,
+                        compilation.format,
+                        registry.names().join("', '")
+                    );
+                    success = false;
+                    continue;
+                }
+            },
+        };
+        let input_file = path(&compilation.input);
+        if !inputs.contains_key(&input_file) {
+            match report.time("read", || std::fs::read_to_string(&input_file)) {
+                Ok(source) => {
+                    inputs.insert(input_file.clone(), (source, None));
+                }
+                Err(err) => {
+                    eprintln!("failed to read input file '{}': {}", input_file, err);
+                    success = false;
+                    continue;
+                }
+            }
+        }
+        let (source, grammar) = inputs.get_mut(&input_file).unwrap();
+        let target = Target {
+            input_file: input_file.clone(),
+            output_file: Some(path(&compilation.output)),
+            depfile: compilation.depfile.as_deref().map(path),
+            selection: compilation.selection(),
+        };
+        success &= build_target(&target, source, backend, report, |report| {
+            if grammar.is_none() {
+                *grammar = analyze(emitter, &mut sources, &input_file, source, report);
+            }
+            generate_output(&sources, grammar.clone()?, &target.selection, backend, report)
+        });
+    }
+    success
 }
 
 fn main() {
     let opt = Opt::from_args();
 
     if opt.version {
-        println!("Packet Description Language parser version 1.0");
+        println!("Packet Description Language parser version {}", stamp::VERSION);
         return;
     }
 
-    let mut sources = ast::SourceDatabase::new();
-    match parser::parse_file(&mut sources, opt.input_file) {
-        Ok(grammar) => {
-            let _ = grammar.lint().print(&sources, termcolor::ColorChoice::Always);
-            println!("{}", serde_json::to_string_pretty(&grammar).unwrap())
+    let emitter = Emitter::new(opt.error_format, opt.color, opt.max_errors);
+    let options = match &opt.command {
+        Some(Command::Compile { wire_hashes, .. }) => {
+            backends::Options { wire_hashes: *wire_hashes }
         }
-        Err(err) => {
-            let writer = termcolor::StandardStream::stderr(termcolor::ColorChoice::Always);
-            let config = term::Config::default();
-            _ = term::emit(&mut writer.lock(), &config, &sources, &err);
+        _ => backends::Options::default(),
+    };
+    let registry = backends::Registry::with_options(&options);
+    let mut report = Report::new();
+    let success = match opt.command {
+        Some(Command::Compile { output_format, template, generate: generate_opt, .. }) => {
+            let template_backend;
+            let backends: Option<Vec<&dyn backends::Backend>> = match template {
+                Some(template) => match backends::template::TemplateBackend::from_file(template) {
+                    Ok(backend) => {
+                        template_backend = backend;
+                        Some(vec![&template_backend])
+                    }
+                    Err(err) => {
+                        eprintln!("invalid template {}", err);
+                        None
+                    }
+                },
+                None => output_format
+                    .iter()
+                    .map(|output_format| {
+                        let backend = registry.get(output_format);
+                        if backend.is_none() {
+                            eprintln!(
+                                "could not parse {:?}, valid options are '{}'.",
+                                output_format,
+                                registry.names().join("', '")
+                            );
+                        }
+                        backend
+                    })
+                    .collect(),
+            };
+            match backends.as_deref() {
+                Some([backend]) => generate(&emitter, generate_opt, *backend, &mut report),
+                Some(backends) => generate_all(&emitter, generate_opt, backends, &mut report),
+                None => false,
+            }
+        }
+        Some(Command::Doc { format, generate: generate_opt }) => {
+            match registry.get(&format).filter(|_| DOC_FORMATS.contains(&format.as_str())) {
+                Some(backend) => generate(&emitter, generate_opt, backend, &mut report),
+                None => {
+                    eprintln!(
+                        "could not parse {:?}, valid options are '{}'.",
+                        format,
+                        DOC_FORMATS.join("', '")
+                    );
+                    false
+                }
+            }
+        }
+        Some(Command::Batch { manifest_file }) => {
+            run_manifest(&emitter, &manifest_file, &registry, &mut report)
         }
+        Some(Command::Lint { input_files }) => lint_files(&emitter, input_files, &mut report),
+        Some(Command::Fmt { check, input_files }) => format_files(&emitter, input_files, check),
+        Some(Command::Rename { old, new, input_files }) => {
+            rename_files(&emitter, &old, &new, input_files)
+        }
+        Some(Command::Lsp) => {
+            let stdin = std::io::stdin();
+            let stdout = std::io::stdout();
+            match lsp::run(&mut stdin.lock(), &mut stdout.lock()) {
+                Ok(success) => success,
+                Err(err) => {
+                    eprintln!("language server error: {}", err);
+                    false
+                }
+            }
+        }
+        Some(Command::Decode { packet, input_file, hex }) => {
+            decode_packet(&emitter, input_file, &packet, &hex)
+        }
+        Some(Command::Bindiff { packet, input_file, old_hex, new_hex }) => {
+            diff_packets(&emitter, input_file, &packet, [&old_hex, &new_hex])
+        }
+        Some(Command::Snoop { declarations, input_file, log_file }) => {
+            decode_snoop(&emitter, input_file, &log_file, &declarations)
+        }
+        Some(Command::Coverage { format, vectors_files, snoop_file, declarations, input_file }) => {
+            report_coverage(
+                &emitter,
+                input_file,
+                &vectors_files,
+                snoop_file.as_deref(),
+                &declarations,
+                &format,
+            )
+        }
+        Some(Command::Identify { input_file, hex }) => identify_packet(&emitter, input_file, &hex),
+        Some(Command::Encode { packet, input_file, fields_file, pcapng_file, hci_type }) => {
+            encode_packet(
+                &emitter,
+                input_file,
+                &packet,
+                &fields_file,
+                pcapng_file.as_deref(),
+                hci_type,
+            )
+        }
+        Some(Command::Compat { old_file, new_file }) => check_compat(&emitter, old_file, new_file),
+        Some(Command::Test {
+            grammar_file,
+            output_format,
+            output,
+            pytest,
+            bless,
+            vectors_file,
+        }) => {
+            let is_directory = std::path::Path::new(&vectors_file).is_dir();
+            match output_format.as_deref().map(|format| (format, registry.get(format))) {
+                _ if pytest.is_some() && output_format.as_deref().unwrap_or("scapy") != "scapy" => {
+                    eprintln!("--pytest requires the scapy output format");
+                    false
+                }
+                _ if pytest.is_some() && is_directory => {
+                    eprintln!("--pytest requires a test vector file");
+                    false
+                }
+                Some((format, None)) => {
+                    eprintln!(
+                        "could not parse {:?}, valid options are '{}'.",
+                        format,
+                        registry.names().join("', '")
+                    );
+                    false
+                }
+                selection if is_directory => test_golden(
+                    &vectors_file,
+                    selection.and_then(|(_, backend)| backend).or_else(|| registry.get("json")),
+                    bless,
+                ),
+                selection => test_vectors(
+                    &emitter,
+                    &vectors_file,
+                    grammar_file,
+                    selection.and_then(|(_, backend)| backend),
+                    pytest.as_deref(),
+                    output.as_deref(),
+                ),
+            }
+        }
+        Some(Command::Corpus { count, seed, output, input_file }) => {
+            generate_corpus(&emitter, input_file, count, seed, output.as_deref())
+        }
+        Some(Command::Infer { name, prefix, samples_file }) => {
+            infer_declaration(&samples_file, &name, prefix.as_deref())
+        }
+        Some(Command::ImportC { output, header_file }) => {
+            import_c_header(&header_file, output.as_deref())
+        }
+        Some(Command::ImportKaitai { output, spec_file }) => {
+            import_kaitai_spec(&spec_file, output.as_deref())
+        }
+        Some(Command::Stdlib { name: None }) => {
+            for (name, _) in stdlib::DEFINITIONS {
+                println!("{}{}", stdlib::PREFIX, name);
+            }
+            true
+        }
+        Some(Command::Stdlib { name: Some(name) }) => match stdlib::get(&name) {
+            Some(source) => {
+                print!("{}", source);
+                true
+            }
+            None => {
+                eprintln!("no bundled definitions named '{}'", name);
+                false
+            }
+        },
+        Some(Command::Packets { input_file }) => {
+            let mut sources = ast::SourceDatabase::new();
+            match Interpreter::from_file(&mut sources, input_file) {
+                Ok(interpreter) => {
+                    print!("{}", registry::Registry::new(interpreter).to_json());
+                    true
+                }
+                Err(err) => {
+                    emitter.emit(&sources, std::slice::from_ref(&err));
+                    false
+                }
+            }
+        }
+        Some(Command::Deps { input_file }) => {
+            let mut sources = ast::SourceDatabase::new();
+            match parser::parse_file(&mut sources, input_file) {
+                Ok(grammar) => {
+                    print!("{}", graph::to_json(&grammar));
+                    true
+                }
+                Err(err) => {
+                    emitter.emit(&sources, std::slice::from_ref(&err));
+                    false
+                }
+            }
+        }
+        Some(Command::Diff { old_file, new_file }) => {
+            match parse_revisions(&emitter, old_file, new_file) {
+                Some((old, new)) => {
+                    print!("{}", diff::diff(&old, &new));
+                    true
+                }
+                None => false,
+            }
+        }
+        Some(Command::Repl) => {
+            let stdin = std::io::stdin();
+            let stdout = std::io::stdout();
+            match repl::run(&mut stdin.lock(), &mut stdout.lock(), true) {
+                Ok(()) => true,
+                Err(err) => {
+                    eprintln!("repl error: {}", err);
+                    false
+                }
+            }
+        }
+        None => {
+            eprintln!("missing subcommand, see --help");
+            false
+        }
+    };
+    emitter.finish();
+    if opt.timings {
+        eprint!("{}", report.timings(SLOWEST_DECLARATIONS));
+    }
+    if let Some(ReportFormat::Json) = opt.report {
+        let summary = report.to_json(success);
+        match &opt.report_file {
+            Some(report_file) => {
+                if let Err(err) = std::fs::write(report_file, summary) {
+                    eprintln!("failed to write {}: {}", report_file, err);
+                    std::process::exit(1);
+                }
+            }
+            None => eprint!("{}", summary),
+        }
+    }
+    if !success {
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,242 @@
+//! Batch manifest.
+//!
+//! A manifest, conventionally `pdl.toml`, lists the compilations run by
+//! `pdl batch`. Input files shared by several compilations are parsed
+//! and linted once. The manifest is written in a subset of TOML: one
+//! `[[compile]]` table per compilation, with string and string array
+//! values on a single line.
+//!
+//! ```text
+//! [[compile]]
+//! input = "hci.pdl"             # required
+//! output = "out/hci.json"       # required
+//! format = "json"               # output format, "json" by default
+//! template = "hci.rs.tera"      # template, replaces the format
+//! roots = ["LeMetaEvent"]       # see `--roots`
+//! only = ["Le*"]                # see `--only`
+//! exclude = ["Vendor*"]         # see `--exclude`
+//! depfile = "out/hci.json.d"    # see `--depfile`
+//! ```
+//!
+//! Paths are relative to the directory of the manifest.
+
+use crate::graph::Selection;
+
+/// Compilation listed in a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compilation {
+    /// Line of the `[[compile]]` header.
+    pub line: usize,
+    pub input: String,
+    pub output: String,
+    pub format: String,
+    pub template: Option<String>,
+    pub depfile: Option<String>,
+    pub roots: Vec<String>,
+    pub only: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Compilation {
+    fn new(line: usize) -> Compilation {
+        Compilation {
+            line,
+            input: String::new(),
+            output: String::new(),
+            format: "json".to_owned(),
+            template: None,
+            depfile: None,
+            roots: vec![],
+            only: vec![],
+            exclude: vec![],
+        }
+    }
+
+    /// Return the selection of the declarations to generate.
+    pub fn selection(&self) -> Selection {
+        Selection {
+            roots: self.roots.clone(),
+            only: self.only.clone(),
+            exclude: self.exclude.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    String(String),
+    Array(Vec<String>),
+}
+
+/// Parse a basic string at the start of `text`, and return its value
+/// and the remaining text.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut chars = text.strip_prefix('"').ok_or("expected a string")?.char_indices();
+    let mut value = String::new();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[index + 2..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                _ => return Err("invalid escape sequence".to_owned()),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_owned())
+}
+
+/// Check that the text following a value is empty or a comment.
+fn expect_end(text: &str) -> Result<(), String> {
+    let text = text.trim_start();
+    if text.is_empty() || text.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected '{}'", text))
+    }
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim_start();
+    if let Some(mut text) = text.strip_prefix('[') {
+        let mut values = vec![];
+        loop {
+            text = text.trim_start();
+            if let Some(rest) = text.strip_prefix(']') {
+                expect_end(rest)?;
+                return Ok(Value::Array(values));
+            }
+            let (value, rest) = parse_string(text)?;
+            values.push(value);
+            text = rest.trim_start();
+            match text.strip_prefix(',') {
+                Some(rest) => text = rest,
+                None if text.starts_with(']') => (),
+                None => return Err("expected ',' or ']'".to_owned()),
+            }
+        }
+    }
+    let (value, rest) = parse_string(text)?;
+    expect_end(rest)?;
+    Ok(Value::String(value))
+}
+
+/// Parse a manifest. Errors are prefixed with the line number.
+pub fn parse(text: &str) -> Result<Vec<Compilation>, String> {
+    let mut compilations: Vec<Compilation> = vec![];
+    let mut keys: Vec<String> = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |err: String| format!("line {}: {}", line_number, err);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            let (table, rest) =
+                header.split_once(']').ok_or_else(|| error("expected ']'".into()))?;
+            match (table, rest.strip_prefix(']')) {
+                ("[compile", Some(rest)) => {
+                    expect_end(rest).map_err(error)?;
+                    compilations.push(Compilation::new(line_number));
+                    keys.clear();
+                }
+                _ => return Err(error(format!("unsupported table '[{}]'", table))),
+            }
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| error("expected '='".into()))?;
+        let key = key.trim();
+        let value = parse_value(value).map_err(error)?;
+        let compilation = compilations
+            .last_mut()
+            .ok_or_else(|| error(format!("key '{}' outside of a [[compile]] table", key)))?;
+        if keys.iter().any(|other| other == key) {
+            return Err(error(format!("duplicate key '{}'", key)));
+        }
+        keys.push(key.to_owned());
+        match (key, value) {
+            ("input", Value::String(value)) => compilation.input = value,
+            ("output", Value::String(value)) => compilation.output = value,
+            ("format", Value::String(value)) => compilation.format = value,
+            ("template", Value::String(value)) => compilation.template = Some(value),
+            ("depfile", Value::String(value)) => compilation.depfile = Some(value),
+            ("roots", Value::Array(values)) => compilation.roots = values,
+            ("only", Value::Array(values)) => compilation.only = values,
+            ("exclude", Value::Array(values)) => compilation.exclude = values,
+            ("input" | "output" | "format" | "template" | "depfile", _) => {
+                return Err(error(format!("expected a string for '{}'", key)))
+            }
+            ("roots" | "only" | "exclude", _) => {
+                return Err(error(format!("expected an array of strings for '{}'", key)))
+            }
+            _ => return Err(error(format!("unknown key '{}'", key))),
+        }
+    }
+    for compilation in &compilations {
+        for (key, value) in [("input", &compilation.input), ("output", &compilation.output)] {
+            if value.is_empty() {
+                return Err(format!("line {}: missing key '{}'", compilation.line, key));
+            }
+        }
+    }
+    Ok(compilations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let compilations = parse(
+            r#"
+# HCI
+[[compile]]
+input = "hci.pdl"
+output = "out/hci.json"
+
+[[compile]]  # LE events only
+input = "hci.pdl"
+output = "out/le.py"
+format = "scapy"
+roots = ["LeMetaEvent", "LeCommand"]  # families
+exclude = [ "Vendor*", ]
+depfile = "out/le \"py\".d"
+"#,
+        )
+        .unwrap();
+        assert_eq!(compilations.len(), 2);
+        assert_eq!(compilations[0].line, 3);
+        assert_eq!(compilations[0].format, "json");
+        assert_eq!(compilations[1].format, "scapy");
+        assert_eq!(compilations[1].roots, vec!["LeMetaEvent", "LeCommand"]);
+        assert_eq!(compilations[1].selection().exclude, vec!["Vendor*"]);
+        assert_eq!(compilations[1].depfile.as_deref(), Some("out/le \"py\".d"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| parse(text).unwrap_err();
+        assert_eq!(
+            error("input = \"a.pdl\""),
+            "line 1: key 'input' outside of a [[compile]] table"
+        );
+        assert_eq!(error("[package]"), "line 1: unsupported table '[package]'");
+        assert_eq!(error("[[compile]]\ninput = \"a.pdl\""), "line 1: missing key 'output'");
+        assert_eq!(error("[[compile]]\nname = \"a\""), "line 2: unknown key 'name'");
+        assert_eq!(
+            error("[[compile]]\nroots = \"A\""),
+            "line 2: expected an array of strings for 'roots'"
+        );
+        assert_eq!(
+            error("[[compile]]\ninput = \"a\"\ninput = \"b\""),
+            "line 3: duplicate key 'input'"
+        );
+        assert_eq!(error("[[compile]]\ninput = \"a"), "line 2: unterminated string");
+        assert_eq!(error("[[compile]]\ninput = \"a\" b"), "line 2: unexpected 'b'");
+    }
+}
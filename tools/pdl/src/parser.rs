@@ -1,9 +1,12 @@
 use super::ast;
+use super::clock::Instant;
+use super::stdlib;
 use codespan_reporting::diagnostic::Diagnostic;
 use codespan_reporting::files;
 use pest::iterators::{Pair, Pairs};
 use pest::{Parser, Token};
 use std::iter::{Filter, Peekable};
+use std::time::Duration;
 
 // Generate the PDL parser.
 // TODO: use #[grammar = "pdl.pest"]
@@ -99,7 +102,11 @@ struct_declaration = {
 }
 
 group_declaration = {
-    "group" ~ identifier ~ "{" ~ field_list ~ "}"
+    "group" ~ identifier ~
+        ("(" ~ constraint_list ~ ")")? ~
+    "{" ~
+        field_list ~
+    "}"
 }
 
 checksum_declaration = {
@@ -145,6 +152,7 @@ trait Helpers<'i> {
     fn children(self) -> NodeIterator<'i>;
     fn as_loc(&self, context: &Context) -> ast::SourceRange;
     fn as_string(&self) -> String;
+    fn as_symbol(&self) -> ast::Symbol;
     fn as_usize(&self) -> Result<usize, String>;
 }
 
@@ -166,6 +174,10 @@ impl<'i> Helpers<'i> for Node<'i> {
         self.as_str().to_owned()
     }
 
+    fn as_symbol(&self) -> ast::Symbol {
+        ast::Symbol::new(self.as_str())
+    }
+
     fn as_usize(&self) -> Result<usize, String> {
         let text = self.as_str();
         if let Some(num) = text.strip_prefix("0x") {
@@ -199,16 +211,16 @@ fn maybe<'i>(iter: &mut NodeIterator<'i>, rule: Rule) -> Option<Node<'i>> {
     iter.next_if(|n| n.as_rule() == rule)
 }
 
-fn parse_identifier(iter: &mut NodeIterator<'_>) -> Result<String, String> {
-    expect(iter, Rule::identifier).map(|n| n.as_string())
+fn parse_identifier(iter: &mut NodeIterator<'_>) -> Result<ast::Symbol, String> {
+    expect(iter, Rule::identifier).map(|n| n.as_symbol())
 }
 
 fn parse_integer(iter: &mut NodeIterator<'_>) -> Result<usize, String> {
     expect(iter, Rule::integer).and_then(|n| n.as_usize())
 }
 
-fn parse_identifier_opt(iter: &mut NodeIterator<'_>) -> Result<Option<String>, String> {
-    Ok(maybe(iter, Rule::identifier).map(|n| n.as_string()))
+fn parse_identifier_opt(iter: &mut NodeIterator<'_>) -> Result<Option<ast::Symbol>, String> {
+    Ok(maybe(iter, Rule::identifier).map(|n| n.as_symbol()))
 }
 
 fn parse_integer_opt(iter: &mut NodeIterator<'_>) -> Result<Option<usize>, String> {
@@ -217,9 +229,9 @@ fn parse_integer_opt(iter: &mut NodeIterator<'_>) -> Result<Option<usize>, Strin
 
 fn parse_identifier_or_integer(
     iter: &mut NodeIterator<'_>,
-) -> Result<(Option<String>, Option<usize>), String> {
+) -> Result<(Option<ast::Symbol>, Option<usize>), String> {
     match iter.next() {
-        Some(n) if n.as_rule() == Rule::identifier => Ok((Some(n.as_string()), None)),
+        Some(n) if n.as_rule() == Rule::identifier => Ok((Some(n.as_symbol()), None)),
         Some(n) if n.as_rule() == Rule::integer => Ok((None, Some(n.as_usize()?))),
         Some(n) => Err(format!(
             "expected rule {:?} or {:?}, got {:?}",
@@ -240,7 +252,7 @@ fn parse_string(iter: &mut NodeIterator<'_>) -> Result<String, String> {
 fn parse_atomic_expr(iter: &mut NodeIterator<'_>, context: &Context) -> Result<ast::Expr, String> {
     match iter.next() {
         Some(n) if n.as_rule() == Rule::identifier => {
-            Ok(ast::Expr::Identifier { loc: n.as_loc(context), name: n.as_string() })
+            Ok(ast::Expr::Identifier { loc: n.as_loc(context), name: n.as_symbol() })
         }
         Some(n) if n.as_rule() == Rule::integer => {
             Ok(ast::Expr::Integer { loc: n.as_loc(context), value: n.as_usize()? })
@@ -331,9 +343,9 @@ fn parse_field(node: Node<'_>, context: &Context) -> Result<ast::Field, String>
         }
         Rule::size_field => {
             let field_id = match children.next() {
-                Some(n) if n.as_rule() == Rule::identifier => n.as_string(),
-                Some(n) if n.as_rule() == Rule::payload_identifier => n.as_string(),
-                Some(n) if n.as_rule() == Rule::body_identifier => n.as_string(),
+                Some(n) if n.as_rule() == Rule::identifier => n.as_symbol(),
+                Some(n) if n.as_rule() == Rule::payload_identifier => n.as_symbol(),
+                Some(n) if n.as_rule() == Rule::body_identifier => n.as_symbol(),
                 Some(n) => err_unexpected_rule(Rule::identifier, n.as_rule())?,
                 None => err_missing_rule(Rule::identifier)?,
             };
@@ -447,21 +459,21 @@ fn parse_grammar(root: Node<'_>, context: &Context) -> Result<ast::Grammar, Stri
                 let id = parse_identifier(&mut children)?;
                 let width = parse_integer(&mut children)?;
                 let function = parse_string(&mut children)?;
-                grammar.declarations.push(ast::Decl::Checksum { id, loc, function, width })
+                grammar.declarations.alloc(ast::Decl::Checksum { id, loc, function, width });
             }
             Rule::custom_field_declaration => {
                 let mut children = node.children();
                 let id = parse_identifier(&mut children)?;
                 let width = parse_integer_opt(&mut children)?;
                 let function = parse_string(&mut children)?;
-                grammar.declarations.push(ast::Decl::CustomField { id, loc, function, width })
+                grammar.declarations.alloc(ast::Decl::CustomField { id, loc, function, width });
             }
             Rule::enum_declaration => {
                 let mut children = node.children();
                 let id = parse_identifier(&mut children)?;
                 let width = parse_integer(&mut children)?;
                 let tags = parse_enum_tag_list(&mut children, context)?;
-                grammar.declarations.push(ast::Decl::Enum { id, loc, width, tags })
+                grammar.declarations.alloc(ast::Decl::Enum { id, loc, width, tags });
             }
             Rule::packet_declaration => {
                 let mut children = node.children();
@@ -469,13 +481,13 @@ fn parse_grammar(root: Node<'_>, context: &Context) -> Result<ast::Grammar, Stri
                 let parent_id = parse_identifier_opt(&mut children)?;
                 let constraints = parse_constraint_list_opt(&mut children, context)?;
                 let fields = parse_field_list_opt(&mut children, context)?;
-                grammar.declarations.push(ast::Decl::Packet {
+                grammar.declarations.alloc(ast::Decl::Packet {
                     id,
                     loc,
                     parent_id,
                     constraints,
                     fields,
-                })
+                });
             }
             Rule::struct_declaration => {
                 let mut children = node.children();
@@ -483,21 +495,29 @@ fn parse_grammar(root: Node<'_>, context: &Context) -> Result<ast::Grammar, Stri
                 let parent_id = parse_identifier_opt(&mut children)?;
                 let constraints = parse_constraint_list_opt(&mut children, context)?;
                 let fields = parse_field_list_opt(&mut children, context)?;
-                grammar.declarations.push(ast::Decl::Struct {
+                grammar.declarations.alloc(ast::Decl::Struct {
                     id,
                     loc,
                     parent_id,
                     constraints,
                     fields,
-                })
+                });
             }
             Rule::group_declaration => {
                 let mut children = node.children();
                 let id = parse_identifier(&mut children)?;
+                let constraints = parse_constraint_list_opt(&mut children, context)?;
                 let fields = parse_field_list(&mut children, context)?;
-                grammar.declarations.push(ast::Decl::Group { id, loc, fields })
+                grammar.declarations.alloc(ast::Decl::Group { id, loc, constraints, fields });
+            }
+            Rule::test_declaration => {
+                let mut children = node.children();
+                let type_id = parse_identifier(&mut children)?;
+                let test_cases = children
+                    .map(|n| ast::TestCase { loc: n.as_loc(context), input: n.as_string() })
+                    .collect();
+                grammar.declarations.alloc(ast::Decl::Test { loc, type_id, test_cases });
             }
-            Rule::test_declaration => {}
             Rule::EOI => (),
             _ => unreachable!(),
         }
@@ -506,6 +526,22 @@ fn parse_grammar(root: Node<'_>, context: &Context) -> Result<ast::Grammar, Stri
     Ok(grammar)
 }
 
+/// Return true if `c` can appear in an identifier, after its first
+/// character, see the `identifier` rule of the grammar.
+pub fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Time spent parsing a source. The pest parser tokenizes and parses
+/// the source in a single pass.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ParseTimings {
+    /// Tokenizing and parsing with the pest grammar.
+    pub syntax: Duration,
+    /// Construction of the AST from the parse tree.
+    pub ast: Duration,
+}
+
 /// Parse a PDL grammar text.
 /// The grammar is added to the compilation database under the
 /// provided name.
@@ -514,19 +550,37 @@ pub fn parse_inline(
     name: String,
     source: String,
 ) -> Result<ast::Grammar, Diagnostic<ast::FileId>> {
-    let root = PDLParser::parse(Rule::grammar, &source)
+    parse_inline_with_timings(sources, name, source, &mut ParseTimings::default())
+}
+
+/// Parse a PDL grammar text, as [`parse_inline`], and add the time
+/// spent in each phase to `timings`.
+pub fn parse_inline_with_timings(
+    sources: &mut ast::SourceDatabase,
+    name: String,
+    source: String,
+    timings: &mut ParseTimings,
+) -> Result<ast::Grammar, Diagnostic<ast::FileId>> {
+    let start = Instant::now();
+    let root = PDLParser::parse(Rule::grammar, &source);
+    timings.syntax += start.elapsed();
+    let root = root
         .map_err(|e| {
             Diagnostic::error()
                 .with_message(format!("failed to parse input file '{}': {}", &name, e))
         })?
         .next()
         .unwrap();
+    let start = Instant::now();
     let line_starts: Vec<_> = files::line_starts(&source).collect();
     let file = sources.add(name, source.clone());
-    parse_grammar(root, &(file, &line_starts)).map_err(|e| Diagnostic::error().with_message(e))
+    let grammar =
+        parse_grammar(root, &(file, &line_starts)).map_err(|e| Diagnostic::error().with_message(e));
+    timings.ast += start.elapsed();
+    grammar
 }
 
-/// Parse a new source file.
+/// Parse a new source file, or the standard input if the name is `-`.
 /// The source file is fully read and added to the compilation database.
 /// Returns the constructed AST, or a descriptive error message in case
 /// of syntax error.
@@ -534,8 +588,36 @@ pub fn parse_file(
     sources: &mut ast::SourceDatabase,
     name: String,
 ) -> Result<ast::Grammar, Diagnostic<ast::FileId>> {
-    let source = std::fs::read_to_string(&name).map_err(|e| {
+    parse_file_with_timings(sources, name, &mut ParseTimings::default())
+}
+
+/// Parse a new source file, as [`parse_file`], and add the time spent
+/// parsing it to `timings`.
+pub fn parse_file_with_timings(
+    sources: &mut ast::SourceDatabase,
+    name: String,
+    timings: &mut ParseTimings,
+) -> Result<ast::Grammar, Diagnostic<ast::FileId>> {
+    let (name, source) = read_source(&name).map_err(|e| {
         Diagnostic::error().with_message(format!("failed to read input file '{}': {}", &name, e))
     })?;
-    parse_inline(sources, name, source)
+    parse_inline_with_timings(sources, name, source, timings)
+}
+
+/// Name of the standard input in diagnostics.
+pub const STDIN_NAME: &str = "<stdin>";
+
+/// Read a source file, the standard input if the name is `-`, or the
+/// bundled definitions if the name is `stdlib:<name>`.
+/// Returns the name of the source to use in diagnostics, and its text.
+pub fn read_source(name: &str) -> std::io::Result<(String, String)> {
+    if let Some(source) = stdlib::resolve(name) {
+        Ok((name.to_owned(), source?.to_owned()))
+    } else if name == "-" {
+        let mut source = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut source)?;
+        Ok((STDIN_NAME.to_owned(), source))
+    } else {
+        Ok((name.to_owned(), std::fs::read_to_string(name)?))
+    }
 }
@@ -0,0 +1,148 @@
+//! pcapng capture writer.
+//!
+//! Writes HCI packets to pcapng files which can be opened in Wireshark.
+//! The capture has a single interface with the link type
+//! `LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR`: each packet is prefixed with
+//! its direction, as a big endian 32-bit integer (0 for sent, 1 for
+//! received), and its H4 packet type indicator. Events are recorded as
+//! received from the controller, and the other packets as sent by the
+//! host. Blocks are written in little endian, with microsecond
+//! timestamps.
+//!
+//! The HCI packet type of a declaration is selected from the name of
+//! its root declaration, e.g. `Command` or `Event` in
+//! `hci_packets.pdl`.
+
+use std::io::{self, Write};
+
+use crate::ast;
+use crate::snoop::PacketType;
+
+const LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR: u16 = 201;
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+/// Return the HCI packet type of a packet declaration, from the name
+/// of its root declaration.
+pub fn packet_type(grammar: &ast::Grammar, id: &str) -> Option<PacketType> {
+    let mut root = id;
+    for _ in 0..grammar.declarations.len() {
+        let parent_id = grammar.declarations.iter().find_map(|decl| match decl {
+            ast::Decl::Packet { id, parent_id, .. } if id == root => parent_id.as_deref(),
+            _ => None,
+        });
+        match parent_id {
+            Some(parent_id) => root = parent_id,
+            None => break,
+        }
+    }
+    root.parse().ok()
+}
+
+/// Writer of a pcapng capture.
+pub struct Writer<W: Write> {
+    output: W,
+}
+
+/// Write a block, with the body padded to 32 bits.
+fn write_block(output: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let padding = (4 - body.len() % 4) % 4;
+    let length = (12 + body.len() + padding) as u32;
+    output.write_all(&block_type.to_le_bytes())?;
+    output.write_all(&length.to_le_bytes())?;
+    output.write_all(body)?;
+    output.write_all(&[0; 3][..padding])?;
+    output.write_all(&length.to_le_bytes())
+}
+
+impl<W: Write> Writer<W> {
+    /// Start a capture, with its section header and interface.
+    pub fn new(mut output: W) -> io::Result<Writer<W>> {
+        let mut section = vec![];
+        section.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend(1u16.to_le_bytes());
+        section.extend(0u16.to_le_bytes());
+        // Unknown section length.
+        section.extend((-1i64).to_le_bytes());
+        write_block(&mut output, SECTION_HEADER_BLOCK, &section)?;
+
+        let mut interface = vec![];
+        interface.extend(LINKTYPE_BLUETOOTH_HCI_H4_WITH_PHDR.to_le_bytes());
+        interface.extend(0u16.to_le_bytes());
+        // No snapshot length limit.
+        interface.extend(0u32.to_le_bytes());
+        write_block(&mut output, INTERFACE_DESCRIPTION_BLOCK, &interface)?;
+        Ok(Writer { output })
+    }
+
+    /// Append a packet, with its timestamp in microseconds since the
+    /// Unix epoch.
+    pub fn write_packet(
+        &mut self,
+        packet_type: PacketType,
+        data: &[u8],
+        timestamp: u64,
+    ) -> io::Result<()> {
+        let length = (data.len() + 5) as u32;
+        let mut block = vec![];
+        block.extend(0u32.to_le_bytes());
+        block.extend(((timestamp >> 32) as u32).to_le_bytes());
+        block.extend((timestamp as u32).to_le_bytes());
+        block.extend(length.to_le_bytes());
+        block.extend(length.to_le_bytes());
+        let received = packet_type == PacketType::Event;
+        block.extend((received as u32).to_be_bytes());
+        block.push(packet_type.h4());
+        block.extend(data);
+        write_block(&mut self.output, ENHANCED_PACKET_BLOCK, &block)?;
+        self.output.flush()
+    }
+}
+
+/// Return the current time in microseconds since the Unix epoch.
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_micros() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_packet_type() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            packet Command { _payload_ }
+            packet Reset : Command { }
+            packet LeReset : Reset { }
+            packet Other { }
+            "#,
+        );
+        assert_eq!(packet_type(&grammar, "LeReset"), Some(PacketType::Command));
+        assert_eq!(packet_type(&grammar, "Command"), Some(PacketType::Command));
+        assert_eq!(packet_type(&grammar, "Other"), None);
+    }
+
+    #[test]
+    fn test_writer() {
+        let mut output = vec![];
+        let mut writer = Writer::new(&mut output).unwrap();
+        writer.write_packet(PacketType::Event, &[0x0e, 0x01, 0x00], 0x1_0000_0002).unwrap();
+        assert_eq!(output.len(), 28 + 20 + 40);
+        assert_eq!(&output[..4], &[0x0a, 0x0d, 0x0d, 0x0a]);
+        assert_eq!(&output[28..32], &[0x01, 0x00, 0x00, 0x00]);
+        assert_eq!(&output[32..36], &[20, 0, 0, 0]);
+        let packet = &output[48..];
+        assert_eq!(&packet[..8], &[0x06, 0, 0, 0, 40, 0, 0, 0]);
+        assert_eq!(&packet[12..20], &[0x01, 0, 0, 0, 0x02, 0, 0, 0]);
+        assert_eq!(&packet[20..24], &[8, 0, 0, 0]);
+        assert_eq!(&packet[28..36], &[0, 0, 0, 1, 0x04, 0x0e, 0x01, 0x00]);
+        assert_eq!(&packet[36..], &[40, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,99 @@
+//! Playground bindings.
+//!
+//! Entry points of the toolchain that take the grammar source as a
+//! string and never access the filesystem, for a browser playground.
+//! When compiled for `wasm32-unknown-unknown` they are exported with
+//! `wasm-bindgen`. Build them from the `tools/pdl` directory:
+//!
+//! ```text
+//! cargo build --lib --target wasm32-unknown-unknown
+//! wasm-bindgen --target web target/wasm32-unknown-unknown/debug/pdl_build.wasm
+//! ```
+//!
+//! Errors and diagnostics are rendered as plain text. On WebAssembly,
+//! the lint checks run on a single thread.
+
+#[cfg(target_arch = "wasm32")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::ast;
+use crate::backends::{GenerateTimings, Registry};
+use crate::build::render;
+use crate::decoder;
+use crate::lint::{self, Lintable};
+use crate::parser;
+
+/// Name of the playground source in the diagnostics.
+const SOURCE_NAME: &str = "playground.pdl";
+
+fn parse_source(source: &str) -> Result<(ast::SourceDatabase, ast::Grammar), String> {
+    let mut sources = ast::SourceDatabase::new();
+    match parser::parse_inline(&mut sources, SOURCE_NAME.to_owned(), source.to_owned()) {
+        Ok(grammar) => Ok((sources, grammar)),
+        Err(err) => Err(render(&sources, &[err])),
+    }
+}
+
+/// Parse the source, and return its AST in JSON.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn parse(source: &str) -> Result<String, String> {
+    let (_, grammar) = parse_source(source)?;
+    serde_json::to_string_pretty(&grammar).map_err(|err| err.to_string())
+}
+
+/// Parse and lint the source, and return the diagnostics.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn lint(source: &str) -> String {
+    match parse_source(source) {
+        Ok((sources, grammar)) => render(&sources, &grammar.lint().diagnostics),
+        Err(err) => err,
+    }
+}
+
+/// Generate the output of the source in one of the built-in formats.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn generate(source: &str, format: &str) -> Result<String, String> {
+    let registry = Registry::new();
+    let backend = registry.get(format).ok_or_else(|| {
+        format!(
+            "unknown format {:?}, valid options are '{}'.",
+            format,
+            registry.names().join("', '")
+        )
+    })?;
+    let (sources, grammar) = parse_source(source)?;
+    let mut output = vec![];
+    let scope = lint::Scope::new(&grammar).ok();
+    let mut timings = GenerateTimings::default();
+    backend
+        .generate(&sources, &grammar, scope.as_ref(), &mut output, &mut timings)
+        .map_err(|err| err.to_string())?;
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Decode a packet given as a hexadecimal string, and return its
+/// fields.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub fn decode_hex(source: &str, packet: &str, hex: &str) -> Result<String, String> {
+    let (_, grammar) = parse_source(source)?;
+    let bytes = decoder::parse_hex(hex)?;
+    Ok(decoder::decode(&grammar, packet, &bytes)?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "little_endian_packets\npacket Foo { a: 8, b: 8 }\n";
+
+    #[test]
+    fn test_playground() {
+        assert!(parse(SOURCE).is_ok());
+        assert!(parse("packet").is_err());
+        assert_eq!(lint(SOURCE), "");
+        assert!(generate(SOURCE, "csv").unwrap().contains("Foo"));
+        assert!(generate(SOURCE, "rust").is_err());
+        assert!(decode_hex(SOURCE, "Foo", "0102").unwrap().contains("Foo"));
+        assert!(decode_hex(SOURCE, "Foo", "01").is_err());
+    }
+}
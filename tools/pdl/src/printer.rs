@@ -0,0 +1,465 @@
+//! PDL source printer.
+//!
+//! Prints a grammar back to PDL source in canonical form:
+//!
+//! - declaration bodies are indented with two spaces,
+//! - fields, enum tags, and test cases end with a comma,
+//! - the `:` of fields and the `=` of enum tags are aligned within
+//!   each group of consecutive lines,
+//! - comments are preserved, either on their own line before the
+//!   following element or at the end of the line they were found on,
+//! - a single blank line is kept where the source has one or more,
+//!   and multi-line declarations are always separated by a blank line.
+//!
+//! Integer literals are printed with their original spelling, so that
+//! hexadecimal values remain hexadecimal.
+
+use codespan_reporting::files::Files;
+
+use crate::ast;
+
+const INDENT: &str = "  ";
+
+/// Content of a printed element.
+enum Content {
+    /// Single line element, with an optional `separator value` part
+    /// aligned with the neighbouring lines.
+    Item { lhs: String, rhs: Option<(&'static str, String)> },
+    /// Multi-line element, printed verbatim.
+    Lines(Vec<String>),
+    /// Comment on its own line.
+    Comment(String),
+}
+
+struct Entry {
+    content: Content,
+    /// The source has a blank line before this element.
+    blank_before: bool,
+    /// Comment found at the end of the element line.
+    trailing: Option<String>,
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    comments: Vec<&'a ast::Comment>,
+    next_comment: usize,
+}
+
+impl<'a> Printer<'a> {
+    /// Return the original spelling of the integer `value` inside
+    /// the source range `loc`.
+    fn literal(&self, loc: &ast::SourceRange, value: usize) -> String {
+        self.source[loc.start.offset..loc.end.offset]
+            .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .filter(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+            .find(|word| {
+                let parsed = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => usize::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                parsed == Ok(value)
+            })
+            .map(str::to_owned)
+            .unwrap_or_else(|| value.to_string())
+    }
+
+    /// Check whether the source has a blank line between the offsets.
+    fn blank_between(&self, start: Option<usize>, end: usize) -> bool {
+        matches!(start, Some(start) if self.source[start..end].matches('\n').count() >= 2)
+    }
+
+    /// Move the comments starting before `offset` to `entries`.
+    fn leading_comments(
+        &mut self,
+        offset: usize,
+        entries: &mut Vec<Entry>,
+        previous_end: &mut Option<usize>,
+    ) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.loc.start.offset >= offset {
+                break;
+            }
+            entries.push(Entry {
+                content: Content::Comment(comment.text.trim_end().to_owned()),
+                blank_before: self.blank_between(*previous_end, comment.loc.start.offset),
+                trailing: None,
+            });
+            *previous_end = Some(comment.loc.end.offset);
+            self.next_comment += 1;
+        }
+    }
+
+    /// Add an element to `entries`, preceded by its leading comments
+    /// and followed by its trailing comment. The content is generated
+    /// after the leading comments have been consumed, so that nested
+    /// elements only see the comments located inside `loc`.
+    fn push<F>(
+        &mut self,
+        loc: &ast::SourceRange,
+        entries: &mut Vec<Entry>,
+        previous_end: &mut Option<usize>,
+        content: F,
+    ) where
+        F: FnOnce(&mut Self) -> Content,
+    {
+        self.leading_comments(loc.start.offset, entries, previous_end);
+        let blank_before = self.blank_between(*previous_end, loc.start.offset);
+        let content = content(self);
+        *previous_end = Some(loc.end.offset);
+        let trailing = match self.comments.get(self.next_comment) {
+            Some(comment)
+                if comment.loc.start.line == loc.end.line
+                    && !comment.text.contains('\n')
+                    && comment.loc.start.offset >= loc.end.offset =>
+            {
+                self.next_comment += 1;
+                *previous_end = Some(comment.loc.end.offset);
+                Some(comment.text.trim_end().to_owned())
+            }
+            _ => None,
+        };
+        entries.push(Entry { content, blank_before, trailing });
+    }
+
+    fn constraint(&self, constraint: &ast::Constraint) -> String {
+        let value = match &constraint.value {
+            ast::Expr::Identifier { name, .. } => name.to_string(),
+            ast::Expr::Integer { value, .. } => self.literal(&constraint.loc, *value),
+            _ => "?".to_owned(),
+        };
+        format!("{} = {}", constraint.id, value)
+    }
+
+    fn constraints(&self, constraints: &[ast::Constraint]) -> String {
+        constraints.iter().map(|c| self.constraint(c)).collect::<Vec<_>>().join(", ")
+    }
+
+    fn field(&self, field: &ast::Field) -> Content {
+        let loc = field.loc();
+        let (lhs, rhs) = match field {
+            ast::Field::Checksum { field_id, .. } => {
+                (format!("_checksum_start_({})", field_id), None)
+            }
+            ast::Field::Padding { width, .. } => {
+                (format!("_padding_[{}]", self.literal(loc, *width)), None)
+            }
+            ast::Field::Size { field_id, width, .. } => {
+                (format!("_size_({})", field_id), Some(self.literal(loc, *width)))
+            }
+            ast::Field::Count { field_id, width, .. } => {
+                (format!("_count_({})", field_id), Some(self.literal(loc, *width)))
+            }
+            ast::Field::Body { .. } => ("_body_".to_owned(), None),
+            ast::Field::Payload { size_modifier: Some(modifier), .. } => {
+                ("_payload_".to_owned(), Some(format!("[{}]", modifier)))
+            }
+            ast::Field::Payload { .. } => ("_payload_".to_owned(), None),
+            ast::Field::Fixed { width: Some(width), value: Some(value), .. } => (
+                format!("_fixed_ = {}", self.literal(loc, *value)),
+                Some(self.literal(loc, *width)),
+            ),
+            ast::Field::Fixed { enum_id, tag_id, .. } => (
+                format!("_fixed_ = {}", tag_id.as_deref().unwrap_or_default()),
+                Some(enum_id.as_deref().unwrap_or_default().to_owned()),
+            ),
+            ast::Field::Reserved { width, .. } => {
+                ("_reserved_".to_owned(), Some(self.literal(loc, *width)))
+            }
+            ast::Field::Array { id, width, type_id, size_modifier, size, .. } => {
+                let element = match (width, type_id) {
+                    (Some(width), _) => self.literal(loc, *width),
+                    (_, Some(type_id)) => type_id.to_string(),
+                    _ => String::new(),
+                };
+                let size = match (size, size_modifier) {
+                    (Some(size), _) => self.literal(loc, *size),
+                    (_, Some(modifier)) => modifier.clone(),
+                    _ => String::new(),
+                };
+                (id.to_string(), Some(format!("{}[{}]", element, size)))
+            }
+            ast::Field::Scalar { id, width, .. } => {
+                (id.to_string(), Some(self.literal(loc, *width)))
+            }
+            ast::Field::Typedef { id, type_id, .. } => (id.to_string(), Some(type_id.to_string())),
+            ast::Field::Group { group_id, constraints, .. } if constraints.is_empty() => {
+                (group_id.to_string(), None)
+            }
+            ast::Field::Group { group_id, constraints, .. } => {
+                (format!("{} {{ {} }}", group_id, self.constraints(constraints)), None)
+            }
+        };
+        Content::Item { lhs, rhs: rhs.map(|rhs| (":", rhs)) }
+    }
+
+    /// Print a declaration body between braces.
+    fn body<T, F>(
+        &mut self,
+        header: String,
+        loc: &ast::SourceRange,
+        items: &[T],
+        item_loc: fn(&T) -> &ast::SourceRange,
+        item: F,
+    ) -> Content
+    where
+        F: Fn(&Self, &T) -> Content,
+    {
+        let mut entries = vec![];
+        let mut previous_end = None;
+        for element in items {
+            self.push(item_loc(element), &mut entries, &mut previous_end, |p| item(p, element));
+        }
+        // Comments located after the last element.
+        self.leading_comments(loc.end.offset, &mut entries, &mut previous_end);
+
+        let mut lines = vec![format!("{} {{", header)];
+        lines.extend(render(&entries, INDENT, ","));
+        lines.push("}".to_owned());
+        Content::Lines(lines)
+    }
+
+    fn decl(&mut self, decl: &ast::Decl) -> Content {
+        let loc = decl.loc();
+        match decl {
+            ast::Decl::Checksum { id, function, width, .. } => Content::Lines(vec![format!(
+                "checksum {} : {} {}",
+                id,
+                self.literal(loc, *width),
+                function
+            )]),
+            ast::Decl::CustomField { id, width: Some(width), function, .. } => Content::Lines(
+                vec![format!("custom_field {} : {} {}", id, self.literal(loc, *width), function)],
+            ),
+            ast::Decl::CustomField { id, function, .. } => {
+                Content::Lines(vec![format!("custom_field {} {}", id, function)])
+            }
+            ast::Decl::Enum { id, tags, width, .. } => {
+                let header = format!("enum {} : {}", id, self.literal(loc, *width));
+                self.body(
+                    header,
+                    loc,
+                    tags,
+                    |t| &t.loc,
+                    |p, t| Content::Item {
+                        lhs: t.id.to_string(),
+                        rhs: Some(("=", p.literal(&t.loc, t.value))),
+                    },
+                )
+            }
+            ast::Decl::Packet { id, constraints, fields, parent_id, .. }
+            | ast::Decl::Struct { id, constraints, fields, parent_id, .. } => {
+                let mut header = format!("{} {}", decl.kind(), id);
+                if let Some(parent_id) = parent_id {
+                    header.push_str(&format!(" : {}", parent_id));
+                }
+                if !constraints.is_empty() {
+                    header.push_str(&format!(" ({})", self.constraints(constraints)));
+                }
+                self.body(header, loc, fields, ast::Field::loc, |p, f| p.field(f))
+            }
+            ast::Decl::Group { id, constraints, fields, .. } => {
+                let mut header = format!("group {}", id);
+                if !constraints.is_empty() {
+                    header.push_str(&format!(" ({})", self.constraints(constraints)));
+                }
+                self.body(header, loc, fields, ast::Field::loc, |p, f| p.field(f))
+            }
+            ast::Decl::Test { type_id, test_cases, .. } => self.body(
+                format!("test {}", type_id),
+                loc,
+                test_cases,
+                |t| &t.loc,
+                |_, t| Content::Item { lhs: t.input.clone(), rhs: None },
+            ),
+        }
+    }
+}
+
+/// Render entries to lines, aligning the items of each group of
+/// lines not separated by blank lines.
+fn render(entries: &[Entry], indent: &str, terminator: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut group_start = 0;
+    while group_start < entries.len() {
+        let group_end = entries[group_start + 1..]
+            .iter()
+            .position(|e| e.blank_before)
+            .map_or(entries.len(), |position| group_start + 1 + position);
+        let group = &entries[group_start..group_end];
+        let width = group
+            .iter()
+            .filter_map(|e| match &e.content {
+                Content::Item { lhs, rhs: Some(_) } => Some(lhs.len()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        if group_start > 0 {
+            lines.push(String::new());
+        }
+        for entry in group {
+            let mut text = match &entry.content {
+                Content::Item { lhs, rhs: Some((separator, rhs)) } => {
+                    format!("{}{:width$} {} {}{}", indent, lhs, separator, rhs, terminator)
+                }
+                Content::Item { lhs, rhs: None } => format!("{}{}{}", indent, lhs, terminator),
+                Content::Lines(content) => content
+                    .iter()
+                    .map(
+                        |line| {
+                            if line.is_empty() {
+                                line.clone()
+                            } else {
+                                indent.to_owned() + line
+                            }
+                        },
+                    )
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                Content::Comment(comment) => format!("{}{}", indent, comment),
+            };
+            if let Some(trailing) = &entry.trailing {
+                text.push(' ');
+                text.push_str(trailing);
+            }
+            lines.push(text);
+        }
+        group_start = group_end;
+    }
+    lines
+}
+
+/// Print the grammar in canonical form.
+pub fn print(sources: &ast::SourceDatabase, grammar: &ast::Grammar) -> String {
+    let mut comments: Vec<_> = grammar.comments.iter().collect();
+    comments.sort_by_key(|c| c.loc.start.offset);
+    let mut printer =
+        Printer { source: sources.source(grammar.file).unwrap(), comments, next_comment: 0 };
+
+    // Multi-line declarations and the endianness declaration are
+    // surrounded by blank lines.
+    let mut entries = vec![];
+    let mut spaced = vec![];
+    let mut previous_end = None;
+    if let Some(endianness) = &grammar.endianness {
+        printer.push(&endianness.loc, &mut entries, &mut previous_end, |_| {
+            Content::Lines(vec![match endianness.value {
+                ast::EndiannessValue::LittleEndian => "little_endian_packets".to_owned(),
+                ast::EndiannessValue::BigEndian => "big_endian_packets".to_owned(),
+            }])
+        });
+        spaced.resize(entries.len(), false);
+        spaced[entries.len() - 1] = true;
+    }
+    for decl in &grammar.declarations {
+        printer.push(decl.loc(), &mut entries, &mut previous_end, |p| p.decl(decl));
+        spaced.resize(entries.len(), false);
+        spaced[entries.len() - 1] =
+            matches!(&entries[entries.len() - 1].content, Content::Lines(lines) if lines.len() > 1);
+    }
+    printer.leading_comments(usize::MAX, &mut entries, &mut previous_end);
+    spaced.resize(entries.len(), false);
+
+    for index in 0..entries.len() {
+        if !spaced[index] {
+            continue;
+        }
+        // Blank lines go before the leading comments of the declaration.
+        let mut first = index;
+        while first > 0 && matches!(entries[first - 1].content, Content::Comment(_)) {
+            first -= 1;
+        }
+        if first > 0 {
+            entries[first].blank_before = true;
+        }
+        if index + 1 < entries.len() {
+            entries[index + 1].blank_before = true;
+        }
+    }
+
+    let mut out = render(&entries, "", "").join("\n");
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    fn format(text: &str) -> String {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(&mut db, text);
+        print(&db, &grammar)
+    }
+
+    #[test]
+    fn test_print() {
+        let formatted = r#"// License header.
+
+little_endian_packets
+
+custom_field Address : 48 "hci/"
+checksum Crc : 0x10 "crc/"
+
+// Operation codes.
+enum Op : 8 {
+  READ  = 0x01,
+  WRITE = 0x02, // Write access.
+
+  /* Reserved range. */
+  RESERVED = 0xff,
+}
+
+group Header {
+  op : Op,
+}
+
+packet Command : Base (a = 0x1, b = READ) {
+  Header { op = WRITE },
+  _size_(_payload_) : 8,
+  _fixed_ = 0x12    : 8,
+  _reserved_        : 4,
+  data              : 8[+2],
+
+  _payload_ : [+1],
+  // Trailing comment.
+}
+
+packet Empty {
+}
+
+test Command {
+  "\x01\x02",
+}
+"#;
+        let unformatted = r#"// License header.
+
+little_endian_packets
+custom_field Address : 48 "hci/"
+checksum Crc:0x10 "crc/"
+// Operation codes.
+enum Op:8{READ=0x01,
+    WRITE = 0x02, // Write access.
+
+
+  /* Reserved range. */
+ RESERVED=0xff}
+group Header { op:Op }
+packet Command:Base(a=0x1,b=READ){
+    Header{op=WRITE}, _size_ (_payload_) : 8,
+    _fixed_ = 0x12:8,
+    _reserved_:4,
+    data:8[+2],
+
+    _payload_:[+1]
+    // Trailing comment.
+}
+packet Empty {}
+test Command { "\x01\x02" }
+"#;
+        assert_eq!(format(unformatted), formatted);
+        assert_eq!(format(formatted), formatted);
+    }
+}
@@ -0,0 +1,295 @@
+//! Declaration reference index.
+//!
+//! Locates the references to each declaration: parent clauses, typedef
+//! and array fields, fixed enum fields, group insertions, test
+//! declarations, and the enum tags used as constraint values.
+//! References are located from the AST, then narrowed to the identifier
+//! tokens in the source text, so that fields, tags, or comments sharing
+//! the name of a declaration are not mistaken for references.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::ast;
+use crate::layout;
+use crate::parser::is_identifier_char;
+
+/// Kind of reference to a declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceKind {
+    /// Parent clause of a packet or struct.
+    Parent,
+    /// Type of a typedef field.
+    Typedef,
+    /// Element type of an array field.
+    Array,
+    /// Enum of a fixed field.
+    Fixed,
+    /// Group insertion.
+    Group,
+    /// Enum tag used as a constraint value. The range is the tag, not
+    /// the enum identifier.
+    Constraint,
+    /// Declaration tested by a test declaration.
+    Test,
+}
+
+/// Reference to a declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub kind: ReferenceKind,
+    /// Byte range of the referencing identifier.
+    pub range: Range<usize>,
+}
+
+/// Index of the references to the declarations of a grammar.
+pub struct Index<'d> {
+    references: HashMap<&'d str, Vec<Reference>>,
+}
+
+/// Return the ranges of the whole word occurrences of `id` in
+/// `text[range]`.
+fn occurrences(text: &str, range: Range<usize>, id: &str) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let slice = &text[range.clone()];
+    for (start, _) in slice.match_indices(id) {
+        let end = start + id.len();
+        let is_boundary = |c: Option<char>| !matches!(c, Some(c) if is_identifier_char(c));
+        if is_boundary(slice[..start].chars().next_back())
+            && is_boundary(slice[end..].chars().next())
+        {
+            ranges.push(range.start + start..range.start + end);
+        }
+    }
+    ranges
+}
+
+/// Restrict a range of `text` to the part before the first of the
+/// `delimiters`.
+fn before(text: &str, range: Range<usize>, delimiters: &[char]) -> Range<usize> {
+    match text[range.clone()].find(delimiters) {
+        Some(end) => range.start..range.start + end,
+        None => range,
+    }
+}
+
+/// Restrict a range of `text` to the part after the first colon.
+fn after_first_colon(text: &str, range: Range<usize>) -> Range<usize> {
+    match text[range.clone()].find(':') {
+        Some(start) => range.start + start + 1..range.end,
+        None => range.end..range.end,
+    }
+}
+
+/// Restrict a range of `text` to the part after the last colon.
+fn after_last_colon(text: &str, range: Range<usize>) -> Range<usize> {
+    match text[range.clone()].rfind(':') {
+        Some(start) => range.start + start + 1..range.end,
+        None => range.end..range.end,
+    }
+}
+
+fn span(loc: &ast::SourceRange) -> Range<usize> {
+    loc.start.offset..loc.end.offset
+}
+
+/// Return the range of the header of a declaration, before its
+/// constraints and body.
+fn header(text: &str, decl: &ast::Decl) -> Range<usize> {
+    before(text, span(decl.loc()), &['{', '(', '"'])
+}
+
+/// Return the range of the identifier of a declaration.
+pub fn declaration_range(text: &str, decl: &ast::Decl) -> Option<Range<usize>> {
+    occurrences(text, header(text, decl), decl.id()?).into_iter().next()
+}
+
+/// Return the type of the field `id`, searching through inserted
+/// groups.
+fn field_type<'d>(
+    decls: &HashMap<&'d str, &'d ast::Decl>,
+    fields: &'d [ast::Field],
+    id: &str,
+) -> Option<&'d str> {
+    layout::flatten(decls, fields).into_iter().find_map(|flat| match flat.field {
+        ast::Field::Typedef { id: field_id, type_id, .. } if field_id == id => {
+            Some(type_id.as_str())
+        }
+        _ => None,
+    })
+}
+
+/// Return the type of the field `id` of a declaration or its parents.
+fn inherited_field_type<'d>(
+    decls: &HashMap<&'d str, &'d ast::Decl>,
+    decl: &'d ast::Decl,
+    id: &str,
+) -> Option<&'d str> {
+    let mut current = Some(decl);
+    for _ in 0..decls.len() {
+        let (fields, parent_id) = match current? {
+            ast::Decl::Packet { fields, parent_id, .. }
+            | ast::Decl::Struct { fields, parent_id, .. } => (fields, parent_id),
+            ast::Decl::Group { fields, .. } => (fields, &None),
+            _ => return None,
+        };
+        if let Some(type_id) = field_type(decls, fields, id) {
+            return Some(type_id);
+        }
+        current = parent_id.as_ref().and_then(|parent_id| decls.get(parent_id.as_str()).copied());
+    }
+    None
+}
+
+impl<'d> Index<'d> {
+    /// Index the references of the grammar, whose source is `text`.
+    pub fn new(text: &str, grammar: &'d ast::Grammar) -> Index<'d> {
+        let decls: HashMap<&str, &ast::Decl> = grammar
+            .declarations
+            .iter()
+            .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+            .collect();
+        let mut index = Index { references: HashMap::new() };
+        for decl in &grammar.declarations {
+            let header = header(text, decl);
+            let (fields, constraints, parent_id) = match decl {
+                ast::Decl::Packet { fields, constraints, parent_id, .. }
+                | ast::Decl::Struct { fields, constraints, parent_id, .. } => {
+                    (&fields[..], &constraints[..], parent_id.as_deref())
+                }
+                ast::Decl::Group { fields, .. } => (&fields[..], &[][..], None),
+                ast::Decl::Test { type_id, .. } => {
+                    for range in occurrences(text, header, type_id) {
+                        index.push(type_id, ReferenceKind::Test, range);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Some(parent_id) = parent_id {
+                for range in occurrences(text, after_first_colon(text, header), parent_id) {
+                    index.push(parent_id, ReferenceKind::Parent, range);
+                }
+                let parent = decls.get(parent_id).copied();
+                for c in constraints {
+                    let type_id = parent.and_then(|p| inherited_field_type(&decls, p, &c.id));
+                    index.push_constraint(type_id, &c.value);
+                }
+            }
+            if let ast::Decl::Group { constraints, .. } = decl {
+                for c in constraints {
+                    let type_id = inherited_field_type(&decls, decl, &c.id);
+                    index.push_constraint(type_id, &c.value);
+                }
+            }
+            for field in fields {
+                let range = span(field.loc());
+                match field {
+                    ast::Field::Typedef { type_id, .. } => {
+                        for range in occurrences(text, after_first_colon(text, range), type_id) {
+                            index.push(type_id, ReferenceKind::Typedef, range);
+                        }
+                    }
+                    ast::Field::Array { type_id: Some(type_id), .. } => {
+                        let range = before(text, after_first_colon(text, range), &['[']);
+                        for range in occurrences(text, range, type_id) {
+                            index.push(type_id, ReferenceKind::Array, range);
+                        }
+                    }
+                    ast::Field::Fixed { enum_id: Some(enum_id), .. } => {
+                        for range in occurrences(text, after_last_colon(text, range), enum_id) {
+                            index.push(enum_id, ReferenceKind::Fixed, range);
+                        }
+                    }
+                    ast::Field::Group { group_id, constraints, .. } => {
+                        for range in occurrences(text, before(text, range, &['{']), group_id) {
+                            index.push(group_id, ReferenceKind::Group, range);
+                        }
+                        let group = decls.get(group_id.as_str()).copied();
+                        for c in constraints {
+                            let type_id =
+                                group.and_then(|g| inherited_field_type(&decls, g, &c.id));
+                            index.push_constraint(type_id, &c.value);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        for references in index.references.values_mut() {
+            references.sort_by_key(|reference| reference.range.start);
+        }
+        index
+    }
+
+    fn push(&mut self, id: &'d str, kind: ReferenceKind, range: Range<usize>) {
+        self.references.entry(id).or_default().push(Reference { kind, range })
+    }
+
+    /// Add the reference of a constraint value to the enum type of the
+    /// constrained field.
+    fn push_constraint(&mut self, type_id: Option<&'d str>, value: &ast::Expr) {
+        if let (Some(type_id), ast::Expr::Identifier { loc, .. }) = (type_id, value) {
+            self.push(type_id, ReferenceKind::Constraint, span(loc));
+        }
+    }
+
+    /// Return the references to the declaration `id`, in source order.
+    pub fn references(&self, id: &str) -> &[Reference] {
+        self.references.get(id).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+    use codespan_reporting::files::Files;
+
+    #[test]
+    fn test_index() {
+        let text = r#"
+little_endian_packets
+enum Op : 8 { Op = 1, WRITE = 2 }
+group Header { _fixed_ = Op : Op, op: Op }
+packet Command { // Op
+  Header { op = Op },
+  ops: Op[4],
+  _payload_,
+}
+packet Write : Command (op = WRITE) { }
+test Command { "\x01" }
+"#;
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(&mut db, text);
+        let text = db.source(grammar.file).unwrap();
+        let index = Index::new(text, &grammar);
+        let references = |id| -> Vec<(ReferenceKind, usize, &str)> {
+            index
+                .references(id)
+                .iter()
+                .map(|r| {
+                    let line = text[..r.range.start].matches('\n').count();
+                    (r.kind, line, &text[r.range.clone()])
+                })
+                .collect()
+        };
+        assert_eq!(
+            references("Op"),
+            vec![
+                (ReferenceKind::Fixed, 3, "Op"),
+                (ReferenceKind::Typedef, 3, "Op"),
+                (ReferenceKind::Constraint, 5, "Op"),
+                (ReferenceKind::Array, 6, "Op"),
+                (ReferenceKind::Constraint, 9, "WRITE"),
+            ]
+        );
+        assert_eq!(
+            references("Command"),
+            vec![(ReferenceKind::Parent, 9, "Command"), (ReferenceKind::Test, 10, "Command")]
+        );
+        assert_eq!(references("Header"), vec![(ReferenceKind::Group, 5, "Header")]);
+        assert!(references("Write").is_empty());
+        assert_eq!(declaration_range(text, grammar.declarations.first().unwrap()), Some(28..30));
+    }
+}
@@ -0,0 +1,274 @@
+//! Dynamic packet registry.
+//!
+//! Indexes the packets and structs of a grammar loaded at runtime by
+//! name, with the schema of their fields, for tools which decode
+//! packets without knowing their types in advance: log viewers, fuzz
+//! triage tools. Packets are decoded by the [`Interpreter`]. The JSON
+//! export of the schemas has the layout:
+//!
+//! ```text
+//! registry := {
+//!     "version": 1,
+//!     "packets": [packet],
+//! }
+//!
+//! packet := {
+//!     "id": string,
+//!     "kind": "packet" | "struct",
+//!     "parent_id": string | null,
+//!     "children": [string],
+//!     "constraints": { string: string },  // constraints on the parents
+//!     "fields": [field],
+//! }
+//!
+//! field := {
+//!     "id": string,            // field name, e.g. `_size_(_payload_)`
+//!     "kind": string,          // "scalar", "enum", "struct", "array", ...
+//!     "type_id": string | null,
+//!     "offset": integer | null, // static bit offset, see `src/layout.rs`
+//!     "width": integer | null,  // static bit width
+//! }
+//! ```
+//!
+//! Fields are listed in declaration order, with groups inlined. The
+//! fields of the parents are listed by the schemas of the parents.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::ast;
+use crate::decoder;
+use crate::interpreter::Interpreter;
+use crate::layout::{FieldLayout, Layout};
+
+/// Version of the JSON registry layout.
+const REGISTRY_VERSION: u64 = 1;
+
+/// Schema of a field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSchema {
+    pub id: String,
+    pub kind: &'static str,
+    pub type_id: Option<String>,
+    /// Static bit offset, from the start of the outermost parent.
+    pub offset: Option<usize>,
+    /// Static bit width.
+    pub width: Option<usize>,
+}
+
+/// Schema of a packet or struct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketSchema {
+    pub id: String,
+    pub kind: &'static str,
+    pub parent_id: Option<String>,
+    pub children: Vec<String>,
+    /// Values of the constraints on the fields of the parents.
+    pub constraints: Vec<(String, String)>,
+    pub fields: Vec<FieldSchema>,
+}
+
+/// Registry of the packets and structs of a grammar.
+pub struct Registry {
+    interpreter: Interpreter,
+    packets: Vec<PacketSchema>,
+    index: HashMap<String, usize>,
+}
+
+fn expr_value(expr: &ast::Expr) -> String {
+    match expr {
+        ast::Expr::Identifier { name, .. } => name.to_string(),
+        ast::Expr::Integer { value, .. } => value.to_string(),
+        _ => "?".to_owned(),
+    }
+}
+
+fn field_schema(typedefs: &HashMap<&str, &ast::Decl>, layout: &FieldLayout) -> FieldSchema {
+    let (id, kind, type_id) = match layout.field {
+        ast::Field::Checksum { field_id, .. } => {
+            (format!("_checksum_start_({})", field_id), "checksum_start", None)
+        }
+        ast::Field::Padding { .. } => ("_padding_".to_owned(), "padding", None),
+        ast::Field::Size { field_id, .. } => (format!("_size_({})", field_id), "size", None),
+        ast::Field::Count { field_id, .. } => (format!("_count_({})", field_id), "count", None),
+        ast::Field::Body { .. } => ("_body_".to_owned(), "body", None),
+        ast::Field::Payload { .. } => ("_payload_".to_owned(), "payload", None),
+        ast::Field::Fixed { enum_id, .. } => {
+            ("_fixed_".to_owned(), "fixed", enum_id.as_deref().map(str::to_owned))
+        }
+        ast::Field::Reserved { .. } => ("_reserved_".to_owned(), "reserved", None),
+        ast::Field::Array { id, type_id, .. } => {
+            (id.to_string(), "array", type_id.as_deref().map(str::to_owned))
+        }
+        ast::Field::Scalar { id, .. } => (id.to_string(), "scalar", None),
+        ast::Field::Typedef { id, type_id, .. } => {
+            let kind = match typedefs.get(type_id.as_str()) {
+                Some(ast::Decl::Enum { .. }) => "enum",
+                Some(ast::Decl::Struct { .. }) => "struct",
+                Some(ast::Decl::Checksum { .. }) => "checksum",
+                Some(ast::Decl::CustomField { .. }) => "custom",
+                _ => "typedef",
+            };
+            (id.to_string(), kind, Some(type_id.to_string()))
+        }
+        // Groups are inlined by the layout.
+        ast::Field::Group { group_id, .. } => (group_id.to_string(), "group", None),
+    };
+    FieldSchema {
+        id,
+        kind,
+        type_id,
+        offset: layout.offset.and_then(|offset| offset.static_offset()),
+        width: layout.width,
+    }
+}
+
+impl Registry {
+    pub fn new(interpreter: Interpreter) -> Self {
+        let grammar = interpreter.grammar();
+        let layout = Layout::new(grammar);
+        let typedefs: HashMap<&str, &ast::Decl> = grammar
+            .declarations
+            .iter()
+            .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+            .collect();
+        let mut packets = vec![];
+        for decl in grammar.declarations.iter() {
+            let (id, kind, constraints, parent_id) = match decl {
+                ast::Decl::Packet { id, constraints, parent_id, .. } => {
+                    (id, "packet", constraints, parent_id)
+                }
+                ast::Decl::Struct { id, constraints, parent_id, .. } => {
+                    (id, "struct", constraints, parent_id)
+                }
+                _ => continue,
+            };
+            let children = grammar
+                .declarations
+                .iter()
+                .filter(|child| match child {
+                    ast::Decl::Packet { parent_id: Some(parent_id), .. }
+                    | ast::Decl::Struct { parent_id: Some(parent_id), .. } => parent_id == id,
+                    _ => false,
+                })
+                .filter_map(|child| child.id().map(|id| id.to_string()))
+                .collect();
+            packets.push(PacketSchema {
+                id: id.to_string(),
+                kind,
+                parent_id: parent_id.as_deref().map(str::to_owned),
+                children,
+                constraints: constraints
+                    .iter()
+                    .map(|constraint| (constraint.id.to_string(), expr_value(&constraint.value)))
+                    .collect(),
+                fields: layout
+                    .fields(decl)
+                    .iter()
+                    .map(|field| field_schema(&typedefs, field))
+                    .collect(),
+            });
+        }
+        let index =
+            packets.iter().enumerate().map(|(index, packet)| (packet.id.clone(), index)).collect();
+        Registry { interpreter, packets, index }
+    }
+
+    /// Return the schemas of the packets and structs, in declaration
+    /// order.
+    pub fn packets(&self) -> &[PacketSchema] {
+        &self.packets
+    }
+
+    /// Return the schema of a packet or struct.
+    pub fn schema(&self, id: &str) -> Option<&PacketSchema> {
+        self.index.get(id).map(|index| &self.packets[*index])
+    }
+
+    /// Decode `data` as the packet or struct `id`, see
+    /// [`Interpreter::decode`].
+    pub fn decode(&self, id: &str, data: &[u8]) -> Result<decoder::Packet, String> {
+        if self.schema(id).is_none() {
+            return Err(format!("unknown packet or struct '{}'", id));
+        }
+        self.interpreter.decode(id, data)
+    }
+
+    /// Return the JSON export of the schemas, see the module
+    /// documentation.
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<Value>| value.unwrap_or(Value::Null);
+        let packets = self.packets().iter().map(|packet| {
+            let mut constraints = Map::new();
+            for (id, value) in &packet.constraints {
+                constraints.insert(id.clone(), Value::String(value.clone()));
+            }
+            let fields = packet.fields.iter().map(|field| {
+                let mut object = Map::new();
+                object.insert("id".to_owned(), Value::String(field.id.clone()));
+                object.insert("kind".to_owned(), Value::String(field.kind.to_owned()));
+                object.insert(
+                    "type_id".to_owned(),
+                    optional(field.type_id.clone().map(Value::String)),
+                );
+                object.insert("offset".to_owned(), optional(field.offset.map(Value::from)));
+                object.insert("width".to_owned(), optional(field.width.map(Value::from)));
+                Value::Object(object)
+            });
+            let mut object = Map::new();
+            object.insert("id".to_owned(), Value::String(packet.id.clone()));
+            object.insert("kind".to_owned(), Value::String(packet.kind.to_owned()));
+            object.insert(
+                "parent_id".to_owned(),
+                optional(packet.parent_id.clone().map(Value::String)),
+            );
+            object.insert(
+                "children".to_owned(),
+                Value::Array(packet.children.iter().cloned().map(Value::String).collect()),
+            );
+            object.insert("constraints".to_owned(), Value::Object(constraints));
+            object.insert("fields".to_owned(), Value::Array(fields.collect()));
+            Value::Object(object)
+        });
+
+        let mut object = Map::new();
+        object.insert("version".to_owned(), Value::from(REGISTRY_VERSION));
+        object.insert("packets".to_owned(), Value::Array(packets.collect()));
+        let mut out = serde_json::to_string_pretty(&Value::Object(object)).unwrap();
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_registry() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+            packet Write : Command (op = WRITE) { addr: 16, data: 8[] }
+            "#,
+        );
+        let registry = Registry::new(Interpreter::new(grammar));
+        let ids: Vec<_> = registry.packets().iter().map(|packet| packet.id.as_str()).collect();
+        assert_eq!(ids, vec!["Command", "Write"]);
+
+        let command = registry.schema("Command").unwrap();
+        assert_eq!(command.children, vec!["Write".to_owned()]);
+        assert_eq!(command.fields[0].kind, "enum");
+        assert_eq!(command.fields[1].id, "_size_(_payload_)");
+        let write = registry.schema("Write").unwrap();
+        assert_eq!(write.constraints, vec![("op".to_owned(), "WRITE".to_owned())]);
+        assert_eq!((write.fields[0].offset, write.fields[0].width), (Some(16), Some(16)));
+        assert_eq!(write.fields[1].width, None);
+
+        assert!(registry.decode("Write", &[0x02, 0x03, 0x34, 0x12, 0x07]).is_ok());
+        assert!(registry.decode("Op", &[0x02]).is_err());
+    }
+}
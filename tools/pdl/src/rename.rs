@@ -0,0 +1,115 @@
+//! Declaration renaming.
+//!
+//! Renames a declaration and every reference to it: parent
+//! declarations, typedef and array fields, fixed enum fields, group
+//! insertions, and test declarations. References are located from the
+//! AST, so that fields, tags, or comments sharing the name are left
+//! unchanged; only the identifier tokens are rewritten, preserving the
+//! formatting of the file.
+
+use codespan_reporting::files::Files;
+use std::ops::Range;
+
+use crate::ast;
+use crate::parser::is_identifier_char;
+use crate::references::{self, ReferenceKind};
+
+/// Return true if `id` is a valid declaration identifier.
+pub fn is_identifier(id: &str) -> bool {
+    id.starts_with(|c: char| c.is_ascii_alphabetic()) && id.chars().all(is_identifier_char)
+}
+
+/// Rename the declaration `old` to `new` in the source of the grammar.
+/// Returns the updated source, or `None` if the grammar neither
+/// declares nor references `old`.
+pub fn rename(
+    sources: &ast::SourceDatabase,
+    grammar: &ast::Grammar,
+    old: &str,
+    new: &str,
+) -> Option<String> {
+    let text = sources.source(grammar.file).ok()?;
+    let mut ranges: Vec<Range<usize>> = grammar
+        .declarations
+        .iter()
+        .filter(|decl| decl.id().map(ast::Symbol::as_str) == Some(old))
+        .filter_map(|decl| references::declaration_range(text, decl))
+        .collect();
+    let index = references::Index::new(text, grammar);
+    ranges.extend(
+        index
+            .references(old)
+            .iter()
+            .filter(|reference| reference.kind != ReferenceKind::Constraint)
+            .map(|reference| reference.range.clone()),
+    );
+    if ranges.is_empty() {
+        return None;
+    }
+
+    ranges.sort_by_key(|range| range.start);
+    ranges.dedup();
+    let mut out = String::new();
+    let mut position = 0;
+    for range in ranges {
+        out.push_str(&text[position..range.start]);
+        out.push_str(new);
+        position = range.end;
+    }
+    out.push_str(&text[position..]);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    fn rename_in(text: &str, old: &str, new: &str) -> Option<String> {
+        let mut db = ast::SourceDatabase::new();
+        let grammar = grammar!(&mut db, text);
+        rename(&db, &grammar, old, new)
+    }
+
+    #[test]
+    fn test_rename() {
+        let text = r#"
+little_endian_packets
+enum Op : 8 { Op = 1, WRITE = 2 }
+struct Op2 { Op: Op, }
+group Header { _fixed_ = Op : Op, Op: 8 }
+packet Command { // Op
+  Header,
+  op: Op,
+  ops: Op[4],
+  _payload_,
+}
+packet Write : Command (op = WRITE) { }
+test Command { "\x01" }
+"#;
+        assert_eq!(
+            rename_in(text, "Op", "Opcode").unwrap(),
+            r#"
+little_endian_packets
+enum Opcode : 8 { Op = 1, WRITE = 2 }
+struct Op2 { Op: Opcode, }
+group Header { _fixed_ = Op : Opcode, Op: 8 }
+packet Command { // Op
+  Header,
+  op: Opcode,
+  ops: Opcode[4],
+  _payload_,
+}
+packet Write : Command (op = WRITE) { }
+test Command { "\x01" }
+"#
+        );
+        let renamed = rename_in(text, "Command", "Cmd").unwrap();
+        assert!(renamed.contains("packet Cmd { // Op\n"));
+        assert!(renamed.contains("packet Write : Cmd (op = WRITE) { }\n"));
+        assert!(renamed.contains("test Cmd {"));
+        assert!(rename_in(text, "Header", "Hdr").unwrap().contains("group Hdr {"));
+        assert!(rename_in(text, "Header", "Hdr").unwrap().contains("  Hdr,\n"));
+        assert_eq!(rename_in(text, "Missing", "Other"), None);
+    }
+}
@@ -0,0 +1,327 @@
+//! Interactive session for packet crafting and inspection.
+//!
+//! The session holds the loaded definitions and the field values of a
+//! current packet, which can be decoded from hexadecimal bytes, edited
+//! field by field, and encoded again. Commands are read line by line:
+//!
+//! ```text
+//! load FILE                 load packet definitions
+//! new PACKET                start an empty PACKET
+//! decode PACKET HEX         decode HEX as PACKET, and make it current
+//! set FIELD VALUE           set a field value, in the JSON format of
+//!                           `pdl encode` (bare words are enum tags)
+//! unset FIELD               remove a field value
+//! show                      print the current field values
+//! encode                    encode the current packet
+//! capture FILE              write the packets encoded next to the
+//!                           pcapng capture FILE
+//! complete LINE             list the completions of the last word
+//! help                      print the list of commands
+//! quit                      end the session
+//! ```
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::ast;
+use crate::decoder;
+use crate::encoder;
+use crate::layout::{self, FlatField};
+use crate::parser;
+use crate::pcapng;
+
+const COMMANDS: [&str; 11] = [
+    "capture", "complete", "decode", "encode", "help", "load", "new", "quit", "set", "show",
+    "unset",
+];
+
+struct Session {
+    grammar: Option<ast::Grammar>,
+    packet: Option<String>,
+    fields: Map<String, Value>,
+    capture: Option<pcapng::Writer<std::fs::File>>,
+}
+
+/// Parse a field value: JSON, or a bare enum tag.
+fn parse_value(text: &str) -> Value {
+    serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_owned()))
+}
+
+impl Session {
+    fn grammar(&self) -> Result<&ast::Grammar, String> {
+        self.grammar.as_ref().ok_or_else(|| "no definitions loaded, see 'load'".to_owned())
+    }
+
+    /// Return the identifiers of the packet and struct declarations.
+    fn packet_ids(&self) -> Vec<&str> {
+        let declarations = self.grammar.iter().flat_map(|grammar| &grammar.declarations);
+        declarations
+            .filter(|decl| matches!(decl, ast::Decl::Packet { .. } | ast::Decl::Struct { .. }))
+            .filter_map(|decl| decl.id().map(ast::Symbol::as_str))
+            .collect()
+    }
+
+    /// Return the identifiers of the fields of the current packet and
+    /// of its parents, with groups inlined.
+    fn field_ids(&self) -> Vec<String> {
+        let (grammar, packet) = match (&self.grammar, &self.packet) {
+            (Some(grammar), Some(packet)) => (grammar, packet),
+            _ => return vec![],
+        };
+        let typedefs: HashMap<&str, &ast::Decl> = grammar
+            .declarations
+            .iter()
+            .filter_map(|decl| decl.id().map(|id| (id.as_str(), decl)))
+            .collect();
+        let mut ids = vec![];
+        let mut current = Some(packet.as_str());
+        let mut depth = 0;
+        while let (Some(id), true) = (current.take(), depth < 16) {
+            if let Some(ast::Decl::Packet { fields, parent_id, .. })
+            | Some(ast::Decl::Struct { fields, parent_id, .. }) = typedefs.get(id)
+            {
+                for FlatField { field, .. } in layout::flatten(&typedefs, fields) {
+                    match field {
+                        // Only the payload of the current packet is not
+                        // described by a child declaration.
+                        ast::Field::Payload { .. } if depth == 0 => {
+                            ids.push("_payload_".to_owned())
+                        }
+                        _ => ids.extend(field.id().map(|id| id.to_string())),
+                    }
+                }
+                current = parent_id.as_deref();
+            }
+            depth += 1;
+        }
+        ids
+    }
+
+    /// Return the completions of the last word of a command line.
+    fn complete(&self, line: &str) -> Vec<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let prefix = if line.ends_with(char::is_whitespace) {
+            ""
+        } else {
+            words.last().copied().unwrap_or("")
+        };
+        let position = if prefix.is_empty() { words.len() } else { words.len() - 1 };
+        let candidates: Vec<String> = match (position, words.first().copied()) {
+            (0, _) => COMMANDS.iter().map(|c| c.to_string()).collect(),
+            (1, Some("new")) | (1, Some("decode")) => {
+                self.packet_ids().into_iter().map(str::to_owned).collect()
+            }
+            (1, Some("set")) | (1, Some("unset")) => self.field_ids(),
+            _ => vec![],
+        };
+        let mut candidates: Vec<String> =
+            candidates.into_iter().filter(|c| c.starts_with(prefix)).collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    /// Execute a command line. Returns the text to print, or an error.
+    fn execute(&mut self, line: &str) -> Result<String, String> {
+        // Trailing whitespace is significant for completions.
+        let line = line.trim_start().trim_end_matches(['\n', '\r']);
+        let (command, raw_arguments) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arguments = raw_arguments.trim();
+        match command {
+            "" => Ok(String::new()),
+            "help" => Ok(COMMANDS.join(" ")),
+            "load" => {
+                let mut sources = ast::SourceDatabase::new();
+                let grammar = parser::parse_file(&mut sources, arguments.to_owned())
+                    .map_err(|err| format!("failed to load {}: {}", arguments, err.message))?;
+                let count = grammar.declarations.len();
+                self.grammar = Some(grammar);
+                self.packet = None;
+                self.fields.clear();
+                Ok(format!("loaded {} declarations", count))
+            }
+            "new" => {
+                if !self.packet_ids().contains(&arguments) {
+                    self.grammar()?;
+                    return Err(format!("unknown packet '{}'", arguments));
+                }
+                self.packet = Some(arguments.to_owned());
+                self.fields.clear();
+                Ok(String::new())
+            }
+            "decode" => {
+                let (packet, hex) =
+                    arguments.split_once(char::is_whitespace).unwrap_or((arguments, ""));
+                let bytes = decoder::parse_hex(hex)?;
+                let decoded = decoder::decode(self.grammar()?, packet, &bytes)?;
+                let mut fields = Map::new();
+                self.packet = Some(decoded.add_fields(&mut fields));
+                self.fields = fields;
+                Ok(decoded.to_string())
+            }
+            "set" => {
+                let (field, value) = arguments
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| "usage: set FIELD VALUE".to_owned())?;
+                if self.packet.is_none() {
+                    return Err("no current packet, see 'new' or 'decode'".to_owned());
+                }
+                self.fields.insert(field.to_owned(), parse_value(value.trim()));
+                Ok(String::new())
+            }
+            "unset" => {
+                self.fields.remove(arguments);
+                Ok(String::new())
+            }
+            "show" => Ok(format!(
+                "{} {}",
+                self.packet.as_deref().unwrap_or("<none>"),
+                serde_json::to_string_pretty(&Value::Object(self.fields.clone()))
+                    .unwrap_or_default()
+            )),
+            "encode" => {
+                let packet = self
+                    .packet
+                    .as_ref()
+                    .ok_or_else(|| "no current packet, see 'new' or 'decode'".to_owned())?;
+                let grammar = self.grammar()?;
+                let bytes = encoder::encode(grammar, packet, &Value::Object(self.fields.clone()))?;
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                let packet_type = match &self.capture {
+                    Some(_) => Some(pcapng::packet_type(grammar, packet).ok_or_else(|| {
+                        format!("cannot select the HCI packet type of {}", packet)
+                    })?),
+                    None => None,
+                };
+                let text = match decoder::decode(grammar, packet, &bytes) {
+                    Ok(decoded) => format!("{}\n{}", hex, decoded),
+                    Err(err) => {
+                        format!("{}\nwarning: the encoded packet does not decode: {}", hex, err)
+                    }
+                };
+                if let (Some(capture), Some(packet_type)) = (&mut self.capture, packet_type) {
+                    capture
+                        .write_packet(packet_type, &bytes, pcapng::now())
+                        .map_err(|err| format!("failed to write the capture: {}", err))?;
+                }
+                Ok(text)
+            }
+            "capture" => {
+                let file = std::fs::File::create(arguments)
+                    .map_err(|err| format!("failed to create {}: {}", arguments, err))?;
+                self.capture = Some(
+                    pcapng::Writer::new(file)
+                        .map_err(|err| format!("failed to write {}: {}", arguments, err))?,
+                );
+                Ok(String::new())
+            }
+            "complete" => Ok(self.complete(raw_arguments).join(" ")),
+            _ => Err(format!("unknown command '{}', see 'help'", command)),
+        }
+    }
+}
+
+/// Run the session on the input lines, until the input is closed or
+/// the `quit` command. A prompt is printed before each command if
+/// `interactive` is set.
+pub fn run(input: &mut impl BufRead, output: &mut impl Write, interactive: bool) -> io::Result<()> {
+    let mut session = Session { grammar: None, packet: None, fields: Map::new(), capture: None };
+    let mut line = String::new();
+    loop {
+        if interactive {
+            write!(output, "pdl> ")?;
+            output.flush()?;
+        }
+        line.clear();
+        if input.read_line(&mut line)? == 0 || line.trim() == "quit" {
+            return Ok(());
+        }
+        match session.execute(&line) {
+            Ok(text) if text.is_empty() => (),
+            Ok(text) => writeln!(output, "{}", text)?,
+            Err(err) => writeln!(output, "error: {}", err)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_session() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(
+            br#"
+            little_endian_packets
+            enum Opcode : 8 { READ = 1, WRITE = 2 }
+            packet Command {
+                opcode: Opcode,
+                _size_(_payload_): 8,
+                _payload_,
+            }
+            packet Write : Command (opcode = WRITE) {
+                handle: 16,
+                value: 8[],
+            }
+            "#,
+        )
+        .unwrap();
+        let script = format!(
+            "load {}\ncomplete de\ncomplete decode C\ndecode Command 0203 3412 ff\ncomplete set \
+             \nset value [1, 2]\nencode\nunset handle\nencode\nquit\nshow\n",
+            file.path().display()
+        );
+        let mut output = vec![];
+        run(&mut script.as_bytes(), &mut output, false).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"loaded 3 declarations
+decode
+Command
+Command {
+  opcode: WRITE (0x2)
+  Write {
+    handle: 4660 (0x1234)
+    value: [
+      255 (0xff),
+    ]
+  }
+}
+handle opcode value
+020434120102
+Command {
+  opcode: WRITE (0x2)
+  Write {
+    handle: 4660 (0x1234)
+    value: [
+      1 (0x1),
+      2 (0x2),
+    ]
+  }
+}
+error: Write: missing value for 'handle'
+"#
+        );
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"little_endian_packets\npacket Event { code: 8 }\n").unwrap();
+        let capture = NamedTempFile::new().unwrap();
+        let script = format!(
+            "load {}\nnew Event\nset code 14\nencode\ncapture {}\nencode\nencode\n",
+            file.path().display(),
+            capture.path().display()
+        );
+        let mut output = vec![];
+        run(&mut script.as_bytes(), &mut output, false).unwrap();
+        assert!(!String::from_utf8(output).unwrap().contains("error"));
+        // Section header and interface, followed by two packets.
+        assert_eq!(std::fs::read(capture.path()).unwrap().len(), 28 + 20 + 2 * 40);
+    }
+}
@@ -0,0 +1,351 @@
+//! Run summary.
+//!
+//! Collects the files processed, the diagnostics, and the time spent
+//! in each phase of a run, for build dashboards. The JSON summary has
+//! the layout:
+//!
+//! ```text
+//! report := {
+//!     "version": 1,
+//!     "success": bool,
+//!     "files": [{
+//!         "file": string,
+//!         "declarations": integer,  // null if the file does not parse
+//!         "errors": integer,
+//!         "warnings": integer,
+//!     }],
+//!     "generated_declarations": integer,
+//!     "errors": integer,
+//!     "warnings": integer,
+//!     "diagnostics": { code: { "severity": string, "count": integer } },
+//!     "phases": { phase: integer },  // elapsed time in microseconds
+//! }
+//! ```
+//!
+//! The phases `parse`, `lint`, and `generate` are detailed by the
+//! sub-phases `parse.syntax` and `parse.ast`, `lint.scope`,
+//! `lint.finalize` and `lint.checks`, and `generate.<backend>`. The
+//! time of the sub-phases is included in the time of their phase.
+//!
+//! The `--timings` flag prints the phases in a table, with the
+//! declarations whose lint checks were the slowest, and the
+//! declarations whose generation was the slowest for each backend
+//! reporting per-declaration timings, see
+//! [`crate::backends::Backend::generate`].
+//!
+//! Diagnostics are counted by code. Diagnostics without a code are
+//! counted by message, with the quoted identifiers replaced by `_`,
+//! e.g. ``undeclared identifier `_` ``.
+
+use codespan_reporting::diagnostic::{Diagnostic, Severity};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::ast;
+use crate::backends::GenerateTimings;
+use crate::lint::LintTimings;
+use crate::parser::ParseTimings;
+
+/// Version of the JSON summary layout.
+const REPORT_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ReportFormat {
+    Json,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            _ => Err(format!("could not parse {:?}, valid options are 'json'.", input)),
+        }
+    }
+}
+
+struct FileReport {
+    file: String,
+    declarations: Option<usize>,
+    errors: usize,
+    warnings: usize,
+}
+
+#[derive(Default)]
+pub struct Report {
+    files: Vec<FileReport>,
+    generated_declarations: usize,
+    diagnostics: BTreeMap<String, (&'static str, usize)>,
+    phases: Vec<(String, Duration)>,
+    /// Time spent in the lint checks of each declaration.
+    lint_checks: Vec<(String, Duration)>,
+    /// Time spent generating each declaration, labelled with the
+    /// backend.
+    generations: Vec<(String, Duration)>,
+}
+
+/// Return the key under which a diagnostic is counted. Diagnostics
+/// differing only by the quoted identifiers share the same key.
+pub fn diagnostic_key(diagnostic: &Diagnostic<ast::FileId>) -> String {
+    if let Some(code) = &diagnostic.code {
+        return code.clone();
+    }
+    let mut key = String::new();
+    // Odd parts are quoted.
+    for (index, part) in diagnostic.message.split('`').enumerate() {
+        if index & 1 == 0 {
+            key.push_str(part);
+        } else {
+            key.push_str("`_`");
+        }
+    }
+    key
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Bug => "bug",
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note => "note",
+        Severity::Help => "help",
+    }
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    /// Run a phase, and add its elapsed time to the phase total.
+    pub fn time<T>(&mut self, phase: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add_time(phase, start.elapsed());
+        result
+    }
+
+    /// Add elapsed time to a phase total.
+    pub fn add_time(&mut self, phase: &str, elapsed: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase.to_owned(), elapsed)),
+        }
+    }
+
+    /// Run the generation of a backend, and add its elapsed time to the
+    /// `generate` phase and to the backend sub-phase.
+    pub fn time_backend<T>(&mut self, backend: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        self.add_time("generate", elapsed);
+        self.add_time(&format!("generate.{}", backend), elapsed);
+        result
+    }
+
+    /// Record the sub-phases of the `parse` phase.
+    pub fn add_parse_timings(&mut self, timings: &ParseTimings) {
+        self.add_time("parse.syntax", timings.syntax);
+        self.add_time("parse.ast", timings.ast);
+    }
+
+    /// Record the sub-phases of the `lint` phase, and the time spent
+    /// in the checks of each declaration.
+    pub fn add_lint_timings(&mut self, timings: &LintTimings) {
+        self.add_time("lint.scope", timings.scope);
+        self.add_time("lint.finalize", timings.finalize);
+        self.add_time("lint.checks", timings.checks);
+        self.lint_checks.extend(timings.declarations.iter().cloned());
+    }
+
+    /// Record the time spent generating each declaration with a
+    /// backend.
+    pub fn add_generate_timings(&mut self, backend: &str, timings: &GenerateTimings) {
+        self.generations.extend(
+            timings
+                .declarations
+                .iter()
+                .map(|(id, elapsed)| (format!("{} ({})", id, backend), *elapsed)),
+        );
+    }
+
+    /// Record a processed file, with its number of declarations if it
+    /// could be parsed, and its diagnostics.
+    pub fn add_file(
+        &mut self,
+        file: &str,
+        declarations: Option<usize>,
+        diagnostics: &[Diagnostic<ast::FileId>],
+    ) {
+        let mut report = FileReport { file: file.to_owned(), declarations, errors: 0, warnings: 0 };
+        for diagnostic in diagnostics {
+            match diagnostic.severity {
+                Severity::Bug | Severity::Error => report.errors += 1,
+                Severity::Warning => report.warnings += 1,
+                _ => (),
+            }
+            let entry = self
+                .diagnostics
+                .entry(diagnostic_key(diagnostic))
+                .or_insert((severity_name(diagnostic.severity), 0));
+            entry.1 += 1;
+        }
+        self.files.push(report);
+    }
+
+    /// Record the declarations of a file for which an output was
+    /// generated.
+    pub fn add_generated(&mut self, declarations: usize) {
+        self.generated_declarations += declarations;
+    }
+
+    /// Return the time spent in each phase, and the `slowest` slowest
+    /// lint checks and generations of declarations, as a table.
+    pub fn timings(&self, slowest: usize) -> String {
+        let millis = |elapsed: &Duration| elapsed.as_secs_f64() * 1000.0;
+        let mut out = format!("{:<32} {:>12}\n", "phase", "time (ms)");
+        for (phase, elapsed) in &self.phases {
+            let indent = if phase.contains('.') { "  " } else { "" };
+            let phase = format!("{}{}", indent, phase);
+            out.push_str(&format!("{:<32} {:>12.3}\n", phase, millis(elapsed)));
+        }
+        for (title, declarations) in
+            [("slowest lint checks", &self.lint_checks), ("slowest generations", &self.generations)]
+        {
+            let mut declarations: Vec<_> = declarations.iter().collect();
+            declarations.sort_by_key(|(_, elapsed)| std::cmp::Reverse(*elapsed));
+            if !declarations.is_empty() && slowest > 0 {
+                out.push_str(&format!("\n{:<32} {:>12}\n", title, "time (ms)"));
+                for (id, elapsed) in declarations.into_iter().take(slowest) {
+                    out.push_str(&format!("{:<32} {:>12.3}\n", id, millis(elapsed)));
+                }
+            }
+        }
+        out
+    }
+
+    /// Return the summary in the JSON format.
+    pub fn to_json(&self, success: bool) -> String {
+        let count = |f: fn(&FileReport) -> usize| self.files.iter().map(f).sum::<usize>() as u64;
+        let files = self.files.iter().map(|file| {
+            let mut object = Map::new();
+            object.insert("file".to_owned(), Value::String(file.file.clone()));
+            object.insert(
+                "declarations".to_owned(),
+                file.declarations.map_or(Value::Null, |count| Value::from(count as u64)),
+            );
+            object.insert("errors".to_owned(), Value::from(file.errors as u64));
+            object.insert("warnings".to_owned(), Value::from(file.warnings as u64));
+            Value::Object(object)
+        });
+        let diagnostics = self.diagnostics.iter().map(|(key, (severity, count))| {
+            let mut object = Map::new();
+            object.insert("severity".to_owned(), Value::String(severity.to_string()));
+            object.insert("count".to_owned(), Value::from(*count as u64));
+            (key.clone(), Value::Object(object))
+        });
+        let phases = self
+            .phases
+            .iter()
+            .map(|(phase, elapsed)| (phase.clone(), Value::from(elapsed.as_micros() as u64)));
+
+        let mut object = Map::new();
+        object.insert("version".to_owned(), Value::from(REPORT_VERSION));
+        object.insert("success".to_owned(), Value::Bool(success));
+        object.insert("files".to_owned(), Value::Array(files.collect()));
+        object.insert(
+            "generated_declarations".to_owned(),
+            Value::from(self.generated_declarations as u64),
+        );
+        object.insert("errors".to_owned(), Value::from(count(|file| file.errors)));
+        object.insert("warnings".to_owned(), Value::from(count(|file| file.warnings)));
+        object.insert("diagnostics".to_owned(), Value::Object(diagnostics.collect()));
+        object.insert("phases".to_owned(), Value::Object(phases.collect()));
+        let mut out = serde_json::to_string_pretty(&Value::Object(object)).unwrap();
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = Report::new();
+        report.time("parse", || ());
+        report.add_file(
+            "a.pdl",
+            Some(3),
+            &[
+                Diagnostic::error().with_message("undeclared identifier `A`"),
+                Diagnostic::error().with_message("undeclared identifier `B`"),
+                Diagnostic::warning().with_message("size field uses array `c` with static size"),
+            ],
+        );
+        report.add_file("b.pdl", None, &[Diagnostic::error().with_message("parse error")]);
+        report.add_generated(3);
+        let json: Value = serde_json::from_str(&report.to_json(false)).unwrap();
+        assert_eq!(json["errors"], 3);
+        assert_eq!(json["warnings"], 1);
+        assert_eq!(json["generated_declarations"], 3);
+        assert_eq!(json["files"][1]["declarations"], Value::Null);
+        assert_eq!(json["diagnostics"]["undeclared identifier `_`"]["count"], 2);
+        assert_eq!(
+            json["diagnostics"]["size field uses array `_` with static size"]["severity"],
+            "warning"
+        );
+        assert!(json["phases"]["parse"].is_u64());
+    }
+
+    #[test]
+    fn test_timings() {
+        let mut report = Report::new();
+        report.add_time("lint", Duration::from_millis(3));
+        report.add_lint_timings(&LintTimings {
+            scope: Duration::from_millis(1),
+            declarations: vec![
+                ("A".to_owned(), Duration::from_millis(1)),
+                ("B".to_owned(), Duration::from_millis(2)),
+                ("C".to_owned(), Duration::from_micros(5)),
+            ],
+            ..LintTimings::default()
+        });
+        report.time_backend("json", || ());
+        let mut generate = GenerateTimings::default();
+        generate.declarations.insert("A".to_owned(), Duration::from_millis(4));
+        report.add_generate_timings("json", &generate);
+        let timings = report.timings(2);
+        let lines: Vec<_> = timings.lines().map(|line| line.split_whitespace().next()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                Some("phase"),
+                Some("lint"),
+                Some("lint.scope"),
+                Some("lint.finalize"),
+                Some("lint.checks"),
+                Some("generate"),
+                Some("generate.json"),
+                None,
+                Some("slowest"),
+                Some("B"),
+                Some("A"),
+                None,
+                Some("slowest"),
+                Some("A"),
+            ]
+        );
+        assert!(timings.contains("slowest lint checks"));
+        assert!(timings.lines().any(|line| line.starts_with("A (json) ")));
+        assert!(timings
+            .lines()
+            .any(|line| line.starts_with("  lint.scope ") && line.ends_with(" 1.000")));
+        let json: Value = serde_json::from_str(&report.to_json(true)).unwrap();
+        assert!(json["phases"]["generate.json"].is_u64());
+    }
+}
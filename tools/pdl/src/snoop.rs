@@ -0,0 +1,216 @@
+//! btsnoop log reader.
+//!
+//! Reads the HCI records of btsnoop files, as written by the Android
+//! Bluetooth stack (`btsnoop_hci.log`). The file starts with a 16 byte
+//! header: the magic `btsnoop\0`, the version (1) and the datalink
+//! type, as big endian 32-bit integers. Each record then has a 24 byte
+//! header: the original length, the included length, the flags and
+//! the cumulative drops as big endian 32-bit integers, and the
+//! timestamp in microseconds as a big endian 64-bit integer, followed
+//! by the included bytes.
+//!
+//! With the H4 datalink, the first byte of a record is the HCI packet
+//! type. With the unencapsulated HCI datalink, the type is deduced from
+//! the record flags. The compressed btsnooz format of bug reports is not
+//! supported, and must be converted to btsnoop first, e.g. with
+//! `btsnooz.py`.
+
+use std::fmt;
+
+const MAGIC: &[u8] = b"btsnoop\0";
+const DATALINK_HCI_UNENCAPSULATED: u32 = 1001;
+const DATALINK_HCI_UART: u32 = 1002;
+
+/// Type of an HCI packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Command,
+    Acl,
+    Sco,
+    Event,
+    Iso,
+}
+
+impl PacketType {
+    const ALL: [PacketType; 5] =
+        [PacketType::Command, PacketType::Acl, PacketType::Sco, PacketType::Event, PacketType::Iso];
+
+    fn from_h4(indicator: u8) -> Option<PacketType> {
+        PacketType::ALL.iter().copied().find(|packet_type| packet_type.h4() == indicator)
+    }
+
+    /// Return the H4 packet type indicator.
+    pub fn h4(&self) -> u8 {
+        match self {
+            PacketType::Command => 0x01,
+            PacketType::Acl => 0x02,
+            PacketType::Sco => 0x03,
+            PacketType::Event => 0x04,
+            PacketType::Iso => 0x05,
+        }
+    }
+}
+
+impl std::str::FromStr for PacketType {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        PacketType::ALL
+            .iter()
+            .copied()
+            .find(|packet_type| packet_type.to_string() == input.to_lowercase())
+            .ok_or_else(|| {
+                format!(
+                    "could not parse {:?}, valid options are 'command', 'acl', 'sco', 'event', \
+                     'iso'.",
+                    input
+                )
+            })
+    }
+}
+
+impl fmt::Display for PacketType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PacketType::Command => "command",
+            PacketType::Acl => "acl",
+            PacketType::Sco => "sco",
+            PacketType::Event => "event",
+            PacketType::Iso => "iso",
+        })
+    }
+}
+
+/// HCI record of a btsnoop file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record {
+    /// Timestamp in microseconds.
+    pub timestamp: u64,
+    /// True if the packet was received from the controller.
+    pub received: bool,
+    pub packet_type: PacketType,
+    /// Packet bytes, without the H4 packet type.
+    pub data: Vec<u8>,
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Parse the records of a btsnoop file. Records truncated at the end
+/// of the file, e.g. by a crash of the stack, are ignored.
+pub fn parse(bytes: &[u8]) -> Result<Vec<Record>, String> {
+    if bytes.len() < 16 || &bytes[..8] != MAGIC {
+        return Err("not a btsnoop file".to_owned());
+    }
+    let version = read_u32(&bytes[8..12]);
+    if version != 1 {
+        return Err(format!("unsupported btsnoop version {}", version));
+    }
+    let datalink = read_u32(&bytes[12..16]);
+    if datalink != DATALINK_HCI_UNENCAPSULATED && datalink != DATALINK_HCI_UART {
+        return Err(format!("unsupported btsnoop datalink type {}", datalink));
+    }
+
+    let mut records = vec![];
+    let mut offset = 16;
+    while bytes.len() - offset >= 24 {
+        let header = &bytes[offset..offset + 24];
+        let included_length = read_u32(&header[4..8]) as usize;
+        let flags = read_u32(&header[8..12]);
+        let timestamp = (read_u32(&header[16..20]) as u64) << 32 | read_u32(&header[20..24]) as u64;
+        offset += 24;
+        if bytes.len() - offset < included_length {
+            break;
+        }
+        let data = &bytes[offset..offset + included_length];
+        offset += included_length;
+
+        let received = flags & 1 != 0;
+        let (packet_type, data) = if datalink == DATALINK_HCI_UART {
+            let packet_type = data.first().and_then(|indicator| PacketType::from_h4(*indicator));
+            match packet_type {
+                Some(packet_type) => (packet_type, &data[1..]),
+                None => {
+                    return Err(format!(
+                        "record {}: invalid HCI packet type {:02x?}",
+                        records.len() + 1,
+                        data.first()
+                    ))
+                }
+            }
+        } else if flags & 2 == 0 {
+            (PacketType::Acl, data)
+        } else if received {
+            (PacketType::Event, data)
+        } else {
+            (PacketType::Command, data)
+        };
+        records.push(Record { timestamp, received, packet_type, data: data.to_vec() });
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(flags: u32, timestamp: u64, data: &[u8]) -> Vec<u8> {
+        let mut record = vec![];
+        record.extend((data.len() as u32).to_be_bytes());
+        record.extend((data.len() as u32).to_be_bytes());
+        record.extend(flags.to_be_bytes());
+        record.extend(0u32.to_be_bytes());
+        record.extend(timestamp.to_be_bytes());
+        record.extend(data);
+        record
+    }
+
+    fn file(datalink: u32, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = MAGIC.to_vec();
+        file.extend(1u32.to_be_bytes());
+        file.extend(datalink.to_be_bytes());
+        for record in records {
+            file.extend(record);
+        }
+        file
+    }
+
+    #[test]
+    fn test_parse() {
+        let bytes = file(
+            DATALINK_HCI_UART,
+            &[record(2, 100, &[0x01, 0x03, 0x0c, 0x00]), record(3, 200, &[0x04, 0x0e, 0x00])],
+        );
+        assert_eq!(
+            parse(&bytes).unwrap(),
+            vec![
+                Record {
+                    timestamp: 100,
+                    received: false,
+                    packet_type: PacketType::Command,
+                    data: vec![0x03, 0x0c, 0x00]
+                },
+                Record {
+                    timestamp: 200,
+                    received: true,
+                    packet_type: PacketType::Event,
+                    data: vec![0x0e, 0x00]
+                },
+            ]
+        );
+
+        let mut bytes = file(
+            DATALINK_HCI_UNENCAPSULATED,
+            &[record(3, 100, &[0x0e, 0x00]), record(1, 200, &[0x01, 0x20])],
+        );
+        bytes.extend(&record(0, 300, &[0x01, 0x02, 0x03, 0x04])[..26]);
+        let records = parse(&bytes).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].packet_type, PacketType::Event);
+        assert_eq!(records[1].packet_type, PacketType::Acl);
+
+        assert!(parse(b"btsnooz").is_err());
+        assert!(parse(&file(DATALINK_HCI_UART, &[record(0, 0, &[0x07])])).is_err());
+    }
+}
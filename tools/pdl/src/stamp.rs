@@ -0,0 +1,112 @@
+//! Content hash stamps.
+//!
+//! A stamp file `<output>.stamp` is written next to every generated
+//! file, with the hash of the inputs it was generated from: the
+//! compiler executable, the backend configuration and output version,
+//! see [`crate::backends::Backend::fingerprint`], and the source. When
+//! the stamp matches, the output is left untouched, with its
+//! modification time, and dependent build targets are not rebuilt.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Version of the compiler.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Hash of the running executable, recorded in the stamps so that the
+/// outputs are regenerated whenever the compiler is rebuilt; the crate
+/// version is not bumped when the generated code changes. Returns
+/// `None` if the executable cannot be read.
+fn compiler_hash() -> Option<&'static str> {
+    static HASH: OnceLock<Option<String>> = OnceLock::new();
+    HASH.get_or_init(|| {
+        let executable = std::env::current_exe().and_then(std::fs::read).ok()?;
+        Some(format!("{:016x}", hash(&[&executable])))
+    })
+    .as_deref()
+}
+
+/// Stamp of an output file.
+pub struct Stamp {
+    path: PathBuf,
+    output: PathBuf,
+    /// Hash of the inputs, or `None` if the compiler executable could
+    /// not be hashed, in which case the output is never fresh.
+    hash: Option<String>,
+}
+
+/// Hash the inputs with 64-bit FNV-1a. Each input is prefixed with its
+/// length, so that the concatenation is unambiguous.
+pub fn hash(inputs: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for input in inputs {
+        for byte in (input.len() as u64).to_le_bytes().iter().chain(input.iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+impl Stamp {
+    /// Compute the stamp of an output file, from the inputs it is
+    /// generated from. The hash of the compiler executable is always
+    /// included.
+    pub fn new(output: &Path, inputs: &[&[u8]]) -> Stamp {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".stamp");
+        let hash = compiler_hash().map(|compiler| {
+            let inputs: Vec<&[u8]> =
+                std::iter::once(compiler.as_bytes()).chain(inputs.iter().copied()).collect();
+            format!("{:016x}\n", hash(&inputs))
+        });
+        Stamp { path: PathBuf::from(path), output: output.to_owned(), hash }
+    }
+
+    /// Return true if the output file exists and was generated from
+    /// the same inputs, by the same compiler.
+    pub fn is_fresh(&self) -> bool {
+        match &self.hash {
+            Some(expected) => {
+                self.output.exists()
+                    && matches!(std::fs::read_to_string(&self.path), Ok(hash) if &hash == expected)
+            }
+            None => false,
+        }
+    }
+
+    /// Record the stamp, once the output file is written. The stamp is
+    /// removed if the compiler executable could not be hashed.
+    pub fn write(&self) -> io::Result<()> {
+        match &self.hash {
+            Some(hash) => std::fs::write(&self.path, hash),
+            None => match std::fs::remove_file(&self.path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stamp() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("out.json");
+        let stamp = Stamp::new(&output, &[b"json", b"packet A {}"]);
+        assert!(!stamp.is_fresh());
+        std::fs::write(&output, "{}").unwrap();
+        assert!(!stamp.is_fresh());
+        stamp.write().unwrap();
+        assert!(stamp.is_fresh());
+        assert!(Stamp::new(&output, &[b"json", b"packet A {}"]).is_fresh());
+        assert!(!Stamp::new(&output, &[b"json", b"packet B {}"]).is_fresh());
+        assert!(!Stamp::new(&output, &[b"jsonpacket A {}", b""]).is_fresh());
+        std::fs::remove_file(&output).unwrap();
+        assert!(!stamp.is_fresh());
+    }
+}
@@ -0,0 +1,94 @@
+//! Bundled Bluetooth definitions.
+//!
+//! Canonical PDL definitions of the core HCI packets, L2CAP signaling,
+//! ATT, and SMP are shipped with the tool, so that grammars and tools
+//! do not carry their own diverging copies. The definitions are loaded
+//! in place of a source file with the name `stdlib:<name>`, e.g.
+//! `pdl compile stdlib:att`, and are listed by `pdl stdlib`.
+
+/// Prefix of the names of the bundled definitions.
+pub const PREFIX: &str = "stdlib:";
+
+/// Bundled definitions, by name.
+pub const DEFINITIONS: &[(&str, &str)] = &[
+    ("hci", include_str!("../stdlib/hci.pdl")),
+    ("l2cap", include_str!("../stdlib/l2cap.pdl")),
+    ("att", include_str!("../stdlib/att.pdl")),
+    ("smp", include_str!("../stdlib/smp.pdl")),
+];
+
+/// Return the source of the bundled definitions `name`.
+pub fn get(name: &str) -> Option<&'static str> {
+    DEFINITIONS.iter().find(|(id, _)| *id == name).map(|(_, source)| *source)
+}
+
+/// Resolve a source file name of the form `stdlib:<name>`. Returns
+/// `None` for other file names.
+pub fn resolve(file_name: &str) -> Option<std::io::Result<&'static str>> {
+    let name = file_name.strip_prefix(PREFIX)?;
+    Some(get(name).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("no bundled definitions named '{}'", name),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast;
+    use crate::decoder;
+    use crate::lint::Lintable;
+    use crate::parser::parse_file;
+    use crate::printer;
+
+    fn load(name: &str) -> ast::Grammar {
+        let mut db = ast::SourceDatabase::new();
+        parse_file(&mut db, format!("{}{}", PREFIX, name)).expect("parsing failure")
+    }
+
+    #[test]
+    fn test_definitions_are_lint_clean() {
+        for (name, _) in DEFINITIONS {
+            let lint = load(name).lint();
+            assert!(lint.diagnostics.is_empty(), "stdlib:{} has lint diagnostics", name);
+        }
+        assert!(resolve("stdlib:unknown").unwrap().is_err());
+        assert!(resolve("hci.pdl").is_none());
+    }
+
+    #[test]
+    fn test_definitions_are_formatted() {
+        for (name, source) in DEFINITIONS {
+            let mut db = ast::SourceDatabase::new();
+            let grammar = parse_file(&mut db, format!("{}{}", PREFIX, name)).unwrap();
+            assert!(printer::print(&db, &grammar) == *source, "stdlib:{} is not formatted", name);
+        }
+    }
+
+    #[test]
+    fn test_conformance() {
+        // Captures of well-known packets, decoded with the bundled
+        // definitions.
+        let vectors: &[(&str, &str, &[u8])] = &[
+            ("hci", "Reset", &[0x03, 0x0c, 0x00]),
+            ("hci", "ResetComplete", &[0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00]),
+            ("hci", "Disconnect", &[0x06, 0x04, 0x03, 0x40, 0x00, 0x13]),
+            ("hci", "Acl", &[0x40, 0x20, 0x02, 0x00, 0xaa, 0xbb]),
+            ("l2cap", "ConnectionRequest", &[0x02, 0x01, 0x04, 0x00, 0x01, 0x00, 0x40, 0x00]),
+            ("l2cap", "FlowControlCredit", &[0x16, 0x02, 0x04, 0x00, 0x40, 0x00, 0x05, 0x00]),
+            ("att", "AttExchangeMtuRequest", &[0x02, 0x00, 0x02]),
+            ("att", "AttReadByGroupTypeRequest", &[0x10, 0x01, 0x00, 0xff, 0xff, 0x00, 0x28]),
+            ("smp", "PairingRequest", &[0x01, 0x03, 0x00, 0x2d, 0x10, 0x0f, 0x0f]),
+            ("smp", "PairingFailed", &[0x05, 0x08]),
+        ];
+        for (name, id, bytes) in vectors {
+            let grammar = load(name);
+            if let Err(err) = decoder::decode(&grammar, id, bytes) {
+                panic!("failed to decode {} from stdlib:{}: {}", id, name, err);
+            }
+        }
+        assert!(decoder::decode(&load("smp"), "PairingFailed", &[0x05]).is_err());
+    }
+}
@@ -180,6 +180,24 @@ pub fn assert_snapshot_eq<P: AsRef<Path>>(snapshot_path: P, actual_content: &str
     }
 }
 
+/// Parse a grammar. The source is added to the database given as
+/// first argument, or to a database dropped after parsing.
+///
+/// This is a macro rather than a function: the paths to the parser are
+/// resolved in the crate using it, see the note at the top of the file.
+macro_rules! grammar {
+    ($text:expr $(,)?) => {{
+        let mut db = crate::ast::SourceDatabase::new();
+        crate::parser::parse_inline(&mut db, "stdin".to_owned(), ($text).to_owned())
+            .expect("parsing failure")
+    }};
+    ($db:expr, $text:expr $(,)?) => {
+        crate::parser::parse_inline($db, "stdin".to_owned(), ($text).to_owned())
+            .expect("parsing failure")
+    };
+}
+pub(crate) use grammar;
+
 #[cfg(test)]
 mod tests {
     use super::*;
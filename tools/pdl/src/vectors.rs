@@ -0,0 +1,222 @@
+//! Test vectors.
+//!
+//! A test vector gives the values of the fields of a packet together
+//! with its expected bytes. The vectors are checked against the
+//! dynamic encoder and decoder by `pdl test`, and converted to
+//! conformance tests of the generated code by the backends, see
+//! [`crate::backends::Backend::generate_tests`], so that the backends
+//! are checked against the same expectations. The vector files have
+//! the layout:
+//!
+//! ```text
+//! vectors := {
+//!     "version": 1,
+//!     "grammar": string | null,  // grammar file, relative to the vectors
+//!     "vectors": [vector],
+//! }
+//!
+//! vector := {
+//!     "name": string | null,     // defaults to `<packet>_<index>`
+//!     "packet": string,
+//!     "fields": object,          // field values, see `src/encoder.rs`
+//!     "bytes": string,           // expected bytes, in hexadecimal
+//! }
+//! ```
+//!
+//! Decoded values are compared with the values of the vector: enum
+//! fields decode to their tag names, and payloads to hexadecimal
+//! strings. Fields missing from the vector are not compared.
+
+use serde_json::{json, Map, Value};
+
+use crate::ast;
+use crate::decoder;
+use crate::encoder;
+
+/// Version of the test vector layout.
+const VECTORS_VERSION: u64 = 1;
+
+/// Test vector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestVector {
+    pub name: String,
+    pub packet: String,
+    pub fields: Map<String, Value>,
+    pub bytes: Vec<u8>,
+}
+
+/// Test vector file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestVectors {
+    /// Grammar file named by the vectors, as written in the file.
+    pub grammar: Option<String>,
+    pub vectors: Vec<TestVector>,
+}
+
+/// Parse a test vector file.
+pub fn parse(text: &str) -> Result<TestVectors, String> {
+    let value: Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    let object = value.as_object().ok_or("expected an object")?;
+    match object.get("version").and_then(Value::as_u64) {
+        Some(VECTORS_VERSION) => (),
+        version => return Err(format!("unsupported version {:?}", version)),
+    }
+    let grammar = object.get("grammar").and_then(Value::as_str).map(str::to_owned);
+    let vectors = object.get("vectors").and_then(Value::as_array).ok_or("missing 'vectors'")?;
+    let vectors = vectors
+        .iter()
+        .enumerate()
+        .map(|(index, vector)| {
+            let get_str = |key: &str| {
+                vector
+                    .get(key)
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| format!("vector {}: missing '{}'", index, key))
+            };
+            let packet = get_str("packet")?.to_owned();
+            let name = vector
+                .get("name")
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+                .unwrap_or_else(|| format!("{}_{}", packet, index));
+            let fields = match vector.get("fields") {
+                Some(Value::Object(fields)) => fields.clone(),
+                None => Map::new(),
+                Some(_) => return Err(format!("vector {}: 'fields' is not an object", index)),
+            };
+            let bytes = decoder::parse_hex(get_str("bytes")?)
+                .map_err(|err| format!("vector {}: {}", index, err))?;
+            Ok(TestVector { name, packet, fields, bytes })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(TestVectors { grammar, vectors })
+}
+
+impl TestVectors {
+    /// Return the test vector file, in JSON.
+    pub fn to_json(&self) -> String {
+        let vectors: Vec<Value> = self
+            .vectors
+            .iter()
+            .map(|vector| {
+                json!({
+                    "name": vector.name,
+                    "packet": vector.packet,
+                    "fields": Value::Object(vector.fields.clone()),
+                    "bytes": hex(&vector.bytes),
+                })
+            })
+            .collect();
+        let mut value = json!({ "version": VECTORS_VERSION, "vectors": vectors });
+        if let (Some(grammar), Some(object)) = (&self.grammar, value.as_object_mut()) {
+            object.insert("grammar".to_owned(), Value::String(grammar.clone()));
+        }
+        serde_json::to_string_pretty(&value).unwrap() + "\n"
+    }
+}
+
+/// Return true if the decoded value matches the expected value of a
+/// test vector.
+fn matches(expected: &Value, decoded: &Value) -> bool {
+    match (expected, decoded) {
+        (Value::Object(expected), Value::Object(decoded)) => expected.iter().all(|(key, value)| {
+            decoded.get(key).map(|decoded| matches(value, decoded)) == Some(true)
+        }),
+        (Value::Array(expected), Value::Array(decoded)) => {
+            expected.len() == decoded.len()
+                && expected
+                    .iter()
+                    .zip(decoded)
+                    .all(|(expected, decoded)| matches(expected, decoded))
+        }
+        (Value::String(expected), Value::String(decoded))
+            if expected.eq_ignore_ascii_case(decoded) =>
+        {
+            true
+        }
+        _ => {
+            expected == decoded
+                || (encoder::integer(expected).is_some()
+                    && encoder::integer(expected) == encoder::integer(decoded))
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Check a test vector: the field values must encode to the expected
+/// bytes, and the bytes must decode to the packet of the vector, with
+/// the field values of the vector.
+pub fn check(grammar: &ast::Grammar, vector: &TestVector) -> Result<(), String> {
+    let fields = Value::Object(vector.fields.clone());
+    let encoded = encoder::encode(grammar, &vector.packet, &fields)
+        .map_err(|err| format!("failed to encode: {}", err))?;
+    if encoded != vector.bytes {
+        return Err(format!("encoded {}, expected {}", hex(&encoded), hex(&vector.bytes)));
+    }
+
+    let decoded = decoder::decode(grammar, &vector.packet, &vector.bytes)
+        .map_err(|err| format!("failed to decode: {}", err))?;
+    let mut decoded_fields = Map::new();
+    let id = decoded.add_fields(&mut decoded_fields);
+    if id != vector.packet {
+        return Err(format!("decoded as {}, expected {}", id, vector.packet));
+    }
+    for (key, expected) in &vector.fields {
+        match decoded_fields.get(key) {
+            Some(value) if matches(expected, value) => (),
+            Some(value) => {
+                return Err(format!("decoded {} = {}, expected {}", key, value, expected))
+            }
+            None => return Err(format!("field {} was not decoded", key)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    #[test]
+    fn test_check() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            packet Command { op: Op, _size_(_payload_): 8, _payload_ }
+            packet Write : Command (op = WRITE) { addr: 16, data: 8[] }
+            "#,
+        );
+        let vectors = parse(
+            r#"{
+                "version": 1,
+                "vectors": [
+                    { "packet": "Write", "fields": { "addr": "0x1234", "data": [7] },
+                      "bytes": "02033412 07" },
+                    { "name": "wrong_bytes", "packet": "Write", "fields": { "addr": 1, "data": [] },
+                      "bytes": "0202 0200" },
+                    { "name": "wrong_packet", "packet": "Command",
+                      "fields": { "op": "WRITE", "_payload_": "0100" }, "bytes": "02020100" }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(vectors.grammar, None);
+        assert_eq!(vectors.vectors[0].name, "Write_0");
+        assert_eq!(check(&grammar, &vectors.vectors[0]), Ok(()));
+        assert_eq!(
+            check(&grammar, &vectors.vectors[1]),
+            Err("encoded 02020100, expected 02020200".to_owned())
+        );
+        assert_eq!(
+            check(&grammar, &vectors.vectors[2]),
+            Err("decoded as Write, expected Command".to_owned())
+        );
+        assert!(parse(r#"{ "version": 2, "vectors": [] }"#).is_err());
+        assert_eq!(parse(&vectors.to_json()), Ok(vectors));
+    }
+}
@@ -0,0 +1,178 @@
+//! AST traversal.
+//!
+//! The [`Visitor`] trait has one method per kind of AST node. The
+//! default implementations call the matching `walk_` function, which
+//! visits the children of the node in source order: passes override
+//! the methods of the nodes they inspect, and call the `walk_`
+//! function from the override to keep descending.
+//!
+//! ```ignore
+//! struct TypedefCount(usize);
+//!
+//! impl<'a> Visitor<'a> for TypedefCount {
+//!     fn visit_field(&mut self, field: &'a ast::Field) {
+//!         if let ast::Field::Typedef { .. } = field {
+//!             self.0 += 1;
+//!         }
+//!     }
+//! }
+//! ```
+
+use crate::ast;
+
+/// AST visitor. The lifetime `'a` is the lifetime of the grammar, so
+/// that visitors can keep references to the nodes they visit.
+pub trait Visitor<'a> {
+    fn visit_grammar(&mut self, grammar: &'a ast::Grammar) {
+        walk_grammar(self, grammar)
+    }
+
+    fn visit_decl(&mut self, decl: &'a ast::Decl) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_field(&mut self, field: &'a ast::Field) {
+        walk_field(self, field)
+    }
+
+    fn visit_constraint(&mut self, constraint: &'a ast::Constraint) {
+        walk_constraint(self, constraint)
+    }
+
+    fn visit_expr(&mut self, expr: &'a ast::Expr) {
+        walk_expr(self, expr)
+    }
+
+    fn visit_tag(&mut self, _tag: &'a ast::Tag) {}
+
+    fn visit_test_case(&mut self, _test_case: &'a ast::TestCase) {}
+}
+
+/// Visit the declarations of the grammar.
+pub fn walk_grammar<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, grammar: &'a ast::Grammar) {
+    for decl in &grammar.declarations {
+        visitor.visit_decl(decl);
+    }
+}
+
+/// Visit the tags of an enum declaration, the constraints then the
+/// fields of a packet, struct or group declaration, or the test cases
+/// of a test declaration.
+pub fn walk_decl<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, decl: &'a ast::Decl) {
+    match decl {
+        ast::Decl::Enum { tags, .. } => {
+            for tag in tags {
+                visitor.visit_tag(tag);
+            }
+        }
+        ast::Decl::Packet { constraints, fields, .. }
+        | ast::Decl::Struct { constraints, fields, .. }
+        | ast::Decl::Group { constraints, fields, .. } => {
+            for constraint in constraints {
+                visitor.visit_constraint(constraint);
+            }
+            for field in fields {
+                visitor.visit_field(field);
+            }
+        }
+        ast::Decl::Test { test_cases, .. } => {
+            for test_case in test_cases {
+                visitor.visit_test_case(test_case);
+            }
+        }
+        ast::Decl::Checksum { .. } | ast::Decl::CustomField { .. } => (),
+    }
+}
+
+/// Visit the constraints of a group field.
+pub fn walk_field<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, field: &'a ast::Field) {
+    if let ast::Field::Group { constraints, .. } = field {
+        for constraint in constraints {
+            visitor.visit_constraint(constraint);
+        }
+    }
+}
+
+/// Visit the value of a constraint.
+pub fn walk_constraint<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    constraint: &'a ast::Constraint,
+) {
+    visitor.visit_expr(&constraint.value)
+}
+
+/// Visit the operands of an expression.
+pub fn walk_expr<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &'a ast::Expr) {
+    match expr {
+        ast::Expr::Unary { operand, .. } => visitor.visit_expr(operand),
+        ast::Expr::Binary { operands, .. } => {
+            visitor.visit_expr(&operands.0);
+            visitor.visit_expr(&operands.1);
+        }
+        ast::Expr::Identifier { .. } | ast::Expr::Integer { .. } => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::grammar;
+
+    /// Record the visited nodes.
+    #[derive(Default)]
+    struct Trace(Vec<String>);
+
+    impl<'a> Visitor<'a> for Trace {
+        fn visit_decl(&mut self, decl: &'a ast::Decl) {
+            self.0.push(format!("decl {}", decl.id().map_or("-", ast::Symbol::as_str)));
+            walk_decl(self, decl)
+        }
+
+        fn visit_field(&mut self, field: &'a ast::Field) {
+            self.0.push(format!("field {}", field.id().map_or("-", ast::Symbol::as_str)));
+            walk_field(self, field)
+        }
+
+        fn visit_constraint(&mut self, constraint: &'a ast::Constraint) {
+            self.0.push(format!("constraint {}", constraint.id));
+            walk_constraint(self, constraint)
+        }
+
+        fn visit_tag(&mut self, tag: &'a ast::Tag) {
+            self.0.push(format!("tag {}", tag.id));
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let grammar = grammar!(
+            r#"
+            little_endian_packets
+            enum Op : 8 { READ = 1, WRITE = 2 }
+            group Header { op: Op, len: 8 }
+            packet Command { Header { len = 0 }, _payload_ }
+            packet Read : Command (op = READ) { handle: 8 }
+            "#,
+        );
+        let mut trace = Trace::default();
+        trace.visit_grammar(&grammar);
+        assert_eq!(
+            trace.0,
+            vec![
+                "decl Op",
+                "tag READ",
+                "tag WRITE",
+                "decl Header",
+                "field op",
+                "field len",
+                "decl Command",
+                "field -",
+                "constraint len",
+                "field -",
+                "decl Read",
+                "constraint op",
+                "field handle",
+            ]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Watch mode.
+//!
+//! The input file is polled for changes, and the output regenerated
+//! when its content changes. The last source and output are cached:
+//! touching the input file without modifying it does not trigger a
+//! new compilation, and the output file is only rewritten when the
+//! generated content differs, so that build tools watching the output
+//! are not triggered needlessly.
+
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+/// Delay between two checks of the input file.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+pub struct Watcher {
+    input_file: String,
+    output_file: Option<String>,
+    source: Option<String>,
+    output: Option<String>,
+}
+
+impl Watcher {
+    /// Create a watcher for `input_file`. The output is written to
+    /// `output_file`, or printed on stdout if unset.
+    pub fn new(input_file: String, output_file: Option<String>) -> Watcher {
+        Watcher { input_file, output_file, source: None, output: None }
+    }
+
+    /// Check the input file, and regenerate the output if the content
+    /// has changed. `compile` returns the generated output, or `None`
+    /// if the input contains errors, after reporting them.
+    /// Returns true if the output was written.
+    pub fn poll<F>(&mut self, compile: F) -> io::Result<bool>
+    where
+        F: FnOnce(&str) -> Option<String>,
+    {
+        let source = fs::read_to_string(&self.input_file)?;
+        if self.source.as_ref() == Some(&source) {
+            return Ok(false);
+        }
+        let output = compile(&source);
+        self.source = Some(source);
+        let output = match output {
+            Some(output) if self.output.as_ref() != Some(&output) => output,
+            _ => return Ok(false),
+        };
+        match &self.output_file {
+            Some(output_file) => fs::write(output_file, &output)?,
+            None => print!("{}", output),
+        }
+        self.output = Some(output);
+        Ok(true)
+    }
+
+    /// Poll the input file forever.
+    pub fn run<F>(mut self, mut compile: F) -> !
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        loop {
+            match self.poll(&mut compile) {
+                Ok(true) => eprintln!("regenerated output from {}", self.input_file),
+                Ok(false) => (),
+                Err(err) => eprintln!("failed to update {}: {}", self.input_file, err),
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_poll() {
+        let mut input = NamedTempFile::new().unwrap();
+        input.write_all(b"a").unwrap();
+        let output = NamedTempFile::new().unwrap();
+        let input_file = input.path().to_str().unwrap().to_owned();
+        let output_file = output.path().to_str().unwrap().to_owned();
+        let mut watcher = Watcher::new(input_file, Some(output_file.clone()));
+        let upper = |source: &str| Some(source.to_uppercase());
+
+        assert!(watcher.poll(upper).unwrap());
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "A");
+
+        // Unchanged input: no compilation.
+        assert!(!watcher.poll(|_| panic!("unexpected compilation")).unwrap());
+
+        // Failed compilation: the previous output is kept.
+        input.write_all(b"b").unwrap();
+        assert!(!watcher.poll(|_| None).unwrap());
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "A");
+
+        input.write_all(b"c").unwrap();
+        assert!(watcher.poll(upper).unwrap());
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "ABC");
+    }
+}